@@ -1,20 +1,241 @@
 //! Quality gates for code verification
 
 use serde::{Deserialize, Serialize};
+use std::io::Read;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QualityGateResult {
     pub name: String,
     pub passed: bool,
+    /// The gate's usual report: stdout, stderr or both depending on the tool,
+    /// or why the command didn't run to completion
     pub output: String,
+    /// None when the command never ran, timed out or was killed by a signal
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
     pub duration_ms: u64,
 }
 
+/// Which streams a gate reports in `output`
+enum Shown {
+    Stdout,
+    Stderr,
+    Both,
+}
+
+impl QualityGateResult {
+    /// A gate that failed before or instead of finishing its command
+    fn failed(name: &str, output: impl Into<String>, start: Instant) -> Self {
+        Self {
+            name: name.to_string(),
+            passed: false,
+            output: output.into(),
+            exit_code: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            duration_ms: start.elapsed().as_millis() as u64,
+        }
+    }
+
+    fn from_command(name: &str, start: Instant, output: Result<Output, String>, shown: Shown) -> Self {
+        let o = match output {
+            Ok(o) => o,
+            Err(e) => return Self::failed(name, e, start),
+        };
+
+        let stdout = String::from_utf8_lossy(&o.stdout).to_string();
+        let stderr = String::from_utf8_lossy(&o.stderr).to_string();
+        let output = match shown {
+            Shown::Stdout => stdout.clone(),
+            Shown::Stderr => stderr.clone(),
+            Shown::Both => format!("{}{}", stdout, stderr),
+        };
+
+        Self {
+            name: name.to_string(),
+            passed: o.status.success(),
+            output,
+            exit_code: o.status.code(),
+            stdout,
+            stderr,
+            duration_ms: start.elapsed().as_millis() as u64,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct QualityGates;
 
+/// How long a gate may run before it is killed and reported as failed
+pub const DEFAULT_GATE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Longest timeout a gate can be given; longer ones are cut to this
+pub const MAX_GATE_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Clippy fails the gate on any warning
+const CLIPPY_ARGS: &[&str] = &["-D", "warnings"];
+
+/// Gates for one project stack, run in order on their own thread
+type Stack<'a> = Vec<Box<dyn FnOnce() -> QualityGateResult + Send + 'a>>;
+
+/// Run a gate command to completion, killing it and everything it started
+/// once `timeout` (at most `MAX_GATE_TIMEOUT`) has passed. Errors carry the
+/// message to report as the gate's output.
+fn run_command(command: &mut Command, timeout: Duration) -> Result<Output, String> {
+    let timeout = timeout.min(MAX_GATE_TIMEOUT);
+
+    // Its own process group, so a timeout also reaches the compilers,
+    // test binaries etc. the tool spawned
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(command, 0);
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Command failed to run: {}", e))?;
+
+    // Drain both pipes while waiting so a chatty tool can't block on a full pipe
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() >= deadline => {
+                kill_process_group(&mut child);
+                let _ = child.wait();
+                return Err(format!("timed out after {}s", timeout.as_secs()));
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(50)),
+            Err(e) => return Err(format!("Command failed to run: {}", e)),
+        }
+    };
+
+    let collect = |reader: Option<std::thread::JoinHandle<Vec<u8>>>| {
+        reader.and_then(|r| r.join().ok()).unwrap_or_default()
+    };
+    Ok(Output {
+        status,
+        stdout: collect(stdout),
+        stderr: collect(stderr),
+    })
+}
+
+/// Kill a child started in its own process group, along with the group
+fn kill_process_group(child: &mut std::process::Child) {
+    #[cfg(unix)]
+    let _ = Command::new("kill")
+        .args(["-KILL", "--", &format!("-{}", child.id())])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    let _ = child.kill();
+}
+
+fn read_to_end(mut pipe: impl Read + Send + 'static) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        buf
+    })
+}
+
+/// Run each stack on its own thread. Gates within a stack stay sequential
+/// since they share build state (e.g. cargo's target lock). Joining in push
+/// order keeps the results deterministic.
+fn run_stacks(stacks: Vec<Stack<'_>>) -> Vec<QualityGateResult> {
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = stacks
+            .into_iter()
+            .map(|gates| scope.spawn(move || gates.into_iter().map(|gate| gate()).collect::<Vec<_>>()))
+            .collect();
+
+        let mut results = Vec::new();
+        for handle in handles {
+            match handle.join() {
+                Ok(stack_results) => results.extend(stack_results),
+                Err(_) => results.push(QualityGateResult::failed("gate_thread", "Quality gate thread panicked", Instant::now())),
+            }
+        }
+        results
+    })
+}
+
+/// Changed files with one of the given extensions
+fn with_extension(files: &[String], extensions: &[&str]) -> Vec<String> {
+    files
+        .iter()
+        .filter(|f| {
+            Path::new(f)
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| extensions.contains(&e))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Cargo packages owning the changed Rust files, found via the nearest
+/// manifest with a [package] section. None if any file has no such
+/// manifest under the project dir, in which case check the whole workspace.
+fn cargo_packages(project_dir: &str, files: &[String]) -> Option<Vec<String>> {
+    let root = Path::new(project_dir);
+    let mut packages = Vec::new();
+
+    for file in files {
+        let name = root
+            .join(file)
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(root))
+            .find_map(|dir| package_name(&dir.join("Cargo.toml")))?;
+        if !packages.contains(&name) {
+            packages.push(name);
+        }
+    }
+
+    Some(packages)
+}
+
+/// The `name` from a manifest's [package] section
+fn package_name(manifest: &Path) -> Option<String> {
+    let text = std::fs::read_to_string(manifest).ok()?;
+    let mut in_package = false;
+
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_package = line == "[package]";
+            continue;
+        }
+        let value = line.strip_prefix("name").map(str::trim_start).and_then(|l| l.strip_prefix('='));
+        if let (true, Some(value)) = (in_package, value) {
+            return Some(value.trim().trim_matches('"').to_string());
+        }
+    }
+
+    None
+}
+
+/// Go packages (as ./dir paths) containing the changed Go files
+fn go_packages(files: &[String]) -> Vec<String> {
+    let mut packages = Vec::new();
+    for file in files {
+        let dir = Path::new(file).parent().map(|d| d.to_string_lossy().to_string()).unwrap_or_default();
+        let package = if dir.is_empty() { ".".to_string() } else { format!("./{}", dir) };
+        if !packages.contains(&package) {
+            packages.push(package);
+        }
+    }
+    packages
+}
+
 /// Validate and canonicalize project directory path
 /// Returns the canonical path or an error if invalid
 fn validate_project_dir(project_dir: &str) -> Result<String, String> {
@@ -40,259 +261,289 @@ fn validate_project_dir(project_dir: &str) -> Result<String, String> {
 impl QualityGates {
     /// Run all quality gates for a project
     pub fn run_all(project_dir: &str) -> Vec<QualityGateResult> {
-        let mut results = Vec::new();
+        Self::run_all_with_timeout(project_dir, DEFAULT_GATE_TIMEOUT)
+    }
 
+    /// Run all quality gates for a project, giving each gate at most `timeout`
+    pub fn run_all_with_timeout(project_dir: &str, timeout: Duration) -> Vec<QualityGateResult> {
         // Validate path first
         let validated_dir = match validate_project_dir(project_dir) {
             Ok(dir) => dir,
-            Err(e) => {
-                results.push(QualityGateResult {
-                    name: "path_validation".to_string(),
-                    passed: false,
-                    output: e,
-                    duration_ms: 0,
-                });
-                return results;
-            }
+            Err(e) => return vec![QualityGateResult::failed("path_validation", e, Instant::now())],
         };
+        let dir = validated_dir.as_str();
+
+        // Try to detect project type and pick the appropriate gates
+        let mut stacks: Vec<Stack> = Vec::new();
 
-        // Try to detect project type and run appropriate gates
-        if Path::new(&validated_dir).join("Cargo.toml").exists() {
-            results.push(Self::rust_check(&validated_dir));
-            results.push(Self::rust_clippy(&validated_dir));
+        if Path::new(dir).join("Cargo.toml").exists() {
+            stacks.push(vec![
+                Box::new(move || Self::rust_check(dir, timeout)),
+                Box::new(move || Self::rust_clippy(dir, timeout)),
+            ]);
         }
 
-        if Path::new(&validated_dir).join("package.json").exists() {
-            results.push(Self::npm_lint(&validated_dir));
-            results.push(Self::npm_typecheck(&validated_dir));
+        if Path::new(dir).join("package.json").exists() {
+            stacks.push(vec![
+                Box::new(move || Self::npm_lint(dir, timeout)),
+                Box::new(move || Self::npm_typecheck(dir, timeout)),
+            ]);
         }
 
-        if Path::new(&validated_dir).join("pyproject.toml").exists()
-            || Path::new(&validated_dir).join("requirements.txt").exists()
+        if Path::new(dir).join("pyproject.toml").exists()
+            || Path::new(dir).join("requirements.txt").exists()
         {
-            results.push(Self::python_ruff(&validated_dir));
-            results.push(Self::python_mypy(&validated_dir));
+            stacks.push(vec![
+                Box::new(move || Self::python_ruff(dir, timeout)),
+                Box::new(move || Self::python_mypy(dir, timeout)),
+            ]);
         }
 
-        results
-    }
+        if Path::new(dir).join("go.mod").exists() {
+            stacks.push(vec![
+                Box::new(move || Self::go_vet(dir, timeout)),
+                Box::new(move || Self::go_build(dir, timeout)),
+                Box::new(move || Self::go_test(dir, timeout)),
+            ]);
+        }
 
-    /// Run rustc check
-    pub fn rust_check(project_dir: &str) -> QualityGateResult {
-        let start = std::time::Instant::now();
+        run_stacks(stacks)
+    }
 
-        let manifest_path = match Path::new(project_dir).join("Cargo.toml").canonicalize() {
-            Ok(p) => p,
-            Err(e) => {
-                return QualityGateResult {
-                    name: "rust-check".to_string(),
-                    passed: false,
-                    output: format!("Cannot resolve manifest path: {}", e),
-                    duration_ms: start.elapsed().as_millis() as u64,
-                };
-            }
+    /// Run the quality gates only over what changed since `base_ref`: cargo
+    /// gates for the affected packages, linters for the changed files, go
+    /// gates for the affected packages. Stacks with no changes are skipped;
+    /// npm typecheck still covers the whole project when any script changed.
+    pub fn run_changed(project_dir: &str, base_ref: &str, timeout: Duration) -> Vec<QualityGateResult> {
+        let validated_dir = match validate_project_dir(project_dir) {
+            Ok(dir) => dir,
+            Err(e) => return vec![QualityGateResult::failed("path_validation", e, Instant::now())],
         };
+        let dir = validated_dir.as_str();
 
-        let output = Command::new("cargo")
-            .args([
-                "check",
-                "--manifest-path",
-                manifest_path.to_str().unwrap_or(""),
-            ])
-            .output();
-
-        let (passed, output_str) = match output {
-            Ok(o) => (
-                o.status.success(),
-                String::from_utf8_lossy(&o.stderr).to_string(),
-            ),
-            Err(e) => (false, format!("Command failed to run: {}", e)),
+        let changed = match Self::changed_files(dir, base_ref) {
+            Ok(files) => files,
+            Err(e) => return vec![QualityGateResult::failed("changed_files", e, Instant::now())],
         };
 
-        QualityGateResult {
-            name: "rust-check".to_string(),
-            passed,
-            output: output_str,
-            duration_ms: start.elapsed().as_millis() as u64,
+        let mut stacks: Vec<Stack> = Vec::new();
+
+        let mut rust_files = with_extension(&changed, &["rs"]);
+        rust_files.extend(changed.iter().filter(|f| f.ends_with("Cargo.toml")).cloned());
+        if Path::new(dir).join("Cargo.toml").exists() && !rust_files.is_empty() {
+            let packages = cargo_packages(dir, &rust_files).unwrap_or_default();
+            let clippy_packages = packages.clone();
+            stacks.push(vec![
+                Box::new(move || Self::cargo_gate("rust-check", "check", &[], dir, timeout, &packages)),
+                Box::new(move || Self::cargo_gate("rust-clippy", "clippy", CLIPPY_ARGS, dir, timeout, &clippy_packages)),
+            ]);
         }
+
+        let script_files = with_extension(&changed, &["js", "jsx", "mjs", "cjs", "ts", "tsx", "mts", "cts", "vue", "svelte"]);
+        if Path::new(dir).join("package.json").exists() && !script_files.is_empty() {
+            stacks.push(vec![
+                Box::new(move || Self::npm_script("npm-lint", "lint", dir, timeout, &script_files)),
+                Box::new(move || Self::npm_typecheck(dir, timeout)),
+            ]);
+        }
+
+        let python_files = with_extension(&changed, &["py", "pyi"]);
+        if (Path::new(dir).join("pyproject.toml").exists() || Path::new(dir).join("requirements.txt").exists())
+            && !python_files.is_empty()
+        {
+            let mypy_files = python_files.clone();
+            stacks.push(vec![
+                Box::new(move || Self::python_tool("ruff", &["check"], dir, timeout, &python_files)),
+                Box::new(move || Self::python_tool("mypy", &[], dir, timeout, &mypy_files)),
+            ]);
+        }
+
+        let go_files = with_extension(&changed, &["go"]);
+        if Path::new(dir).join("go.mod").exists() && !go_files.is_empty() {
+            let packages = go_packages(&go_files);
+            let (build_packages, test_packages) = (packages.clone(), packages.clone());
+            stacks.push(vec![
+                Box::new(move || Self::go_command("go-vet", "vet", dir, timeout, &packages)),
+                Box::new(move || Self::go_command("go-build", "build", dir, timeout, &build_packages)),
+                Box::new(move || Self::go_command("go-test", "test", dir, timeout, &test_packages)),
+            ]);
+        }
+
+        run_stacks(stacks)
     }
 
-    /// Run clippy
-    pub fn rust_clippy(project_dir: &str) -> QualityGateResult {
-        let start = std::time::Instant::now();
+    /// Files under `project_dir` that differ from `base_ref`, committed or
+    /// not, plus untracked files; relative to `project_dir`. Deleted files
+    /// are left out since there is nothing left to check.
+    pub fn changed_files(project_dir: &str, base_ref: &str) -> Result<Vec<String>, String> {
+        if base_ref.is_empty() || base_ref.starts_with('-') {
+            return Err(format!("Invalid base ref: {}", base_ref));
+        }
 
-        let manifest_path = match Path::new(project_dir).join("Cargo.toml").canonicalize() {
-            Ok(p) => p,
-            Err(e) => {
-                return QualityGateResult {
-                    name: "rust-clippy".to_string(),
-                    passed: false,
-                    output: format!("Cannot resolve manifest path: {}", e),
-                    duration_ms: start.elapsed().as_millis() as u64,
-                };
+        let git = |args: &[&str]| -> Result<String, String> {
+            let output = Command::new("git")
+                .args(args)
+                .current_dir(project_dir)
+                .output()
+                .map_err(|e| format!("Failed to run git: {}", e))?;
+            if !output.status.success() {
+                return Err(format!("git {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim()));
             }
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
         };
 
-        let output = Command::new("cargo")
-            .args([
-                "clippy",
-                "--manifest-path",
-                manifest_path.to_str().unwrap_or(""),
-                "--",
-                "-D",
-                "warnings",
-            ])
-            .output();
-
-        let (passed, output_str) = match output {
-            Ok(o) => (
-                o.status.success(),
-                String::from_utf8_lossy(&o.stderr).to_string(),
-            ),
-            Err(e) => (false, format!("Command failed to run: {}", e)),
-        };
-
-        QualityGateResult {
-            name: "rust-clippy".to_string(),
-            passed,
-            output: output_str,
-            duration_ms: start.elapsed().as_millis() as u64,
-        }
+        let diff = git(&["diff", "--name-only", "--relative", base_ref, "--"])?;
+        let untracked = git(&["ls-files", "--others", "--exclude-standard"])?;
+
+        let mut files: Vec<String> = diff
+            .lines()
+            .chain(untracked.lines())
+            .filter(|f| !f.is_empty() && Path::new(project_dir).join(f).is_file())
+            .map(String::from)
+            .collect();
+        files.sort();
+        files.dedup();
+        Ok(files)
     }
 
-    /// Run npm lint
-    pub fn npm_lint(project_dir: &str) -> QualityGateResult {
-        let start = std::time::Instant::now();
+    /// Run rustc check
+    pub fn rust_check(project_dir: &str, timeout: Duration) -> QualityGateResult {
+        Self::cargo_gate("rust-check", "check", &[], project_dir, timeout, &[])
+    }
 
-        // Validate directory exists
-        if !Path::new(project_dir).join("package.json").exists() {
-            return QualityGateResult {
-                name: "npm-lint".to_string(),
-                passed: false,
-                output: "package.json not found".to_string(),
-                duration_ms: start.elapsed().as_millis() as u64,
-            };
-        }
+    /// Run clippy
+    pub fn rust_clippy(project_dir: &str, timeout: Duration) -> QualityGateResult {
+        Self::cargo_gate("rust-clippy", "clippy", CLIPPY_ARGS, project_dir, timeout, &[])
+    }
 
-        let output = Command::new("npm")
-            .args(["run", "lint"])
-            .current_dir(project_dir)
-            .output();
+    /// Run a cargo subcommand on the given packages (all when empty), with
+    /// `trailing` passed after `--`
+    fn cargo_gate(
+        name: &str,
+        subcommand: &str,
+        trailing: &[&str],
+        project_dir: &str,
+        timeout: Duration,
+        packages: &[String],
+    ) -> QualityGateResult {
+        let start = Instant::now();
 
-        let (passed, output_str) = match output {
-            Ok(o) => {
-                let mut s = String::from_utf8_lossy(&o.stdout).to_string();
-                s.push_str(&String::from_utf8_lossy(&o.stderr));
-                (o.status.success(), s)
+        let manifest_path = match Path::new(project_dir).join("Cargo.toml").canonicalize() {
+            Ok(p) => p,
+            Err(e) => {
+                return QualityGateResult::failed(name, format!("Cannot resolve manifest path: {}", e), start);
             }
-            Err(e) => (false, format!("Command failed to run: {}", e)),
         };
 
-        QualityGateResult {
-            name: "npm-lint".to_string(),
-            passed,
-            output: output_str,
-            duration_ms: start.elapsed().as_millis() as u64,
+        let mut command = Command::new("cargo");
+        command
+            .arg(subcommand)
+            .args(["--manifest-path", manifest_path.to_str().unwrap_or("")]);
+        for package in packages {
+            command.args(["--package", package]);
+        }
+        if !trailing.is_empty() {
+            command.arg("--").args(trailing);
         }
+
+        let output = run_command(&mut command, timeout);
+
+        QualityGateResult::from_command(name, start, output, Shown::Stderr)
+    }
+
+    /// Run npm lint
+    pub fn npm_lint(project_dir: &str, timeout: Duration) -> QualityGateResult {
+        Self::npm_script("npm-lint", "lint", project_dir, timeout, &[])
     }
 
     /// Run npm typecheck
-    pub fn npm_typecheck(project_dir: &str) -> QualityGateResult {
-        let start = std::time::Instant::now();
+    pub fn npm_typecheck(project_dir: &str, timeout: Duration) -> QualityGateResult {
+        Self::npm_script("npm-typecheck", "typecheck", project_dir, timeout, &[])
+    }
+
+    /// Run an npm script, passing any files through to it
+    fn npm_script(name: &str, script: &str, project_dir: &str, timeout: Duration, files: &[String]) -> QualityGateResult {
+        let start = Instant::now();
 
         // Validate directory exists
         if !Path::new(project_dir).join("package.json").exists() {
-            return QualityGateResult {
-                name: "npm-typecheck".to_string(),
-                passed: false,
-                output: "package.json not found".to_string(),
-                duration_ms: start.elapsed().as_millis() as u64,
-            };
+            return QualityGateResult::failed(name, "package.json not found", start);
         }
 
-        let output = Command::new("npm")
-            .args(["run", "typecheck"])
-            .current_dir(project_dir)
-            .output();
+        let mut command = Command::new("npm");
+        command.args(["run", script]).current_dir(project_dir);
+        if !files.is_empty() {
+            command.arg("--").args(files);
+        }
 
-        let (passed, output_str) = match output {
-            Ok(o) => {
-                let mut s = String::from_utf8_lossy(&o.stdout).to_string();
-                s.push_str(&String::from_utf8_lossy(&o.stderr));
-                (o.status.success(), s)
-            }
-            Err(e) => (false, format!("Command failed to run: {}", e)),
-        };
+        let output = run_command(&mut command, timeout);
 
-        QualityGateResult {
-            name: "npm-typecheck".to_string(),
-            passed,
-            output: output_str,
-            duration_ms: start.elapsed().as_millis() as u64,
-        }
+        QualityGateResult::from_command(name, start, output, Shown::Both)
     }
 
     /// Run ruff (Python linter)
-    pub fn python_ruff(project_dir: &str) -> QualityGateResult {
-        let start = std::time::Instant::now();
-        let output = Command::new("ruff").arg("check").arg(project_dir).output();
-
-        let (passed, output_str) = match output {
-            Ok(o) => (
-                o.status.success(),
-                String::from_utf8_lossy(&o.stdout).to_string(),
-            ),
-            Err(e) => (false, format!("Command failed to run: {}", e)),
-        };
-
-        QualityGateResult {
-            name: "ruff".to_string(),
-            passed,
-            output: output_str,
-            duration_ms: start.elapsed().as_millis() as u64,
-        }
+    pub fn python_ruff(project_dir: &str, timeout: Duration) -> QualityGateResult {
+        Self::python_tool("ruff", &["check"], project_dir, timeout, &[project_dir.to_string()])
     }
 
     /// Run mypy (Python type checker)
-    pub fn python_mypy(project_dir: &str) -> QualityGateResult {
-        let start = std::time::Instant::now();
-        let output = Command::new("mypy").arg(project_dir).output();
-
-        let (passed, output_str) = match output {
-            Ok(o) => (
-                o.status.success(),
-                String::from_utf8_lossy(&o.stdout).to_string(),
-            ),
-            Err(e) => (false, format!("Command failed to run: {}", e)),
-        };
+    pub fn python_mypy(project_dir: &str, timeout: Duration) -> QualityGateResult {
+        Self::python_tool("mypy", &[], project_dir, timeout, &[project_dir.to_string()])
+    }
 
-        QualityGateResult {
-            name: "mypy".to_string(),
-            passed,
-            output: output_str,
-            duration_ms: start.elapsed().as_millis() as u64,
-        }
+    /// Run a Python checker over the given paths
+    fn python_tool(tool: &str, args: &[&str], project_dir: &str, timeout: Duration, paths: &[String]) -> QualityGateResult {
+        let start = Instant::now();
+        let output = run_command(
+            Command::new(tool)
+                .args(args)
+                .args(paths)
+                .current_dir(project_dir),
+            timeout,
+        );
+
+        QualityGateResult::from_command(tool, start, output, Shown::Stdout)
     }
 
     /// Run pytest (Python tests)
-    pub fn python_pytest(project_dir: &str) -> QualityGateResult {
-        let start = std::time::Instant::now();
-        let output = Command::new("pytest").arg(project_dir).output();
-
-        let (passed, output_str) = match output {
-            Ok(o) => {
-                let mut s = String::from_utf8_lossy(&o.stdout).to_string();
-                s.push_str(&String::from_utf8_lossy(&o.stderr));
-                (o.status.success(), s)
-            }
-            Err(e) => (false, format!("Command failed to run: {}", e)),
-        };
+    pub fn python_pytest(project_dir: &str, timeout: Duration) -> QualityGateResult {
+        let start = Instant::now();
+        let output = run_command(Command::new("pytest").arg(project_dir), timeout);
 
-        QualityGateResult {
-            name: "pytest".to_string(),
-            passed,
-            output: output_str,
-            duration_ms: start.elapsed().as_millis() as u64,
+        QualityGateResult::from_command("pytest", start, output, Shown::Both)
+    }
+
+    /// Run go vet
+    pub fn go_vet(project_dir: &str, timeout: Duration) -> QualityGateResult {
+        Self::go_command("go-vet", "vet", project_dir, timeout, &["./...".to_string()])
+    }
+
+    /// Run go build
+    pub fn go_build(project_dir: &str, timeout: Duration) -> QualityGateResult {
+        Self::go_command("go-build", "build", project_dir, timeout, &["./...".to_string()])
+    }
+
+    /// Run go test
+    pub fn go_test(project_dir: &str, timeout: Duration) -> QualityGateResult {
+        Self::go_command("go-test", "test", project_dir, timeout, &["./...".to_string()])
+    }
+
+    /// Run a go subcommand over the given packages
+    fn go_command(name: &str, subcommand: &str, project_dir: &str, timeout: Duration, packages: &[String]) -> QualityGateResult {
+        let start = Instant::now();
+
+        if !Path::new(project_dir).join("go.mod").exists() {
+            return QualityGateResult::failed(name, "go.mod not found", start);
         }
+
+        let output = run_command(
+            Command::new("go")
+                .arg(subcommand)
+                .args(packages)
+                .current_dir(project_dir),
+            timeout,
+        );
+
+        QualityGateResult::from_command(name, start, output, Shown::Both)
     }
 }
@@ -1,13 +1,14 @@
 //! CLI commands
 
+use std::str::FromStr;
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 
 use crate::db::{
     repositories::project::ProjectRepository,
-    repositories::session::{AgentType, SessionRepository, SessionStatus, SessionType},
+    repositories::session::{AgentType, SessionFilter, SessionRepository, SessionStatus, SessionType},
     Database,
 };
 
@@ -21,10 +22,17 @@ struct Cli {
     /// Database path (default: ~/.supercode/supercode.db)
     #[arg(long)]
     database: Option<String>,
+
+    /// Print lists and created records as JSON
+    #[arg(long, global = true)]
+    json: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Show database and provider health, session counts and blocked sessions
+    Status,
+
     /// List all sessions
     Sessions {
         /// Filter by project ID
@@ -34,6 +42,14 @@ enum Commands {
         /// Filter by status
         #[arg(long)]
         status: Option<String>,
+
+        /// Filter by agent type (manager, developer, reviewer)
+        #[arg(long)]
+        agent_type: Option<String>,
+
+        /// Only sessions with this tag (repeat to require several)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
     },
 
     /// Create a new session
@@ -42,7 +58,7 @@ enum Commands {
         #[arg(long)]
         agent_type: String,
 
-        /// Session type (opencode, claude)
+        /// Session type (opencode, claude, openai)
         #[arg(long)]
         session_type: String,
 
@@ -55,10 +71,100 @@ enum Commands {
         working_dir: Option<String>,
     },
 
-    /// Kill a session
+    /// Kill a session at its provider and mark it terminated. Live sessions
+    /// other than OpenCode ones must be killed by the server holding them.
     KillSession {
         /// Session ID
         session_id: String,
+
+        /// Show what would be killed without doing it
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Don't ask for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+
+    /// Delete finished sessions (and their messages) created more than some days ago
+    Cleanup {
+        /// Delete sessions created more than this many days ago
+        #[arg(long)]
+        older_than_days: u32,
+
+        /// Only delete sessions in this status (repeat for several; default:
+        /// completed, failed and terminated). Pending and running sessions
+        /// are only deleted when named here, and are killed first.
+        #[arg(long = "status")]
+        statuses: Vec<String>,
+
+        /// Show what would be deleted without doing it
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Don't ask for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+
+    /// Send a message to a session and print the response. Only OpenCode
+    /// sessions can be reached from outside the server that spawned them.
+    SendMessage {
+        /// Session ID
+        session_id: String,
+
+        /// Message content
+        content: String,
+
+        /// OpenCode server URL (overrides config)
+        #[arg(long)]
+        opencode_url: Option<String>,
+    },
+
+    /// Reattach a session to its existing OpenCode session, e.g. after a crash
+    ResumeSession {
+        /// Session ID
+        session_id: String,
+
+        /// Provider session to attach to (default: the one stored for the session)
+        #[arg(long)]
+        provider_session_id: Option<String>,
+
+        /// OpenCode server URL (overrides config)
+        #[arg(long)]
+        opencode_url: Option<String>,
+    },
+
+    /// Follow a session's activity until it finishes (Ctrl-C to stop)
+    Tail {
+        /// Session ID
+        session_id: String,
+
+        /// Seconds between checks
+        #[arg(long, default_value = "1")]
+        interval: u64,
+    },
+
+    /// Export a session's transcript (e.g. to paste into a PR or issue), or
+    /// without a session, every project, session and message as JSON Lines
+    Export {
+        /// Session whose transcript to export
+        session_id: Option<String>,
+
+        /// Output format: markdown for a transcript, jsonl for a full export
+        #[arg(long)]
+        format: Option<String>,
+
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        out: Option<String>,
+    },
+
+    /// Import a JSON Lines export, skipping records whose id already exists
+    Import {
+        /// File written by `export --format jsonl`
+        #[arg(long = "in")]
+        input: String,
     },
 
     /// List all projects
@@ -74,11 +180,57 @@ enum Commands {
         description: Option<String>,
     },
 
+    /// Update an existing project
+    UpdateProject {
+        /// Project ID
+        project_id: String,
+
+        /// New project name
+        #[arg(long)]
+        name: Option<String>,
+
+        /// New project description
+        #[arg(long)]
+        description: Option<String>,
+    },
+
     /// Start MCP server
     Serve {
-        /// Port number
+        /// MCP server port
         #[arg(long, default_value = "8080")]
         port: u16,
+
+        /// MCP server bind address (overrides config; default 127.0.0.1)
+        #[arg(long)]
+        host: Option<String>,
+
+        /// Transport: "tcp" (HTTP on --port, plus the peer server) or "stdio"
+        #[arg(long, default_value = "tcp")]
+        transport: String,
+
+        /// OpenCode server URL (overrides config)
+        #[arg(long)]
+        opencode_url: Option<String>,
+
+        /// Claude Code CLI path (overrides config)
+        #[arg(long)]
+        claude_binary: Option<String>,
+
+        /// Directory for Claude session working dirs (overrides config)
+        #[arg(long)]
+        claude_sessions_dir: Option<String>,
+
+        /// Peer server port (default: --port + 1)
+        #[arg(long)]
+        peer_port: Option<u16>,
+
+        /// Start only the MCP server
+        #[arg(long, conflicts_with = "peer_only")]
+        mcp_only: bool,
+
+        /// Start only the peer server
+        #[arg(long)]
+        peer_only: bool,
     },
 
     /// Manage peers
@@ -89,7 +241,7 @@ enum Commands {
 
     /// Generate keypair for this node
     Keygen {
-        /// Optional password to encrypt the config
+        /// Optional password to encrypt the private key with
         #[arg(long)]
         password: Option<String>,
     },
@@ -139,6 +291,27 @@ enum PeerCommands {
         /// Peer name
         name: String,
     },
+
+    /// Send a message to a peer, queueing it if the peer is unreachable
+    Send {
+        /// Peer name
+        name: String,
+
+        /// Message type, e.g. spawn_session
+        message_type: String,
+
+        /// Message payload
+        payload: String,
+    },
+
+    /// Mark a peer's key as trusted after checking it out-of-band
+    Verify {
+        /// Peer name
+        name: String,
+
+        /// Public key the peer's operator gave you
+        expected_public_key: String,
+    },
 }
 
 pub fn run() -> Result<()> {
@@ -158,6 +331,8 @@ pub fn run() -> Result<()> {
         let _ = std::fs::create_dir_all(parent);
     }
 
+    let json = cli.json;
+
     // Initialize database
     let db = Database::new(&db_path)?;
     let session_repo = SessionRepository::new(db.clone());
@@ -170,18 +345,89 @@ pub fn run() -> Result<()> {
 
     rt.block_on(async {
         match cli.command {
-        Commands::Sessions { project_id, status } => {
-            let status = status.map(|s| SessionStatus::from_str(&s)).transpose()?;
+        Commands::Status => {
+            let config = crate::config::Config::load_locked(None)?;
+            let session_manager = crate::session::SessionManager::from_config(db, &config)?;
+
+            // Don't let one hung provider hold up the whole view
+            let (database, providers) = tokio::join!(
+                session_manager.check_database_health(),
+                session_manager.check_providers_health(Some(std::time::Duration::from_secs(2))),
+            );
+            let database = database.unwrap_or(false);
+            let gauges = session_manager.session_gauges().await?;
+            let mut blocked = Vec::new();
+            for activity in session_manager.get_blocked_sessions().await? {
+                let name = session_repo.get(&activity.session_id).await?.and_then(|session| session.name);
+                blocked.push((name, activity));
+            }
+
+            if json {
+                return print_json(&serde_json::json!({
+                    "database": database,
+                    "providers": providers.iter().cloned().collect::<std::collections::BTreeMap<_, _>>(),
+                    "sessions": gauges.by_status.iter().cloned().collect::<std::collections::BTreeMap<_, _>>(),
+                    "blocked": blocked.iter().map(|(name, activity)| serde_json::json!({
+                        "session_id": activity.session_id,
+                        "name": name,
+                        "approval_type": activity.approval_type.map(|t| t.as_str()),
+                        "approval_description": activity.approval_description,
+                        "since": activity.state_changed_at.to_rfc3339(),
+                    })).collect::<Vec<_>>(),
+                }));
+            }
+
+            let health = |up: bool| if up { "ok" } else { "unavailable" };
+            println!("Database:  {}", health(database));
+            println!("Providers:");
+            for (session_type, up) in &providers {
+                println!("  {:<10} {}", session_type, health(*up));
+            }
+            println!(
+                "Sessions:  {}",
+                gauges.by_status.iter().map(|(status, count)| format!("{} {}", count, status)).collect::<Vec<_>>().join(", ")
+            );
+            if blocked.is_empty() {
+                println!("Blocked:   none");
+            } else {
+                println!("Blocked:   {}", blocked.len());
+                for (name, activity) in &blocked {
+                    println!(
+                        "  {} {}waiting on {}{} (since {})",
+                        activity.session_id,
+                        name.as_deref().map(|name| format!("\"{}\" ", name)).unwrap_or_default(),
+                        activity.approval_type.map(|t| t.as_str()).unwrap_or("approval"),
+                        activity.approval_description.as_deref().map(|d| format!(": {}", d)).unwrap_or_default(),
+                        activity.state_changed_at.format("%Y-%m-%d %H:%M"),
+                    );
+                }
+            }
+            Ok(())
+        }
 
-            let sessions = session_repo.list(project_id.as_deref(), status).await?;
+        Commands::Sessions { project_id, status, agent_type, tags } => {
+            let status = status.map(|s| SessionStatus::from_str(&s)).transpose()?;
+            let agent_type = agent_type.map(|s| AgentType::from_str(&s)).transpose()?;
 
-            if sessions.is_empty() {
+            let filter = SessionFilter {
+                project_id: project_id.as_deref(),
+                status,
+                agent_type,
+                tags: &tags,
+                ..SessionFilter::default()
+            };
+            let (sessions, _) = session_repo.list_filtered(&filter, None, 0).await?;
+
+            if json {
+                print_json(&sessions)?;
+            } else if sessions.is_empty() {
                 println!("No sessions found");
             } else {
                 for session in sessions {
                     println!(
-                        "[{}] {} - {} ({}) - {}",
+                        "[{}] {}{} - {} ({}) - {}",
                         session.id.chars().take(8).collect::<String>(),
+                        session.name.as_deref().map(|name| format!("{}: ", name)).unwrap_or_default(),
                         session.agent_type.as_str(),
                         session.session_type.as_str(),
                         session.status.as_str(),
@@ -208,21 +454,251 @@ pub fn run() -> Result<()> {
                 working_dir,
             ).await?;
 
-            println!("Created session: {}", session.id);
+            if json {
+                print_json(&session)?;
+            } else {
+                println!("Created session: {}", session.id);
+            }
             Ok(())
         }
 
-        Commands::KillSession { session_id } => {
+        Commands::KillSession { session_id, dry_run, yes } => {
+            let session = session_repo.get(&session_id).await?
+                .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+            let config = crate::config::Config::load_locked(None)?;
+            let session_manager = crate::session::SessionManager::from_config(db, &config)?;
+            if let Some(reason) = held_by_server(&session_manager, &session) {
+                anyhow::bail!("Can't kill session {} from the command line: {}; use that server's kill_session tool", session_id, reason);
+            }
+
+            if json && dry_run {
+                return print_json(&serde_json::json!({
+                    "dry_run": true,
+                    "session": session,
+                    "provider_kill": session.opencode_session_id,
+                }));
+            }
+
+            println!("{} session:", if dry_run { "Would kill" } else { "Killing" });
+            println!("  {}", describe_session(&session));
+            if let Some(provider_id) = &session.opencode_session_id {
+                println!("  and its {} provider session {}", session.session_type.as_str(), provider_id);
+            }
+            if dry_run || !confirm(yes)? {
+                return Ok(());
+            }
+
+            // A session whose provider kill fails is left as it is, so it
+            // still shows up as running and can be killed again
+            if let Some(provider_id) = &session.opencode_session_id {
+                session_manager.kill_provider_session(provider_id, session.session_type.as_str()).await
+                    .with_context(|| format!("Failed to kill provider session {}; session {} left {}", provider_id, session_id, session.status.as_str()))?;
+            }
             session_repo.update_status(&session_id, SessionStatus::Terminated).await?;
 
             println!("Terminated session: {}", session_id);
             Ok(())
         }
 
+        Commands::Cleanup { older_than_days, statuses, dry_run, yes } => {
+            let statuses = statuses.iter()
+                .map(|status| status.parse())
+                .collect::<Result<Vec<_>>>()?;
+            // Live sessions only when asked for by name
+            let statuses = if statuses.is_empty() { SessionStatus::FINISHED.to_vec() } else { statuses };
+            let older_than = chrono::TimeDelta::try_days(older_than_days.into())
+                .and_then(|age| chrono::Utc::now().checked_sub_signed(age))
+                .ok_or_else(|| anyhow::anyhow!("--older-than-days is too large"))?;
+
+            // The same sessions delete_where removes: created strictly before the cutoff
+            let mut matched = Vec::new();
+            for status in &statuses {
+                let filter = SessionFilter { status: Some(*status), created_before: Some(older_than), ..SessionFilter::default() };
+                matched.extend(session_repo.list_filtered(&filter, None, 0).await?.0);
+            }
+
+            // Deleting these here would leave their agents running with no
+            // row to find them by
+            let config = crate::config::Config::load_locked(None)?;
+            let session_manager = crate::session::SessionManager::from_config(db, &config)?;
+            let mut held = Vec::new();
+            for session in &matched {
+                if let Some(reason) = held_by_server(&session_manager, session) {
+                    held.push((session.id.clone(), reason));
+                }
+            }
+            matched.retain(|session| !held.iter().any(|(id, _)| *id == session.id));
+            for (session_id, reason) in &held {
+                eprintln!("Skipping session {}: {}; use that server's delete_session tool", session_id, reason);
+            }
+
+            if json && dry_run {
+                return print_json(&serde_json::json!({ "dry_run": true, "sessions": matched }));
+            }
+            if matched.is_empty() {
+                println!("No sessions match");
+                return Ok(());
+            }
+
+            println!("{} {} session(s):", if dry_run { "Would delete" } else { "Deleting" }, matched.len());
+            for session in &matched {
+                println!("  {}", describe_session(session));
+                if let Some(provider_id) = live_provider_id(session) {
+                    println!("    and its {} provider session {}", session.session_type.as_str(), provider_id);
+                }
+            }
+            if dry_run || !confirm(yes)? {
+                return Ok(());
+            }
+
+            // Delete exactly what was listed, stopping live sessions at their
+            // provider first. One whose provider kill fails is kept.
+            let summary = session_manager.delete_sessions(&matched).await?;
+            for (session_id, error) in &summary.kept {
+                eprintln!("Kept session {}: failed to kill its provider session: {}", session_id, error);
+            }
+
+            println!("Deleted {} session(s)", summary.deleted.len());
+            Ok(())
+        }
+
+        Commands::SendMessage { session_id, content, opencode_url } => {
+            if content.is_empty() {
+                anyhow::bail!("content cannot be empty");
+            }
+
+            let session = session_repo.get(&session_id).await?
+                .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+            let provider_session_id = session.opencode_session_id
+                .ok_or_else(|| anyhow::anyhow!("Session {} has no provider session; spawn it first", session_id))?;
+
+            let mut config = crate::config::Config::load_locked(None)?;
+            if let Some(url) = opencode_url {
+                config.opencode_url = url;
+            }
+            let session_manager = crate::session::SessionManager::from_config(db, &config)?;
+
+            // Sessions of other providers live only in the process that
+            // spawned them, so this one has nothing to send to
+            let session_type = session.session_type.as_str();
+            if !session_manager.provider_capabilities(session_type)?.supports_resume {
+                anyhow::bail!(
+                    "Can't send to {} session {} from the command line: it only exists in the server that spawned it; use that server's send_message tool",
+                    session_type, session_id
+                );
+            }
+
+            let response = session_manager.send_message(
+                &session_id,
+                &provider_session_id,
+                session_type,
+                &content,
+            ).await?;
+
+            if json {
+                print_json(&serde_json::json!({ "session_id": session_id, "response": response }))?;
+            } else {
+                println!("{}", response);
+            }
+            Ok(())
+        }
+
+        Commands::ResumeSession { session_id, provider_session_id, opencode_url } => {
+            let session = session_repo.get(&session_id).await?
+                .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+            let provider_session_id = provider_session_id
+                .or(session.opencode_session_id)
+                .ok_or_else(|| anyhow::anyhow!("Session {} has no provider session; pass --provider-session-id", session_id))?;
+
+            let mut config = crate::config::Config::load_locked(None)?;
+            if let Some(url) = opencode_url {
+                config.opencode_url = url;
+            }
+            let session_manager = crate::session::SessionManager::from_config(db, &config)?;
+
+            let handle = session_manager.resume_session(
+                &session_id,
+                &provider_session_id,
+                session.session_type.as_str(),
+            ).await?;
+
+            if json {
+                print_json(&serde_json::json!({ "session_id": session_id, "provider_session_id": handle.provider_id }))?;
+            } else {
+                println!("Resumed session {} on {}", session_id, handle.provider_id);
+            }
+            Ok(())
+        }
+
+        Commands::Tail { session_id, interval } => {
+            if session_repo.get(&session_id).await?.is_none() {
+                anyhow::bail!("Session not found: {}", session_id);
+            }
+
+            tokio::select! {
+                result = tail_session(&session_repo, &session_id, std::time::Duration::from_secs(interval.max(1))) => result,
+                _ = tokio::signal::ctrl_c() => Ok(()),
+            }
+        }
+
+        Commands::Export { session_id, format, out } => {
+            let format = format.unwrap_or_else(|| if session_id.is_some() { "markdown" } else { "jsonl" }.to_string());
+
+            match (session_id, format.as_str()) {
+                (Some(session_id), "markdown") => {
+                    let transcript = crate::session::SessionManager::new(db).export_transcript(&session_id).await?;
+                    match out {
+                        Some(path) => std::fs::write(&path, &transcript)
+                            .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path, e))?,
+                        None if json => print_json(&serde_json::json!({ "session_id": session_id, "format": format, "transcript": transcript }))?,
+                        None => print!("{}", transcript),
+                    }
+                }
+                (None, "jsonl") => match out {
+                    Some(path) => {
+                        let file = std::fs::File::create(&path)
+                            .map_err(|e| anyhow::anyhow!("Failed to create {}: {}", path, e))?;
+                        let counts = crate::db::dump::export_jsonl(&db, &mut std::io::BufWriter::new(file)).await?;
+                        if json {
+                            print_json(&counts)?;
+                        } else {
+                            println!("Exported {} projects, {} sessions and {} messages to {}", counts.projects, counts.sessions, counts.messages, path);
+                        }
+                    }
+                    None => {
+                        crate::db::dump::export_jsonl(&db, &mut std::io::stdout().lock()).await?;
+                    }
+                },
+                (Some(_), format) => anyhow::bail!("Unsupported transcript format: {} (supported: markdown)", format),
+                (None, format) => anyhow::bail!("Unsupported export format: {} (supported: jsonl; pass a session id for markdown)", format),
+            }
+            Ok(())
+        }
+
+        Commands::Import { input } => {
+            let file = std::fs::File::open(&input)
+                .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", input, e))?;
+            let summary = crate::db::dump::import_jsonl(&db, std::io::BufReader::new(file)).await?;
+
+            if json {
+                print_json(&summary)?;
+            } else {
+                let imported = summary.imported;
+                let skipped = summary.skipped;
+                println!("Imported {} projects, {} sessions and {} messages", imported.projects, imported.sessions, imported.messages);
+                if skipped != crate::db::dump::DumpCounts::default() {
+                    println!("Skipped {} projects, {} sessions and {} messages already present", skipped.projects, skipped.sessions, skipped.messages);
+                }
+            }
+            Ok(())
+        }
+
         Commands::Projects => {
             let projects = project_repo.list().await?;
 
-            if projects.is_empty() {
+            if json {
+                print_json(&projects)?;
+            } else if projects.is_empty() {
                 println!("No projects found");
             } else {
                 for project in projects {
@@ -240,35 +716,168 @@ pub fn run() -> Result<()> {
         Commands::CreateProject { name, description } => {
             let project = project_repo.create(name, description).await?;
 
-            println!("Created project: {} ({})", project.name, project.id);
+            if json {
+                print_json(&project)?;
+            } else {
+                println!("Created project: {} ({})", project.name, project.id);
+            }
+            Ok(())
+        }
+
+        Commands::UpdateProject { project_id, name, description } => {
+            if name.is_none() && description.is_none() {
+                anyhow::bail!("Nothing to update: pass --name and/or --description");
+            }
+
+            let project = project_repo.update(&project_id, name, description).await?
+                .ok_or_else(|| anyhow::anyhow!("Project not found: {}", project_id))?;
+
+            if json {
+                print_json(&project)?;
+            } else {
+                println!("Updated project: {} ({})", project.name, project.id);
+            }
             Ok(())
         }
 
-        Commands::Serve { port } => {
-            tracing::info!("Starting MCP server on port {}", port);
+        Commands::Serve { port, host, transport, opencode_url, claude_binary, claude_sessions_dir, peer_port, mcp_only, peer_only } => {
+            if transport != "tcp" && transport != "stdio" {
+                anyhow::bail!("Unknown transport: {}. Must be one of: tcp, stdio", transport);
+            }
+            if transport == "stdio" && peer_only {
+                anyhow::bail!("--peer-only needs the tcp transport");
+            }
+            tracing::info!("Starting MCP server ({})", transport);
             
-            // Load config for peer server
-            let config = crate::config::Config::load(None)?;
+            // Load config for peer server and providers. Only the peer
+            // server needs the private key up front; anything else that
+            // reaches a peer unlocks it when it does.
+            let mut config = crate::config::Config::load_locked(None)?;
+            if transport != "stdio" && !mcp_only {
+                config.unlock(None)?;
+            }
+            if let Some(url) = opencode_url {
+                config.opencode_url = url;
+            }
+            if claude_binary.is_some() {
+                config.claude_binary_path = claude_binary;
+            }
+            if claude_sessions_dir.is_some() {
+                config.claude_sessions_dir = claude_sessions_dir;
+            }
+            // An explicit --host is the opt-in to bind it
+            let host = host.unwrap_or_else(|| config.server.bind_host().to_string());
             
-            // Create session manager
-            let session_manager = Arc::new(crate::session::SessionManager::new(db));
+            // Create session manager; it and the peer server share one config
+            // so peers accepted while running can be spawned on
+            let shared_config = Arc::new(tokio::sync::RwLock::new(config.clone()));
+            let peer_outbox = crate::db::repositories::peer_message::PeerMessageRepository::new(db.clone());
+            let session_manager = Arc::new(
+                crate::session::SessionManager::from_config(db, &config)?.with_peers(shared_config.clone())
+            );
             
             // Create MCP server
-            let mcp_server = crate::mcp::McpServer::new(port, session_manager);
-            
-            // Create and start peer server (port + 1)
-            let peer_port = port + 1;
-            let config = Arc::new(tokio::sync::RwLock::new(config));
-            let peer_server = crate::mcp::PeerServer::new(peer_port, config.clone());
+            let mut mcp_server = crate::mcp::McpServer::new(port, session_manager.clone())
+                .with_host(host.clone());
+            if config.server.tool_calls_per_second > 0.0 {
+                mcp_server = mcp_server.with_rate_limit(crate::mcp::RateLimit {
+                    per_second: config.server.tool_calls_per_second,
+                    burst: config.server.tool_call_burst.max(1),
+                });
+            }
+
+            // A per-client stdio server doesn't own the sessions in the
+            // database, and one reconciler (with its webhook) is enough
+            if !peer_only && transport != "stdio" {
+                reattach_sessions(&session_manager).await;
+
+                if config.status_refresh_secs > 0 {
+                    session_manager.spawn_reconciler(std::time::Duration::from_secs(config.status_refresh_secs));
+                }
+            }
+
+            // stdio clients launch one server per connection; no peer server
+            if transport == "stdio" {
+                return mcp_server.run_stdio().await;
+            }
             
-            // Start both servers
+            // Create the peer server (port + 1 unless given)
+            let peer_port = match peer_port {
+                Some(peer_port) => peer_port,
+                None => port.checked_add(1)
+                    .ok_or_else(|| anyhow::anyhow!("No port above {} for the peer server; pass --peer-port", port))?,
+            };
+            let config = shared_config;
+            let peer_server = crate::mcp::PeerServer::new(peer_port, config.clone())
+                .with_outbox(peer_outbox.clone())
+                .with_session_manager(session_manager.clone());
+            let peer_retry_secs = config.read().await.peer_retry_secs;
+            if !mcp_only && peer_retry_secs > 0 {
+                spawn_peer_retry(config.clone(), peer_outbox, std::time::Duration::from_secs(peer_retry_secs));
+            }
+
+            // Start the selected servers; both watch the same shutdown flag.
+            // A server left out just waits for that flag, so it neither ends
+            // the select below nor holds up the shutdown join.
+            let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+            let mcp_shutdown = shutdown_rx.clone();
+
+            // Metrics are scraped on their own port; a failure there
+            // shouldn't take the MCP or peer server down with it
+            let metrics = {
+                let config = config.read().await;
+                (config.server.metrics_enabled && !peer_only).then(|| {
+                    crate::mcp::MetricsServer::new(config.server.metrics_port, session_manager.clone())
+                        .with_host(host.clone())
+                })
+            };
+            let metrics_task = metrics.map(|metrics_server| {
+                let shutdown = shutdown_requested(shutdown_rx.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = metrics_server.start_until(shutdown).await {
+                        tracing::error!("Metrics server stopped: {}", e);
+                    }
+                })
+            });
+            let mcp = async {
+                if peer_only {
+                    shutdown_requested(mcp_shutdown).await;
+                    return Ok(());
+                }
+                mcp_server.run_until(shutdown_requested(mcp_shutdown)).await
+            };
+            let peer = async {
+                if mcp_only {
+                    shutdown_requested(shutdown_rx).await;
+                    return Ok(());
+                }
+                peer_server.start_until(shutdown_requested(shutdown_rx)).await
+            };
+            tokio::pin!(mcp, peer);
+
             tokio::select! {
-                result = mcp_server.run() => {
+                result = &mut mcp => {
                     result?;
                 }
-                result = peer_server.start() => {
+                result = &mut peer => {
                     result?;
                 }
+                _ = shutdown_signal() => {
+                    tracing::info!("Shutdown requested, waiting for in-flight requests");
+                    let _ = shutdown_tx.send(true);
+                    let (mcp_result, peer_result) = tokio::join!(mcp, peer);
+                    mcp_result?;
+                    peer_result?;
+                    if let Some(metrics_task) = metrics_task {
+                        let _ = metrics_task.await;
+                    }
+
+                    // Don't leave provider sessions running with nobody to manage them
+                    if !peer_only {
+                        terminate_sessions(&session_manager).await;
+                    }
+                    tracing::info!("Shutdown complete");
+                }
             }
             
             Ok(())
@@ -280,8 +889,9 @@ pub fn run() -> Result<()> {
             // Generate keypair
             let (private_key, public_key) = keygen::generate_keypair()?;
 
-            // Load or create config
-            let mut config = Config::load(None).unwrap_or_default();
+            // Load or create config; the old key is replaced, so it never
+            // needs decrypting
+            let mut config = Config::load_locked(None).unwrap_or_default();
 
             // Set name if not set
             if config.name.is_empty() {
@@ -291,6 +901,10 @@ pub fn run() -> Result<()> {
                 config.name = input.trim().to_string();
             }
 
+            config.encrypted_private_key = match password.as_deref() {
+                Some(password) if !password.is_empty() => Some(keygen::encrypt_secret(&private_key, password)?),
+                _ => None,
+            };
             config.private_key = private_key;
             config.public_key = public_key;
 
@@ -299,16 +913,19 @@ pub fn run() -> Result<()> {
 
             println!("Generated keypair for node: {}", config.name);
             println!("Public key: {}", config.public_key);
+            if config.encrypted_private_key.is_some() {
+                println!("Private key encrypted; unlock with SUPERCODE_PASSWORD or at the prompt");
+            }
             println!("Config saved to: ~/.supercode/config.yml");
             Ok(())
         }
 
         Commands::Peer { command } => {
-            use crate::config::{Config, PeerConfig};
+            use crate::config::{Config, PeerConfig, PeerManager};
 
             match command {
                 PeerCommands::Add { name, hostname, auth } => {
-                    let mut config = Config::load(None)?;
+                    let mut config = Config::load_locked(None)?;
 
                     let peer = PeerConfig {
                         auth: auth.unwrap_or_default(),
@@ -325,9 +942,20 @@ pub fn run() -> Result<()> {
                 }
 
                 PeerCommands::List => {
-                    let config = Config::load(None)?;
-
-                    if config.peers.is_empty() {
+                    let config = Config::load_locked(None)?;
+
+                    if json {
+                        // Leave the shared auth secret out of scriptable output
+                        let peers: Vec<_> = config.peers.iter()
+                            .map(|(name, peer)| serde_json::json!({
+                                "name": name,
+                                "hostnames": peer.hostnames,
+                                "public_key": peer.public_key,
+                                "verified": peer.verified,
+                            }))
+                            .collect();
+                        print_json(&peers)?;
+                    } else if config.peers.is_empty() {
                         println!("No peers configured");
                     } else {
                         for (name, peer) in config.peers {
@@ -338,7 +966,7 @@ pub fn run() -> Result<()> {
                 }
 
                 PeerCommands::Remove { name } => {
-                    let mut config = Config::load(None)?;
+                    let mut config = Config::load_locked(None)?;
                     config.remove_peer(&name);
                     config.save(None)?;
                     println!("Removed peer: {}", name);
@@ -346,10 +974,12 @@ pub fn run() -> Result<()> {
                 }
 
                 PeerCommands::Pending => {
-                    let config = Config::load(None)?;
+                    let config = Config::load_locked(None)?;
                     let requests = config.get_pending_requests();
 
-                    if requests.is_empty() {
+                    if json {
+                        print_json(&requests)?;
+                    } else if requests.is_empty() {
                         println!("No pending peer requests");
                     } else {
                         for req in requests {
@@ -360,7 +990,7 @@ pub fn run() -> Result<()> {
                 }
 
                 PeerCommands::Accept { name } => {
-                    let mut config = Config::load(None)?;
+                    let mut config = Config::load_locked(None)?;
                     let requests: Vec<_> = config.get_pending_requests();
 
                     let request = requests.iter()
@@ -383,13 +1013,58 @@ pub fn run() -> Result<()> {
                 }
 
                 PeerCommands::Deny { name } => {
-                    let mut config = Config::load(None)?;
+                    let mut config = Config::load_locked(None)?;
                     config.deny_peer(&name);
                     config.save(None)?;
                     println!("Denied peer: {}", name);
                     Ok(())
                 }
 
+                PeerCommands::Verify { name, expected_public_key } => {
+                    let mut config = Config::load_locked(None)?;
+
+                    let peer = config.get_peer(&name)
+                        .ok_or_else(|| anyhow::anyhow!("Peer not found: {}", name))?;
+
+                    // Peers added by hand have no key yet; ask the peer for it
+                    if peer.public_key.is_empty() {
+                        println!("No key on record for {}; fetching it from {:?}...", name, peer.hostnames);
+                        let received = PeerManager::new(config.clone()).fetch_public_key(&name).await?;
+                        if let Some(peer) = config.peers.get_mut(&name) {
+                            peer.public_key = received;
+                        }
+                    }
+
+                    config.verify_peer(&name, &expected_public_key)?;
+                    config.save(None)?;
+
+                    println!("Verified peer: {}", name);
+                    Ok(())
+                }
+
+                PeerCommands::Send { name, message_type, payload } => {
+                    let config = Config::load(None)?;
+
+                    if !config.can_peer() {
+                        anyhow::bail!("Cannot send: run 'supercode keygen' first");
+                    }
+                    if config.get_peer(&name).is_none() {
+                        anyhow::bail!("Peer not found: {}", name);
+                    }
+
+                    let message = crate::config::PeerMessage::new(message_type, payload, config.name.clone());
+                    let id = message.id.clone();
+                    let outbox = crate::db::repositories::peer_message::PeerMessageRepository::new(db);
+                    let manager = PeerManager::new(config).with_outbox(outbox);
+
+                    if manager.send_to_peer(&name, message).await? {
+                        println!("Delivered message {} to {}", id, name);
+                    } else {
+                        println!("Peer {} is unreachable; queued message {} for delivery when it's back", name, id);
+                    }
+                    Ok(())
+                }
+
                 PeerCommands::Connect { name } => {
                     let config = Config::load(None)?;
 
@@ -400,8 +1075,19 @@ pub fn run() -> Result<()> {
                     let peer = config.get_peer(&name)
                         .ok_or_else(|| anyhow::anyhow!("Peer not found: {}", name))?;
 
+                    let expected_key = peer.public_key.clone();
+
                     println!("Connecting to peer {} at {:?}...", name, peer.hostnames);
-                    // TODO: Implement actual connection
+                    let manager = PeerManager::new(config);
+                    let connection = manager.connect_to_peer(&name).await
+                        .map_err(|e| anyhow::anyhow!("Failed to connect to peer {}: {}", name, e))?;
+
+                    println!("Connected to peer {}", name);
+                    println!("Remote public key: {}", connection.remote_public_key);
+
+                    if !expected_key.is_empty() && expected_key != connection.remote_public_key {
+                        println!("Warning: remote public key does not match the key stored for {}", name);
+                    }
                     Ok(())
                 }
             }
@@ -409,3 +1095,195 @@ pub fn run() -> Result<()> {
         }
     })
 }
+
+/// Reconnect sessions left in flight by a previous run, logging the outcome
+async fn reattach_sessions(session_manager: &crate::session::SessionManager) {
+    match session_manager.reattach_sessions().await {
+        Ok(summary) => {
+            if !summary.reattached.is_empty() {
+                tracing::info!("Reattached {} session(s)", summary.reattached.len());
+            }
+            for (session_id, reason) in &summary.failed {
+                tracing::warn!("Marked session {} failed: {}", session_id, reason);
+            }
+            for (session_id, error) in &summary.unreachable {
+                tracing::warn!("Could not reattach session {}: {}", session_id, error);
+            }
+            for (session_id, reason) in &summary.skipped {
+                tracing::info!("Left session {} as it is: {}", session_id, reason);
+            }
+        }
+        Err(e) => tracing::error!("Failed to reattach sessions: {}", e),
+    }
+}
+
+/// Kill the running sessions this process holds on the way out, logging
+/// the ones that couldn't be killed
+async fn terminate_sessions(session_manager: &crate::session::SessionManager) {
+    match session_manager.shutdown_all().await {
+        Ok(summary) => {
+            tracing::info!("Terminated {} running session(s)", summary.terminated.len());
+            for (session_id, error) in &summary.failed {
+                tracing::warn!("Failed to terminate session {}: {}", session_id, error);
+            }
+        }
+        Err(e) => tracing::error!("Failed to terminate running sessions: {}", e),
+    }
+}
+
+/// Print `value` as pretty JSON on stdout
+fn print_json<T: serde::Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Resolve on Ctrl-C, or SIGTERM where there is one (e.g. systemd stop)
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(sigterm) => sigterm,
+            Err(e) => {
+                tracing::warn!("Cannot listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Resolve once the shutdown flag is set (or its sender is gone)
+async fn shutdown_requested(mut shutdown: tokio::sync::watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stop| *stop).await;
+}
+
+/// Print a session's state transitions and new responses as they are
+/// recorded, until the session stops running
+async fn tail_session(repo: &SessionRepository, session_id: &str, interval: std::time::Duration) -> Result<()> {
+    let mut last_state = None;
+    let mut last_response = None;
+
+    loop {
+        if let Some(activity) = repo.latest_activity(session_id).await? {
+            if last_state != Some(activity.state) {
+                let detail = match (&activity.approval_type, &activity.approval_description) {
+                    (Some(kind), Some(description)) => format!(" ({}: {})", kind.as_str(), description),
+                    (Some(kind), None) => format!(" ({})", kind.as_str()),
+                    _ => String::new(),
+                };
+                println!(
+                    "[{}] {}{}",
+                    activity.state_changed_at.format("%H:%M:%S"),
+                    activity.state.as_str(),
+                    detail
+                );
+                last_state = Some(activity.state);
+            }
+            if activity.last_response.is_some() && activity.last_response != last_response {
+                println!("{}", activity.last_response.as_deref().unwrap_or_default());
+                last_response = activity.last_response;
+            }
+        }
+
+        let session = repo.get(session_id).await?
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+        if matches!(session.status, SessionStatus::Completed | SessionStatus::Failed | SessionStatus::Terminated) {
+            println!("Session {}", session.status.as_str());
+            return Ok(());
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Periodically deliver messages queued for peers that were unreachable
+fn spawn_peer_retry(
+    config: Arc<tokio::sync::RwLock<crate::config::Config>>,
+    outbox: crate::db::repositories::peer_message::PeerMessageRepository,
+    every: std::time::Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(every);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let config = config.read().await.clone();
+            if !config.can_peer() {
+                continue;
+            }
+            match crate::config::PeerManager::new(config).with_outbox(outbox.clone()).retry_outbox().await {
+                Ok(0) => {}
+                Ok(delivered) => tracing::info!("Delivered {} queued peer message(s)", delivered),
+                Err(e) => tracing::warn!("Failed to retry queued peer messages: {}", e),
+            }
+        }
+    })
+}
+
+/// The provider session of a session that may still be live there
+fn live_provider_id(session: &crate::db::repositories::session::Session) -> Option<&str> {
+    match session.status {
+        SessionStatus::Pending | SessionStatus::Running => session.opencode_session_id.as_deref(),
+        _ => None,
+    }
+}
+
+/// Why a live session can't be stopped from here, if it can't: sessions of
+/// providers that can't resume exist only in the server that spawned them,
+/// and this process's provider would report killing them without doing so.
+/// Sessions of providers this process doesn't have fail when killed instead.
+fn held_by_server(
+    session_manager: &crate::session::SessionManager,
+    session: &crate::db::repositories::session::Session,
+) -> Option<String> {
+    live_provider_id(session)?;
+    let session_type = session.session_type.as_str();
+    let capabilities = session_manager.provider_capabilities(session_type).ok()?;
+    (!capabilities.supports_resume)
+        .then(|| format!("{} sessions only exist in the server that spawned them", session_type))
+}
+
+/// One line identifying a session, for previews of destructive commands
+fn describe_session(session: &crate::db::repositories::session::Session) -> String {
+    format!(
+        "{} {}({}/{}, {}, created {})",
+        session.id,
+        session.name.as_deref().map(|name| format!("\"{}\" ", name)).unwrap_or_default(),
+        session.agent_type.as_str(),
+        session.session_type.as_str(),
+        session.status.as_str(),
+        session.created_at.format("%Y-%m-%d %H:%M"),
+    )
+}
+
+/// Ask before a destructive command goes ahead, unless `yes` was passed.
+/// Refuses when there's no terminal to ask on.
+fn confirm(yes: bool) -> Result<bool> {
+    use std::io::{IsTerminal, Write};
+
+    if yes {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("Not a terminal; pass --yes to go ahead or --dry-run to preview");
+    }
+
+    print!("Proceed? [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    let proceed = matches!(answer.trim().to_lowercase().as_str(), "y" | "yes");
+    if !proceed {
+        println!("Aborted");
+    }
+    Ok(proceed)
+}
@@ -10,6 +10,15 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, info};
 
+use super::keygen;
+
+/// Days a pending peer request is kept before it is dropped unanswered
+const PENDING_REQUEST_DAYS: i64 = 7;
+
+/// Most pending peer requests kept; the oldest are dropped first. Anyone
+/// who can reach the peer port can queue one.
+const MAX_PENDING_REQUESTS: usize = 100;
+
 /// Supercode configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -17,10 +26,16 @@ pub struct Config {
     #[serde(default)]
     pub name: String,
 
-    /// Private key (base64 encoded) - REQUIRED for peering
-    #[serde(default)]
+    /// Private key (base64 encoded) - REQUIRED for peering.
+    /// Not written to disk when `encrypted_private_key` is set.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub private_key: String,
 
+    /// Password-encrypted private key (see `keygen::encrypt_secret`).
+    /// Decrypted into `private_key` on load.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_private_key: Option<String>,
+
     /// Public key (base64 encoded) - derived from private_key
     #[serde(default)]
     pub public_key: String,
@@ -33,29 +48,172 @@ pub struct Config {
     #[serde(default)]
     pub server: ServerConfig,
 
+    /// OpenCode server URL
+    #[serde(default = "default_opencode_url")]
+    pub opencode_url: String,
+
+    /// Seconds a whole OpenCode request may take, including a streamed
+    /// reply (0: no limit). Raise for slow local models.
+    #[serde(default = "default_opencode_request_timeout_secs")]
+    pub opencode_request_timeout_secs: u64,
+
+    /// Seconds connecting to the OpenCode server may take
+    #[serde(default = "default_opencode_connect_timeout_secs")]
+    pub opencode_connect_timeout_secs: u64,
+
+    /// Claude Code CLI path (default: `claude` on PATH)
+    #[serde(default)]
+    pub claude_binary_path: Option<String>,
+
+    /// Directory for Claude Code session working dirs
+    /// (default: `$CLAUDE_WORK_DIR` or a temp dir)
+    #[serde(default)]
+    pub claude_sessions_dir: Option<String>,
+
+    /// Directory isolated session workspaces (worktrees and copies) are
+    /// created in (default: a temp dir). They are kept after the session
+    /// ends, so the agent's work can be merged.
+    #[serde(default)]
+    pub workspaces_dir: Option<String>,
+
+    /// Seconds a single Claude Code message may run before its process is killed
+    #[serde(default = "default_claude_message_timeout_secs")]
+    pub claude_message_timeout_secs: u64,
+
+    /// Root of an OpenAI-compatible API for `openai` sessions, e.g.
+    /// `https://api.openai.com/v1` (default: none, `openai` sessions off)
+    #[serde(default)]
+    pub openai_base_url: Option<String>,
+
+    /// Model `openai` sessions chat with; required with `openai_base_url`
+    #[serde(default)]
+    pub openai_model: Option<String>,
+
+    /// API key for `openai` sessions (default: `$OPENAI_API_KEY`, if set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub openai_api_key: Option<String>,
+
+    /// How many times to retry an unreachable provider when spawning
+    #[serde(default = "default_spawn_retries")]
+    pub spawn_retries: u32,
+
+    /// Most sessions that may be pending or running at once (default: no limit)
+    #[serde(default)]
+    pub max_concurrent_sessions: Option<usize>,
+
+    /// Seconds between background syncs of active sessions' status and
+    /// agent state from their provider (0: never)
+    #[serde(default = "default_status_refresh_secs")]
+    pub status_refresh_secs: u64,
+
+    /// URL the status reconciler POSTs a JSON event to when a session
+    /// becomes blocked, completes or fails (default: none)
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+
+    /// Seconds between attempts to deliver messages queued for
+    /// unreachable peers (0: only when the peer reconnects)
+    #[serde(default = "default_peer_retry_secs")]
+    pub peer_retry_secs: u64,
+
+    /// Run `spawn_session` requests from verified peers. Spawned agents
+    /// run commands on this machine, so this is off unless turned on.
+    #[serde(default)]
+    pub accept_peer_spawns: bool,
+
     /// Known peers
     #[serde(default)]
     pub peers: HashMap<String, PeerConfig>,
 
-    /// Pending peer requests (runtime only, not serialized)
+    /// Pending peer requests, kept in `pending_peers.yml` next to the
+    /// config file rather than in it
     #[serde(skip)]
     pub pending_requests: HashMap<String, PeerRequest>,
+
+    /// Pending requests added (`Some`) or cleared (`None`) since loading.
+    /// Saving applies just these to the file, so requests another process
+    /// queued or answered meanwhile aren't undone.
+    #[serde(skip)]
+    pub pending_changes: HashMap<String, Option<PeerRequest>>,
+}
+
+/// Drop expired pending requests, then the oldest beyond the cap
+fn prune_pending_requests(mut pending: HashMap<String, PeerRequest>) -> HashMap<String, PeerRequest> {
+    let cutoff = Utc::now() - chrono::Duration::days(PENDING_REQUEST_DAYS);
+    pending.retain(|_, request| request.received_at > cutoff);
+
+    if pending.len() > MAX_PENDING_REQUESTS {
+        let mut oldest: Vec<(DateTime<Utc>, String)> = pending.iter()
+            .map(|(name, request)| (request.received_at, name.clone()))
+            .collect();
+        oldest.sort();
+        for (_, name) in oldest.into_iter().take(pending.len() - MAX_PENDING_REQUESTS) {
+            pending.remove(&name);
+        }
+    }
+    pending
 }
 
 fn default_db_path() -> String {
     "~/.supercode/supercode.db".to_string()
 }
 
+fn default_opencode_url() -> String {
+    "http://localhost:9090".to_string()
+}
+
+fn default_opencode_request_timeout_secs() -> u64 {
+    30
+}
+
+fn default_opencode_connect_timeout_secs() -> u64 {
+    5
+}
+
+fn default_claude_message_timeout_secs() -> u64 {
+    300
+}
+
+fn default_spawn_retries() -> u32 {
+    3
+}
+
+fn default_status_refresh_secs() -> u64 {
+    60
+}
+
+fn default_peer_retry_secs() -> u64 {
+    30
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             name: String::new(),
             private_key: String::new(),
+            encrypted_private_key: None,
             public_key: String::new(),
             database_path: default_db_path(),
             server: ServerConfig::default(),
+            opencode_url: default_opencode_url(),
+            opencode_request_timeout_secs: default_opencode_request_timeout_secs(),
+            opencode_connect_timeout_secs: default_opencode_connect_timeout_secs(),
+            claude_binary_path: None,
+            claude_sessions_dir: None,
+            workspaces_dir: None,
+            claude_message_timeout_secs: default_claude_message_timeout_secs(),
+            openai_base_url: None,
+            openai_model: None,
+            openai_api_key: None,
+            spawn_retries: default_spawn_retries(),
+            max_concurrent_sessions: None,
+            status_refresh_secs: default_status_refresh_secs(),
+            webhook_url: None,
+            peer_retry_secs: default_peer_retry_secs(),
+            accept_peer_spawns: false,
             peers: HashMap::new(),
             pending_requests: HashMap::new(),
+            pending_changes: HashMap::new(),
         }
     }
 }
@@ -63,25 +221,77 @@ impl Default for Config {
 /// Server configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
+    /// Address the MCP server binds to. Only loopback addresses are used
+    /// unless `allow_remote` is set (see `bind_host`).
     #[serde(default = "default_host")]
     pub host: String,
+    /// Let `host` be an address other machines can reach. The MCP server
+    /// has no authentication, so this must be turned on explicitly.
+    #[serde(default)]
+    pub allow_remote: bool,
     #[serde(default = "default_port")]
     pub port: u16,
+    /// Tool calls each MCP connection may make per second (0: unlimited)
+    #[serde(default = "default_tool_calls_per_second")]
+    pub tool_calls_per_second: f64,
+    /// Tool calls a connection may make in a burst before being limited
+    #[serde(default = "default_tool_call_burst")]
+    pub tool_call_burst: u32,
+    /// Serve Prometheus metrics at `/metrics` on `metrics_port`
+    #[serde(default)]
+    pub metrics_enabled: bool,
+    /// Port the metrics endpoint listens on, on the same host as the MCP server
+    #[serde(default = "default_metrics_port")]
+    pub metrics_port: u16,
 }
 
 fn default_host() -> String {
-    "0.0.0.0".to_string()
+    "127.0.0.1".to_string()
 }
 
 fn default_port() -> u16 {
     9091
 }
 
+fn default_tool_calls_per_second() -> f64 {
+    5.0
+}
+
+fn default_tool_call_burst() -> u32 {
+    20
+}
+
+fn default_metrics_port() -> u16 {
+    9093
+}
+
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
             host: default_host(),
+            allow_remote: false,
             port: default_port(),
+            tool_calls_per_second: default_tool_calls_per_second(),
+            tool_call_burst: default_tool_call_burst(),
+            metrics_enabled: false,
+            metrics_port: default_metrics_port(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The address to bind: `host` if it is loopback or `allow_remote` is
+    /// set, loopback otherwise. Earlier versions saved `host: 0.0.0.0` into
+    /// every config file without ever binding it, so a stored non-loopback
+    /// host alone doesn't expose the server.
+    pub fn bind_host(&self) -> &str {
+        let loopback = self.host == "localhost"
+            || self.host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback());
+        if loopback || self.allow_remote {
+            &self.host
+        } else {
+            tracing::warn!("Ignoring server.host {} without server.allow_remote; binding 127.0.0.1", self.host);
+            "127.0.0.1"
         }
     }
 }
@@ -104,8 +314,8 @@ pub struct PeerConfig {
     pub verified: bool,
 }
 
-/// A pending peer request (runtime only)
-#[derive(Debug, Clone)]
+/// A pending peer request
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PeerRequest {
     /// Requesting node's name
     pub name: String,
@@ -118,8 +328,26 @@ pub struct PeerRequest {
 }
 
 impl Config {
-    /// Load config from the default location or specified path
+    /// Load config from the default location or specified path.
+    ///
+    /// An encrypted private key is decrypted with `$SUPERCODE_PASSWORD`, or
+    /// a password prompted for on the terminal.
     pub fn load(path: Option<&str>) -> Result<Self> {
+        Self::load_with_password(path, None)
+    }
+
+    /// Load config, decrypting an encrypted private key with `password`
+    /// (falling back to `$SUPERCODE_PASSWORD` or a prompt when `None`)
+    pub fn load_with_password(path: Option<&str>, password: Option<&str>) -> Result<Self> {
+        let mut config = Self::load_locked(path)?;
+        config.unlock(password)?;
+        Ok(config)
+    }
+
+    /// Load config without decrypting the private key; `private_key` stays
+    /// empty if it is encrypted. Enough for anything but talking to peers;
+    /// `unlock` it before that.
+    pub fn load_locked(path: Option<&str>) -> Result<Self> {
         let config_path = Self::config_path(path)?;
 
         if !config_path.exists() {
@@ -127,14 +355,18 @@ impl Config {
                 "Config file not found, creating default at {:?}",
                 config_path
             );
-            let config = Config::default();
+            let config = Config {
+                pending_requests: Self::load_pending_requests(path)?,
+                ..Config::default()
+            };
             config.save(path)?;
             return Ok(config);
         }
 
         let raw = fs::read_to_string(&config_path).context("Failed to read config file")?;
 
-        let config: Config = serde_yaml::from_str(&raw).context("Failed to parse config file")?;
+        let mut config: Config = serde_yaml::from_str(&raw).context("Failed to parse config file")?;
+        config.pending_requests = Self::load_pending_requests(path)?;
 
         debug!("Loaded config from {:?}", config_path);
         Ok(config)
@@ -148,13 +380,86 @@ impl Config {
             fs::create_dir_all(parent)?;
         }
 
-        let content = serde_yaml::to_string(&self)?;
+        // Only the encrypted form of an encrypted key goes to disk
+        let content = if self.encrypted_private_key.is_some() {
+            let mut on_disk = self.clone();
+            on_disk.private_key.clear();
+            serde_yaml::to_string(&on_disk)?
+        } else {
+            serde_yaml::to_string(&self)?
+        };
         fs::write(&config_path, content).context("Failed to write config file")?;
+        self.save_pending_requests(path)?;
 
         info!("Saved config to {:?}", config_path);
         Ok(())
     }
 
+    /// Replace peers and pending requests with what is on disk, so a
+    /// long-running process sees changes made by the CLI. Keys and other
+    /// settings are left alone.
+    pub fn reload_peers(&mut self, path: Option<&str>) -> Result<()> {
+        let on_disk = Self::load_locked(path)?;
+        self.peers = on_disk.peers;
+        self.pending_requests = on_disk.pending_requests;
+        self.pending_changes.clear();
+        Ok(())
+    }
+
+    /// Load the pending peer requests stored next to the config file,
+    /// leaving out expired ones
+    pub fn load_pending_requests(path: Option<&str>) -> Result<HashMap<String, PeerRequest>> {
+        let pending_path = Self::pending_requests_path(path)?;
+        if !pending_path.exists() {
+            return Ok(HashMap::new());
+        }
+
+        let raw = fs::read_to_string(&pending_path).context("Failed to read pending peer requests")?;
+        let pending = serde_yaml::from_str(&raw).context("Failed to parse pending peer requests")?;
+        Ok(prune_pending_requests(pending))
+    }
+
+    /// Write the pending peer requests next to the config file. The file is
+    /// read again first and only the requests added or cleared here are
+    /// changed in it.
+    pub fn save_pending_requests(&self, path: Option<&str>) -> Result<()> {
+        let pending_path = Self::pending_requests_path(path)?;
+
+        if let Some(parent) = pending_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut pending = Self::load_pending_requests(path)?;
+        for (name, change) in &self.pending_changes {
+            match change {
+                Some(request) => pending.insert(name.clone(), request.clone()),
+                None => pending.remove(name),
+            };
+        }
+
+        let content = serde_yaml::to_string(&prune_pending_requests(pending))?;
+        fs::write(&pending_path, content).context("Failed to write pending peer requests")?;
+        Ok(())
+    }
+
+    /// Record a pending request and persist it, picking up requests that
+    /// were accepted or denied elsewhere (e.g. by the CLI) since this
+    /// config was loaded
+    pub fn store_pending_request(&mut self, request: PeerRequest, path: Option<&str>) -> Result<()> {
+        self.add_pending_request(request);
+        self.save_pending_requests(path)?;
+        self.pending_requests = Self::load_pending_requests(path)?;
+        self.pending_changes.clear();
+        Ok(())
+    }
+
+    /// Pending requests live in `pending_peers.yml` beside the config file
+    fn pending_requests_path(path: Option<&str>) -> Result<PathBuf> {
+        let config_path = Self::config_path(path)?;
+        let dir = config_path.parent().map(PathBuf::from).unwrap_or_default();
+        Ok(dir.join("pending_peers.yml"))
+    }
+
     /// Get the config file path
     fn config_path(path: Option<&str>) -> Result<PathBuf> {
         // Check env override first
@@ -170,9 +475,54 @@ impl Config {
         Ok(home.join(".supercode").join("config.yml"))
     }
 
+    /// Whether the private key is encrypted and hasn't been decrypted yet
+    pub fn is_locked(&self) -> bool {
+        self.encrypted_private_key.is_some() && self.private_key.is_empty()
+    }
+
+    /// Decrypt an encrypted private key with `password` (falling back to
+    /// `$SUPERCODE_PASSWORD` or a prompt when `None`). Does nothing if the
+    /// key isn't locked.
+    pub fn unlock(&mut self, password: Option<&str>) -> Result<()> {
+        match &self.encrypted_private_key {
+            Some(encrypted) if self.private_key.is_empty() => {
+                let password = match password {
+                    Some(password) => password.to_string(),
+                    None => read_password()?,
+                };
+                self.private_key = keygen::decrypt_secret(encrypted, &password)
+                    .context("Failed to decrypt private key")?;
+            }
+            _ => {}
+        }
+
+        self.validate_keys()
+    }
+
     /// Check if this node is ready for peering
     pub fn can_peer(&self) -> bool {
-        !self.name.is_empty() && !self.private_key.is_empty() && !self.public_key.is_empty()
+        !self.name.is_empty()
+            && !self.private_key.is_empty()
+            && !self.public_key.is_empty()
+            && self.validate_keys().is_ok()
+    }
+
+    /// Check that `public_key` is the one derived from `private_key`.
+    /// Passes when either key is missing.
+    pub fn validate_keys(&self) -> Result<()> {
+        if self.private_key.is_empty() || self.public_key.is_empty() {
+            return Ok(());
+        }
+
+        let derived = keygen::get_public_key(&self.private_key)
+            .context("Config private_key is not a valid key")?;
+        if derived != self.public_key {
+            anyhow::bail!(
+                "Config key mismatch: public_key does not match private_key (expected {}). Fix the config or run 'supercode keygen'.",
+                derived
+            );
+        }
+        Ok(())
     }
 
     /// Add a new peer
@@ -190,8 +540,31 @@ impl Config {
         self.peers.get(name)
     }
 
+    /// Mark a peer as verified if its public key matches one confirmed
+    /// out-of-band. A mismatch leaves the peer untouched.
+    pub fn verify_peer(&mut self, name: &str, expected_public_key: &str) -> Result<()> {
+        let peer = self.peers.get_mut(name)
+            .ok_or_else(|| anyhow::anyhow!("Peer not found: {}", name))?;
+
+        if peer.public_key.is_empty() {
+            anyhow::bail!("No public key known for peer {}", name);
+        }
+        if peer.public_key != expected_public_key.trim() {
+            anyhow::bail!(
+                "Public key mismatch for peer {}: have {}, expected {}",
+                name,
+                peer.public_key,
+                expected_public_key.trim()
+            );
+        }
+
+        peer.verified = true;
+        Ok(())
+    }
+
     /// Add a pending peer request
     pub fn add_pending_request(&mut self, request: PeerRequest) {
+        self.pending_changes.insert(request.name.clone(), Some(request.clone()));
         self.pending_requests.insert(request.name.clone(), request);
     }
 
@@ -203,6 +576,7 @@ impl Config {
     /// Clear a pending request
     pub fn clear_pending_request(&mut self, name: &str) {
         self.pending_requests.remove(name);
+        self.pending_changes.insert(name.to_string(), None);
     }
 
     /// Deny a pending peer request
@@ -216,6 +590,62 @@ impl Config {
         let path = self.database_path.replace("~", &home.to_string_lossy());
         Ok(PathBuf::from(path))
     }
+
+    /// Resolve the Claude sessions directory, if configured (expand ~)
+    pub fn resolve_claude_sessions_dir(&self) -> Result<Option<PathBuf>> {
+        let Some(dir) = &self.claude_sessions_dir else {
+            return Ok(None);
+        };
+        let home = dirs::home_dir().context("Cannot find home directory")?;
+        Ok(Some(PathBuf::from(dir.replace("~", &home.to_string_lossy()))))
+    }
+
+    /// Resolve the isolated workspaces directory, if configured (expand ~)
+    pub fn resolve_workspaces_dir(&self) -> Result<Option<PathBuf>> {
+        let Some(dir) = &self.workspaces_dir else {
+            return Ok(None);
+        };
+        let home = dirs::home_dir().context("Cannot find home directory")?;
+        Ok(Some(PathBuf::from(dir.replace("~", &home.to_string_lossy()))))
+    }
+}
+
+/// Get the config password from `$SUPERCODE_PASSWORD` or the terminal
+fn read_password() -> Result<String> {
+    use std::io::{IsTerminal, Write};
+
+    if let Ok(password) = std::env::var("SUPERCODE_PASSWORD") {
+        return Ok(password);
+    }
+
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("Config private key is encrypted; set SUPERCODE_PASSWORD to unlock it");
+    }
+
+    eprint!("Config password: ");
+    std::io::stderr().flush()?;
+
+    // Hide the password while it is typed, where stty is available
+    let echo_off = std::process::Command::new("stty")
+        .arg("-echo")
+        .stdin(std::process::Stdio::inherit())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+
+    let mut password = String::new();
+    let read = std::io::stdin().read_line(&mut password);
+
+    if echo_off {
+        let _ = std::process::Command::new("stty")
+            .arg("echo")
+            .stdin(std::process::Stdio::inherit())
+            .status();
+        eprintln!();
+    }
+
+    read.context("Failed to read password")?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
 }
 
 // Helper for debug logging
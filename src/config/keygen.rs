@@ -2,6 +2,17 @@
 
 use anyhow::{Context, Result};
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Domain separator mixed into handshake proofs
+const HANDSHAKE_CONTEXT: &[u8] = b"supercode-peer-handshake-v1";
+
+/// Prefix marking a password-encrypted secret in the config file
+const ENCRYPTED_PREFIX: &str = "argon2id-aes256gcm:";
+
+/// Domain separator mixed into per-connection session keys
+const SESSION_CONTEXT: &[u8] = b"supercode-peer-session-v1";
 
 /// Generate a new key pair for this node (x25519 for key exchange)
 pub fn generate_keypair() -> Result<(String, String)> {
@@ -22,13 +33,152 @@ pub fn generate_keypair() -> Result<(String, String)> {
 pub fn get_public_key(private_key_base64: &str) -> Result<String> {
     use x25519_dalek::{PublicKey, StaticSecret};
 
-    let bytes = BASE64.decode(private_key_base64)?;
+    let secret = StaticSecret::from(decode_key(private_key_base64)?);
+    let public = PublicKey::from(&secret);
+    Ok(BASE64.encode(public.to_bytes()))
+}
+
+/// Generate a random handshake challenge (base64)
+pub fn generate_nonce() -> String {
+    use rand::RngCore;
+
+    let mut nonce = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+    BASE64.encode(nonce)
+}
+
+/// Answer a handshake challenge, proving ownership of `private_key`.
+///
+/// The proof is an HMAC of the nonce and the prover's name, keyed by the
+/// x25519 shared secret between the two nodes. Only holders of either
+/// private key can produce it, and the verifier checks it against the
+/// public key it has on record for the peer.
+pub fn handshake_proof(private_key: &str, peer_public_key: &str, nonce: &str, name: &str) -> Result<String> {
+    let mac = handshake_mac(private_key, peer_public_key, nonce, name)?;
+    Ok(BASE64.encode(mac.finalize().into_bytes()))
+}
+
+/// Check a proof produced by `handshake_proof` on the other node
+pub fn verify_handshake_proof(
+    private_key: &str,
+    peer_public_key: &str,
+    nonce: &str,
+    name: &str,
+    proof: &str,
+) -> Result<bool> {
+    let Ok(proof) = BASE64.decode(proof) else {
+        return Ok(false);
+    };
+    let mac = handshake_mac(private_key, peer_public_key, nonce, name)?;
+    Ok(mac.verify_slice(&proof).is_ok())
+}
+
+/// Derive the key that encrypts message payloads on one peer connection.
+///
+/// Both ends compute it from the x25519 shared secret and the handshake
+/// nonce, so every connection gets its own key.
+pub fn session_key(private_key: &str, peer_public_key: &str, nonce: &str) -> Result<[u8; 32]> {
+    use sha2::Digest;
+
+    let shared = shared_secret(private_key, peer_public_key)?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(SESSION_CONTEXT);
+    hasher.update(shared);
+    hasher.update(nonce.as_bytes());
+    Ok(hasher.finalize().into())
+}
+
+fn handshake_mac(private_key: &str, peer_public_key: &str, nonce: &str, name: &str) -> Result<Hmac<Sha256>> {
+    let shared = shared_secret(private_key, peer_public_key)?;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(&shared)
+        .context("Failed to key handshake MAC")?;
+    mac.update(HANDSHAKE_CONTEXT);
+    mac.update(nonce.as_bytes());
+    mac.update(name.as_bytes());
+    Ok(mac)
+}
+
+/// x25519 Diffie-Hellman between our private key and a peer's public key
+fn shared_secret(private_key: &str, peer_public_key: &str) -> Result<[u8; 32]> {
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    let secret = StaticSecret::from(decode_key(private_key).context("Invalid private key")?);
+    let peer = PublicKey::from(decode_key(peer_public_key).context("Invalid peer public key")?);
+
+    let shared = secret.diffie_hellman(&peer);
+    if !shared.was_contributory() {
+        anyhow::bail!("Peer public key is a low-order point");
+    }
+    Ok(shared.to_bytes())
+}
+
+/// Encrypt a secret for storage with a password.
+///
+/// The key is derived with Argon2id from the password and a random salt;
+/// the result is `argon2id-aes256gcm:` followed by base64 of
+/// salt (16 bytes) + nonce (12 bytes) + AES-256-GCM ciphertext.
+pub fn encrypt_secret(secret: &str, password: &str) -> Result<String> {
+    use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+    use rand::RngCore;
+
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+    let cipher = Aes256Gcm::new_from_slice(&password_key(password, &salt)?)
+        .context("Invalid encryption key")?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), secret.as_bytes())
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt secret"))?;
+
+    let mut blob = salt.to_vec();
+    blob.extend_from_slice(&nonce);
+    blob.extend_from_slice(&ciphertext);
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, BASE64.encode(blob)))
+}
+
+/// Decrypt a secret produced by `encrypt_secret`
+pub fn decrypt_secret(encrypted: &str, password: &str) -> Result<String> {
+    use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+
+    let encoded = encrypted
+        .strip_prefix(ENCRYPTED_PREFIX)
+        .ok_or_else(|| anyhow::anyhow!("Unsupported encrypted secret format"))?;
+    let blob = BASE64.decode(encoded).context("Encrypted secret is not valid base64")?;
+    if blob.len() < 16 + 12 {
+        anyhow::bail!("Encrypted secret is truncated");
+    }
+    let (salt, rest) = blob.split_at(16);
+    let (nonce, ciphertext) = rest.split_at(12);
+
+    let cipher = Aes256Gcm::new_from_slice(&password_key(password, salt)?)
+        .context("Invalid encryption key")?;
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Wrong password or corrupted secret"))?;
+
+    String::from_utf8(plaintext).context("Decrypted secret is not UTF-8")
+}
+
+/// Derive a 256-bit key from a password with Argon2id
+fn password_key(password: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive key from password: {}", e))?;
+    Ok(key)
+}
+
+/// Decode a base64 x25519 key
+fn decode_key(key_base64: &str) -> Result<[u8; 32]> {
+    let bytes = BASE64.decode(key_base64)?;
     if bytes.len() != 32 {
         anyhow::bail!("Invalid key length, expected 32 bytes, got {}", bytes.len());
     }
     let mut arr = [0u8; 32];
     arr.copy_from_slice(&bytes);
-    let secret = StaticSecret::from(arr);
-    let public = PublicKey::from(&secret);
-    Ok(BASE64.encode(public.to_bytes()))
+    Ok(arr)
 }
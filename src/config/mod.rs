@@ -6,4 +6,4 @@ pub mod keygen;
 pub mod peer;
 
 pub use config::{Config, PeerConfig, PeerRequest, ServerConfig};
-pub use peer::{PeerHandshake, PeerHandshakeResponse, PeerManager};
+pub use peer::{PeerChallenge, PeerChallengeResponse, PeerConnection, PeerHandshake, PeerHandshakeResponse, PeerManager, PeerMessage};
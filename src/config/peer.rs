@@ -1,24 +1,35 @@
 //! Peer management for remote Supercode instances
 
-use std::collections::HashMap;
-use anyhow::Result;
+use std::collections::HashSet;
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
-use tokio::net::tcp::OwnedReadHalf;
-use tracing::{info, warn};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
 
-use super::{Config, PeerConfig, PeerRequest};
+use super::{keygen, Config, PeerConfig, PeerRequest};
+use crate::db::repositories::peer_message::PeerMessageRepository;
 
 /// Peer manager for handling peer connections
 pub struct PeerManager {
     config: Config,
+    /// Where messages wait while their peer is unreachable (None: not kept)
+    outbox: Option<PeerMessageRepository>,
 }
 
 impl PeerManager {
     pub fn new(config: Config) -> Self {
-        Self { config }
+        Self { config, outbox: None }
+    }
+
+    /// Keep messages in `outbox` until their peer takes them
+    pub fn with_outbox(mut self, outbox: PeerMessageRepository) -> Self {
+        self.outbox = Some(outbox);
+        self
     }
 
     pub fn config(&self) -> &Config {
@@ -39,7 +50,7 @@ impl PeerManager {
             .ok_or_else(|| anyhow::anyhow!("Peer not found: {}", peer_name))?;
 
         for hostname in &peer.hostnames {
-            match self.try_connect(peer_name, hostname, &peer.auth).await {
+            match self.try_connect(peer_name, hostname, &peer.auth, &peer.public_key).await {
                 Ok(conn) => return Ok(conn),
                 Err(e) => {
                     warn!("Failed to connect to {} at {}: {}", peer_name, hostname, e);
@@ -50,8 +61,107 @@ impl PeerManager {
         anyhow::bail!("Could not connect to peer {} on any hostname", peer_name)
     }
 
-    async fn try_connect(&self, peer_name: &str, hostname: &str, auth: &str) -> Result<PeerConnection> {
-        let addr = format!("{}:9092", hostname);
+    /// Send a message to a peer. With an outbox the message is queued first
+    /// and stays queued if the peer can't be reached, so this only fails
+    /// if queueing does; returns whether it was delivered now.
+    pub async fn send_to_peer(&self, peer_name: &str, msg: PeerMessage) -> Result<bool> {
+        let Some(outbox) = &self.outbox else {
+            let mut connection = self.connect_to_peer(peer_name).await?;
+            connection.send(msg).await?;
+            return Ok(true);
+        };
+
+        let id = msg.id.clone();
+        outbox.enqueue(peer_name, &msg).await?;
+        match self.flush_outbox(peer_name).await {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                warn!("Queued message {} for unreachable peer {}: {}", id, peer_name, e);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Connect to a peer and deliver everything queued for it, returning how
+    /// many messages went out. A failed attempt is recorded on the queue.
+    pub async fn flush_outbox(&self, peer_name: &str) -> Result<usize> {
+        let Some(outbox) = &self.outbox else {
+            return Ok(0);
+        };
+        if outbox.pending(peer_name).await?.is_empty() {
+            return Ok(0);
+        }
+
+        let delivered = match self.connect_to_peer(peer_name).await {
+            Ok(mut connection) => connection.deliver_queued(outbox).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &delivered {
+            outbox.record_failure(peer_name, &e.to_string()).await?;
+        }
+        delivered
+    }
+
+    /// Try every peer that has queued messages, returning how many were
+    /// delivered. Peers still unreachable keep their messages.
+    pub async fn retry_outbox(&self) -> Result<usize> {
+        let Some(outbox) = &self.outbox else {
+            return Ok(0);
+        };
+
+        let mut delivered = 0;
+        for peer_name in outbox.peers_with_pending().await? {
+            match self.flush_outbox(&peer_name).await {
+                Ok(count) => delivered += count,
+                Err(e) => debug!("Peer {} still unreachable: {}", peer_name, e),
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Ask a peer for its public key without completing a connection.
+    ///
+    /// The key is whatever the peer presents, so it must be checked
+    /// out-of-band before it is trusted. A peer that doesn't know us yet
+    /// records this as a pending request.
+    pub async fn fetch_public_key(&self, peer_name: &str) -> Result<String> {
+        let peer = self.config.get_peer(peer_name)
+            .ok_or_else(|| anyhow::anyhow!("Peer not found: {}", peer_name))?;
+
+        for hostname in &peer.hostnames {
+            let reply = match self.send_handshake(hostname, &peer.auth).await {
+                Ok((_, _, reply)) => reply,
+                Err(e) => {
+                    warn!("Failed to reach {} at {}: {}", peer_name, hostname, e);
+                    continue;
+                }
+            };
+
+            let key = match reply {
+                HandshakeReply::Challenge(challenge) => challenge.public_key,
+                HandshakeReply::Response(response) => response.public_key,
+            };
+            if !key.is_empty() {
+                return Ok(key);
+            }
+            warn!("Peer {} at {} did not present a public key", peer_name, hostname);
+        }
+
+        anyhow::bail!("Could not get a public key from peer {} on any hostname", peer_name)
+    }
+
+    /// Open a connection and send our handshake, returning the peer's first reply
+    async fn send_handshake(
+        &self,
+        hostname: &str,
+        auth: &str,
+    ) -> Result<(BufReader<OwnedReadHalf>, OwnedWriteHalf, HandshakeReply)> {
+        // Hostnames may carry their own port
+        let addr = if hostname.contains(':') {
+            hostname.to_string()
+        } else {
+            format!("{}:9092", hostname)
+        };
         info!("Attempting to connect to peer at {}", addr);
         
         let stream = TcpStream::connect(&addr).await?;
@@ -72,20 +182,60 @@ impl PeerManager {
 
         let mut line = String::new();
         reader.read_line(&mut line).await?;
+        let reply = serde_json::from_str(&line)?;
+
+        Ok((reader, writer, reply))
+    }
+
+    async fn try_connect(&self, peer_name: &str, hostname: &str, auth: &str, expected_key: &str) -> Result<PeerConnection> {
+        let (mut reader, mut writer, reply) = self.send_handshake(hostname, auth).await?;
+        let mut line = String::new();
 
-        let response: PeerHandshakeResponse = serde_json::from_str(&line)?;
+        // Known peers challenge us to prove we own our key before answering;
+        // the challenge also seeds the key that encrypts this connection
+        let mut session_key = None;
+        let response = match reply {
+            HandshakeReply::Response(response) => response,
+            HandshakeReply::Challenge(challenge) => {
+                if !expected_key.is_empty() && challenge.public_key != expected_key {
+                    anyhow::bail!("Peer {} presented a different public key than the one on record", peer_name);
+                }
+
+                let proof = keygen::handshake_proof(
+                    &self.config.private_key,
+                    &challenge.public_key,
+                    &challenge.nonce,
+                    &self.config.name,
+                )?;
+
+                session_key = Some(keygen::session_key(
+                    &self.config.private_key,
+                    &challenge.public_key,
+                    &challenge.nonce,
+                )?);
+
+                let answer = serde_json::to_string(&PeerChallengeResponse { proof })?;
+                writer.write_all(answer.as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+                writer.flush().await?;
+
+                line.clear();
+                reader.read_line(&mut line).await?;
+                serde_json::from_str(&line)?
+            }
+        };
 
         if !response.accepted {
             anyhow::bail!("Peer {} rejected connection: {}", peer_name, response.message);
         }
 
+        let session_key = session_key
+            .ok_or_else(|| anyhow::anyhow!("Peer {} accepted without a key challenge", peer_name))?;
+
         info!("Successfully connected to peer {}", peer_name);
 
-        Ok(PeerConnection {
-            name: peer_name.to_string(),
-            stream: reader,
-            remote_public_key: response.public_key,
-        })
+        Ok(PeerConnection::from_parts(peer_name, reader, writer, response.public_key)
+            .with_session_key(session_key))
     }
 
     pub fn handle_peer_request(&mut self, request: PeerRequest) {
@@ -126,7 +276,150 @@ impl PeerManager {
 pub struct PeerConnection {
     pub name: String,
     pub stream: BufReader<OwnedReadHalf>,
+    pub writer: OwnedWriteHalf,
     pub remote_public_key: String,
+    /// Key that message payloads are encrypted with, once agreed
+    session_key: Option<[u8; 32]>,
+    /// Ids of the messages sent and received so far; one seen again is a
+    /// replay (or a reflection of our own) and is refused
+    seen_ids: HashSet<String>,
+}
+
+impl PeerConnection {
+    /// Wrap a stream whose handshake has already completed
+    pub fn new(name: impl Into<String>, stream: TcpStream, remote_public_key: impl Into<String>) -> Self {
+        let (reader, writer) = stream.into_split();
+        Self::from_parts(name, BufReader::new(reader), writer, remote_public_key)
+    }
+
+    /// Wrap the halves of a stream whose handshake has already completed
+    pub fn from_parts(
+        name: impl Into<String>,
+        stream: BufReader<OwnedReadHalf>,
+        writer: OwnedWriteHalf,
+        remote_public_key: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            stream,
+            writer,
+            remote_public_key: remote_public_key.into(),
+            session_key: None,
+            seen_ids: HashSet::new(),
+        }
+    }
+
+    /// Encrypt payloads with `key` (see `keygen::session_key`). Once set,
+    /// unencrypted messages from the peer are refused.
+    pub fn with_session_key(mut self, key: [u8; 32]) -> Self {
+        self.session_key = Some(key);
+        self
+    }
+
+    /// Whether payloads on this connection are encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.session_key.is_some()
+    }
+
+    /// Send a message to the peer as one line of JSON
+    pub async fn send(&mut self, mut msg: PeerMessage) -> Result<()> {
+        if !self.seen_ids.insert(msg.id.clone()) {
+            anyhow::bail!("Message {} was already sent on this connection", msg.id);
+        }
+        if let Some(key) = &self.session_key {
+            msg.payload = seal(key, &msg.payload, &msg.header()?)?;
+            msg.encrypted = true;
+        }
+
+        let json = serde_json::to_string(&msg)?;
+        self.writer.write_all(json.as_bytes()).await?;
+        self.writer.write_all(b"\n").await?;
+        self.writer.flush().await?;
+
+        debug!("Sent {} message {} to peer {}", msg.message_type, msg.id, self.name);
+        Ok(())
+    }
+
+    /// Send everything queued for this peer that no other attempt has
+    /// claimed, oldest first, marking each message delivered once the peer
+    /// acknowledges it. Messages not acknowledged are left for a retry.
+    pub async fn deliver_queued(&mut self, outbox: &PeerMessageRepository) -> Result<usize> {
+        let messages = outbox.claim(&self.name).await?;
+        let ids: Vec<String> = messages.iter().map(|msg| msg.id.clone()).collect();
+        let mut count = 0;
+        let mut result = Ok(());
+        for msg in messages {
+            result = self.send_acknowledged(msg).await;
+            if result.is_err() {
+                break;
+            }
+            outbox.mark_delivered(&ids[count]).await?;
+            count += 1;
+        }
+
+        // The peer may have taken the failed one without its ack getting
+        // back; it recognizes the redelivery by id
+        outbox.release(&ids[count..]).await?;
+        result?;
+
+        if count > 0 {
+            info!("Delivered {} queued message(s) to peer {}", count, self.name);
+        }
+        Ok(count)
+    }
+
+    /// Send a message and wait for the peer's `ACK` of it, passing over
+    /// anything else the peer sends meanwhile
+    async fn send_acknowledged(&mut self, msg: PeerMessage) -> Result<()> {
+        let id = msg.id.clone();
+        self.send(msg).await?;
+
+        tokio::time::timeout(ACK_TIMEOUT, async {
+            loop {
+                let reply = self.recv().await?;
+                if reply.message_type == ACK && reply.in_reply_to.as_deref() == Some(id.as_str()) {
+                    return Ok(());
+                }
+                debug!("Passed over {} message {} from peer {} awaiting an ack", reply.message_type, reply.id, self.name);
+            }
+        }).await
+            .map_err(|_| anyhow::anyhow!("Peer {} didn't acknowledge message {} within {:?}", self.name, id, ACK_TIMEOUT))?
+    }
+
+    /// Wait for the next message from the peer
+    pub async fn recv(&mut self) -> Result<PeerMessage> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.stream.read_line(&mut line).await? == 0 {
+                anyhow::bail!("Peer {} closed the connection", self.name);
+            }
+            // Tolerate blank keep-alive lines
+            if !line.trim().is_empty() {
+                break;
+            }
+        }
+
+        let mut msg: PeerMessage = serde_json::from_str(line.trim())
+            .with_context(|| format!("Invalid message from peer {}", self.name))?;
+
+        match (&self.session_key, msg.encrypted) {
+            (Some(key), true) => {
+                msg.payload = open(key, &msg.payload, &msg.header()?)
+                    .with_context(|| format!("Failed to decrypt message from peer {}", self.name))?;
+                msg.encrypted = false;
+            }
+            (Some(_), false) => anyhow::bail!("Peer {} sent an unencrypted message", self.name),
+            (None, true) => anyhow::bail!("Peer {} sent an encrypted message without a session key", self.name),
+            (None, false) => {}
+        }
+        if !self.seen_ids.insert(msg.id.clone()) {
+            anyhow::bail!("Peer {} replayed message {}", self.name, msg.id);
+        }
+
+        debug!("Received {} message {} from peer {}", msg.message_type, msg.id, self.name);
+        Ok(msg)
+    }
 }
 
 /// Peer handshake message
@@ -146,6 +439,30 @@ pub struct PeerHandshakeResponse {
     pub public_key: String,
 }
 
+/// Challenge sent to a known peer after its handshake: sign `nonce` to
+/// prove ownership of the public key on record
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeerChallenge {
+    pub nonce: String,
+    /// The challenger's public key, to derive the shared secret from
+    pub public_key: String,
+}
+
+/// Answer to a `PeerChallenge`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeerChallengeResponse {
+    /// `keygen::handshake_proof` over the challenge nonce
+    pub proof: String,
+}
+
+/// What a client may receive right after sending its handshake
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum HandshakeReply {
+    Challenge(PeerChallenge),
+    Response(PeerHandshakeResponse),
+}
+
 /// Message from/to peer
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PeerMessage {
@@ -155,4 +472,98 @@ pub struct PeerMessage {
     pub from: String,
     pub timestamp: DateTime<Utc>,
     pub signature: Option<String>,
+    /// `payload` is base64 nonce + AES-256-GCM ciphertext under the
+    /// connection's session key, authenticated together with the header
+    #[serde(default)]
+    pub encrypted: bool,
+    /// Id of the message this one answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<String>,
+}
+
+/// Receipt for a message, sent back with `in_reply_to` set to its id. A
+/// queued message counts as delivered only once this arrives.
+pub const ACK: &str = "ack";
+
+/// How long to wait for a peer to acknowledge a queued message
+const ACK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Ask a peer to spawn a session; the payload is `spawn_session` tool arguments
+pub const SPAWN_SESSION: &str = "spawn_session";
+
+/// Answer to `SPAWN_SESSION`: `{"result": ...}` with the tool's result, or
+/// `{"error": ...}` if the peer couldn't run it
+pub const SPAWN_SESSION_RESULT: &str = "spawn_session_result";
+
+/// Ask a peer for its sessions; the payload is `list_sessions` tool arguments
+pub const LIST_SESSIONS: &str = "list_sessions";
+
+/// Answer to `LIST_SESSIONS`, shaped like `SPAWN_SESSION_RESULT`
+pub const LIST_SESSIONS_RESULT: &str = "list_sessions_result";
+
+impl PeerMessage {
+    /// Create an unsigned message from this node
+    pub fn new(message_type: impl Into<String>, payload: impl Into<String>, from: impl Into<String>) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            message_type: message_type.into(),
+            payload: payload.into(),
+            from: from.into(),
+            timestamp: Utc::now(),
+            signature: None,
+            encrypted: false,
+            in_reply_to: None,
+        }
+    }
+
+    /// Create an answer to this message
+    pub fn reply(&self, message_type: impl Into<String>, payload: impl Into<String>, from: impl Into<String>) -> Self {
+        Self {
+            in_reply_to: Some(self.id.clone()),
+            ..Self::new(message_type, payload, from)
+        }
+    }
+
+    /// The fields an encrypted payload is bound to, so they can't be
+    /// swapped or rewritten in transit
+    fn header(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&(&self.message_type, &self.id, &self.from, &self.in_reply_to))?)
+    }
+}
+
+/// Encrypt a payload, authenticating `header` along with it, and return
+/// base64 of nonce followed by ciphertext
+fn seal(key: &[u8; 32], plaintext: &str, header: &[u8]) -> Result<String> {
+    use aes_gcm::{aead::{Aead, Payload}, Aes256Gcm, KeyInit, Nonce};
+    use rand::RngCore;
+
+    let cipher = Aes256Gcm::new_from_slice(key).context("Invalid session key")?;
+    let mut nonce = [0u8; 12];
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext.as_bytes(), aad: header })
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt payload"))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(BASE64.encode(sealed))
+}
+
+/// Decrypt a payload produced by `seal` with the same `header`
+fn open(key: &[u8; 32], sealed: &str, header: &[u8]) -> Result<String> {
+    use aes_gcm::{aead::{Aead, Payload}, Aes256Gcm, KeyInit, Nonce};
+
+    let sealed = BASE64.decode(sealed).context("Encrypted payload is not base64")?;
+    if sealed.len() < 12 {
+        anyhow::bail!("Encrypted payload is too short");
+    }
+    let (nonce, ciphertext) = sealed.split_at(12);
+
+    let cipher = Aes256Gcm::new_from_slice(key).context("Invalid session key")?;
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+        .map_err(|_| anyhow::anyhow!("Payload failed authentication"))?;
+
+    String::from_utf8(plaintext).context("Decrypted payload is not UTF-8")
 }
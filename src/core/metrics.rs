@@ -0,0 +1,111 @@
+//! Prometheus metrics for sessions, tool calls and quality gates
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+/// Counters collected while serving, rendered in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// (agent_type, session_type) -> spawns that reached a running agent
+    spawned: Mutex<BTreeMap<(String, String), u64>>,
+    /// (agent_type, session_type) -> spawns that failed
+    spawn_failures: Mutex<BTreeMap<(String, String), u64>>,
+    /// (tool, outcome) -> MCP tool calls
+    tool_calls: Mutex<BTreeMap<(String, &'static str), u64>>,
+    /// (gate, result) -> quality gate runs
+    gate_runs: Mutex<BTreeMap<(String, &'static str), u64>>,
+    /// gate -> (total seconds, runs)
+    gate_durations: Mutex<BTreeMap<String, (f64, u64)>>,
+}
+
+/// Point-in-time values read from the database at scrape time
+#[derive(Debug, Default)]
+pub struct SessionGauges {
+    /// status -> sessions currently in it
+    pub by_status: Vec<(&'static str, usize)>,
+    /// Sessions waiting for approval
+    pub blocked: usize,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a spawn attempt by its outcome
+    pub fn record_spawn(&self, agent_type: &str, session_type: &str, succeeded: bool) {
+        let counters = if succeeded { &self.spawned } else { &self.spawn_failures };
+        *counters.lock().unwrap()
+            .entry((agent_type.to_string(), session_type.to_string()))
+            .or_default() += 1;
+    }
+
+    /// Count a tool call by whether it returned a result or an error
+    pub fn record_tool_call(&self, tool: &str, succeeded: bool) {
+        let outcome = if succeeded { "ok" } else { "error" };
+        *self.tool_calls.lock().unwrap().entry((tool.to_string(), outcome)).or_default() += 1;
+    }
+
+    /// Count a quality gate run and how long it took
+    pub fn record_gate(&self, gate: &str, passed: bool, duration_ms: u64) {
+        let result = if passed { "passed" } else { "failed" };
+        *self.gate_runs.lock().unwrap().entry((gate.to_string(), result)).or_default() += 1;
+
+        let mut durations = self.gate_durations.lock().unwrap();
+        let (seconds, runs) = durations.entry(gate.to_string()).or_default();
+        *seconds += duration_ms as f64 / 1000.0;
+        *runs += 1;
+    }
+
+    /// Everything in the Prometheus text exposition format
+    pub fn render(&self, gauges: &SessionGauges) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "supercode_sessions_spawned_total", "counter", "Sessions spawned, by agent and session type");
+        for ((agent_type, session_type), count) in self.spawned.lock().unwrap().iter() {
+            let _ = writeln!(out, "supercode_sessions_spawned_total{{agent_type=\"{}\",session_type=\"{}\"}} {}", escape(agent_type), escape(session_type), count);
+        }
+
+        header(&mut out, "supercode_sessions_failed_total", "counter", "Session spawns that failed, by agent and session type");
+        for ((agent_type, session_type), count) in self.spawn_failures.lock().unwrap().iter() {
+            let _ = writeln!(out, "supercode_sessions_failed_total{{agent_type=\"{}\",session_type=\"{}\"}} {}", escape(agent_type), escape(session_type), count);
+        }
+
+        header(&mut out, "supercode_sessions", "gauge", "Sessions currently in each status");
+        for (status, count) in &gauges.by_status {
+            let _ = writeln!(out, "supercode_sessions{{status=\"{}\"}} {}", status, count);
+        }
+
+        header(&mut out, "supercode_sessions_blocked", "gauge", "Sessions waiting for approval");
+        let _ = writeln!(out, "supercode_sessions_blocked {}", gauges.blocked);
+
+        header(&mut out, "supercode_tool_calls_total", "counter", "MCP tool calls, by tool and outcome");
+        for ((tool, outcome), count) in self.tool_calls.lock().unwrap().iter() {
+            let _ = writeln!(out, "supercode_tool_calls_total{{tool=\"{}\",outcome=\"{}\"}} {}", escape(tool), outcome, count);
+        }
+
+        header(&mut out, "supercode_quality_gate_runs_total", "counter", "Quality gate runs, by gate and result");
+        for ((gate, result), count) in self.gate_runs.lock().unwrap().iter() {
+            let _ = writeln!(out, "supercode_quality_gate_runs_total{{gate=\"{}\",result=\"{}\"}} {}", escape(gate), result, count);
+        }
+
+        header(&mut out, "supercode_quality_gate_duration_seconds", "summary", "Time spent running quality gates");
+        for (gate, (seconds, runs)) in self.gate_durations.lock().unwrap().iter() {
+            let _ = writeln!(out, "supercode_quality_gate_duration_seconds_sum{{gate=\"{}\"}} {}", escape(gate), seconds);
+            let _ = writeln!(out, "supercode_quality_gate_duration_seconds_count{{gate=\"{}\"}} {}", escape(gate), runs);
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
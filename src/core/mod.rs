@@ -1,5 +1,6 @@
 //! Core orchestration module
 
+pub mod metrics;
 pub mod state;
 
 pub use state::AppState;
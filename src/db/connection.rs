@@ -1,28 +1,59 @@
 //! Database connection management
 //!
-//! NOTE: This implementation uses synchronous rusqlite with tokio::Mutex.
-//! For production use with 10+ concurrent sessions, switch to sqlx with
-//! async-native SQLite support for true non-blocking operations.
+//! Connections come from an r2d2 pool, so queries from different sessions
+//! no longer serialize behind a single mutex. rusqlite is still synchronous:
+//! each query blocks its runtime thread while it runs.
 
 use anyhow::{Context, Result};
-use rusqlite::Connection;
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
 use std::path::Path;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
 use tracing::info;
 
-use super::schema::SCHEMA;
+use super::schema::{ADDED_COLUMNS, ADDED_INDEXES, MESSAGE_SEARCH, SCHEMA};
 
+/// A connection checked out of the pool
+pub type DbConnection = PooledConnection<SqliteConnectionManager>;
+
+/// Connection pragmas applied when the database is opened
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    /// `PRAGMA journal_mode` (default: WAL, so readers don't block the writer)
+    pub journal_mode: String,
+    /// How long to wait on a locked database before failing
+    pub busy_timeout: Duration,
+    /// `PRAGMA synchronous` (default: NORMAL, safe with WAL)
+    pub synchronous: String,
+    /// Maximum number of pooled connections
+    pub pool_size: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            journal_mode: "WAL".to_string(),
+            busy_timeout: Duration::from_millis(5000),
+            synchronous: "NORMAL".to_string(),
+            pool_size: 16,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Database {
-    /// NOTE: Using synchronous rusqlite with Mutex - this blocks the async
-    /// runtime thread during DB operations. For 10+ sessions, use sqlx instead.
-    conn: Arc<Mutex<Connection>>,
+    pool: Pool<SqliteConnectionManager>,
     path: String,
 }
 
 impl Database {
-    /// Create a new database connection
+    /// Create a new database connection with the default pragmas
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::with_config(path, DatabaseConfig::default())
+    }
+
+    /// Create a new database connection with custom pragmas
+    pub fn with_config<P: AsRef<Path>>(path: P, config: DatabaseConfig) -> Result<Self> {
         let path = path.as_ref();
 
         // Create parent directories if needed
@@ -30,32 +61,56 @@ impl Database {
             std::fs::create_dir_all(parent)?;
         }
 
-        let conn = Connection::open(path)
-            .with_context(|| format!("Failed to open database at {:?}", path))?;
+        // Pragmas other than journal_mode are per connection, so apply them
+        // to every connection the pool opens
+        let pool_size = config.pool_size;
+        let manager = SqliteConnectionManager::file(path).with_init(move |conn| {
+            // Enable foreign keys
+            conn.execute_batch("PRAGMA foreign_keys = ON;")?;
 
-        // Enable foreign keys
-        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+            // Tolerate concurrent access from many sessions
+            conn.pragma_update(None, "journal_mode", &config.journal_mode)?;
+            conn.pragma_update(None, "synchronous", &config.synchronous)?;
+            conn.busy_timeout(config.busy_timeout)?;
+            Ok(())
+        });
+
+        let pool = Pool::builder()
+            .max_size(pool_size)
+            .min_idle(Some(1))
+            .build(manager)
+            .with_context(|| format!("Failed to open database at {:?}", path))?;
 
         // Initialize schema
+        let conn = pool.get()?;
         conn.execute_batch(SCHEMA)?;
+        add_missing_columns(&conn)?;
+        conn.execute_batch(ADDED_INDEXES)?;
+        create_message_search(&conn)?;
+        drop(conn);
 
         info!("Database initialized at {:?}", path);
 
         Ok(Self {
-            conn: Arc::new(Mutex::new(conn)),
+            pool,
             path: path.to_string_lossy().to_string(),
         })
     }
 
-    /// Get a locked connection
-    /// 
-    /// WARNING: This holds the mutex for the duration of the operation,
-    /// blocking other async tasks from accessing the database.
-    /// 
-    /// For production with high concurrency, migrate to sqlx:
-    /// https://github.com/launchbadge/sqlx
-    pub async fn lock(&self) -> tokio::sync::MutexGuard<'_, Connection> {
-        self.conn.lock().await
+    /// Get a connection from the pool.
+    ///
+    /// Waiting for a free connection happens on a blocking thread so an
+    /// exhausted pool doesn't stall the runtime.
+    pub async fn get(&self) -> Result<DbConnection> {
+        if let Some(conn) = self.pool.try_get() {
+            return Ok(conn);
+        }
+
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || pool.get())
+            .await
+            .context("Database connection task failed")?
+            .context("Failed to get a database connection")
     }
 
     /// Get the database path
@@ -65,9 +120,9 @@ impl Database {
 
     /// Check if database is accessible (for health checks)
     pub async fn health_check(&self) -> Result<bool> {
-        let conn = self.lock().await;
+        let conn = self.get().await?;
         // Simple query to check connectivity
-        match conn.execute("SELECT 1", []) {
+        match conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0)) {
             Ok(_) => Ok(true),
             Err(e) => {
                 tracing::warn!("Database health check failed: {}", e);
@@ -77,11 +132,37 @@ impl Database {
     }
 }
 
-impl Clone for Database {
-    fn clone(&self) -> Self {
-        Self {
-            conn: Arc::clone(&self.conn),
-            path: self.path.clone(),
+/// Bring tables created by older versions up to date with `ADDED_COLUMNS`
+fn add_missing_columns(conn: &rusqlite::Connection) -> Result<()> {
+    for (table, column, definition) in ADDED_COLUMNS {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .filter_map(|name| name.ok())
+            .any(|name| name == *column);
+
+        if !exists {
+            conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+                .with_context(|| format!("Failed to add column {}.{}", table, column))?;
+            info!("Added column {}.{}", table, column);
         }
     }
+    Ok(())
+}
+
+/// Create the message search index, indexing messages written before it existed
+fn create_message_search(conn: &rusqlite::Connection) -> Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'messages_fts')",
+        [],
+        |row| row.get(0),
+    )?;
+
+    conn.execute_batch(MESSAGE_SEARCH).context("Failed to create message search index")?;
+    if !exists {
+        conn.execute_batch("INSERT INTO messages_fts (messages_fts) VALUES ('rebuild')")
+            .context("Failed to index existing messages")?;
+        info!("Created message search index");
+    }
+    Ok(())
 }
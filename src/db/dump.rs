@@ -0,0 +1,148 @@
+//! Portable JSON Lines dump of projects, sessions (with their tags) and messages
+
+use std::io::{BufRead, Write};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::repositories::{
+    message::{Message, MessageRepository},
+    project::{Project, ProjectRepository},
+    session::{Session, SessionRepository},
+};
+use super::Database;
+
+/// One line of a dump
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DumpRecord {
+    Project(Project),
+    Session(SessionRecord),
+    Message(Message),
+}
+
+/// A session with what is stored alongside its row
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionRecord {
+    #[serde(flatten)]
+    pub session: Session,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+}
+
+/// Records written or imported, by kind
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DumpCounts {
+    pub projects: usize,
+    pub sessions: usize,
+    pub messages: usize,
+}
+
+impl DumpCounts {
+    fn add(&mut self, record: &DumpRecord) {
+        match record {
+            DumpRecord::Project(_) => self.projects += 1,
+            DumpRecord::Session(_) => self.sessions += 1,
+            DumpRecord::Message(_) => self.messages += 1,
+        }
+    }
+}
+
+/// What an import did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ImportSummary {
+    pub imported: DumpCounts,
+    /// Records whose id was already in the database
+    pub skipped: DumpCounts,
+}
+
+/// Write every project, then every session, then every message to `out`,
+/// one JSON record per line, oldest first. Importing in that order keeps
+/// messages after the sessions they belong to.
+pub async fn export_jsonl(db: &Database, out: &mut impl Write) -> Result<DumpCounts> {
+    let mut counts = DumpCounts::default();
+    let mut write = |record: DumpRecord| -> Result<()> {
+        serde_json::to_writer(&mut *out, &record)?;
+        out.write_all(b"\n")?;
+        counts.add(&record);
+        Ok(())
+    };
+
+    for project in ProjectRepository::new(db.clone()).list().await?.into_iter().rev() {
+        write(DumpRecord::Project(project))?;
+    }
+
+    let session_repo = SessionRepository::new(db.clone());
+    let sessions = session_repo.list(None, None, None).await?;
+    let ids: Vec<&str> = sessions.iter().map(|s| s.id.as_str()).collect();
+    let mut tags = session_repo.tags_for(&ids).await?;
+    let message_repo = MessageRepository::new(db.clone());
+    let mut session_ids = Vec::with_capacity(sessions.len());
+    for session in sessions.into_iter().rev() {
+        session_ids.push(session.id.clone());
+        let record = SessionRecord {
+            tags: tags.remove(&session.id).unwrap_or_default(),
+            idempotency_key: session_repo.idempotency_key(&session.id).await?,
+            session,
+        };
+        write(DumpRecord::Session(record))?;
+    }
+    for session_id in session_ids {
+        for message in message_repo.list_for_session(&session_id).await? {
+            write(DumpRecord::Message(message))?;
+        }
+    }
+
+    out.flush()?;
+    Ok(counts)
+}
+
+/// Import a dump written by `export_jsonl`. Records whose id already
+/// exists are skipped, so importing the same dump twice changes nothing.
+pub async fn import_jsonl(db: &Database, input: impl BufRead) -> Result<ImportSummary> {
+    let project_repo = ProjectRepository::new(db.clone());
+    let session_repo = SessionRepository::new(db.clone());
+    let message_repo = MessageRepository::new(db.clone());
+
+    let mut summary = ImportSummary::default();
+    for (index, line) in input.lines().enumerate() {
+        let line = line.context("Failed to read dump")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: DumpRecord = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid record on line {}", index + 1))?;
+        let inserted = match &record {
+            DumpRecord::Project(project) => project_repo.import(project).await,
+            DumpRecord::Session(record) => import_session(&session_repo, record).await,
+            DumpRecord::Message(message) => message_repo.import(message).await,
+        }.with_context(|| format!("Failed to import line {}", index + 1))?;
+
+        if inserted {
+            summary.imported.add(&record);
+        } else {
+            summary.skipped.add(&record);
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Insert a session unless it exists, then restore whichever of its tags
+/// are missing. Returns whether the session was inserted.
+async fn import_session(session_repo: &SessionRepository, record: &SessionRecord) -> Result<bool> {
+    let inserted = session_repo.import(&record.session).await?;
+    if inserted {
+        if let Some(key) = &record.idempotency_key {
+            // Another session may have taken the key since; it keeps it
+            session_repo.set_idempotency_key(&record.session.id, key).await?;
+        }
+    }
+    for tag in &record.tags {
+        session_repo.add_tag(&record.session.id, tag).await?;
+    }
+    Ok(inserted)
+}
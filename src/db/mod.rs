@@ -3,6 +3,7 @@
 pub mod schema;
 pub mod connection;
 pub mod repositories;
+pub mod dump;
 
-pub use connection::Database;
+pub use connection::{Database, DatabaseConfig, DbConnection};
 pub use repositories::session::SessionRepository;
@@ -0,0 +1,144 @@
+//! Agent config repository
+
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::Database;
+use super::session::AgentType;
+
+/// A stored agent template: a named role with its own prompt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentConfig {
+    pub id: String,
+    /// Base agent type sessions spawned from this config are recorded as
+    pub agent_type: AgentType,
+    /// Role name, e.g. "qa" or "architect"
+    pub name: String,
+    pub description: Option<String>,
+    pub model: String,
+    /// Replaces the built-in role prompt when set
+    pub system_prompt: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+pub struct AgentConfigRepository {
+    db: Database,
+}
+
+impl AgentConfigRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Get the database reference
+    pub fn db(&self) -> &Database {
+        &self.db
+    }
+
+    /// Create a new agent config. Names are unique.
+    pub async fn create(
+        &self,
+        agent_type: AgentType,
+        name: String,
+        description: Option<String>,
+        model: String,
+        system_prompt: Option<String>,
+    ) -> Result<AgentConfig> {
+        if self.get_by_name(&name).await?.is_some() {
+            anyhow::bail!("Agent config already exists: {}", name);
+        }
+
+        let now = Utc::now();
+        let config = AgentConfig {
+            id: Uuid::new_v4().to_string(),
+            agent_type,
+            name,
+            description,
+            model,
+            system_prompt,
+            created_at: now,
+            updated_at: now,
+        };
+
+        let conn = self.db.get().await?;
+        conn.execute(
+            "INSERT INTO agent_configs (id, agent_type, name, description, model, system_prompt, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                config.id,
+                config.agent_type.as_str(),
+                config.name,
+                config.description,
+                config.model,
+                config.system_prompt,
+                config.created_at.to_rfc3339(),
+                config.updated_at.to_rfc3339(),
+            ],
+        ).context("Failed to insert agent config")?;
+
+        tracing::debug!("Created agent config: {}", config.name);
+        Ok(config)
+    }
+
+    /// Get an agent config by name
+    pub async fn get_by_name(&self, name: &str) -> Result<Option<AgentConfig>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT id, agent_type, name, description, model, system_prompt, created_at, updated_at
+             FROM agent_configs WHERE name = ?1"
+        )?;
+
+        match stmt.query_row(params![name], Self::map_row) {
+            Ok(config) => Ok(Some(config)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e).context("Failed to get agent config"),
+        }
+    }
+
+    /// List agent configs, optionally filtered by base agent type
+    pub async fn list(&self, agent_type: Option<AgentType>) -> Result<Vec<AgentConfig>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT id, agent_type, name, description, model, system_prompt, created_at, updated_at
+             FROM agent_configs WHERE (?1 IS NULL OR agent_type = ?1)
+             ORDER BY name ASC"
+        )?;
+
+        let configs = stmt.query_map(params![agent_type.as_ref().map(|t| t.as_str())], Self::map_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Failed to collect agent configs")?;
+
+        Ok(configs)
+    }
+
+    /// Delete an agent config by name
+    pub async fn delete(&self, name: &str) -> Result<bool> {
+        let conn = self.db.get().await?;
+        let deleted = conn.execute("DELETE FROM agent_configs WHERE name = ?1", params![name])?;
+        tracing::debug!("Deleted agent config: {}", name);
+        Ok(deleted > 0)
+    }
+
+    fn map_row(row: &rusqlite::Row) -> rusqlite::Result<AgentConfig> {
+        Ok(AgentConfig {
+            id: row.get(0)?,
+            agent_type: AgentType::from_str(&row.get::<_, String>(1)?).unwrap_or(AgentType::Developer),
+            name: row.get(2)?,
+            description: row.get(3)?,
+            model: row.get(4)?,
+            system_prompt: row.get(5)?,
+            created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(6)?)
+                .map(|dt| dt.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now()),
+            updated_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(7)?)
+                .map(|dt| dt.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now()),
+        })
+    }
+}
@@ -0,0 +1,57 @@
+//! Leases on jobs that only one process should run
+
+use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
+use rusqlite::params;
+
+use crate::db::Database;
+
+/// Longest a lease can be taken for in one go
+const MAX_TTL: chrono::Duration = chrono::Duration::days(365);
+
+/// Time-limited claims on named jobs, shared by every process using the
+/// database. A holder keeps its lease by renewing it before it expires; if
+/// the holder goes away, another can take the lease once it has.
+#[derive(Clone)]
+pub struct LeaseRepository {
+    db: Database,
+}
+
+impl LeaseRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Take or renew the lease on `name` for `holder` until `ttl` from now.
+    /// Returns false if another holder's lease hasn't expired yet.
+    pub async fn acquire(&self, name: &str, holder: &str, ttl: chrono::Duration) -> Result<bool> {
+        let conn = self.db.get().await?;
+        // Fixed-width timestamps so expiry can be compared as text
+        let now = Utc::now();
+        let expires_at = now + ttl.clamp(chrono::Duration::zero(), MAX_TTL);
+
+        let changed = conn.execute(
+            "INSERT INTO leases (name, holder, expires_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET holder = excluded.holder, expires_at = excluded.expires_at
+             WHERE leases.holder = excluded.holder OR leases.expires_at < ?4",
+            params![
+                name,
+                holder,
+                expires_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+                now.to_rfc3339_opts(SecondsFormat::Micros, true),
+            ],
+        ).context("Failed to acquire lease")?;
+
+        Ok(changed > 0)
+    }
+
+    /// Give up `holder`'s lease on `name`, if it still has it
+    pub async fn release(&self, name: &str, holder: &str) -> Result<()> {
+        let conn = self.db.get().await?;
+        conn.execute(
+            "DELETE FROM leases WHERE name = ?1 AND holder = ?2",
+            params![name, holder],
+        ).context("Failed to release lease")?;
+        Ok(())
+    }
+}
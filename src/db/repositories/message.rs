@@ -0,0 +1,222 @@
+//! Message repository
+
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::Database;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub id: String,
+    pub session_id: String,
+    pub role: MessageRole,
+    pub content: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageRole {
+    User,
+    Assistant,
+    System,
+}
+
+impl MessageRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageRole::User => "user",
+            MessageRole::Assistant => "assistant",
+            MessageRole::System => "system",
+        }
+    }
+}
+
+impl FromStr for MessageRole {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "user" => Ok(MessageRole::User),
+            "assistant" => Ok(MessageRole::Assistant),
+            "system" => Ok(MessageRole::System),
+            _ => anyhow::bail!("Unknown message role: {}", s),
+        }
+    }
+}
+
+/// A message matching a search, with the matching text highlighted
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageMatch {
+    pub message_id: String,
+    pub session_id: String,
+    pub role: MessageRole,
+    pub timestamp: DateTime<Utc>,
+    /// Excerpt around the match, with matched terms in `[` `]`
+    pub snippet: String,
+}
+
+pub struct MessageRepository {
+    db: Database,
+}
+
+impl MessageRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Get the database reference
+    pub fn db(&self) -> &Database {
+        &self.db
+    }
+
+    /// Record a new message for a session
+    pub async fn create(
+        &self,
+        session_id: &str,
+        role: MessageRole,
+        content: &str,
+    ) -> Result<Message> {
+        let message = Message {
+            id: Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            role,
+            content: content.to_string(),
+            timestamp: Utc::now(),
+        };
+
+        let conn = self.db.get().await?;
+        conn.execute(
+            "INSERT INTO messages (id, session_id, role, content, timestamp)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                message.id,
+                message.session_id,
+                message.role.as_str(),
+                message.content,
+                message.timestamp.to_rfc3339(),
+            ],
+        ).context("Failed to insert message")?;
+
+        tracing::debug!("Recorded {} message for session {}", role.as_str(), session_id);
+        Ok(message)
+    }
+
+    /// Insert `message` exactly as given, unless a message with its id
+    /// already exists. Returns whether it was inserted.
+    pub async fn import(&self, message: &Message) -> Result<bool> {
+        let conn = self.db.get().await?;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO messages (id, session_id, role, content, timestamp)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                message.id,
+                message.session_id,
+                message.role.as_str(),
+                message.content,
+                message.timestamp.to_rfc3339(),
+            ],
+        ).context("Failed to import message")?;
+        Ok(inserted > 0)
+    }
+
+    /// List all messages for a session, oldest first
+    pub async fn list_for_session(&self, session_id: &str) -> Result<Vec<Message>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT id, session_id, role, content, timestamp
+             FROM messages WHERE session_id = ?1
+             ORDER BY timestamp ASC, rowid ASC"
+        )?;
+
+        let messages = stmt.query_map(params![session_id], Self::map_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Failed to collect messages")?;
+
+        Ok(messages)
+    }
+
+    /// List the most recent `limit` messages for a session, oldest first
+    pub async fn list_recent_for_session(&self, session_id: &str, limit: usize) -> Result<Vec<Message>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT id, session_id, role, content, timestamp FROM (
+                 SELECT id, session_id, role, content, timestamp, rowid AS seq
+                 FROM messages WHERE session_id = ?1
+                 ORDER BY timestamp DESC, rowid DESC
+                 LIMIT ?2
+             ) ORDER BY timestamp ASC, seq ASC"
+        )?;
+
+        let messages = stmt.query_map(params![session_id, limit as i64], Self::map_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Failed to collect messages")?;
+
+        Ok(messages)
+    }
+
+    /// Find messages containing every word of `query`, best matches first.
+    ///
+    /// Words are matched as whole tokens (case-insensitive), so file names
+    /// and error text can be searched as typed; FTS5 query syntax is not
+    /// interpreted.
+    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<MessageMatch>> {
+        let terms: Vec<String> = query.split_whitespace()
+            .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+            .collect();
+        if terms.is_empty() {
+            anyhow::bail!("Search query is empty");
+        }
+
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT m.id, m.session_id, m.role, m.timestamp,
+                    snippet(messages_fts, 0, '[', ']', '...', 16)
+             FROM messages_fts JOIN messages m ON m.rowid = messages_fts.rowid
+             WHERE messages_fts MATCH ?1
+             ORDER BY rank
+             LIMIT ?2"
+        )?;
+
+        let matches = stmt.query_map(params![terms.join(" "), limit as i64], |row| {
+            Ok(MessageMatch {
+                message_id: row.get(0)?,
+                session_id: row.get(1)?,
+                role: MessageRole::from_str(&row.get::<_, String>(2).unwrap_or_default()).unwrap_or(MessageRole::User),
+                timestamp: DateTime::parse_from_rfc3339(&row.get::<_, String>(3).unwrap_or_default())
+                    .map(|dt| dt.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now()),
+                snippet: row.get(4)?,
+            })
+        })?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Failed to search messages")?;
+
+        Ok(matches)
+    }
+
+    /// Delete all messages for a session
+    pub async fn delete_for_session(&self, session_id: &str) -> Result<usize> {
+        let conn = self.db.get().await?;
+        let deleted = conn.execute("DELETE FROM messages WHERE session_id = ?1", params![session_id])?;
+        tracing::debug!("Deleted {} messages for session: {}", deleted, session_id);
+        Ok(deleted)
+    }
+
+    fn map_row(row: &rusqlite::Row) -> rusqlite::Result<Message> {
+        Ok(Message {
+            id: row.get(0)?,
+            session_id: row.get(1)?,
+            role: MessageRole::from_str(&row.get::<_, String>(2).unwrap_or_default()).unwrap_or(MessageRole::User),
+            content: row.get(3)?,
+            timestamp: DateTime::parse_from_rfc3339(&row.get::<_, String>(4).unwrap_or_default())
+                .map(|dt| dt.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now()),
+        })
+    }
+}
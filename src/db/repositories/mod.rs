@@ -2,3 +2,7 @@
 
 pub mod session;
 pub mod project;
+pub mod message;
+pub mod agent_config;
+pub mod peer_message;
+pub mod lease;
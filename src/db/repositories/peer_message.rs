@@ -0,0 +1,175 @@
+//! Outgoing peer message queue
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::params;
+
+use crate::config::PeerMessage;
+use crate::db::Database;
+
+/// How long a delivery attempt keeps its messages to itself. A claim older
+/// than this is taken to belong to an attempt that died, and is taken over.
+const CLAIM_TIMEOUT: chrono::Duration = chrono::Duration::minutes(10);
+
+/// How long a received message's id is remembered for spotting redeliveries
+const RECEIVED_RETENTION: chrono::Duration = chrono::Duration::days(7);
+
+/// Messages for peers that are held until the peer takes them, so a peer
+/// being unreachable doesn't lose them
+#[derive(Clone)]
+pub struct PeerMessageRepository {
+    db: Database,
+}
+
+impl PeerMessageRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Queue `message` for delivery to `peer_name`
+    pub async fn enqueue(&self, peer_name: &str, message: &PeerMessage) -> Result<()> {
+        let conn = self.db.get().await?;
+        conn.execute(
+            "INSERT INTO peer_messages (id, peer_name, message_type, payload, from_node, created_at, signature)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                message.id,
+                peer_name,
+                message.message_type,
+                message.payload,
+                message.from,
+                message.timestamp.to_rfc3339(),
+                message.signature,
+            ],
+        ).context("Failed to queue peer message")?;
+
+        tracing::debug!("Queued {} message {} for peer {}", message.message_type, message.id, peer_name);
+        Ok(())
+    }
+
+    /// Undelivered messages for a peer, oldest first
+    pub async fn pending(&self, peer_name: &str) -> Result<Vec<PeerMessage>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT id, message_type, payload, from_node, created_at, signature
+             FROM peer_messages WHERE peer_name = ?1 AND delivered_at IS NULL
+             ORDER BY created_at ASC, rowid ASC"
+        )?;
+
+        let messages = stmt.query_map(params![peer_name], Self::map_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Failed to collect peer messages")?;
+
+        Ok(messages)
+    }
+
+    /// Take the undelivered messages for a peer that no other delivery
+    /// attempt is working on, oldest first. They stay claimed until marked
+    /// delivered or released, or until the claim times out.
+    pub async fn claim(&self, peer_name: &str) -> Result<Vec<PeerMessage>> {
+        let conn = self.db.get().await?;
+        // Fixed-width timestamps so claims can be compared as text
+        let now = Utc::now();
+        let mut stmt = conn.prepare(
+            "UPDATE peer_messages SET claimed_at = ?1
+             WHERE peer_name = ?2 AND delivered_at IS NULL AND (claimed_at IS NULL OR claimed_at < ?3)
+             RETURNING id, message_type, payload, from_node, created_at, signature, rowid"
+        )?;
+
+        let mut messages = stmt.query_map(
+            params![
+                now.to_rfc3339_opts(SecondsFormat::Micros, true),
+                peer_name,
+                (now - CLAIM_TIMEOUT).to_rfc3339_opts(SecondsFormat::Micros, true),
+            ],
+            |row| Ok((Self::map_row(row)?, row.get::<_, i64>(6)?)),
+        )?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Failed to claim peer messages")?;
+
+        // RETURNING gives rows in no particular order
+        messages.sort_by(|(a, a_rowid), (b, b_rowid)| (a.timestamp, a_rowid).cmp(&(b.timestamp, b_rowid)));
+        Ok(messages.into_iter().map(|(message, _)| message).collect())
+    }
+
+    /// Give claimed messages back for the next delivery attempt
+    pub async fn release(&self, ids: &[String]) -> Result<()> {
+        let conn = self.db.get().await?;
+        for id in ids {
+            conn.execute(
+                "UPDATE peer_messages SET claimed_at = NULL WHERE id = ?1 AND delivered_at IS NULL",
+                params![id],
+            ).context("Failed to release peer message")?;
+        }
+        Ok(())
+    }
+
+    /// Remember that message `id` came from `peer_name`. Returns false if it
+    /// had already, i.e. this is a redelivery.
+    pub async fn record_received(&self, peer_name: &str, id: &str) -> Result<bool> {
+        let conn = self.db.get().await?;
+        let now = Utc::now();
+        conn.execute(
+            "DELETE FROM peer_messages_received WHERE received_at < ?1",
+            params![(now - RECEIVED_RETENTION).to_rfc3339_opts(SecondsFormat::Micros, true)],
+        ).context("Failed to prune received peer messages")?;
+
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO peer_messages_received (peer_name, id, received_at) VALUES (?1, ?2, ?3)",
+            params![peer_name, id, now.to_rfc3339_opts(SecondsFormat::Micros, true)],
+        ).context("Failed to record received peer message")?;
+
+        Ok(inserted > 0)
+    }
+
+    /// Peers with at least one undelivered message
+    pub async fn peers_with_pending(&self) -> Result<Vec<String>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT peer_name FROM peer_messages
+             WHERE delivered_at IS NULL ORDER BY peer_name"
+        )?;
+
+        let peers = stmt.query_map([], |row| row.get(0))?
+            .collect::<std::result::Result<Vec<String>, _>>()
+            .context("Failed to collect peers")?;
+
+        Ok(peers)
+    }
+
+    /// Record that the peer acknowledged a message
+    pub async fn mark_delivered(&self, id: &str) -> Result<()> {
+        let conn = self.db.get().await?;
+        conn.execute(
+            "UPDATE peer_messages SET delivered_at = ?1 WHERE id = ?2",
+            params![Utc::now().to_rfc3339(), id],
+        ).context("Failed to mark peer message delivered")?;
+        Ok(())
+    }
+
+    /// Record a failed delivery attempt against a peer's queued messages
+    pub async fn record_failure(&self, peer_name: &str, error: &str) -> Result<()> {
+        let conn = self.db.get().await?;
+        conn.execute(
+            "UPDATE peer_messages SET attempts = attempts + 1, last_error = ?1
+             WHERE peer_name = ?2 AND delivered_at IS NULL",
+            params![error, peer_name],
+        ).context("Failed to record peer delivery failure")?;
+        Ok(())
+    }
+
+    fn map_row(row: &rusqlite::Row) -> rusqlite::Result<PeerMessage> {
+        Ok(PeerMessage {
+            id: row.get(0)?,
+            message_type: row.get(1)?,
+            payload: row.get(2)?,
+            from: row.get(3)?,
+            timestamp: DateTime::parse_from_rfc3339(&row.get::<_, String>(4).unwrap_or_default())
+                .map(|dt| dt.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now()),
+            signature: row.get(5)?,
+            encrypted: false,
+            in_reply_to: None,
+        })
+    }
+}
@@ -50,7 +50,7 @@ impl ProjectRepository {
             metadata: None,
         };
 
-        let conn = self.db.lock().await;
+        let conn = self.db.get().await?;
         conn.execute(
             "INSERT INTO projects (id, name, description, created_at, updated_at, metadata)
              VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
@@ -70,7 +70,7 @@ impl ProjectRepository {
 
     /// Get a project by ID
     pub async fn get(&self, id: &str) -> Result<Option<Project>> {
-        let conn = self.db.lock().await;
+        let conn = self.db.get().await?;
         let mut stmt = conn.prepare(
             "SELECT id, name, description, created_at, updated_at, metadata
              FROM projects WHERE id = ?1"
@@ -98,9 +98,28 @@ impl ProjectRepository {
         }
     }
 
+    /// Insert `project` exactly as given, unless a project with its id
+    /// already exists. Returns whether it was inserted.
+    pub async fn import(&self, project: &Project) -> Result<bool> {
+        let conn = self.db.get().await?;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO projects (id, name, description, created_at, updated_at, metadata)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                project.id,
+                project.name,
+                project.description,
+                project.created_at.to_rfc3339(),
+                project.updated_at.to_rfc3339(),
+                project.metadata,
+            ],
+        ).context("Failed to import project")?;
+        Ok(inserted > 0)
+    }
+
     /// List all projects
     pub async fn list(&self) -> Result<Vec<Project>> {
-        let conn = self.db.lock().await;
+        let conn = self.db.get().await?;
         let mut stmt = conn.prepare(
             "SELECT id, name, description, created_at, updated_at, metadata
              FROM projects ORDER BY created_at DESC"
@@ -124,9 +143,35 @@ impl ProjectRepository {
         Ok(projects)
     }
 
+    /// Update a project's name and/or description, leaving omitted fields untouched
+    pub async fn update(
+        &self,
+        id: &str,
+        name: Option<String>,
+        description: Option<String>,
+    ) -> Result<Option<Project>> {
+        let updated = {
+            let conn = self.db.get().await?;
+            let now = Utc::now().to_rfc3339();
+
+            conn.execute(
+                "UPDATE projects SET name = COALESCE(?1, name), description = COALESCE(?2, description), updated_at = ?3
+                 WHERE id = ?4",
+                params![name, description, now, id],
+            ).context("Failed to update project")?
+        };
+
+        if updated == 0 {
+            return Ok(None);
+        }
+
+        tracing::debug!("Updated project: {}", id);
+        self.get(id).await
+    }
+
     /// Delete a project
     pub async fn delete(&self, id: &str) -> Result<()> {
-        let conn = self.db.lock().await;
+        let conn = self.db.get().await?;
         conn.execute("DELETE FROM projects WHERE id = ?1", params![id])?;
         tracing::debug!("Deleted project: {}", id);
         Ok(())
@@ -1,8 +1,11 @@
 //! Session repository
 
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
-use rusqlite::params;
+use rusqlite::{params, TransactionBehavior};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -20,59 +23,224 @@ pub struct Session {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub metadata: Option<String>,
+    /// The session this one was forked from
+    pub forked_from: Option<String>,
+    /// The session that forked or spawned this one
+    pub parent_session_id: Option<String>,
+    /// Name the agent was spawned with
+    pub name: Option<String>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
+/// Filters for listing sessions; `None` fields match everything
+#[derive(Debug, Clone, Default)]
+pub struct SessionFilter<'a> {
+    pub project_id: Option<&'a str>,
+    pub status: Option<SessionStatus>,
+    pub agent_type: Option<AgentType>,
+    /// Only sessions created at or after this time
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only sessions created strictly before this time
+    pub created_before: Option<DateTime<Utc>>,
+    /// Only sessions carrying every one of these tags
+    pub tags: &'a [String],
+    /// How matching sessions are sorted
+    pub order: SessionOrder,
+}
+
+/// Timestamp sessions can be listed by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionOrderBy {
+    #[default]
+    CreatedAt,
+    /// Last change to the session or its activity
+    UpdatedAt,
+}
+
+impl SessionOrderBy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionOrderBy::CreatedAt => "created_at",
+            SessionOrderBy::UpdatedAt => "updated_at",
+        }
+    }
+}
+
+impl FromStr for SessionOrderBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "created_at" => Ok(SessionOrderBy::CreatedAt),
+            "updated_at" => Ok(SessionOrderBy::UpdatedAt),
+            _ => anyhow::bail!("Unknown session order: {}", s),
+        }
+    }
+}
+
+/// Sort order for listing sessions; newest created first by default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionOrder {
+    pub by: SessionOrderBy,
+    pub ascending: bool,
+}
+
+impl SessionOrder {
+    fn sql(&self) -> String {
+        format!("{} {}", self.by.as_str(), if self.ascending { "ASC" } else { "DESC" })
+    }
+}
+
+impl SessionFilter<'_> {
+    fn values(&self) -> FilterValues {
+        FilterValues {
+            status: self.status.map(|st| st.as_str()),
+            agent_type: self.agent_type.as_ref().map(|at| at.as_str().to_string()),
+            created_after: self.created_after.map(|t| t.to_rfc3339()),
+            created_before: self.created_before.map(|t| t.to_rfc3339()),
+            tags: (!self.tags.is_empty()).then(|| {
+                let distinct: std::collections::BTreeSet<&String> = self.tags.iter().collect();
+                (serde_json::json!(distinct).to_string(), distinct.len() as i64)
+            }),
+        }
+    }
+}
+
+/// A filter's values in the form they are bound to the query
+struct FilterValues {
+    status: Option<&'static str>,
+    agent_type: Option<String>,
+    created_after: Option<String>,
+    created_before: Option<String>,
+    /// Distinct tags as a JSON array, and how many there are
+    tags: Option<(String, i64)>,
+}
+
+/// A session and every session forked or spawned under it
+#[derive(Debug, Clone)]
+pub struct SessionTree {
+    pub session: Session,
+    pub children: Vec<SessionTree>,
+}
+
+/// Agent role. The built-in roles have dedicated prompts; any other
+/// validated name (e.g. `security-auditor`) is a custom role.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(into = "String", try_from = "String")]
 pub enum AgentType {
     Manager,
     Developer,
     Reviewer,
+    Custom(String),
 }
 
 impl AgentType {
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match self {
             AgentType::Manager => "manager",
             AgentType::Developer => "developer",
             AgentType::Reviewer => "reviewer",
+            AgentType::Custom(name) => name,
         }
     }
+}
 
-    pub fn from_str(s: &str) -> Result<Self> {
+/// Whether `name` may be used as a custom agent or session type: 1-64
+/// characters of lowercase letters, digits, `-` or `_`, starting with a letter.
+fn is_valid_custom_name(name: &str) -> bool {
+    name.len() <= 64
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+impl FromStr for AgentType {
+    type Err = anyhow::Error;
+
+    /// Parse an agent type. Any other name must pass `is_valid_custom_name`.
+    fn from_str(s: &str) -> Result<Self> {
         match s {
             "manager" => Ok(AgentType::Manager),
             "developer" => Ok(AgentType::Developer),
             "reviewer" => Ok(AgentType::Reviewer),
-            _ => anyhow::bail!("Unknown agent type: {}", s),
+            _ => {
+                if !is_valid_custom_name(s) {
+                    anyhow::bail!("Invalid agent type: {}", s);
+                }
+                Ok(AgentType::Custom(s.to_string()))
+            }
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
+impl From<AgentType> for String {
+    fn from(agent_type: AgentType) -> Self {
+        agent_type.as_str().to_string()
+    }
+}
+
+impl TryFrom<String> for AgentType {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        AgentType::from_str(&s)
+    }
+}
+
+/// Session backend. Any other validated name is a provider registered
+/// with the session manager under that name.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(into = "String", try_from = "String")]
 pub enum SessionType {
     OpenCode,
     Claude,
+    /// An OpenAI-compatible chat completions API
+    OpenAi,
+    Custom(String),
 }
 
 impl SessionType {
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match self {
             SessionType::OpenCode => "opencode",
             SessionType::Claude => "claude",
+            SessionType::OpenAi => "openai",
+            SessionType::Custom(name) => name,
         }
     }
+}
+
+impl FromStr for SessionType {
+    type Err = anyhow::Error;
 
-    pub fn from_str(s: &str) -> Result<Self> {
+    /// Parse a session type. Any other name must pass `is_valid_custom_name`.
+    fn from_str(s: &str) -> Result<Self> {
         match s {
             "opencode" => Ok(SessionType::OpenCode),
             "claude" => Ok(SessionType::Claude),
-            _ => anyhow::bail!("Unknown session type: {}", s),
+            "openai" => Ok(SessionType::OpenAi),
+            _ => {
+                if !is_valid_custom_name(s) {
+                    anyhow::bail!("Invalid session type: {}", s);
+                }
+                Ok(SessionType::Custom(s.to_string()))
+            }
         }
     }
 }
 
+impl From<SessionType> for String {
+    fn from(session_type: SessionType) -> Self {
+        session_type.as_str().to_string()
+    }
+}
+
+impl TryFrom<String> for SessionType {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        SessionType::from_str(&s)
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum SessionStatus {
@@ -84,6 +252,10 @@ pub enum SessionStatus {
 }
 
 impl SessionStatus {
+    /// Statuses of sessions that have stopped; cleanup removes only these
+    /// unless live statuses are asked for by name
+    pub const FINISHED: [SessionStatus; 3] = [SessionStatus::Completed, SessionStatus::Failed, SessionStatus::Terminated];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             SessionStatus::Pending => "pending",
@@ -93,8 +265,12 @@ impl SessionStatus {
             SessionStatus::Terminated => "terminated",
         }
     }
+}
 
-    pub fn from_str(s: &str) -> Result<Self> {
+impl FromStr for SessionStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
         match s {
             "pending" => Ok(SessionStatus::Pending),
             "running" => Ok(SessionStatus::Running),
@@ -106,6 +282,101 @@ impl SessionStatus {
     }
 }
 
+/// What an agent is doing, as last observed by the session manager
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentState {
+    Idle,
+    Processing,
+    /// Blocked until someone grants or denies a pending action
+    WaitingForApproval,
+    Error,
+}
+
+impl AgentState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AgentState::Idle => "idle",
+            AgentState::Processing => "processing",
+            AgentState::WaitingForApproval => "waiting_for_approval",
+            AgentState::Error => "error",
+        }
+    }
+}
+
+impl FromStr for AgentState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "idle" => Ok(AgentState::Idle),
+            "processing" => Ok(AgentState::Processing),
+            "waiting_for_approval" => Ok(AgentState::WaitingForApproval),
+            "error" => Ok(AgentState::Error),
+            _ => anyhow::bail!("Unknown agent state: {}", s),
+        }
+    }
+}
+
+/// Kind of action a blocked agent is waiting to have approved
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalType {
+    FileWrite,
+    Command,
+    ToolUse,
+    Other,
+}
+
+impl ApprovalType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApprovalType::FileWrite => "file_write",
+            ApprovalType::Command => "command",
+            ApprovalType::ToolUse => "tool_use",
+            ApprovalType::Other => "other",
+        }
+    }
+}
+
+impl FromStr for ApprovalType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "file_write" => Ok(ApprovalType::FileWrite),
+            "command" => Ok(ApprovalType::Command),
+            "tool_use" => Ok(ApprovalType::ToolUse),
+            "other" => Ok(ApprovalType::Other),
+            _ => anyhow::bail!("Unknown approval type: {}", s),
+        }
+    }
+}
+
+/// Snapshot of a session's activity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionActivity {
+    pub session_id: String,
+    pub state: AgentState,
+    pub last_message: Option<String>,
+    pub last_response: Option<String>,
+    pub state_changed_at: DateTime<Utc>,
+    /// What is awaiting approval, when `state` is `WaitingForApproval`
+    pub approval_type: Option<ApprovalType>,
+    /// Human-readable description of the pending action
+    pub approval_description: Option<String>,
+}
+
+/// An activity snapshot together with the state the session was in before it
+#[derive(Debug, Clone)]
+pub struct ActivityChange {
+    /// Position in the activity log; later snapshots have larger IDs
+    pub id: i64,
+    pub activity: SessionActivity,
+    /// The session's previous recorded state, if any
+    pub previous_state: Option<AgentState>,
+}
+
 pub struct SessionRepository {
     db: Database,
 }
@@ -142,12 +413,49 @@ impl SessionRepository {
             created_at: now,
             updated_at: now,
             metadata: None,
+            forked_from: None,
+            parent_session_id: None,
+            name: None,
         };
 
-        let conn = self.db.lock().await;
+        self.insert(&session).await?;
+
+        tracing::debug!("Created session: {}", id);
+        Ok(session)
+    }
+
+    /// Create a session forked from `parent`, keeping its project, working
+    /// directory and metadata
+    pub async fn create_fork(&self, parent: &Session) -> Result<Session> {
+        let now = Utc::now();
+
+        let session = Session {
+            id: Uuid::new_v4().to_string(),
+            project_id: parent.project_id.clone(),
+            agent_type: parent.agent_type.clone(),
+            session_type: parent.session_type.clone(),
+            status: SessionStatus::Pending,
+            working_dir: parent.working_dir.clone(),
+            opencode_session_id: None,
+            created_at: now,
+            updated_at: now,
+            metadata: parent.metadata.clone(),
+            forked_from: Some(parent.id.clone()),
+            parent_session_id: Some(parent.id.clone()),
+            name: None,
+        };
+
+        self.insert(&session).await?;
+
+        tracing::debug!("Forked session {} from {}", session.id, parent.id);
+        Ok(session)
+    }
+
+    async fn insert(&self, session: &Session) -> Result<()> {
+        let conn = self.db.get().await?;
         conn.execute(
-            "INSERT INTO sessions (id, project_id, agent_type, session_type, status, working_dir, created_at, updated_at, metadata)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            "INSERT INTO sessions (id, project_id, agent_type, session_type, status, working_dir, created_at, updated_at, metadata, forked_from, parent_session_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
             params![
                 session.id,
                 session.project_id,
@@ -158,19 +466,46 @@ impl SessionRepository {
                 session.created_at.to_rfc3339(),
                 session.updated_at.to_rfc3339(),
                 session.metadata,
+                session.forked_from,
+                session.parent_session_id,
             ],
         ).context("Failed to insert session")?;
+        Ok(())
+    }
 
-        tracing::debug!("Created session: {}", id);
-        Ok(session)
+    /// Insert `session` exactly as given, unless a session with its id
+    /// already exists. Returns whether it was inserted.
+    pub async fn import(&self, session: &Session) -> Result<bool> {
+        let conn = self.db.get().await?;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO sessions (id, project_id, agent_type, session_type, status, working_dir,
+                                             opencode_session_id, created_at, updated_at, metadata, forked_from, parent_session_id, name)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                session.id,
+                session.project_id,
+                session.agent_type.as_str(),
+                session.session_type.as_str(),
+                session.status.as_str(),
+                session.working_dir,
+                session.opencode_session_id,
+                session.created_at.to_rfc3339(),
+                session.updated_at.to_rfc3339(),
+                session.metadata,
+                session.forked_from,
+                session.parent_session_id,
+                session.name,
+            ],
+        ).context("Failed to import session")?;
+        Ok(inserted > 0)
     }
 
     /// Get a session by ID
     pub async fn get(&self, id: &str) -> Result<Option<Session>> {
-        let conn = self.db.lock().await;
+        let conn = self.db.get().await?;
         let mut stmt = conn.prepare(
             "SELECT id, project_id, agent_type, session_type, status, working_dir, 
-                    opencode_session_id, created_at, updated_at, metadata
+                    opencode_session_id, created_at, updated_at, metadata, forked_from, parent_session_id, name
              FROM sessions WHERE id = ?1"
         )?;
 
@@ -204,6 +539,9 @@ impl SessionRepository {
                 created_at,
                 updated_at,
                 metadata: row.get(9)?,
+                forked_from: row.get(10)?,
+                parent_session_id: row.get(11)?,
+                name: row.get(12)?,
             })
         });
 
@@ -219,49 +557,139 @@ impl SessionRepository {
         &self,
         project_id: Option<&str>,
         status: Option<SessionStatus>,
+        agent_type: Option<AgentType>,
     ) -> Result<Vec<Session>> {
-        let conn = self.db.lock().await;
+        let (sessions, _) = self.list_paginated(project_id, status, agent_type, None, 0).await?;
+        Ok(sessions)
+    }
 
-        let mut query = String::from(
-            "SELECT id, project_id, agent_type, session_type, status, working_dir,
-                    opencode_session_id, created_at, updated_at, metadata
-             FROM sessions WHERE 1=1"
-        );
+    /// List a page of sessions, optionally filtered, along with the total
+    /// number of sessions matching the filters
+    pub async fn list_paginated(
+        &self,
+        project_id: Option<&str>,
+        status: Option<SessionStatus>,
+        agent_type: Option<AgentType>,
+        limit: Option<usize>,
+        offset: usize,
+    ) -> Result<(Vec<Session>, usize)> {
+        let filter = SessionFilter { project_id, status, agent_type, ..SessionFilter::default() };
+        self.list_filtered(&filter, limit, offset).await
+    }
+
+    /// List a page of sessions matching `filter`, along with the total
+    /// number of matching sessions
+    pub async fn list_filtered(
+        &self,
+        filter: &SessionFilter<'_>,
+        limit: Option<usize>,
+        offset: usize,
+    ) -> Result<(Vec<Session>, usize)> {
+        let conn = self.db.get().await?;
 
-        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        let values = filter.values();
+        let (clause, mut params) = Self::filter_clause(filter, &values);
 
-        if project_id.is_some() {
-            query.push_str(" AND project_id = ?1");
-        }
-        if status.is_some() {
-            // Use correct parameter number based on what's in the query
-            let param_num = if project_id.is_some() { 2 } else { 1 };
-            query.push_str(&format!(" AND status = ?{}", param_num));
-        }
-        query.push_str(" ORDER BY created_at DESC");
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM sessions WHERE 1=1{}", clause),
+            params.as_slice(),
+            |row| row.get(0),
+        ).context("Failed to count sessions")?;
 
-        let mut stmt = conn.prepare(&query)?;
+        let query = format!(
+            "SELECT id, project_id, agent_type, session_type, status, working_dir,
+                    opencode_session_id, created_at, updated_at, metadata, forked_from, parent_session_id, name
+             FROM sessions WHERE 1=1{}
+             ORDER BY {}
+             LIMIT :limit OFFSET :offset",
+            clause,
+            filter.order.sql()
+        );
 
-        if let Some(pid) = project_id {
-            params.push(Box::new(pid.to_string()));
-        }
-        if let Some(st) = status {
-            params.push(Box::new(st.as_str().to_string()));
-        }
+        // SQLite treats a negative limit as "no limit"
+        let limit = limit.map(|l| l as i64).unwrap_or(-1);
+        let offset = offset as i64;
+        params.push((":limit", &limit));
+        params.push((":offset", &offset));
 
-        let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+        let mut stmt = conn.prepare(&query)?;
 
-        let sessions = stmt.query_map(params_refs.as_slice(), Self::map_row)?;
+        let sessions = stmt.query_map(params.as_slice(), Self::map_row)?;
 
         let sessions_list: Vec<Session> = sessions.collect::<std::result::Result<Vec<_>, _>>()
             .context("Failed to collect sessions")?;
 
-        Ok(sessions_list)
+        Ok((sessions_list, total as usize))
+    }
+
+    /// Count sessions matching the filters
+    pub async fn count(
+        &self,
+        project_id: Option<&str>,
+        status: Option<SessionStatus>,
+        agent_type: Option<AgentType>,
+    ) -> Result<usize> {
+        let conn = self.db.get().await?;
+
+        let filter = SessionFilter { project_id, status, agent_type, ..SessionFilter::default() };
+        let values = filter.values();
+        let (clause, params) = Self::filter_clause(&filter, &values);
+
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM sessions WHERE 1=1{}", clause),
+            params.as_slice(),
+            |row| row.get(0),
+        ).context("Failed to count sessions")?;
+
+        Ok(total as usize)
+    }
+
+    /// Build the WHERE clause fragment for the list filters.
+    /// Uses named parameters so filter ordering can't drift from the bound values.
+    fn filter_clause<'a>(
+        filter: &'a SessionFilter<'_>,
+        values: &'a FilterValues,
+    ) -> (String, Vec<(&'static str, &'a dyn rusqlite::ToSql)>) {
+        let mut clause = String::new();
+        let mut params: Vec<(&'static str, &'a dyn rusqlite::ToSql)> = Vec::new();
+
+        if let Some(pid) = &filter.project_id {
+            clause.push_str(" AND project_id = :project_id");
+            params.push((":project_id", pid));
+        }
+        if let Some(st) = &values.status {
+            clause.push_str(" AND status = :status");
+            params.push((":status", st));
+        }
+        if let Some(at) = &values.agent_type {
+            clause.push_str(" AND agent_type = :agent_type");
+            params.push((":agent_type", at));
+        }
+        // Timestamps are stored as UTC RFC 3339, which sorts as text
+        if let Some(after) = &values.created_after {
+            clause.push_str(" AND created_at >= :created_after");
+            params.push((":created_after", after));
+        }
+        if let Some(before) = &values.created_before {
+            clause.push_str(" AND created_at < :created_before");
+            params.push((":created_before", before));
+        }
+        if let Some((tags, tag_count)) = &values.tags {
+            clause.push_str(
+                " AND id IN (SELECT session_id FROM session_tags
+                             WHERE tag IN (SELECT value FROM json_each(:tags))
+                             GROUP BY session_id HAVING COUNT(*) = :tag_count)"
+            );
+            params.push((":tags", tags));
+            params.push((":tag_count", tag_count));
+        }
+
+        (clause, params)
     }
 
     /// Update session status
     pub async fn update_status(&self, id: &str, status: SessionStatus) -> Result<()> {
-        let conn = self.db.lock().await;
+        let conn = self.db.get().await?;
         let now = Utc::now().to_rfc3339();
 
         conn.execute(
@@ -275,7 +703,7 @@ impl SessionRepository {
 
     /// Update session with OpenCode session ID
     pub async fn set_opencode_session_id(&self, id: &str, opencode_session_id: &str) -> Result<()> {
-        let conn = self.db.lock().await;
+        let conn = self.db.get().await?;
         let now = Utc::now().to_rfc3339();
 
         conn.execute(
@@ -286,14 +714,399 @@ impl SessionRepository {
         Ok(())
     }
 
+    /// Record the session that spawned this one
+    pub async fn set_parent_session_id(&self, id: &str, parent_session_id: &str) -> Result<()> {
+        let conn = self.db.get().await?;
+        let now = Utc::now().to_rfc3339();
+
+        conn.execute(
+            "UPDATE sessions SET parent_session_id = ?1, updated_at = ?2 WHERE id = ?3",
+            params![parent_session_id, now, id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Tag a session. Returns false if it already had the tag.
+    pub async fn add_tag(&self, id: &str, tag: &str) -> Result<bool> {
+        let conn = self.db.get().await?;
+        let added = conn.execute(
+            "INSERT OR IGNORE INTO session_tags (session_id, tag) VALUES (?1, ?2)",
+            params![id, tag],
+        ).context("Failed to tag session")?;
+        Ok(added > 0)
+    }
+
+    /// Remove a tag from a session. Returns false if it didn't have the tag.
+    pub async fn remove_tag(&self, id: &str, tag: &str) -> Result<bool> {
+        let conn = self.db.get().await?;
+        let removed = conn.execute(
+            "DELETE FROM session_tags WHERE session_id = ?1 AND tag = ?2",
+            params![id, tag],
+        )?;
+        Ok(removed > 0)
+    }
+
+    /// A session's tags, alphabetically
+    pub async fn tags(&self, id: &str) -> Result<Vec<String>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare("SELECT tag FROM session_tags WHERE session_id = ?1 ORDER BY tag")?;
+
+        let tags = stmt.query_map(params![id], |row| row.get(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Failed to collect session tags")?;
+        Ok(tags)
+    }
+
+    /// Tags for each of `ids` that has any, alphabetically
+    pub async fn tags_for(&self, ids: &[&str]) -> Result<HashMap<String, Vec<String>>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT session_id, tag FROM session_tags
+             WHERE session_id IN (SELECT value FROM json_each(?1))
+             ORDER BY tag"
+        )?;
+
+        let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+        let rows = stmt.query_map(params![serde_json::json!(ids).to_string()], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (session_id, tag) = row.context("Failed to collect session tags")?;
+            tags.entry(session_id).or_default().push(tag);
+        }
+        Ok(tags)
+    }
+
+    /// Sessions carrying `tag`, newest first
+    pub async fn list_by_tag(&self, tag: &str) -> Result<Vec<Session>> {
+        let tags = [tag.to_string()];
+        let filter = SessionFilter { tags: &tags, ..SessionFilter::default() };
+        let (sessions, _) = self.list_filtered(&filter, None, 0).await?;
+        Ok(sessions)
+    }
+
+    /// Record the name the agent was spawned with
+    pub async fn set_name(&self, id: &str, name: &str) -> Result<()> {
+        let conn = self.db.get().await?;
+        let now = Utc::now().to_rfc3339();
+
+        conn.execute(
+            "UPDATE sessions SET name = ?1, updated_at = ?2 WHERE id = ?3",
+            params![name, now, id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Move a session to another working directory
+    pub async fn set_working_dir(&self, id: &str, working_dir: &str) -> Result<()> {
+        let conn = self.db.get().await?;
+        let now = Utc::now().to_rfc3339();
+
+        conn.execute(
+            "UPDATE sessions SET working_dir = ?1, updated_at = ?2 WHERE id = ?3",
+            params![working_dir, now, id],
+        )?;
+
+        Ok(())
+    }
+
+    /// The most recently created session with this name
+    pub async fn get_by_name(&self, name: &str) -> Result<Option<Session>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT id, project_id, agent_type, session_type, status, working_dir,
+                    opencode_session_id, created_at, updated_at, metadata, forked_from, parent_session_id, name
+             FROM sessions WHERE name = ?1
+             ORDER BY created_at DESC LIMIT 1"
+        )?;
+
+        match stmt.query_row(params![name], Self::map_row) {
+            Ok(session) => Ok(Some(session)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e).context("Failed to get session by name"),
+        }
+    }
+
+    /// Claim `key` for this session so repeated spawns with the same key
+    /// find it. Returns false if another session already holds the key.
+    pub async fn set_idempotency_key(&self, id: &str, key: &str) -> Result<bool> {
+        let conn = self.db.get().await?;
+
+        match conn.execute(
+            "UPDATE sessions SET idempotency_key = ?1 WHERE id = ?2",
+            params![key, id],
+        ) {
+            Ok(_) => Ok(true),
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::ConstraintViolation => Ok(false),
+            Err(e) => Err(e).context("Failed to set idempotency key"),
+        }
+    }
+
+    /// The idempotency key a session was created with, if any
+    pub async fn idempotency_key(&self, id: &str) -> Result<Option<String>> {
+        let conn = self.db.get().await?;
+        match conn.query_row("SELECT idempotency_key FROM sessions WHERE id = ?1", params![id], |row| row.get(0)) {
+            Ok(key) => Ok(key),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e).context("Failed to get idempotency key"),
+        }
+    }
+
+    /// The session created with this idempotency key, if any
+    pub async fn find_by_idempotency_key(&self, key: &str) -> Result<Option<Session>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT id, project_id, agent_type, session_type, status, working_dir,
+                    opencode_session_id, created_at, updated_at, metadata, forked_from, parent_session_id, name
+             FROM sessions WHERE idempotency_key = ?1"
+        )?;
+
+        match stmt.query_row(params![key], Self::map_row) {
+            Ok(session) => Ok(Some(session)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e).context("Failed to look up idempotency key"),
+        }
+    }
+
+    /// Sessions forked or spawned directly under `parent_id`, oldest first
+    pub async fn children(&self, parent_id: &str) -> Result<Vec<Session>> {
+        self.list_by_parent(Some(parent_id)).await
+    }
+
+    /// Sessions with no parent, oldest first
+    pub async fn roots(&self) -> Result<Vec<Session>> {
+        self.list_by_parent(None).await
+    }
+
+    async fn list_by_parent(&self, parent_id: Option<&str>) -> Result<Vec<Session>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT id, project_id, agent_type, session_type, status, working_dir,
+                    opencode_session_id, created_at, updated_at, metadata, forked_from, parent_session_id, name
+             FROM sessions WHERE parent_session_id IS ?1
+             ORDER BY created_at ASC"
+        )?;
+
+        let sessions = stmt.query_map(params![parent_id], Self::map_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Failed to collect sessions")?;
+        Ok(sessions)
+    }
+
+    /// The session `id` with all of its descendants
+    pub async fn tree(&self, id: &str) -> Result<Option<SessionTree>> {
+        let Some(root) = self.get(id).await? else {
+            return Ok(None);
+        };
+
+        // Load each level of descendants, skipping anything seen already in
+        // case a hand-edited row made a cycle
+        let mut children: HashMap<String, Vec<Session>> = HashMap::new();
+        let mut seen = HashSet::from([root.id.clone()]);
+        let mut queue = vec![root.id.clone()];
+        while let Some(parent) = queue.pop() {
+            let kids: Vec<Session> = self.children(&parent).await?
+                .into_iter()
+                .filter(|s| seen.insert(s.id.clone()))
+                .collect();
+            queue.extend(kids.iter().map(|s| s.id.clone()));
+            children.insert(parent, kids);
+        }
+
+        Ok(Some(assemble_tree(root, &mut children)))
+    }
+
+    /// Deep-merge a JSON object into the session's metadata and return the result.
+    /// Nested objects are merged key by key; `null` values remove keys.
+    pub async fn merge_metadata(&self, id: &str, patch: serde_json::Value) -> Result<Option<serde_json::Value>> {
+        if !patch.is_object() {
+            anyhow::bail!("Metadata patch must be a JSON object");
+        }
+
+        let mut conn = self.db.get().await?;
+        // Take the write lock before reading, so concurrent merges from
+        // other connections or processes can't drop each other's keys
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)
+            .context("Failed to start metadata update")?;
+
+        let existing: Option<String> = match tx.query_row(
+            "SELECT metadata FROM sessions WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        ) {
+            Ok(metadata) => metadata,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(e) => return Err(e).context("Failed to read session metadata"),
+        };
+
+        let mut metadata = match existing {
+            Some(raw) => serde_json::from_str(&raw)
+                .context("Existing session metadata is not valid JSON")?,
+            None => serde_json::Value::Object(serde_json::Map::new()),
+        };
+        merge_json(&mut metadata, patch);
+
+        let now = Utc::now().to_rfc3339();
+        tx.execute(
+            "UPDATE sessions SET metadata = ?1, updated_at = ?2 WHERE id = ?3",
+            params![metadata.to_string(), now, id],
+        )?;
+        tx.commit().context("Failed to save session metadata")?;
+
+        tracing::debug!("Updated metadata for session {}", id);
+        Ok(Some(metadata))
+    }
+
+    /// Persist an activity snapshot for a session
+    pub async fn record_activity(&self, activity: &SessionActivity) -> Result<()> {
+        let conn = self.db.get().await?;
+
+        conn.execute(
+            "INSERT INTO activity (session_id, state, last_message, last_response, state_changed_at,
+                                   approval_type, approval_description)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                activity.session_id,
+                activity.state.as_str(),
+                activity.last_message,
+                activity.last_response,
+                activity.state_changed_at.to_rfc3339(),
+                activity.approval_type.map(|t| t.as_str()),
+                activity.approval_description,
+            ],
+        ).context("Failed to insert activity")?;
+
+        // Activity counts as an update, so listing by updated_at puts busy sessions first
+        conn.execute(
+            "UPDATE sessions SET updated_at = ?1 WHERE id = ?2",
+            params![Utc::now().to_rfc3339(), activity.session_id],
+        ).context("Failed to update session")?;
+
+        tracing::debug!("Session {} is now {}", activity.session_id, activity.state.as_str());
+        Ok(())
+    }
+
+    /// Get the most recent activity snapshot for a session
+    pub async fn latest_activity(&self, session_id: &str) -> Result<Option<SessionActivity>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT session_id, state, last_message, last_response, state_changed_at,
+                    approval_type, approval_description
+             FROM activity WHERE session_id = ?1
+             ORDER BY id DESC LIMIT 1"
+        )?;
+
+        match stmt.query_row(params![session_id], Self::map_activity_row) {
+            Ok(activity) => Ok(Some(activity)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e).context("Failed to get activity"),
+        }
+    }
+
+    /// ID of the newest activity snapshot (0 when there are none)
+    pub async fn latest_activity_id(&self) -> Result<i64> {
+        let conn = self.db.get().await?;
+        let id = conn.query_row("SELECT COALESCE(MAX(id), 0) FROM activity", [], |row| row.get(0))?;
+        Ok(id)
+    }
+
+    /// Activity snapshots recorded after `after_id`, oldest first
+    pub async fn activity_after(&self, after_id: i64) -> Result<Vec<ActivityChange>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT a.session_id, a.state, a.last_message, a.last_response, a.state_changed_at,
+                    a.approval_type, a.approval_description, a.id,
+                    (SELECT p.state FROM activity p
+                     WHERE p.session_id = a.session_id AND p.id < a.id
+                     ORDER BY p.id DESC LIMIT 1)
+             FROM activity a
+             WHERE a.id > ?1
+             ORDER BY a.id ASC"
+        )?;
+
+        let changes = stmt.query_map(params![after_id], |row| {
+            Ok(ActivityChange {
+                activity: Self::map_activity_row(row)?,
+                id: row.get(7)?,
+                previous_state: row.get::<_, Option<String>>(8)?
+                    .and_then(|state| AgentState::from_str(&state).ok()),
+            })
+        })?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Failed to collect activity")?;
+
+        Ok(changes)
+    }
+
+    /// List the latest activity of every session currently waiting for approval
+    pub async fn list_blocked(&self) -> Result<Vec<SessionActivity>> {
+        let conn = self.db.get().await?;
+        let mut stmt = conn.prepare(
+            "SELECT a.session_id, a.state, a.last_message, a.last_response, a.state_changed_at,
+                    a.approval_type, a.approval_description
+             FROM activity a
+             WHERE a.id = (SELECT MAX(id) FROM activity WHERE session_id = a.session_id)
+               AND a.state = ?1
+             ORDER BY a.state_changed_at ASC"
+        )?;
+
+        let blocked = stmt.query_map(params![AgentState::WaitingForApproval.as_str()], Self::map_activity_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Failed to collect blocked sessions")?;
+
+        Ok(blocked)
+    }
+
+    fn map_activity_row(row: &rusqlite::Row) -> rusqlite::Result<SessionActivity> {
+        Ok(SessionActivity {
+            session_id: row.get(0)?,
+            state: AgentState::from_str(&row.get::<_, String>(1)?).unwrap_or(AgentState::Idle),
+            last_message: row.get(2)?,
+            last_response: row.get(3)?,
+            state_changed_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(4)?)
+                .map(|dt| dt.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now()),
+            approval_type: row.get::<_, Option<String>>(5)?
+                .and_then(|t| ApprovalType::from_str(&t).ok()),
+            approval_description: row.get(6)?,
+        })
+    }
+
     /// Delete a session
     pub async fn delete(&self, id: &str) -> Result<()> {
-        let conn = self.db.lock().await;
+        let conn = self.db.get().await?;
         conn.execute("DELETE FROM sessions WHERE id = ?1", params![id])?;
         tracing::debug!("Deleted session: {}", id);
         Ok(())
     }
 
+    /// Delete sessions created before `older_than`, optionally restricted to a status.
+    /// Returns the number of deleted rows.
+    pub async fn delete_where(
+        &self,
+        status: Option<SessionStatus>,
+        older_than: DateTime<Utc>,
+    ) -> Result<usize> {
+        let conn = self.db.get().await?;
+        let cutoff = older_than.to_rfc3339();
+
+        let deleted = match status {
+            Some(st) => conn.execute(
+                "DELETE FROM sessions WHERE status = ?1 AND created_at < ?2",
+                params![st.as_str(), cutoff],
+            )?,
+            None => conn.execute(
+                "DELETE FROM sessions WHERE created_at < ?1",
+                params![cutoff],
+            )?,
+        };
+
+        tracing::debug!("Deleted {} sessions older than {}", deleted, cutoff);
+        Ok(deleted)
+    }
+
     fn map_row(row: &rusqlite::Row) -> rusqlite::Result<Session> {
         Ok(Session {
             id: row.get(0)?,
@@ -310,6 +1123,33 @@ impl SessionRepository {
                 .map(|dt| dt.with_timezone(&Utc))
                 .unwrap_or_else(|_| Utc::now()),
             metadata: row.get(9)?,
+            forked_from: row.get(10)?,
+            parent_session_id: row.get(11)?,
+            name: row.get(12)?,
         })
     }
 }
+
+fn assemble_tree(session: Session, children: &mut HashMap<String, Vec<Session>>) -> SessionTree {
+    let kids = children.remove(&session.id).unwrap_or_default();
+    SessionTree {
+        children: kids.into_iter().map(|kid| assemble_tree(kid, children)).collect(),
+        session,
+    }
+}
+
+/// Recursively merge `patch` into `target`
+fn merge_json(target: &mut serde_json::Value, patch: serde_json::Value) {
+    match (target, patch) {
+        (serde_json::Value::Object(target), serde_json::Value::Object(patch)) => {
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(&key);
+                } else {
+                    merge_json(target.entry(key).or_insert(serde_json::Value::Null), value);
+                }
+            }
+        }
+        (target, patch) => *target = patch,
+    }
+}
@@ -12,7 +12,11 @@ CREATE TABLE IF NOT EXISTS sessions (
     opencode_session_id TEXT,
     created_at TEXT NOT NULL,
     updated_at TEXT NOT NULL,
-    metadata TEXT
+    metadata TEXT,
+    forked_from TEXT,
+    parent_session_id TEXT,
+    idempotency_key TEXT,
+    name TEXT
 );
 
 -- Projects table
@@ -47,8 +51,103 @@ CREATE TABLE IF NOT EXISTS agent_configs (
     updated_at TEXT NOT NULL
 );
 
+-- Session activity snapshots
+CREATE TABLE IF NOT EXISTS activity (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id TEXT NOT NULL,
+    state TEXT NOT NULL,
+    last_message TEXT,
+    last_response TEXT,
+    state_changed_at TEXT NOT NULL,
+    approval_type TEXT,
+    approval_description TEXT,
+    FOREIGN KEY (session_id) REFERENCES sessions(id) ON DELETE CASCADE
+);
+
+-- Free-form session tags
+CREATE TABLE IF NOT EXISTS session_tags (
+    session_id TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (session_id, tag),
+    FOREIGN KEY (session_id) REFERENCES sessions(id) ON DELETE CASCADE
+);
+
+-- Messages for peers, kept until delivered
+CREATE TABLE IF NOT EXISTS peer_messages (
+    id TEXT PRIMARY KEY,
+    peer_name TEXT NOT NULL,
+    message_type TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    from_node TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    signature TEXT,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    claimed_at TEXT,
+    delivered_at TEXT
+);
+
+-- Ids of messages taken from peers, so a redelivered one is recognized
+CREATE TABLE IF NOT EXISTS peer_messages_received (
+    peer_name TEXT NOT NULL,
+    id TEXT NOT NULL,
+    received_at TEXT NOT NULL,
+    PRIMARY KEY (peer_name, id)
+);
+
+-- Named jobs only one process may run at a time, held until expires_at
+CREATE TABLE IF NOT EXISTS leases (
+    name TEXT PRIMARY KEY,
+    holder TEXT NOT NULL,
+    expires_at TEXT NOT NULL
+);
+
 -- Indexes
 CREATE INDEX IF NOT EXISTS idx_sessions_project_id ON sessions(project_id);
 CREATE INDEX IF NOT EXISTS idx_sessions_status ON sessions(status);
 CREATE INDEX IF NOT EXISTS idx_messages_session_id ON messages(session_id);
+CREATE INDEX IF NOT EXISTS idx_activity_session_id ON activity(session_id);
+CREATE INDEX IF NOT EXISTS idx_session_tags_tag ON session_tags(tag);
+CREATE INDEX IF NOT EXISTS idx_peer_messages_peer ON peer_messages(peer_name, delivered_at);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_agent_configs_name ON agent_configs(name);
+"#;
+
+/// Columns added after their table first shipped: (table, column, definition).
+/// Applied to existing databases that predate them.
+pub const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("activity", "approval_type", "TEXT"),
+    ("activity", "approval_description", "TEXT"),
+    ("sessions", "forked_from", "TEXT"),
+    ("sessions", "parent_session_id", "TEXT"),
+    ("sessions", "idempotency_key", "TEXT"),
+    ("sessions", "name", "TEXT"),
+];
+
+/// Indexes over `ADDED_COLUMNS`, created once those columns exist
+pub const ADDED_INDEXES: &str = r#"
+CREATE UNIQUE INDEX IF NOT EXISTS idx_sessions_idempotency_key ON sessions(idempotency_key);
+CREATE INDEX IF NOT EXISTS idx_sessions_name ON sessions(name);
+"#;
+
+/// Full-text index over message content, kept in step with `messages` by
+/// triggers. Existing messages are indexed when the table is first created.
+pub const MESSAGE_SEARCH: &str = r#"
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+    content,
+    content = 'messages',
+    content_rowid = 'rowid'
+);
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages BEGIN
+    INSERT INTO messages_fts (rowid, content) VALUES (new.rowid, new.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_update AFTER UPDATE OF content ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+    INSERT INTO messages_fts (rowid, content) VALUES (new.rowid, new.content);
+END;
 "#;
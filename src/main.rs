@@ -18,7 +18,8 @@ fn main() -> Result<()> {
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "supercode=debug,info".into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        // Logs go to stderr so stdout stays clean for the stdio transport
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .init();
 
     tracing::info!("Starting Supercode v{}", env!("CARGO_PKG_VERSION"));
@@ -0,0 +1,143 @@
+//! HTTP endpoint serving Prometheus metrics
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+use super::server::{drain, SHUTDOWN_GRACE};
+use crate::session::SessionManager;
+
+/// How long a scraper gets to send its request line and headers
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest request or header line accepted
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+
+/// Serves `GET /metrics` for a Prometheus scraper
+pub struct MetricsServer {
+    host: String,
+    port: u16,
+    session_manager: Arc<SessionManager>,
+}
+
+impl MetricsServer {
+    pub fn new(port: u16, session_manager: Arc<SessionManager>) -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port,
+            session_manager,
+        }
+    }
+
+    /// Bind to `host` instead of localhost
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = host.into();
+        self
+    }
+
+    /// Serve until `shutdown` completes
+    pub async fn start_until(&self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let listener = TcpListener::bind((self.host.as_str(), self.port)).await
+            .with_context(|| format!("Failed to bind metrics server to {}:{}", self.host, self.port))?;
+
+        info!("Metrics server listening on {}", listener.local_addr()?);
+
+        let mut connections = JoinSet::new();
+        tokio::pin!(shutdown);
+
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = &mut shutdown => break,
+                Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            };
+
+            match accepted {
+                Ok((stream, _)) => {
+                    let session_manager = self.session_manager.clone();
+                    connections.spawn(async move {
+                        if let Err(e) = Self::handle_scrape(stream, &session_manager).await {
+                            warn!("Error serving metrics: {}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    warn!("Error accepting metrics connection: {}", e);
+                }
+            }
+        }
+
+        drop(listener);
+        drain(connections, SHUTDOWN_GRACE).await;
+        Ok(())
+    }
+
+    /// Answer one request and close the connection
+    async fn handle_scrape(stream: TcpStream, session_manager: &SessionManager) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        // A client that never finishes its request can't hold the task
+        let request_line = tokio::time::timeout(REQUEST_TIMEOUT, Self::read_request(&mut reader))
+            .await
+            .map_err(|_| anyhow::anyhow!("timed out reading the request"))??;
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let path = parts.next().unwrap_or_default();
+        let path = path.split('?').next().unwrap_or_default();
+
+        let (status, content_type, body) = if method == "GET" && path == "/metrics" {
+            match session_manager.session_gauges().await {
+                Ok(gauges) => (
+                    "200 OK",
+                    "text/plain; version=0.0.4",
+                    session_manager.metrics().render(&gauges),
+                ),
+                Err(e) => ("500 Internal Server Error", "text/plain", format!("Failed to read sessions: {}\n", e)),
+            }
+        } else {
+            ("404 Not Found", "text/plain", "Not found\n".to_string())
+        };
+
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        );
+        writer.write_all(response.as_bytes()).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Read the request line, skipping the headers; the request has no body
+    /// worth reading
+    async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<String> {
+        let request_line = Self::read_line(reader).await?;
+        loop {
+            let line = Self::read_line(reader).await?;
+            if line.is_empty() || line.trim().is_empty() {
+                break;
+            }
+        }
+        Ok(request_line)
+    }
+
+    /// Read one line of at most `MAX_LINE_LENGTH` bytes ("" at end of stream)
+    async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<String> {
+        let mut line = String::new();
+        (&mut *reader).take(MAX_LINE_LENGTH).read_line(&mut line).await?;
+        if line.len() as u64 == MAX_LINE_LENGTH && !line.ends_with('\n') {
+            anyhow::bail!("request line longer than {} bytes", MAX_LINE_LENGTH);
+        }
+        Ok(line)
+    }
+}
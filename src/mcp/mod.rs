@@ -1,9 +1,13 @@
 //! MCP server module
 
+pub mod metrics_server;
 pub mod peer_server;
+pub mod rate_limit;
 pub mod server;
 pub mod types;
 
+pub use metrics_server::MetricsServer;
 pub use server::McpServer;
 pub use peer_server::PeerServer;
+pub use rate_limit::RateLimit;
 pub use types::*;
@@ -1,21 +1,34 @@
 //! Peer server for handling incoming peer connections
 
+use std::future::Future;
 use std::sync::Arc;
 
 use anyhow::Result;
 use chrono::Utc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::RwLock;
+use tokio::task::JoinSet;
 use tracing::{error, info, warn};
 
-use crate::config::{Config, PeerHandshake, PeerHandshakeResponse, PeerManager, PeerRequest};
+use super::server::{call_tool_text, drain, SHUTDOWN_GRACE};
+use crate::config::peer::{ACK, LIST_SESSIONS, LIST_SESSIONS_RESULT, SPAWN_SESSION, SPAWN_SESSION_RESULT};
+use crate::session::SessionManager;
+use crate::db::repositories::peer_message::PeerMessageRepository;
+use crate::config::{keygen, Config, PeerChallenge, PeerChallengeResponse, PeerConnection, PeerHandshake, PeerHandshakeResponse, PeerManager, PeerRequest};
 
 /// Peer server that handles incoming peer connections
 pub struct PeerServer {
     port: u16,
     config: Arc<RwLock<Config>>,
+    /// Config file the shared config was loaded from (None: default location)
+    config_path: Option<String>,
     peer_manager: Arc<RwLock<Option<PeerManager>>>,
+    /// Messages queued for peers, delivered when a peer connects again
+    outbox: Option<PeerMessageRepository>,
+    /// Runs sessions peers ask for (None: spawn requests are refused)
+    session_manager: Option<Arc<SessionManager>>,
 }
 
 impl PeerServer {
@@ -23,25 +36,64 @@ impl PeerServer {
         Self {
             port,
             config,
+            config_path: None,
             peer_manager: Arc::new(RwLock::new(None)),
+            outbox: None,
+            session_manager: None,
         }
     }
 
+    /// Spawn sessions peers ask for through `session_manager`
+    pub fn with_session_manager(mut self, session_manager: Arc<SessionManager>) -> Self {
+        self.session_manager = Some(session_manager);
+        self
+    }
+
+    /// Deliver messages queued in `outbox` to peers as they reconnect
+    pub fn with_outbox(mut self, outbox: PeerMessageRepository) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
+    /// Persist state next to this config file instead of the default one
+    pub fn with_config_path(mut self, path: impl Into<String>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
     /// Start the peer server
     pub async fn start(&self) -> Result<()> {
+        self.start_until(std::future::pending()).await
+    }
+
+    /// Start the peer server and run until `shutdown` completes, then stop
+    /// accepting peers and close open peer connections after a grace period
+    pub async fn start_until(&self, shutdown: impl Future<Output = ()>) -> Result<()> {
         let addr = format!("0.0.0.0:{}", self.port);
         let listener = TcpListener::bind(&addr).await?;
         
         info!("Peer server listening on {}", addr);
 
+        let mut connections = JoinSet::new();
+        tokio::pin!(shutdown);
+
         loop {
-            match listener.accept().await {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = &mut shutdown => break,
+                Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            };
+
+            match accepted {
                 Ok((stream, addr)) => {
                     let config = self.config.clone();
+                    let config_path = self.config_path.clone();
                     let peer_manager = self.peer_manager.clone();
+                    let outbox = self.outbox.clone();
+                    let session_manager = self.session_manager.clone();
                     
-                    tokio::spawn(async move {
-                        if let Err(e) = Self::handle_peer_connection(stream, addr, config, peer_manager).await {
+                    connections.spawn(async move {
+                        if let Err(e) = Self::handle_peer_connection(stream, addr, config, config_path, peer_manager, outbox, session_manager).await {
                             error!("Error handling peer connection from {}: {}", addr, e);
                         }
                     });
@@ -51,6 +103,11 @@ impl PeerServer {
                 }
             }
         }
+
+        drop(listener);
+        info!("Peer server stopped accepting connections");
+        drain(connections, SHUTDOWN_GRACE).await;
+        Ok(())
     }
 
     /// Handle an incoming peer connection
@@ -58,7 +115,10 @@ impl PeerServer {
         stream: TcpStream,
         addr: std::net::SocketAddr,
         config: Arc<RwLock<Config>>,
+        config_path: Option<String>,
         peer_manager: Arc<RwLock<Option<PeerManager>>>,
+        outbox: Option<PeerMessageRepository>,
+        session_manager: Option<Arc<SessionManager>>,
     ) -> Result<()> {
         let (reader, mut writer) = stream.into_split();
         let mut reader = BufReader::new(reader);
@@ -70,6 +130,12 @@ impl PeerServer {
         let handshake: PeerHandshake = serde_json::from_str(&line.trim())?;
         info!("Received peer handshake from {} ({})", handshake.name, addr.ip());
 
+        // The config file is the source of truth for peers: pick up any
+        // accepts, removals or verifications made by the CLI since startup
+        if let Err(e) = config.write().await.reload_peers(config_path.as_deref()) {
+            warn!("Failed to reload peers from config: {}", e);
+        }
+
         // Check if we have a config with keys
         let config_guard = config.read().await;
         
@@ -80,6 +146,7 @@ impl PeerServer {
         // Also accept if it's a new request (no existing peer)
         let is_new_request = peer_config.is_none();
 
+        let mut session_key = None;
         let response = if !config_guard.can_peer() {
             // We don't have keys, can't peer
             PeerHandshakeResponse {
@@ -101,7 +168,9 @@ impl PeerServer {
             // Store pending request
             drop(config_guard);
             let mut config_guard = config.write().await;
-            config_guard.add_pending_request(request);
+            if let Err(e) = config_guard.store_pending_request(request, config_path.as_deref()) {
+                warn!("Failed to persist pending peer request from {}: {}", handshake.name, e);
+            }
             drop(config_guard);
 
             PeerHandshakeResponse {
@@ -110,13 +179,45 @@ impl PeerServer {
                 public_key: config.read().await.public_key.clone(),
             }
         } else if auth_valid {
-            // Existing peer with valid auth
-            info!("Accepted peer connection from {}", handshake.name);
-            
-            PeerHandshakeResponse {
-                accepted: true,
-                message: "Connected successfully".to_string(),
-                public_key: config_guard.public_key.clone(),
+            // Existing peer with valid auth: it must also prove it owns the
+            // key we have on record. Don't hold the config lock over the
+            // challenge round trip or the connection that follows.
+            let private_key = config_guard.private_key.clone();
+            let public_key = config_guard.public_key.clone();
+            let peer_key = peer_config.map(|p| p.public_key.clone()).unwrap_or_default();
+            drop(config_guard);
+
+            if peer_key.is_empty() {
+                warn!("Peer connection rejected: no public key on record for {}", handshake.name);
+                PeerHandshakeResponse {
+                    accepted: false,
+                    message: "No public key on record for this node; ask to be re-added as a peer".to_string(),
+                    public_key: String::new(),
+                }
+            } else {
+                let nonce = keygen::generate_nonce();
+                send_json(&mut writer, &PeerChallenge { nonce: nonce.clone(), public_key: public_key.clone() }).await?;
+
+                line.clear();
+                reader.read_line(&mut line).await?;
+                let answer: PeerChallengeResponse = serde_json::from_str(line.trim())?;
+
+                if keygen::verify_handshake_proof(&private_key, &peer_key, &nonce, &handshake.name, &answer.proof)? {
+                    info!("Accepted peer connection from {}", handshake.name);
+                    session_key = Some(keygen::session_key(&private_key, &peer_key, &nonce)?);
+                    PeerHandshakeResponse {
+                        accepted: true,
+                        message: "Connected successfully".to_string(),
+                        public_key,
+                    }
+                } else {
+                    warn!("Peer connection rejected: bad handshake proof from {}", handshake.name);
+                    PeerHandshakeResponse {
+                        accepted: false,
+                        message: "Handshake proof did not match the public key on record".to_string(),
+                        public_key: String::new(),
+                    }
+                }
             }
         } else {
             // Auth failed
@@ -129,21 +230,132 @@ impl PeerServer {
         };
 
         // Send response
-        let response_json = serde_json::to_string(&response)?; 
-        writer.write_all(response_json.as_bytes()).await?;
-        writer.write_all(b"\n").await?;
-        writer.flush().await?;
+        send_json(&mut writer, &response).await?;
+
+        if !response.accepted {
+            return Ok(());
+        }
+
+        info!("Successfully established peer connection with {}", handshake.name);
 
-        if response.accepted {
-            info!("Successfully established peer connection with {}", handshake.name);
+        // The peer is reachable again: hand over anything queued for it
+        // through its own peer server, without holding up this connection
+        if let Some(outbox) = outbox.clone() {
+            let manager = PeerManager::new(config.read().await.clone()).with_outbox(outbox);
+            let peer_name = handshake.name.clone();
+            tokio::spawn(async move {
+                if let Err(e) = manager.flush_outbox(&peer_name).await {
+                    warn!("Failed to deliver queued messages to peer {}: {}", peer_name, e);
+                }
+            });
+        }
+
+        // Keep the connection open and take messages until the peer hangs up
+        let remote_public_key = handshake.public_key.clone();
+        let mut connection = PeerConnection::from_parts(handshake.name, reader, writer, remote_public_key);
+        if let Some(key) = session_key {
+            connection = connection.with_session_key(key);
+        }
+        loop {
+            match connection.recv().await {
+                Ok(msg) => {
+                    if msg.message_type == ACK {
+                        continue;
+                    }
+                    info!("Message {} ({}) from peer {}", msg.id, msg.message_type, msg.from);
+
+                    // Acknowledge before acting, so the sender stops
+                    // queueing it; a redelivery is only acknowledged again
+                    let first_delivery = match &outbox {
+                        Some(outbox) => outbox.record_received(&connection.name, &msg.id).await?,
+                        None => true,
+                    };
+                    let local_name = config.read().await.name.clone();
+                    connection.send(msg.reply(ACK, "", local_name.clone())).await?;
+                    if !first_delivery {
+                        info!("Ignored redelivered message {} from peer {}", msg.id, connection.name);
+                        continue;
+                    }
+
+                    let request = match msg.message_type.as_str() {
+                        SPAWN_SESSION => Some(("spawn_session", SPAWN_SESSION_RESULT)),
+                        LIST_SESSIONS => Some(("list_sessions", LIST_SESSIONS_RESULT)),
+                        _ => None,
+                    };
+                    if let Some((tool, reply_type)) = request {
+                        let payload = match Self::refusal(tool, &connection.name, &config, config_path.as_deref()).await {
+                            Some(reason) => json!({ "error": reason }).to_string(),
+                            None => Self::handle_tool_request(tool, &msg.payload, session_manager.as_ref()).await,
+                        };
+                        connection.send(msg.reply(reply_type, payload, local_name)).await?;
+                    }
+                }
+                Err(e) => {
+                    info!("Peer connection with {} ended: {}", connection.name, e);
+                    break;
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Why `peer_name` may not run `tool` here, if it may not. Only peers
+    /// marked verified get answers, and spawns need `accept_peer_spawns`.
+    /// Checked per request, so `peer verify` and removals apply to open
+    /// connections too.
+    async fn refusal(tool: &str, peer_name: &str, config: &RwLock<Config>, config_path: Option<&str>) -> Option<String> {
+        if let Err(e) = config.write().await.reload_peers(config_path) {
+            warn!("Failed to reload peers from config: {}", e);
+        }
+        let config = config.read().await;
+
+        if !config.get_peer(peer_name).is_some_and(|peer| peer.verified) {
+            warn!("Refused {} from unverified peer {}", tool, peer_name);
+            return Some(format!("Peer {} is not verified on this node", peer_name));
+        }
+        if tool == "spawn_session" && !config.accept_peer_spawns {
+            warn!("Refused spawn_session from peer {}: accept_peer_spawns is off", peer_name);
+            return Some("This node doesn't accept spawn requests from peers (accept_peer_spawns is off)".to_string());
+        }
+        None
+    }
+
+    /// Run `tool` for a peer, returning the reply payload
+    async fn handle_tool_request(tool: &str, payload: &str, session_manager: Option<&Arc<SessionManager>>) -> String {
+        let Some(session_manager) = session_manager else {
+            return json!({ "error": "This node doesn't serve session requests from peers" }).to_string();
+        };
+        let arguments: serde_json::Value = match serde_json::from_str(payload) {
+            Ok(arguments) => arguments,
+            Err(e) => return json!({ "error": format!("Invalid {} request: {}", tool, e) }).to_string(),
+        };
+        // One hop only: a peer can't route a spawn on through this node
+        if arguments.get("remote_peer").is_some() {
+            return json!({ "error": "Requests from peers can't name a remote_peer" }).to_string();
+        }
+
+        match call_tool_text(tool, arguments, session_manager).await {
+            Ok(result) => {
+                let result = serde_json::from_str(&result).unwrap_or(serde_json::Value::String(result));
+                json!({ "result": result }).to_string()
+            }
+            Err(e) => json!({ "error": e.to_string() }).to_string(),
+        }
+    }
+
     /// Update the peer manager
     pub async fn set_peer_manager(&self, manager: PeerManager) {
         let mut pm = self.peer_manager.write().await;
         *pm = Some(manager);
     }
 }
+
+/// Write one line of JSON to a peer
+async fn send_json<W: AsyncWrite + Unpin, T: serde::Serialize>(writer: &mut W, value: &T) -> Result<()> {
+    let json = serde_json::to_string(value)?;
+    writer.write_all(json.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await?;
+    Ok(())
+}
@@ -1,70 +1,269 @@
 //! MCP server
 
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::task::JoinSet;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use serde_json::json;
 
+use super::rate_limit::{RateLimit, TokenBucket};
 use super::types::*;
+use crate::db::repositories::session::SessionActivity;
+use crate::session::ProviderError;
 
 pub struct McpServer {
+    host: String,
     port: u16,
     session_manager: Arc<crate::session::SessionManager>,
+    /// Tool call limit applied to each connection (None: unlimited)
+    rate_limit: Option<RateLimit>,
+}
+
+/// How often a running `send_message` is checked for agent state changes
+const PROGRESS_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Method of the notifications pushed while `send_message` runs
+pub const SESSION_PROGRESS: &str = "notifications/session/progress";
+
+/// How long `/healthz` waits on each provider before calling it down
+const HEALTHZ_PROVIDER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Matches `search_messages` returns when no limit is given
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+/// Sessions `list_remote_sessions` returns when not given a limit
+const DEFAULT_REMOTE_LIST_LIMIT: u64 = 100;
+
+/// How long a stopping server waits for in-flight connections
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Wait up to `grace` for spawned connection handlers to finish, then abort
+/// whatever is left (e.g. idle keep-alive connections)
+pub(crate) async fn drain(mut connections: JoinSet<()>, grace: Duration) {
+    let finished = tokio::time::timeout(grace, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+
+    if finished.is_err() {
+        tracing::warn!("Aborting {} connection(s) still open after {:?}", connections.len(), grace);
+        connections.shutdown().await;
+    }
 }
 
 impl McpServer {
     pub fn new(port: u16, session_manager: Arc<crate::session::SessionManager>) -> Self {
-        Self { port, session_manager }
+        Self { host: "127.0.0.1".to_string(), port, session_manager, rate_limit: None }
+    }
+
+    /// Bind to `host` instead of loopback
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = host.into();
+        self
+    }
+
+    /// Limit how fast each connection may call tools; calls over the limit
+    /// are answered with a "rate limited" error without running
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
     }
 
     pub async fn run(&self) -> Result<()> {
-        let addr = format!("127.0.0.1:{}", self.port);
-        let listener = TcpListener::bind(&addr).await?;
-        
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Serve until `shutdown` completes, then stop accepting connections and
+    /// give in-flight requests `SHUTDOWN_GRACE` to finish
+    pub async fn run_until(&self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let listener = TcpListener::bind((self.host.as_str(), self.port)).await
+            .with_context(|| format!("Failed to bind MCP server to {}:{}", self.host, self.port))?;
+        let addr = listener.local_addr()?;
+
         tracing::info!("MCP server listening on {}", addr);
+        if !addr.ip().is_loopback() {
+            tracing::warn!("MCP server is reachable beyond this machine on {}; it has no authentication", addr);
+        }
+
+        let mut connections = JoinSet::new();
+        tokio::pin!(shutdown);
 
         loop {
-            let (stream, addr) = listener.accept().await?;
+            let (stream, addr) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = &mut shutdown => break,
+                // Reap finished handlers so the set doesn't grow unbounded
+                Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            };
             tracing::debug!("Accepted connection from {}", addr);
             
             let session_manager = self.session_manager.clone();
-            tokio::spawn(async move {
-                if let Err(e) = Self::handle_connection(stream, session_manager).await {
+            let bucket = self.rate_limit.map(TokenBucket::new);
+            connections.spawn(async move {
+                if let Err(e) = Self::handle_connection(stream, session_manager, bucket).await {
                     tracing::error!("Error handling connection: {}", e);
                 }
             });
         }
+
+        drop(listener);
+        tracing::info!("MCP server stopped accepting connections");
+        drain(connections, SHUTDOWN_GRACE).await;
+        Ok(())
     }
 
-    async fn handle_connection(mut stream: TcpStream, session_manager: Arc<crate::session::SessionManager>) -> Result<()> {
-        let mut buffer = vec![0u8; 8192];
-        
-        loop {
-            // Read request
-            let n = stream.read(&mut buffer).await?;
-            if n == 0 {
-                break;
+    /// Serve newline-delimited JSON-RPC over stdin/stdout
+    pub async fn run_stdio(&self) -> Result<()> {
+        tracing::info!("MCP server listening on stdio");
+        let stdin = BufReader::new(tokio::io::stdin());
+        self.serve_lines(stdin, tokio::io::stdout()).await
+    }
+
+    /// Serve newline-delimited JSON-RPC: one request per line in, one
+    /// response per line out. Notifications (no `id`) get no response.
+    pub async fn serve_lines<R, W>(&self, reader: R, mut writer: W) -> Result<()>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut lines = reader.lines();
+        let mut bucket = self.rate_limit.map(TokenBucket::new);
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            tracing::debug!("Received line: {}", line);
+
+            let message: serde_json::Value = match serde_json::from_str(&line) {
+                Ok(v) => v,
+                Err(e) => {
+                    let response = JsonRpcResponse::error(json!(null), -32700, &format!("Parse error: {}", e));
+                    write_line(&mut writer, &response).await?;
+                    continue;
+                }
+            };
+
+            let response = match serde_json::from_value::<JsonRpcRequest>(message) {
+                Ok(request) if rate_limited(&mut bucket, &request) => request.id.map(limited_response),
+                Ok(request) => match progress_session_id(&request) {
+                    Some(session_id) => {
+                        let handling = Self::handle_message(request, &self.session_manager);
+                        self.with_progress(handling, &session_id, &mut writer).await?
+                    }
+                    None => Self::handle_message(request, &self.session_manager).await,
+                },
+                Err(e) => Some(JsonRpcResponse::error(json!(null), -32600, &format!("Invalid request: {}", e))),
+            };
+            if let Some(response) = response {
+                write_line(&mut writer, &response).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drive `handling` to completion while pushing a `SESSION_PROGRESS`
+    /// notification each time the session's agent state changes, including
+    /// the state it settled in once the call returns
+    async fn with_progress<F, W>(&self, handling: F, session_id: &str, writer: &mut W) -> Result<Option<JsonRpcResponse>>
+    where
+        F: Future<Output = Option<JsonRpcResponse>>,
+        W: AsyncWrite + Unpin,
+    {
+        let mut last_seen = self.progress_activity(session_id).await
+            .map(|a| (a.state, a.state_changed_at));
+
+        let mut report = |activity: Option<SessionActivity>| {
+            let activity = activity?;
+            let seen = Some((activity.state, activity.state_changed_at));
+            if seen == last_seen {
+                return None;
+            }
+            last_seen = seen;
+            Some(JsonRpcNotification::new(SESSION_PROGRESS, json!({
+                "session_id": activity.session_id,
+                "state": activity.state.as_str(),
+                "last_response": activity.last_response,
+                "approval_type": activity.approval_type.map(|t| t.as_str()),
+                "approval_description": activity.approval_description,
+                "state_changed_at": activity.state_changed_at.to_rfc3339(),
+            })))
+        };
+
+        tokio::pin!(handling);
+        let mut ticks = tokio::time::interval(PROGRESS_POLL_INTERVAL);
+        let response = loop {
+            tokio::select! {
+                response = &mut handling => break response,
+                _ = ticks.tick() => {
+                    if let Some(notification) = report(self.progress_activity(session_id).await) {
+                        write_line(writer, &notification).await?;
+                    }
+                }
+            }
+        };
+
+        if let Some(notification) = report(self.progress_activity(session_id).await) {
+            write_line(writer, &notification).await?;
+        }
+        Ok(response)
+    }
+
+    /// A session's latest activity for a progress notification. Failing to
+    /// read it only skips a notification; it must not end the server or
+    /// the call being reported on.
+    async fn progress_activity(&self, session_id: &str) -> Option<SessionActivity> {
+        match self.session_manager.repository().latest_activity(session_id).await {
+            Ok(activity) => activity,
+            Err(e) => {
+                tracing::warn!("Failed to read activity of session {} for progress: {:#}", session_id, e);
+                None
             }
+        }
+    }
 
-            let request_str = String::from_utf8_lossy(&buffer[..n]);
-            tracing::debug!("Received raw: {}", request_str);
+    async fn handle_connection(
+        stream: TcpStream,
+        session_manager: Arc<crate::session::SessionManager>,
+        mut bucket: Option<TokenBucket>,
+    ) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
 
-            // Extract JSON body from HTTP request
-            let json_body = match Self::extract_json_body(&request_str) {
-                Some(body) => body,
-                None => {
+        loop {
+            // Read one complete HTTP request (headers + Content-Length body)
+            let request = match read_http_request(&mut reader).await {
+                Ok(Some(request)) => request,
+                Ok(None) => break,
+                Err(e) => {
+                    // Framing is lost; report and drop the connection
                     let response = JsonRpcResponse::error(
                         json!(null),
                         -32700,
-                        "Invalid HTTP request"
+                        &format!("Invalid HTTP request: {}", e)
                     );
-                    send_response(&mut stream, response).await?;
-                    continue;
+                    send_response(&mut writer, response).await?;
+                    break;
                 }
             };
 
+            // Plain HTTP probe for load balancers; not a tool call, so not rate limited
+            if request.method == "GET" && request.path.split('?').next() == Some("/healthz") {
+                let (healthy, body) = Self::healthz(&session_manager).await;
+                send_health(&mut writer, healthy, &body).await?;
+                continue;
+            }
+
+            let json_body = request.body;
+            tracing::debug!("Received body: {}", json_body);
+
             // Parse JSON-RPC request
             let request: JsonRpcRequest = match serde_json::from_str(&json_body) {
                 Ok(r) => r,
@@ -74,43 +273,143 @@ impl McpServer {
                         -32700,
                         &format!("Parse error: {}", e)
                     );
-                    send_response(&mut stream, response).await?;
+                    send_response(&mut writer, response).await?;
                     continue;
                 }
             };
 
-            // Handle request
-            let response = Self::handle_request(request, &session_manager).await;
-            send_response(&mut stream, response).await?;
+            // Handle request; notifications are acknowledged without a body
+            let response = if rate_limited(&mut bucket, &request) {
+                request.id.map(limited_response)
+            } else {
+                Self::handle_message(request, &session_manager).await
+            };
+            match response {
+                Some(response) => send_response(&mut writer, response).await?,
+                None => send_accepted(&mut writer).await?,
+            }
         }
 
         Ok(())
     }
 
-    /// Extract JSON body from HTTP request
-    fn extract_json_body(http_request: &str) -> Option<String> {
-        // Find the double newline that separates headers from body
-        let parts: Vec<&str> = http_request.splitn(2, "\r\n\r\n").collect();
-        if parts.len() < 2 {
-            // Try without CRLF
-            let parts: Vec<&str> = http_request.splitn(2, "\n\n").collect();
-            if parts.len() < 2 {
-                return None;
-            }
-            return Some(parts[1].to_string());
+    /// Database and provider health for `GET /healthz`. Only the database
+    /// decides whether the server is healthy: a provider being down doesn't
+    /// mean restarting this process would help.
+    async fn healthz(session_manager: &crate::session::SessionManager) -> (bool, serde_json::Value) {
+        let (database, providers) = tokio::join!(
+            session_manager.check_database_health(),
+            session_manager.check_providers_health(Some(HEALTHZ_PROVIDER_TIMEOUT)),
+        );
+        let database = database.unwrap_or(false);
+
+        let mut health = serde_json::Map::new();
+        health.insert("status".to_string(), json!(if database { "ok" } else { "unavailable" }));
+        health.insert("database".to_string(), json!(database));
+        for (session_type, healthy) in providers {
+            health.insert(session_type, json!(healthy));
         }
-        
-        // Check if this is a POST request with JSON
-        let headers = parts[0];
-        if !headers.contains("Content-Type: application/json") {
-            // For now, try to parse anyway
+        (database, serde_json::Value::Object(health))
+    }
+}
+
+/// One HTTP request off an MCP connection
+struct HttpRequest {
+    method: String,
+    path: String,
+    body: String,
+}
+
+/// Largest request body accepted
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Read one HTTP request: its request line and body.
+///
+/// Returns `None` on a clean end of stream before a new request starts.
+async fn read_http_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<HttpRequest>> {
+    // Request line, skipping stray blank lines between keep-alive requests
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
         }
-        
-        Some(parts[1].to_string())
+        if !line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        anyhow::bail!("malformed request line: {}", line.trim());
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    // Headers
+    let mut content_length = 0usize;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            anyhow::bail!("connection closed while reading headers");
+        }
+        let header = line.trim_end_matches(['\r', '\n']);
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()
+                    .map_err(|_| anyhow::anyhow!("invalid Content-Length: {}", value.trim()))?;
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_SIZE {
+        anyhow::bail!("body of {} bytes exceeds the {} byte limit", content_length, MAX_BODY_SIZE);
     }
+
+    // Body, however many reads it takes
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).await?;
+
+    Ok(Some(HttpRequest { method, path, body: String::from_utf8(body)? }))
+}
+
+/// Answer a health probe: 200 when healthy, 503 otherwise
+async fn send_health<W: AsyncWrite + Unpin>(stream: &mut W, healthy: bool, body: &serde_json::Value) -> Result<()> {
+    let body = body.to_string();
+    let status = if healthy { "200 OK" } else { "503 Service Unavailable" };
+    let http_response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+
+    stream.write_all(http_response.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Acknowledge a notification with an empty `202 Accepted`
+async fn send_accepted<W: AsyncWrite + Unpin>(stream: &mut W) -> Result<()> {
+    stream.write_all(b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n").await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn write_line<W: AsyncWrite + Unpin, T: serde::Serialize>(writer: &mut W, message: &T) -> Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    tracing::debug!("Sending: {}", line);
+    line.push('\n');
+
+    writer.write_all(line.as_bytes()).await?;
+    writer.flush().await?;
+
+    Ok(())
 }
 
-async fn send_response(stream: &mut TcpStream, response: JsonRpcResponse) -> Result<()> {
+async fn send_response<W: AsyncWrite + Unpin>(stream: &mut W, response: JsonRpcResponse) -> Result<()> {
     let response_str = serde_json::to_string(&response)?;
     tracing::debug!("Sending: {}", response_str);
     
@@ -127,13 +426,185 @@ async fn send_response(stream: &mut TcpStream, response: JsonRpcResponse) -> Res
     Ok(())
 }
 
+/// Tool call failures caused by what the client asked for, as opposed to
+/// something going wrong on this side
+#[derive(Debug, thiserror::Error)]
+enum ToolError {
+    /// Missing or malformed arguments
+    #[error("{0}")]
+    InvalidParams(String),
+    /// The session, project or agent config named doesn't exist
+    #[error("{0}")]
+    NotFound(String),
+}
+
+fn invalid_params(message: impl Into<String>) -> anyhow::Error {
+    ToolError::InvalidParams(message.into()).into()
+}
+
+fn not_found(message: impl Into<String>) -> anyhow::Error {
+    ToolError::NotFound(message.into()).into()
+}
+
+/// A failed tool call, coded so clients can branch without parsing the
+/// message: -32602 for bad arguments, -32001 for anything not found,
+/// -32002..-32004 for other provider failures (with `data` saying whether
+/// retrying may help), and -32000 for the rest.
+fn tool_error(id: serde_json::Value, error: &anyhow::Error) -> JsonRpcResponse {
+    match error.downcast_ref::<ToolError>() {
+        Some(ToolError::InvalidParams(_)) => {
+            return JsonRpcResponse::error(id, -32602, &error.to_string());
+        }
+        Some(ToolError::NotFound(_)) => {
+            let data = json!({ "kind": "not_found", "retryable": false });
+            return JsonRpcResponse::error_with_data(id, -32001, &error.to_string(), data);
+        }
+        None => {}
+    }
+
+    let Some(provider_error) = ProviderError::find(error) else {
+        return JsonRpcResponse::error(id, -32000, &error.to_string());
+    };
+
+    let code = match provider_error {
+        ProviderError::NotFound(_) => -32001,
+        ProviderError::Unreachable(_) => -32002,
+        ProviderError::Timeout(_) => -32003,
+        ProviderError::Api { .. } => -32004,
+        ProviderError::Interrupted(_) => -32005,
+    };
+    let mut data = json!({
+        "kind": provider_error.kind(),
+        "retryable": provider_error.is_transient(),
+    });
+    if let ProviderError::Api { status, .. } = provider_error {
+        data["status"] = json!(status);
+    }
+    JsonRpcResponse::error_with_data(id, code, &error.to_string(), data)
+}
+
+/// Whether `request` is a tool call over the connection's rate limit
+fn rate_limited(bucket: &mut Option<TokenBucket>, request: &JsonRpcRequest) -> bool {
+    request.method == "tools/call" && bucket.as_mut().is_some_and(|bucket| !bucket.try_acquire())
+}
+
+fn limited_response(id: serde_json::Value) -> JsonRpcResponse {
+    JsonRpcResponse::error(id, -32000, "rate limited")
+}
+
+/// The session a `send_message` tool call targets, if that's what `request` is
+fn progress_session_id(request: &JsonRpcRequest) -> Option<String> {
+    if request.method != "tools/call" || request.params["name"] != "send_message" {
+        return None;
+    }
+    request.params["arguments"]["session_id"].as_str()
+        .filter(|id| !id.is_empty())
+        .map(String::from)
+}
+
+/// Parse an optional RFC 3339 timestamp argument
+fn parse_time_arg(args: &serde_json::Value, name: &str) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+    args[name].as_str()
+        .map(|value| {
+            chrono::DateTime::parse_from_rfc3339(value)
+                .map(|t| t.with_timezone(&chrono::Utc))
+                .map_err(|e| invalid_params(format!("Invalid {}: {} ({})", name, value, e)))
+        })
+        .transpose()
+}
+
+/// A `send_message` attachment as a message part
+fn attachment_part(attachment: &serde_json::Value) -> Result<crate::session::MessagePart> {
+    if let Some(path) = attachment["path"].as_str() {
+        return crate::session::MessagePart::file(path).map_err(|e| invalid_params(format!("{:#}", e)));
+    }
+
+    let url = attachment["url"].as_str()
+        .ok_or_else(|| invalid_params("attachments need a path or a url"))?;
+    let mime = attachment["mime"].as_str()
+        .ok_or_else(|| invalid_params(format!("mime is required for attachment {}", url)))?;
+
+    Ok(crate::session::MessagePart::File {
+        mime: mime.to_string(),
+        filename: attachment["filename"].as_str().map(String::from),
+        url: url.to_string(),
+    })
+}
+
+/// Run `name` and return its text result, e.g. for a request from a peer.
+/// Arguments are checked against the tool's schema just as for an MCP client.
+pub(crate) async fn call_tool_text(
+    name: &str,
+    arguments: serde_json::Value,
+    session_manager: &Arc<crate::session::SessionManager>,
+) -> Result<String> {
+    let tool_call = ToolCall { name: name.to_string(), arguments };
+    McpServer::validate_arguments(&tool_call).map_err(invalid_params)?;
+
+    let result = McpServer::call_tool(&tool_call, session_manager).await?;
+    Ok(result.content.into_iter()
+        .filter_map(|block| match block {
+            ContentBlock::Text { text } => Some(text),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// `spawn_session` result for a session an earlier call with the same
+/// idempotency key already created
+fn existing_spawn_result(session: &crate::db::repositories::session::Session, name: &str) -> ToolCallResult {
+    ToolCallResult {
+        content: vec![ContentBlock::Text {
+            text: json!({
+                "session_id": session.id,
+                "name": session.name.as_deref().unwrap_or(name),
+                "provider_session_id": session.opencode_session_id,
+                "status": session.status.as_str(),
+                "existing": true
+            }).to_string()
+        }]
+    }
+}
+
+/// A session tree node as returned by `get_session_tree`
+fn session_tree_json(tree: &crate::db::repositories::session::SessionTree) -> serde_json::Value {
+    let s = &tree.session;
+    json!({
+        "id": s.id,
+        "name": s.name,
+        "agent_type": s.agent_type.as_str(),
+        "session_type": s.session_type.as_str(),
+        "status": s.status.as_str(),
+        "project_id": s.project_id,
+        "forked_from": s.forked_from,
+        "created_at": s.created_at.to_rfc3339(),
+        "children": tree.children.iter().map(session_tree_json).collect::<Vec<_>>()
+    })
+}
+
 impl McpServer {
+    /// Handle a request or notification. Notifications (no `id`) are
+    /// processed but produce no response, per JSON-RPC.
+    async fn handle_message(request: JsonRpcRequest, session_manager: &Arc<crate::session::SessionManager>) -> Option<JsonRpcResponse> {
+        if request.id.is_none() {
+            tracing::debug!("Received notification: {}", request.method);
+            // Unknown notifications (e.g. notifications/initialized) are fine to ignore
+            if request.method.parse::<McpMethod>().is_ok() {
+                let _ = Self::handle_request(request, session_manager).await;
+            }
+            return None;
+        }
+
+        Some(Self::handle_request(request, session_manager).await)
+    }
+
     async fn handle_request(request: JsonRpcRequest, session_manager: &Arc<crate::session::SessionManager>) -> JsonRpcResponse {
-        let id = request.id;
+        let id = request.id.unwrap_or(serde_json::Value::Null);
         
-        let method = match McpMethod::from_str(&request.method) {
-            Some(m) => m,
-            None => {
+        let method = match request.method.parse::<McpMethod>() {
+            Ok(m) => m,
+            Err(()) => {
                 return JsonRpcResponse::error(id, -32601, "Method not found");
             }
         };
@@ -145,7 +616,7 @@ impl McpServer {
                     capabilities: Capabilities {
                         tools: true,
                         resources: false,
-                        prompts: false,
+                        prompts: true,
                     },
                     server_info: ServerInfo {
                         name: "supercode".to_string(),
@@ -169,9 +640,35 @@ impl McpServer {
                     }
                 };
 
-                match Self::call_tool(&params, session_manager).await {
+                if let Err(message) = Self::validate_arguments(&params) {
+                    return JsonRpcResponse::error(id, -32602, &message);
+                }
+
+                let result = Self::call_tool(&params, session_manager).await;
+                // Label unknown tools together so callers can't grow the label set
+                let tool = if Self::tool_names().contains(params.name.as_str()) { params.name.as_str() } else { "unknown" };
+                session_manager.metrics().record_tool_call(tool, result.is_ok());
+
+                match result {
+                    Ok(result) => JsonRpcResponse::success(id, serde_json::to_value(result).unwrap()),
+                    Err(e) => tool_error(id, &e),
+                }
+            }
+            
+            // Cheap liveness check for long-lived connections: `"result": {}`
+            McpMethod::Ping => JsonRpcResponse::success(id, json!({})),
+
+            McpMethod::PromptGet => {
+                let params: PromptGetParams = match serde_json::from_value(request.params) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        return JsonRpcResponse::error(id, -32602, &format!("Invalid params: {}", e));
+                    }
+                };
+
+                match Self::get_prompt(&params, session_manager).await {
                     Ok(result) => JsonRpcResponse::success(id, serde_json::to_value(result).unwrap()),
-                    Err(e) => JsonRpcResponse::error(id, -32000, &e.to_string()),
+                    Err(e) => JsonRpcResponse::error(id, -32602, &e.to_string()),
                 }
             }
             
@@ -179,6 +676,63 @@ impl McpServer {
         }
     }
 
+    /// Check tool arguments against the tool's declared input schema,
+    /// returning every validation failure in one message
+    fn validate_arguments(tool_call: &ToolCall) -> std::result::Result<(), String> {
+        let Some(tool) = Self::get_tools().into_iter().find(|t| t.name == tool_call.name) else {
+            // Unknown tools are reported by call_tool
+            return Ok(());
+        };
+
+        let validator = jsonschema::validator_for(&tool.input_schema)
+            .map_err(|e| format!("Invalid schema for tool {}: {}", tool.name, e))?;
+
+        let failures: Vec<String> = validator.iter_errors(&tool_call.arguments)
+            .map(|e| {
+                let path = e.instance_path.to_string();
+                if path.is_empty() {
+                    format!("arguments: {}", e)
+                } else {
+                    format!("arguments{}: {}", path, e)
+                }
+            })
+            .collect();
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Invalid arguments for {}: {}", tool.name, failures.join("; ")))
+        }
+    }
+
+    /// Assemble the prompt an agent of the given type would be spawned with
+    async fn get_prompt(params: &PromptGetParams, session_manager: &Arc<crate::session::SessionManager>) -> Result<PromptGetResult> {
+        let agent_type = crate::db::repositories::session::AgentType::from_str(&params.name)
+            .map_err(|_| anyhow::anyhow!("Unknown prompt: {}", params.name))?;
+
+        let args = params.arguments.clone().unwrap_or_default();
+        let text = session_manager.agent_prompt(
+            agent_type.as_str(),
+            args["name"].as_str(),
+            args["extra_prompt"].as_str(),
+            args["agent_config"].as_str(),
+        ).await?;
+
+        Ok(PromptGetResult {
+            description: format!("System prompt for a {} agent", agent_type.as_str()),
+            messages: vec![PromptMessage {
+                role: "user".to_string(),
+                content: ContentBlock::Text { text },
+            }],
+        })
+    }
+
+    /// Names of the tools `get_tools` lists, built once
+    fn tool_names() -> &'static HashSet<String> {
+        static NAMES: OnceLock<HashSet<String>> = OnceLock::new();
+        NAMES.get_or_init(|| Self::get_tools().into_iter().map(|tool| tool.name).collect())
+    }
+
     fn get_tools() -> Vec<Tool> {
         vec![
             Tool {
@@ -193,13 +747,13 @@ impl McpServer {
                         },
                         "agent_type": {
                             "type": "string",
-                            "enum": ["manager", "developer", "reviewer"],
-                            "description": "Type of agent to spawn"
+                            "description": "Type of agent to spawn: manager, developer, reviewer, or a custom role name (lowercase letters, digits, '-' or '_'). A stored agent config with the same name supplies a custom role's prompt"
                         },
                         "session_type": {
                             "type": "string",
-                            "enum": ["opencode", "claude"],
-                            "description": "Session backend type"
+                            // Same rule as SessionType::from_str, for clients that check schemas
+                            "pattern": "^[a-z][a-z0-9_-]{0,63}$",
+                            "description": "Session backend type: opencode, claude, openai (when configured), or the name of another registered provider"
                         },
                         "project_id": {
                             "type": "string",
@@ -209,9 +763,44 @@ impl McpServer {
                             "type": "string",
                             "description": "Working directory for the agent"
                         },
+                        "isolation": {
+                            "type": "string",
+                            "enum": ["none", "worktree", "copy"],
+                            "description": "Give the agent its own workspace so parallel agents don't overwrite each other's uncommitted changes: 'worktree' (a detached git worktree of working_dir at HEAD) or 'copy' (a full copy of working_dir, uncommitted changes included). The workspace becomes the session's working_dir and is kept after the session ends, until delete_session (default: none, work in working_dir itself)"
+                        },
+                        "use_worktree": {
+                            "type": "boolean",
+                            "description": "If working_dir is in a git repository, give the agent its own worktree on a new branch, supercode/<session_id>, so its work can be reviewed and merged like any branch. The worktree is removed by delete_session; the branch is kept (default: false)"
+                        },
+                        "base_branch": {
+                            "type": "string",
+                            "minLength": 1,
+                            "description": "Branch or commit the use_worktree branch starts from (default: the repository's HEAD)"
+                        },
                         "extra_prompt": {
                             "type": "string",
                             "description": "Optional additional instructions for the agent"
+                        },
+                        "agent_config": {
+                            "type": "string",
+                            "description": "Optional name of a stored agent config whose prompt replaces the built-in role prompt"
+                        },
+                        "keep_failed": {
+                            "type": "boolean",
+                            "description": "Keep the session as 'failed' (with the error in its metadata) if the provider fails to start it, instead of deleting it (default: false)"
+                        },
+                        "parent_session_id": {
+                            "type": "string",
+                            "description": "Session spawning this one (e.g. the manager's own session ID), for get_session_tree"
+                        },
+                        "idempotency_key": {
+                            "type": "string",
+                            "description": "Optional key identifying this spawn request. If a session was already created with the same key, it is returned (with \"existing\": true) instead of spawning another agent, so retries are safe"
+                        },
+                        "remote_peer": {
+                            "type": "string",
+                            "minLength": 1,
+                            "description": "Spawn on this peer instead of locally. working_dir, project_id, agent_config and parent_session_id then refer to the peer's own; the returned session_id is the peer's"
                         }
                     },
                     "required": ["agent_type", "session_type", "working_dir", "name"]
@@ -234,13 +823,79 @@ impl McpServer {
                         "agent_type": {
                             "type": "string",
                             "description": "Filter by agent type"
+                        },
+                        "created_after": {
+                            "type": "string",
+                            "description": "Only sessions created at or after this RFC 3339 time"
+                        },
+                        "created_before": {
+                            "type": "string",
+                            "description": "Only sessions created before this RFC 3339 time"
+                        },
+                        "tags": {
+                            "type": "array",
+                            "items": { "type": "string", "minLength": 1 },
+                            "description": "Only sessions carrying all of these tags"
+                        },
+                        "order_by": {
+                            "type": "string",
+                            "enum": ["created_at", "updated_at"],
+                            "description": "Timestamp to sort by (default: created_at). updated_at also moves with session activity, so it lists the most recently active sessions first"
+                        },
+                        "order": {
+                            "type": "string",
+                            "enum": ["asc", "desc"],
+                            "description": "Sort direction (default: desc)"
+                        },
+                        "limit": {
+                            "type": "integer",
+                            "description": "Maximum number of sessions to return (default: all)"
+                        },
+                        "offset": {
+                            "type": "integer",
+                            "description": "Number of sessions to skip (default: 0)"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "list_remote_sessions".to_string(),
+                description: "List sessions on this node and every verified peer in one list, newest first, each tagged with the node it runs on. total counts every matching session on the nodes that answered. Peers that can't be reached are listed under \"unreachable\"; a node that can't talk to peers lists only its own".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "status": {
+                            "type": "string",
+                            "description": "Filter by status"
+                        },
+                        "agent_type": {
+                            "type": "string",
+                            "description": "Filter by agent type"
+                        },
+                        "created_after": {
+                            "type": "string",
+                            "description": "Only sessions created at or after this RFC 3339 time"
+                        },
+                        "created_before": {
+                            "type": "string",
+                            "description": "Only sessions created before this RFC 3339 time"
+                        },
+                        "tags": {
+                            "type": "array",
+                            "items": { "type": "string", "minLength": 1 },
+                            "description": "Only sessions carrying all of these tags"
+                        },
+                        "limit": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "Most sessions to return across all nodes (default: 100)"
                         }
                     }
                 }),
             },
             Tool {
                 name: "send_message".to_string(),
-                description: "Send a message to a session".to_string(),
+                description: "Send a message to a session. Over stdio, notifications/session/progress notifications report the agent's state changes while the call runs".to_string(),
                 input_schema: json!({
                     "type": "object",
                     "properties": {
@@ -251,6 +906,45 @@ impl McpServer {
                         "content": {
                             "type": "string",
                             "description": "Message content to send"
+                        },
+                        "attachments": {
+                            "type": "array",
+                            "description": "Files or images to send along with the content, e.g. a screenshot for a reviewer. OpenCode receives them as file parts; Claude Code sessions are told the local paths to read",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "path": {
+                                        "type": "string",
+                                        "description": "Local file to attach; its MIME type is guessed from the extension"
+                                    },
+                                    "url": {
+                                        "type": "string",
+                                        "description": "file://, data: or http(s):// URL to attach instead of a path"
+                                    },
+                                    "mime": {
+                                        "type": "string",
+                                        "description": "MIME type, required with url (e.g. image/png)"
+                                    },
+                                    "filename": {
+                                        "type": "string",
+                                        "description": "Name to show for a url attachment"
+                                    }
+                                },
+                                "anyOf": [
+                                    { "required": ["path"] },
+                                    { "required": ["url", "mime"] }
+                                ]
+                            }
+                        },
+                        "wait": {
+                            "type": "boolean",
+                            "description": "Block until the agent has finished processing and return its final response (default: false)"
+                        },
+                        "timeout_secs": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "maximum": 3600,
+                            "description": "How long to wait when 'wait' is set (default: 600, at most 3600)"
                         }
                     },
                     "required": ["session_id", "content"]
@@ -271,77 +965,343 @@ impl McpServer {
                 }),
             },
             Tool {
-                name: "get_session".to_string(),
-                description: "Get session details and history".to_string(),
+                name: "restart_session".to_string(),
+                description: "Restart a failed or stuck session in place, keeping its ID, working directory and recorded history. The agent starts a new conversation (Claude Code sessions held by this server resume theirs) and is sent its original agent prompt again; anything else it was told is lost and must be re-sent".to_string(),
                 input_schema: json!({
                     "type": "object",
                     "properties": {
                         "session_id": {
                             "type": "string",
-                            "description": "The session ID"
+                            "description": "The session ID to restart"
                         }
                     },
                     "required": ["session_id"]
                 }),
             },
             Tool {
-                name: "fork_session".to_string(),
-                description: "Fork an existing session for parallel work".to_string(),
+                name: "resume_session".to_string(),
+                description: "Reattach a session to its existing provider session (e.g. after supercode restarted) without creating a new one. Only OpenCode sessions can be resumed".to_string(),
                 input_schema: json!({
                     "type": "object",
                     "properties": {
                         "session_id": {
                             "type": "string",
-                            "description": "The session ID to fork"
+                            "description": "The session ID to resume"
                         },
-                        "fork_message": {
+                        "provider_session_id": {
                             "type": "string",
-                            "description": "Optional message for the forked session"
+                            "description": "Provider session to attach to (default: the one stored for the session)"
                         }
                     },
                     "required": ["session_id"]
                 }),
             },
             Tool {
-                name: "list_projects".to_string(),
-                description: "List all projects".to_string(),
+                name: "delete_session".to_string(),
+                description: "Kill a session if running and permanently delete it and its history, along with the worktree or copy it was given. A worktree with uncommitted changes is kept, and the session with it, unless discard_changes is set".to_string(),
                 input_schema: json!({
                     "type": "object",
-                    "properties": {}
+                    "properties": {
+                        "session_id": {
+                            "type": "string",
+                            "description": "The session ID to delete"
+                        },
+                        "discard_changes": {
+                            "type": "boolean",
+                            "description": "Remove the session's worktree even if it has uncommitted changes (default: false)"
+                        }
+                    },
+                    "required": ["session_id"]
                 }),
             },
             Tool {
-                name: "create_project".to_string(),
-                description: "Create a new project".to_string(),
+                name: "cleanup_sessions".to_string(),
+                description: "Delete sessions older than a number of days, optionally filtered by status".to_string(),
                 input_schema: json!({
                     "type": "object",
                     "properties": {
-                        "name": {
-                            "type": "string",
-                            "description": "Project name"
+                        "older_than_days": {
+                            "type": "integer",
+                            "description": "Delete sessions created more than this many days ago"
                         },
-                        "description": {
-                            "type": "string",
-                            "description": "Project description"
+                        "statuses": {
+                            "type": "array",
+                            "items": {
+                                "type": "string",
+                                "enum": ["pending", "running", "completed", "failed", "terminated"]
+                            },
+                            "description": "Only delete sessions in these statuses (default: completed, failed and terminated). Pending and running sessions are killed at their provider first."
                         }
                     },
-                    "required": ["name"]
+                    "required": ["older_than_days"]
                 }),
             },
             Tool {
-                name: "run_quality_gates".to_string(),
-                description: "Run quality gates on a project directory".to_string(),
+                name: "get_session".to_string(),
+                description: "Get session details and history".to_string(),
                 input_schema: json!({
                     "type": "object",
                     "properties": {
-                        "project_dir": {
+                        "session_id": {
                             "type": "string",
-                            "description": "Path to the project directory"
+                            "description": "The session ID"
+                        },
+                        "limit": {
+                            "type": "integer",
+                            "description": "Maximum number of most recent messages to include (default: 50)"
+                        }
+                    },
+                    "required": ["session_id"]
+                }),
+            },
+            Tool {
+                name: "get_session_by_name".to_string(),
+                description: "Get session details and history for the agent spawned with this name (the most recent one if the name was reused)".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "name": {
+                            "type": "string",
+                            "description": "The name given to spawn_session"
+                        },
+                        "limit": {
+                            "type": "integer",
+                            "description": "Maximum number of most recent messages to include (default: 50)"
+                        }
+                    },
+                    "required": ["name"]
+                }),
+            },
+            Tool {
+                name: "refresh_session_status".to_string(),
+                description: "Ask the session's provider whether it is still alive and update the stored status (which otherwise only changes on spawn and kill)".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": {
+                            "type": "string",
+                            "description": "The session ID"
+                        }
+                    },
+                    "required": ["session_id"]
+                }),
+            },
+            Tool {
+                name: "get_session_tree".to_string(),
+                description: "Get the sessions forked or spawned under a session, recursively; without session_id, every top-level session with its descendants".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": {
+                            "type": "string",
+                            "description": "The session at the root of the tree"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "get_session_output".to_string(),
+                description: "Get a session's raw output (Claude Code process output, OpenCode transcript, or stored messages) as text".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": {
+                            "type": "string",
+                            "description": "The session ID"
+                        },
+                        "tail_lines": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "Only return the last N lines"
+                        }
+                    },
+                    "required": ["session_id"]
+                }),
+            },
+            Tool {
+                name: "search_messages".to_string(),
+                description: "Search stored message content across all sessions, e.g. for an error or file name. Returns the best matching messages with their session and a snippet".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "query": {
+                            "type": "string",
+                            "minLength": 1,
+                            "description": "Words that must all appear in the message"
+                        },
+                        "limit": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "Maximum number of matches (default: 20)"
+                        }
+                    },
+                    "required": ["query"]
+                }),
+            },
+            Tool {
+                name: "tag_session".to_string(),
+                description: "Add a free-form tag (e.g. sprint-14, hotfix) to a session, for grouping with list_sessions".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": {
+                            "type": "string",
+                            "description": "The session ID"
+                        },
+                        "tag": {
+                            "type": "string",
+                            "minLength": 1,
+                            "description": "The tag to add"
+                        }
+                    },
+                    "required": ["session_id", "tag"]
+                }),
+            },
+            Tool {
+                name: "untag_session".to_string(),
+                description: "Remove a tag from a session".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": {
+                            "type": "string",
+                            "description": "The session ID"
+                        },
+                        "tag": {
+                            "type": "string",
+                            "minLength": 1,
+                            "description": "The tag to remove"
+                        }
+                    },
+                    "required": ["session_id", "tag"]
+                }),
+            },
+            Tool {
+                name: "set_metadata".to_string(),
+                description: "Merge key/values into a session's metadata (null removes a key)".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": {
+                            "type": "string",
+                            "description": "The session ID"
+                        },
+                        "metadata": {
+                            "type": "object",
+                            "description": "Object to deep-merge into the existing metadata"
+                        }
+                    },
+                    "required": ["session_id", "metadata"]
+                }),
+            },
+            Tool {
+                name: "fork_session".to_string(),
+                description: "Fork an existing session for parallel work. Fails for providers without supports_fork (see get_capabilities)".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": {
+                            "type": "string",
+                            "description": "The session ID to fork"
+                        },
+                        "fork_message": {
+                            "type": "string",
+                            "description": "Optional message for the forked session"
+                        }
+                    },
+                    "required": ["session_id"]
+                }),
+            },
+            Tool {
+                name: "list_session_forks".to_string(),
+                description: "List the sessions forked from a session, with their status and current state".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": {
+                            "type": "string",
+                            "description": "The session whose forks to list"
+                        }
+                    },
+                    "required": ["session_id"]
+                }),
+            },
+            Tool {
+                name: "list_projects".to_string(),
+                description: "List all projects".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {}
+                }),
+            },
+            Tool {
+                name: "create_project".to_string(),
+                description: "Create a new project".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "name": {
+                            "type": "string",
+                            "description": "Project name"
+                        },
+                        "description": {
+                            "type": "string",
+                            "description": "Project description"
+                        }
+                    },
+                    "required": ["name"]
+                }),
+            },
+            Tool {
+                name: "update_project".to_string(),
+                description: "Rename or re-describe an existing project".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "project_id": {
+                            "type": "string",
+                            "description": "The project ID"
+                        },
+                        "name": {
+                            "type": "string",
+                            "description": "New project name"
+                        },
+                        "description": {
+                            "type": "string",
+                            "description": "New project description"
+                        }
+                    },
+                    "required": ["project_id"]
+                }),
+            },
+            Tool {
+                name: "run_quality_gates".to_string(),
+                description: "Run quality gates on a project directory. all_passed is false if no gate ran, e.g. when gate is 'all' and no known project type was found; gates_run says how many did".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "project_dir": {
+                            "type": "string",
+                            "description": "Path to the project directory"
                         },
                         "gate": {
                             "type": "string",
-                            "enum": ["all", "rust_check", "rust_clippy", "npm_lint", "npm_typecheck", "python_ruff", "python_mypy", "python_pytest"],
+                            "enum": ["all", "rust_check", "rust_clippy", "npm_lint", "npm_typecheck", "python_ruff", "python_mypy", "python_pytest", "go_vet", "go_build", "go_test"],
                             "description": "Which gate to run (default: all)"
+                        },
+                        "timeout_secs": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "maximum": 3600,
+                            "description": "Kill any gate still running after this many seconds (default: 600, at most 3600)"
+                        },
+                        "changed_only": {
+                            "type": "boolean",
+                            "description": "Only check what changed since base_ref (git repos, gate \"all\" only)"
+                        },
+                        "base_ref": {
+                            "type": "string",
+                            "description": "Git ref to diff against when changed_only is set (default: HEAD)"
                         }
                     },
                     "required": ["project_dir"]
@@ -405,6 +1365,133 @@ impl McpServer {
                     "required": ["name"]
                 }),
             },
+            Tool {
+                name: "list_blocked_sessions".to_string(),
+                description: "List sessions that are blocked waiting for approval".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {}
+                }),
+            },
+            Tool {
+                name: "wait_for_blocked".to_string(),
+                description: "Block until a session starts waiting for approval, or the timeout passes (then returns an empty list)".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "timeout_secs": {
+                            "type": "integer",
+                            "minimum": 0,
+                            "maximum": 3600,
+                            "description": "How long to wait (default: 60, at most 3600)"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "respond_to_approval".to_string(),
+                description: "Grant or deny the action a blocked session is waiting on (see get_capabilities for supports_approval). An OpenCode session carries on with its turn. A Claude Code session's turn has already ended: approving allows the tool on its later messages, so send it one to retry".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": {
+                            "type": "string",
+                            "description": "The blocked session ID"
+                        },
+                        "approved": {
+                            "type": "boolean",
+                            "description": "true to allow the pending action, false to deny it"
+                        },
+                        "note": {
+                            "type": "string",
+                            "description": "Optional message passed to the agent with the decision"
+                        }
+                    },
+                    "required": ["session_id", "approved"]
+                }),
+            },
+            Tool {
+                name: "interrupt_session".to_string(),
+                description: "Stop the turn a session is working on, e.g. after giving it a wrong instruction. The session stays alive and takes the next send_message as usual (see get_capabilities for supports_interrupt). Reports interrupted: false if no turn was running. A Claude Code turn can only be stopped by the HTTP server running it: not over stdio, which handles one request at a time, and not from another process".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": {
+                            "type": "string",
+                            "description": "The session to interrupt"
+                        }
+                    },
+                    "required": ["session_id"]
+                }),
+            },
+            Tool {
+                name: "create_agent_config".to_string(),
+                description: "Define a custom agent role with its own system prompt".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "name": {
+                            "type": "string",
+                            "description": "Role name, e.g. 'qa' or 'architect'. A config named after a base agent type overrides its built-in prompt"
+                        },
+                        "agent_type": {
+                            "type": "string",
+                            "description": "Agent type sessions using this config are recorded as: manager, developer, reviewer, or a custom role name"
+                        },
+                        "system_prompt": {
+                            "type": "string",
+                            "description": "Prompt replacing the built-in role prompt"
+                        },
+                        "model": {
+                            "type": "string",
+                            "description": "Model name (default: 'default')"
+                        },
+                        "description": {
+                            "type": "string",
+                            "description": "Optional description of the role"
+                        }
+                    },
+                    "required": ["name", "agent_type", "system_prompt"]
+                }),
+            },
+            Tool {
+                name: "list_agent_configs".to_string(),
+                description: "List stored agent configs".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "agent_type": {
+                            "type": "string",
+                            "description": "Filter by base agent type"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "get_health".to_string(),
+                description: "Check which session providers are reachable".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {}
+                }),
+            },
+            Tool {
+                name: "get_capabilities".to_string(),
+                description: "Report what session providers support (real forks, streaming, resuming, approvals), for one session's provider, one session type, or every registered type. Check supports_fork before forking: without it fork_session fails".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": {
+                            "type": "string",
+                            "description": "Report on this session's provider"
+                        },
+                        "session_type": {
+                            "type": "string",
+                            "description": "Report on the provider for this session type"
+                        }
+                    }
+                }),
+            },
             Tool {
                 name: "get_node_info".to_string(),
                 description: "Get this node's info (name, public key)".to_string(),
@@ -421,26 +1508,81 @@ impl McpServer {
         
         match tool_call.name.as_str() {
             "spawn_session" => {
+                if let Some(peer_name) = args["remote_peer"].as_str() {
+                    let mut arguments = args.clone();
+                    if let Some(arguments) = arguments.as_object_mut() {
+                        arguments.remove("remote_peer");
+                    }
+                    let result = session_manager.spawn_remote(peer_name, arguments).await?;
+                    return Ok(ToolCallResult {
+                        content: vec![ContentBlock::Text { text: result.to_string() }]
+                    });
+                }
+
                 // Validate required fields with proper error messages
                 let agent_type = args["agent_type"].as_str()
-                    .ok_or_else(|| anyhow::anyhow!("agent_type is required"))?;
+                    .ok_or_else(|| invalid_params("agent_type is required"))?;
                 let session_type = args["session_type"].as_str()
-                    .ok_or_else(|| anyhow::anyhow!("session_type is required"))?;
+                    .ok_or_else(|| invalid_params("session_type is required"))?;
                 let working_dir = args["working_dir"].as_str()
-                    .ok_or_else(|| anyhow::anyhow!("working_dir is required"))?;
+                    .ok_or_else(|| invalid_params("working_dir is required"))?;
                 let name = args["name"].as_str()
-                    .ok_or_else(|| anyhow::anyhow!("name is required"))?;
+                    .ok_or_else(|| invalid_params("name is required"))?;
                 let project_id = args["project_id"].as_str().map(String::from);
                 let extra_prompt = args["extra_prompt"].as_str();
+                let keep_failed = args["keep_failed"].as_bool().unwrap_or(false);
+                let agent_config = args["agent_config"].as_str();
+                let parent_session_id = args["parent_session_id"].as_str();
+                let idempotency_key = args["idempotency_key"].as_str();
+                let isolation = args["isolation"].as_str()
+                    .map(crate::session::workspace::WorkspaceIsolation::from_str)
+                    .transpose()
+                    .map_err(|e| invalid_params(e.to_string()))?
+                    .unwrap_or_default();
+                let use_worktree = args["use_worktree"].as_bool().unwrap_or(false);
+                let base_branch = args["base_branch"].as_str();
+
+                if idempotency_key == Some("") {
+                    return Err(invalid_params("idempotency_key must not be empty"));
+                }
+
+                if use_worktree && isolation != crate::session::workspace::WorkspaceIsolation::None {
+                    return Err(invalid_params("use_worktree can't be combined with isolation"));
+                }
+                if base_branch.is_some() && !use_worktree {
+                    return Err(invalid_params("base_branch requires use_worktree"));
+                }
+
+                // A retried request gets the session the first one created
+                if let Some(key) = idempotency_key {
+                    if let Some(existing) = session_manager.repository().find_by_idempotency_key(key).await? {
+                        return Ok(existing_spawn_result(&existing, name));
+                    }
+                }
 
                 // Validate agent_type enum
                 let agent_type_enum = crate::db::repositories::session::AgentType::from_str(agent_type)
-                    .map_err(|_| anyhow::anyhow!("Invalid agent_type: {}. Must be one of: manager, developer, reviewer", agent_type))?;
+                    .map_err(|_| invalid_params(format!("Invalid agent_type: {}. Must be manager, developer, reviewer, or a custom name of lowercase letters, digits, '-' or '_'", agent_type)))?;
                 
                 // Validate session_type enum  
                 let session_type_enum = crate::db::repositories::session::SessionType::from_str(session_type)
-                    .map_err(|_| anyhow::anyhow!("Invalid session_type: {}. Must be one of: opencode, claude", session_type))?;
+                    .map_err(|_| invalid_params(format!("Invalid session_type: {}. Must be one of: {}", session_type, session_manager.session_types().join(", "))))?;
                 
+                // Don't leave a pending row behind for a provider that can't be reached
+                // or a working directory that doesn't exist
+                session_manager.ensure_provider_available(session_type).await?;
+                let working_dir = crate::session::SessionManager::validate_working_dir(working_dir)?;
+
+                if let Some(config_name) = agent_config {
+                    session_manager.agent_configs().get_by_name(config_name).await?
+                        .ok_or_else(|| not_found(format!("Agent config not found: {}", config_name)))?;
+                }
+
+                if let Some(parent_id) = parent_session_id {
+                    session_manager.repository().get(parent_id).await?
+                        .ok_or_else(|| not_found(format!("Parent session not found: {}", parent_id)))?;
+                }
+
                 // Create DB session record
                 let db = session_manager.repository().db().clone();
                 let session_repo = crate::db::repositories::session::SessionRepository::new(db);
@@ -449,14 +1591,42 @@ impl McpServer {
                     agent_type_enum,
                     session_type_enum,
                     project_id,
-                    Some(working_dir.to_string()),
+                    Some(working_dir.to_string_lossy().to_string()),
                 ).await?;
 
-                // Use provided name (now required)
+                // Lost a race with a concurrent request using the same key
+                if let Some(key) = idempotency_key {
+                    if !session_repo.set_idempotency_key(&session.id, key).await? {
+                        session_repo.delete(&session.id).await?;
+                        if let Some(existing) = session_repo.find_by_idempotency_key(key).await? {
+                            return Ok(existing_spawn_result(&existing, name));
+                        }
+                        anyhow::bail!("Session for idempotency key {} disappeared", key);
+                    }
+                }
+
+                if let Some(parent_id) = parent_session_id {
+                    session_repo.set_parent_session_id(&session.id, parent_id).await?;
+                }
+
+                session_repo.set_name(&session.id, name).await?;
                 let agent_name = name;
 
+                let workspace = if use_worktree {
+                    session_manager.create_worktree(&session.id, base_branch).await
+                } else {
+                    session_manager.isolate_workspace(&session.id, isolation).await.map(|_| None)
+                };
+                let branch = match workspace {
+                    Ok(worktree) => worktree.map(|(_, branch)| branch),
+                    Err(e) => {
+                        session_repo.delete(&session.id).await?;
+                        return Err(e);
+                    }
+                };
+
                 // Try to spawn with the provider (name will be included in initial prompt)
-                match session_manager.spawn_session(&session.id, agent_type, session_type, Some(agent_name), extra_prompt).await {
+                match session_manager.spawn_session(&session.id, agent_type, session_type, Some(agent_name), extra_prompt, agent_config).await {
                     Ok(handle) => {
                         Ok(ToolCallResult {
                             content: vec![ContentBlock::Text {
@@ -464,19 +1634,42 @@ impl McpServer {
                                     "session_id": session.id,
                                     "name": agent_name,
                                     "provider_session_id": handle.provider_id,
-                                    "status": "running"
+                                    "status": "running",
+                                    "branch": branch
                                 }).to_string()
                             }]
                         })
                     }
                     Err(e) => {
-                        // Session created in DB but provider failed
+                        // The provider session may exist if only the initial prompt failed
+                        if let Some(provider_id) = session_repo.get(&session.id).await?
+                            .and_then(|s| s.opencode_session_id)
+                        {
+                            let _ = session_manager.kill_provider_session(&provider_id, session_type).await;
+                        }
+
+                        // Don't leave a dead-on-arrival row behind unless asked to
+                        let status = if keep_failed {
+                            session_repo.update_status(
+                                &session.id,
+                                crate::db::repositories::session::SessionStatus::Failed
+                            ).await?;
+                            session_repo.merge_metadata(&session.id, json!({ "error": e.to_string() })).await?;
+                            "failed"
+                        } else {
+                            if let Err(e) = session_manager.remove_workspace(&session.id, false).await {
+                                tracing::warn!("Failed to remove workspace of session {}: {:#}", session.id, e);
+                            }
+                            session_repo.delete(&session.id).await?;
+                            "deleted"
+                        };
+
                         Ok(ToolCallResult {
                             content: vec![ContentBlock::Text {
                                 text: json!({
                                     "session_id": session.id,
                                     "name": agent_name,
-                                    "status": "error",
+                                    "status": status,
                                     "error": e.to_string()
                                 }).to_string()
                             }]
@@ -488,126 +1681,566 @@ impl McpServer {
             "list_sessions" => {
                 let project_id = args["project_id"].as_str();
                 let status = args["status"].as_str()
-                    .map(|s| crate::db::repositories::session::SessionStatus::from_str(s))
+                    .map(crate::db::repositories::session::SessionStatus::from_str)
                     .transpose()?;
-                let agent_type = args["agent_type"].as_str();
+                let agent_type = args["agent_type"].as_str()
+                    .map(crate::db::repositories::session::AgentType::from_str)
+                    .transpose()?;
+
+                let created_after = parse_time_arg(args, "created_after")?;
+                let created_before = parse_time_arg(args, "created_before")?;
+                let tags: Vec<String> = args["tags"].as_array()
+                    .map(|tags| tags.iter().filter_map(|t| t.as_str()).map(String::from).collect())
+                    .unwrap_or_default();
+
+                let order = crate::db::repositories::session::SessionOrder {
+                    by: args["order_by"].as_str()
+                        .map(crate::db::repositories::session::SessionOrderBy::from_str)
+                        .transpose()?
+                        .unwrap_or_default(),
+                    ascending: args["order"].as_str() == Some("asc"),
+                };
+
+                let limit = args["limit"].as_u64().map(|l| l as usize);
+                let offset = args["offset"].as_u64().unwrap_or(0) as usize;
+
+                let filter = crate::db::repositories::session::SessionFilter {
+                    project_id,
+                    status,
+                    agent_type,
+                    created_after,
+                    created_before,
+                    tags: &tags,
+                    order,
+                };
+                let (sessions, total) = session_manager.repository()
+                    .list_filtered(&filter, limit, offset)
+                    .await?;
+
+                let ids: Vec<&str> = sessions.iter().map(|s| s.id.as_str()).collect();
+                let mut session_tags = session_manager.repository().tags_for(&ids).await?;
+
+                let session_list: Vec<serde_json::Value> = sessions.iter().map(|s| {
+                    json!({
+                        "id": s.id,
+                        "name": s.name,
+                        "agent_type": s.agent_type.as_str(),
+                        "session_type": s.session_type.as_str(),
+                        "status": s.status.as_str(),
+                        "project_id": s.project_id,
+                        "tags": session_tags.remove(&s.id).unwrap_or_default(),
+                        "working_dir": s.working_dir,
+                        "created_at": s.created_at.to_rfc3339(),
+                        "updated_at": s.updated_at.to_rfc3339()
+                    })
+                }).collect();
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({ "sessions": session_list, "total": total }).to_string()
+                    }]
+                })
+            }
+            
+            "list_remote_sessions" => {
+                // Project ids and offsets are per node, so only these filters travel
+                let mut filters: serde_json::Map<String, serde_json::Value> = ["status", "agent_type", "created_after", "created_before", "tags"]
+                    .into_iter()
+                    .filter_map(|key| args.get(key).map(|value| (key.to_string(), value.clone())))
+                    .collect();
+                // Each node's newest `limit` hold the newest `limit` overall
+                let limit = args["limit"].as_u64().unwrap_or(DEFAULT_REMOTE_LIST_LIMIT).max(1);
+                filters.insert("limit".to_string(), json!(limit));
+                let filters = serde_json::Value::Object(filters);
+
+                // Boxed: call_tool can't await itself directly
+                let local = Box::pin(call_tool_text("list_sessions", filters.clone(), session_manager)).await?;
+                let mut nodes = vec![(session_manager.node_name().await, Ok(serde_json::from_str(&local)?))];
+                match session_manager.list_peer_sessions(&filters).await {
+                    Ok(peers) => nodes.extend(peers),
+                    Err(e) => tracing::debug!("Listing local sessions only: {:#}", e),
+                }
+
+                let mut sessions = Vec::new();
+                let mut total = 0;
+                let mut unreachable = Vec::new();
+                for (node, listed) in nodes {
+                    match listed {
+                        Ok(mut listed) => {
+                            total += listed["total"].as_u64().unwrap_or(0);
+                            if let Some(listed) = listed["sessions"].as_array_mut() {
+                                for mut session in listed.drain(..) {
+                                    session["node"] = json!(node);
+                                    sessions.push(session);
+                                }
+                            }
+                        }
+                        Err(e) => unreachable.push(json!({ "node": node, "error": e.to_string() })),
+                    }
+                }
+                // Newest first across nodes, as list_sessions orders each node's
+                sessions.sort_by(|a, b| b["created_at"].as_str().cmp(&a["created_at"].as_str()));
+                sessions.truncate(limit as usize);
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({
+                            "sessions": sessions,
+                            "total": total,
+                            "unreachable": unreachable
+                        }).to_string()
+                    }]
+                })
+            }
+
+            "send_message" => {
+                let session_id = args["session_id"].as_str()
+                    .ok_or_else(|| invalid_params("session_id is required"))?;
+                let content = args["content"].as_str()
+                    .ok_or_else(|| invalid_params("content is required"))?;
+
+                if session_id.is_empty() {
+                    return Err(invalid_params("session_id cannot be empty"));
+                }
+                if content.is_empty() {
+                    return Err(invalid_params("content cannot be empty"));
+                }
+
+                // Get the session to find provider session ID
+                let session = session_manager.repository().get(session_id).await?
+                    .ok_or_else(|| not_found("Session not found"))?;
+
+                let provider_session_id = session.opencode_session_id
+                    .ok_or_else(|| anyhow::anyhow!("No provider session ID"))?;
+
+                let wait = args["wait"].as_bool().unwrap_or(false);
+                let timeout = std::time::Duration::from_secs(args["timeout_secs"].as_u64().unwrap_or(600));
+
+                let mut parts = vec![crate::session::MessagePart::text(content)];
+                for attachment in args["attachments"].as_array().into_iter().flatten() {
+                    parts.push(attachment_part(attachment)?);
+                }
+
+                let mut response = session_manager.send_message_parts(
+                    session_id,
+                    &provider_session_id,
+                    session.session_type.as_str(),
+                    parts
+                ).await?;
+
+                // The provider may answer before the agent is done; wait for
+                // the activity to settle and return the final response
+                if wait {
+                    let activity = session_manager.wait_for_idle(session_id, timeout).await?;
+                    if let Some(activity) = activity {
+                        if activity.state == crate::db::repositories::session::AgentState::Error {
+                            anyhow::bail!(
+                                "Session {} failed: {}",
+                                session_id,
+                                activity.last_response.unwrap_or_default()
+                            );
+                        }
+                        if let Some(last_response) = activity.last_response {
+                            response = last_response;
+                        }
+                    }
+                }
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text { text: response }]
+                })
+            }
+            
+            "kill_session" => {
+                let session_id = args["session_id"].as_str()
+                    .ok_or_else(|| invalid_params("session_id is required"))?;
+                
+                if session_id.is_empty() {
+                    return Err(invalid_params("session_id cannot be empty"));
+                }
+
+                // Kill the provider session first
+                if let Some(session) = session_manager.repository().get(session_id).await? {
+                    if let Some(provider_id) = session.opencode_session_id {
+                        let _ = session_manager.kill_provider_session(&provider_id, session.session_type.as_str()).await;
+                    }
+                }
+                
+                session_manager.repository().update_status(
+                    session_id,
+                    crate::db::repositories::session::SessionStatus::Terminated
+                ).await?;
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({ "success": true, "session_id": session_id }).to_string()
+                    }]
+                })
+            }
+            
+            "restart_session" => {
+                let session_id = args["session_id"].as_str()
+                    .ok_or_else(|| invalid_params("session_id is required"))?;
+
+                if session_id.is_empty() {
+                    return Err(invalid_params("session_id cannot be empty"));
+                }
+
+                let session = session_manager.repository().get(session_id).await?
+                    .ok_or_else(|| not_found("Session not found"))?;
+
+                let handle = session_manager.restart_session(
+                    session_id,
+                    session.opencode_session_id.as_deref(),
+                    session.session_type.as_str(),
+                ).await?;
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({
+                            "session_id": session_id,
+                            "provider_session_id": handle.provider_id,
+                            "status": "running"
+                        }).to_string()
+                    }]
+                })
+            }
+
+            "resume_session" => {
+                let session_id = args["session_id"].as_str()
+                    .ok_or_else(|| invalid_params("session_id is required"))?;
+
+                if session_id.is_empty() {
+                    return Err(invalid_params("session_id cannot be empty"));
+                }
+
+                let session = session_manager.repository().get(session_id).await?
+                    .ok_or_else(|| not_found("Session not found"))?;
+
+                let provider_session_id = args["provider_session_id"].as_str()
+                    .or(session.opencode_session_id.as_deref())
+                    .ok_or_else(|| invalid_params("Session has no provider session ID; pass provider_session_id"))?;
+
+                let handle = session_manager.resume_session(
+                    session_id,
+                    provider_session_id,
+                    session.session_type.as_str(),
+                ).await?;
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({
+                            "session_id": session_id,
+                            "provider_session_id": handle.provider_id,
+                            "status": "running"
+                        }).to_string()
+                    }]
+                })
+            }
+
+            "delete_session" => {
+                let session_id = args["session_id"].as_str()
+                    .ok_or_else(|| invalid_params("session_id is required"))?;
+
+                if session_id.is_empty() {
+                    return Err(invalid_params("session_id cannot be empty"));
+                }
+
+                let session = session_manager.repository().get(session_id).await?
+                    .ok_or_else(|| not_found("Session not found"))?;
+
+                // Kill the provider session if it may still be alive
+                let live = matches!(
+                    session.status,
+                    crate::db::repositories::session::SessionStatus::Pending
+                        | crate::db::repositories::session::SessionStatus::Running
+                );
+                if live {
+                    if let Some(provider_id) = session.opencode_session_id {
+                        let _ = session_manager.kill_provider_session(&provider_id, session.session_type.as_str()).await;
+                    }
+                }
+
+                let discard_changes = args["discard_changes"].as_bool().unwrap_or(false);
+                if let Err(e) = session_manager.remove_workspace(session_id, discard_changes).await {
+                    if live {
+                        session_manager.repository().update_status(
+                            session_id,
+                            crate::db::repositories::session::SessionStatus::Terminated
+                        ).await?;
+                    }
+                    return Err(anyhow::anyhow!(
+                        "Kept session {}: its workspace couldn't be removed ({:#}). Commit or discard the changes, or pass discard_changes: true",
+                        session_id, e
+                    ));
+                }
+
+                // Messages are removed through ON DELETE CASCADE
+                session_manager.repository().delete(session_id).await?;
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({ "deleted": true, "session_id": session_id }).to_string()
+                    }]
+                })
+            }
+
+            "cleanup_sessions" => {
+                let days = args["older_than_days"].as_i64()
+                    .ok_or_else(|| invalid_params("older_than_days is required"))?;
+
+                if days < 0 {
+                    return Err(invalid_params("older_than_days cannot be negative"));
+                }
+
+                use crate::db::repositories::session::{SessionFilter, SessionStatus};
+
+                // Live sessions only when asked for by name
+                let statuses = match args["statuses"].as_array() {
+                    Some(values) => values.iter()
+                        .map(|v| {
+                            let s = v.as_str().ok_or_else(|| invalid_params("statuses must be strings"))?;
+                            SessionStatus::from_str(s)
+                        })
+                        .collect::<Result<Vec<_>>>()?,
+                    None => SessionStatus::FINISHED.to_vec(),
+                };
+
+                let older_than = chrono::TimeDelta::try_days(days)
+                    .and_then(|age| chrono::Utc::now().checked_sub_signed(age))
+                    .ok_or_else(|| invalid_params("older_than_days is too large"))?;
+
+                let mut matched = Vec::new();
+                for status in statuses {
+                    let filter = SessionFilter { status: Some(status), created_before: Some(older_than), ..SessionFilter::default() };
+                    matched.extend(session_manager.repository().list_filtered(&filter, None, 0).await?.0);
+                }
+
+                let summary = session_manager.delete_sessions(&matched).await?;
+                let kept: Vec<serde_json::Value> = summary.kept.iter()
+                    .map(|(session_id, error)| json!({ "session_id": session_id, "error": error }))
+                    .collect();
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({ "deleted": summary.deleted.len(), "kept": kept }).to_string()
+                    }]
+                })
+            }
+
+            "get_session" | "get_session_by_name" => {
+                let limit = args["limit"].as_u64().unwrap_or(50) as usize;
+
+                let session = if tool_call.name == "get_session_by_name" {
+                    let name = args["name"].as_str()
+                        .filter(|name| !name.is_empty())
+                        .ok_or_else(|| invalid_params("name is required"))?;
+
+                    session_manager.repository().get_by_name(name).await?
+                        .ok_or_else(|| not_found(format!("No session named {}", name)))?
+                } else {
+                    let session_id = args["session_id"].as_str()
+                        .ok_or_else(|| invalid_params("session_id is required"))?;
+
+                    if session_id.is_empty() {
+                        return Err(invalid_params("session_id cannot be empty"));
+                    }
+
+                    session_manager.repository().get(session_id).await?
+                        .ok_or_else(|| not_found("Session not found"))?
+                };
 
-                let sessions = session_manager.repository().list(project_id, status).await?;
+                let messages = session_manager.messages()
+                    .list_recent_for_session(&session.id, limit)
+                    .await?;
+                let tags = session_manager.repository().tags(&session.id).await?;
 
-                let session_list: Vec<serde_json::Value> = sessions.iter().map(|s| {
+                let message_list: Vec<serde_json::Value> = messages.iter().map(|m| {
                     json!({
-                        "id": s.id,
-                        "agent_type": s.agent_type.as_str(),
-                        "session_type": s.session_type.as_str(),
-                        "status": s.status.as_str(),
-                        "project_id": s.project_id,
-                        "working_dir": s.working_dir,
-                        "created_at": s.created_at.to_rfc3339()
+                        "role": m.role.as_str(),
+                        "content": m.content,
+                        "timestamp": m.timestamp.to_rfc3339()
                     })
                 }).collect();
 
                 Ok(ToolCallResult {
                     content: vec![ContentBlock::Text {
-                        text: json!({ "sessions": session_list }).to_string()
+                        text: json!({
+                            "id": session.id,
+                            "name": session.name,
+                            "agent_type": session.agent_type.as_str(),
+                            "session_type": session.session_type.as_str(),
+                            "status": session.status.as_str(),
+                            "project_id": session.project_id,
+                            "tags": tags,
+                            "working_dir": session.working_dir,
+                            "opencode_session_id": session.opencode_session_id,
+                            "forked_from": session.forked_from,
+                            "parent_session_id": session.parent_session_id,
+                            "created_at": session.created_at.to_rfc3339(),
+                            "updated_at": session.updated_at.to_rfc3339(),
+                            "messages": message_list
+                        }).to_string()
                     }]
                 })
             }
             
-            "send_message" => {
+            "refresh_session_status" => {
                 let session_id = args["session_id"].as_str()
-                    .ok_or_else(|| anyhow::anyhow!("session_id is required"))?;
-                let content = args["content"].as_str()
-                    .ok_or_else(|| anyhow::anyhow!("content is required"))?;
+                    .ok_or_else(|| invalid_params("session_id is required"))?;
 
-                if session_id.is_empty() {
-                    return Err(anyhow::anyhow!("session_id cannot be empty"));
-                }
-                if content.is_empty() {
-                    return Err(anyhow::anyhow!("content cannot be empty"));
+                if session_manager.repository().get(session_id).await?.is_none() {
+                    return Err(not_found("Session not found"));
                 }
 
-                // Get the session to find provider session ID
-                let session = session_manager.repository().get(session_id).await?
-                    .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+                let (previous, current) = session_manager.refresh_status(session_id).await?;
 
-                let provider_session_id = session.opencode_session_id
-                    .ok_or_else(|| anyhow::anyhow!("No provider session ID"))?;
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({
+                            "session_id": session_id,
+                            "status": current.as_str(),
+                            "previous_status": previous.as_str(),
+                            "changed": previous != current
+                        }).to_string()
+                    }]
+                })
+            }
 
-                let response = session_manager.send_message(
-                    session_id,
-                    &provider_session_id,
-                    session.session_type.as_str(),
-                    content
-                ).await?;
+            "get_session_tree" => {
+                let session_repo = session_manager.repository();
+
+                let trees = match args["session_id"].as_str() {
+                    Some(session_id) => vec![
+                        session_repo.tree(session_id).await?
+                            .ok_or_else(|| not_found("Session not found"))?,
+                    ],
+                    None => {
+                        let mut trees = Vec::new();
+                        for root in session_repo.roots().await? {
+                            if let Some(tree) = session_repo.tree(&root.id).await? {
+                                trees.push(tree);
+                            }
+                        }
+                        trees
+                    }
+                };
+
+                let trees: Vec<serde_json::Value> = trees.iter().map(session_tree_json).collect();
 
                 Ok(ToolCallResult {
-                    content: vec![ContentBlock::Text { text: response }]
+                    content: vec![ContentBlock::Text {
+                        text: json!({ "sessions": trees }).to_string()
+                    }]
                 })
             }
-            
-            "kill_session" => {
+
+            "get_session_output" => {
                 let session_id = args["session_id"].as_str()
-                    .ok_or_else(|| anyhow::anyhow!("session_id is required"))?;
-                
-                if session_id.is_empty() {
-                    return Err(anyhow::anyhow!("session_id cannot be empty"));
+                    .ok_or_else(|| invalid_params("session_id is required"))?;
+                let tail_lines = args["tail_lines"].as_u64().map(|n| n as usize);
+
+                let output = session_manager.get_session_output(session_id, tail_lines).await?;
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text { text: output }]
+                })
+            }
+
+            "search_messages" => {
+                let query = args["query"].as_str().unwrap_or("");
+                if query.trim().is_empty() {
+                    return Err(invalid_params("query cannot be empty"));
                 }
+                let limit = args["limit"].as_u64().unwrap_or(DEFAULT_SEARCH_LIMIT as u64) as usize;
 
-                // Kill the provider session first
-                if let Some(session) = session_manager.repository().get(session_id).await? {
-                    if let Some(provider_id) = session.opencode_session_id {
-                        let _ = session_manager.kill_provider_session(&provider_id, session.session_type.as_str()).await;
+                let matches = session_manager.messages().search(query, limit).await?;
+
+                let mut session_names = std::collections::HashMap::new();
+                let mut match_list = Vec::new();
+                for m in matches {
+                    if !session_names.contains_key(&m.session_id) {
+                        let name = session_manager.repository().get(&m.session_id).await?
+                            .and_then(|session| session.name);
+                        session_names.insert(m.session_id.clone(), name);
                     }
+                    match_list.push(json!({
+                        "session_id": m.session_id,
+                        "session_name": session_names[&m.session_id],
+                        "message_id": m.message_id,
+                        "role": m.role.as_str(),
+                        "timestamp": m.timestamp.to_rfc3339(),
+                        "snippet": m.snippet
+                    }));
                 }
-                
-                session_manager.repository().update_status(
-                    session_id,
-                    crate::db::repositories::session::SessionStatus::Terminated
-                ).await?;
 
                 Ok(ToolCallResult {
                     content: vec![ContentBlock::Text {
-                        text: json!({ "success": true, "session_id": session_id }).to_string()
+                        text: json!({ "matches": match_list, "total": match_list.len() }).to_string()
                     }]
                 })
             }
-            
-            "get_session" => {
+
+            "tag_session" | "untag_session" => {
                 let session_id = args["session_id"].as_str()
-                    .ok_or_else(|| anyhow::anyhow!("session_id is required"))?;
+                    .ok_or_else(|| invalid_params("session_id is required"))?;
+                let tag = args["tag"].as_str()
+                    .filter(|tag| !tag.is_empty())
+                    .ok_or_else(|| invalid_params("tag is required"))?;
 
-                if session_id.is_empty() {
-                    return Err(anyhow::anyhow!("session_id cannot be empty"));
+                let repo = session_manager.repository();
+                if repo.get(session_id).await?.is_none() {
+                    return Err(not_found("Session not found"));
                 }
-                
-                let session = session_manager.repository().get(session_id).await?
-                    .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+
+                let changed = if tool_call.name == "tag_session" {
+                    repo.add_tag(session_id, tag).await?
+                } else {
+                    repo.remove_tag(session_id, tag).await?
+                };
 
                 Ok(ToolCallResult {
                     content: vec![ContentBlock::Text {
                         text: json!({
-                            "id": session.id,
-                            "agent_type": session.agent_type.as_str(),
-                            "session_type": session.session_type.as_str(),
-                            "status": session.status.as_str(),
-                            "project_id": session.project_id,
-                            "working_dir": session.working_dir,
-                            "opencode_session_id": session.opencode_session_id,
-                            "created_at": session.created_at.to_rfc3339(),
-                            "updated_at": session.updated_at.to_rfc3339()
+                            "session_id": session_id,
+                            "tags": repo.tags(session_id).await?,
+                            "changed": changed
                         }).to_string()
                     }]
                 })
             }
-            
+
+            "set_metadata" => {
+                let session_id = args["session_id"].as_str()
+                    .ok_or_else(|| invalid_params("session_id is required"))?;
+
+                if session_id.is_empty() {
+                    return Err(invalid_params("session_id cannot be empty"));
+                }
+                if !args["metadata"].is_object() {
+                    return Err(invalid_params("metadata must be an object"));
+                }
+
+                let metadata = session_manager.repository()
+                    .merge_metadata(session_id, args["metadata"].clone())
+                    .await?
+                    .ok_or_else(|| not_found("Session not found"))?;
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({ "session_id": session_id, "metadata": metadata }).to_string()
+                    }]
+                })
+            }
+
             "fork_session" => {
                 let session_id = args["session_id"].as_str().unwrap_or("");
                 
                 // Get original session
                 let original = session_manager.repository().get(session_id).await?
-                    .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+                    .ok_or_else(|| not_found("Session not found"))?;
 
-                let provider_session_id = original.opencode_session_id
+                let provider_session_id = original.opencode_session_id.clone()
                     .ok_or_else(|| anyhow::anyhow!("No provider session ID"))?;
 
                 // Fork with provider
@@ -616,30 +2249,93 @@ impl McpServer {
                     original.session_type.as_str()
                 ).await?;
 
-                // Create new DB record
-                let db = session_manager.repository().db().clone();
-                let session_repo = crate::db::repositories::session::SessionRepository::new(db);
-                
-                let new_session = session_repo.create(
-                    original.agent_type,
-                    original.session_type,
-                    original.project_id.clone(),
-                    original.working_dir.clone(),
-                ).await?;
+                // Create new DB record, linked to the original
+                let session_repo = session_manager.repository();
+                let new_session = session_repo.create_fork(&original).await?;
 
                 session_repo.set_opencode_session_id(&new_session.id, &handle.provider_id).await?;
 
+                // Start the fork off with its own instruction if given
+                let response = match args["fork_message"].as_str() {
+                    Some(message) => Some(
+                        session_manager.send_message(
+                            &new_session.id,
+                            &handle.provider_id,
+                            original.session_type.as_str(),
+                            message,
+                        ).await
+                            .with_context(|| format!("Forked session {} created, but sending fork_message failed", new_session.id))?,
+                    ),
+                    None => None,
+                };
+
                 Ok(ToolCallResult {
                     content: vec![ContentBlock::Text {
                         text: json!({
                             "session_id": new_session.id,
                             "forked_from": session_id,
-                            "status": "running"
+                            "status": "running",
+                            "response": response
                         }).to_string()
                     }]
                 })
             }
             
+            "list_session_forks" => {
+                let session_id = args["session_id"].as_str()
+                    .ok_or_else(|| invalid_params("session_id is required"))?;
+
+                let session = session_manager.repository().get(session_id).await?
+                    .ok_or_else(|| not_found("Session not found"))?;
+
+                // Forks made through supercode, plus any the provider knows
+                // about that were made elsewhere
+                let tracked: Vec<_> = session_manager.repository().children(session_id).await?
+                    .into_iter()
+                    .filter(|s| s.forked_from.as_deref() == Some(session_id))
+                    .collect();
+
+                let (provider_children, provider_error) = match &session.opencode_session_id {
+                    Some(provider_id) => match session_manager.get_provider_children(provider_id, session.session_type.as_str()).await {
+                        Ok(children) => (children, None),
+                        Err(e) => (Vec::new(), Some(format!("{:#}", e))),
+                    },
+                    None => (Vec::new(), None),
+                };
+
+                let mut forks = Vec::new();
+                for fork in &tracked {
+                    let state = session_manager.get_session_activity(&fork.id).await?
+                        .map(|a| a.state.as_str().to_string());
+                    forks.push(json!({
+                        "session_id": fork.id,
+                        "provider_session_id": fork.opencode_session_id,
+                        "status": fork.status.as_str(),
+                        "state": state,
+                        "created_at": fork.created_at.to_rfc3339()
+                    }));
+                }
+                for provider_id in provider_children {
+                    if tracked.iter().any(|f| f.opencode_session_id.as_deref() == Some(provider_id.as_str())) {
+                        continue;
+                    }
+                    forks.push(json!({
+                        "session_id": null,
+                        "provider_session_id": provider_id
+                    }));
+                }
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({
+                            "session_id": session_id,
+                            "forks": forks,
+                            "provider_error": provider_error
+                        }).to_string()
+                    }]
+                })
+            }
+
             "list_projects" => {
                 let db = session_manager.repository().db().clone();
                 let project_repo = crate::db::repositories::project::ProjectRepository::new(db);
@@ -682,99 +2378,296 @@ impl McpServer {
                 })
             }
             
+            "update_project" => {
+                let project_id = args["project_id"].as_str()
+                    .ok_or_else(|| invalid_params("project_id is required"))?;
+                let name = args["name"].as_str().map(String::from);
+                let description = args["description"].as_str().map(String::from);
+
+                if project_id.is_empty() {
+                    return Err(invalid_params("project_id cannot be empty"));
+                }
+                if name.as_deref() == Some("") {
+                    return Err(invalid_params("name cannot be empty"));
+                }
+
+                let db = session_manager.repository().db().clone();
+                let project_repo = crate::db::repositories::project::ProjectRepository::new(db);
+
+                let project = project_repo.update(project_id, name, description).await?
+                    .ok_or_else(|| not_found("Project not found"))?;
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({
+                            "id": project.id,
+                            "name": project.name,
+                            "description": project.description,
+                            "updated_at": project.updated_at.to_rfc3339()
+                        }).to_string()
+                    }]
+                })
+            }
+            
             "run_quality_gates" => {
                 let project_dir = args["project_dir"].as_str()
-                    .ok_or_else(|| anyhow::anyhow!("project_dir is required"))?;
+                    .ok_or_else(|| invalid_params("project_dir is required"))?;
                 
                 let gate = args["gate"].as_str().unwrap_or("all");
 
                 if project_dir.is_empty() {
-                    return Err(anyhow::anyhow!("project_dir cannot be empty"));
+                    return Err(invalid_params("project_dir cannot be empty"));
                 }
 
-                use crate::agent::gates::QualityGates;
+                use crate::agent::gates::{QualityGateResult, QualityGates, DEFAULT_GATE_TIMEOUT, MAX_GATE_TIMEOUT};
+
+                let timeout = args["timeout_secs"].as_u64()
+                    .map(std::time::Duration::from_secs)
+                    .unwrap_or(DEFAULT_GATE_TIMEOUT)
+                    .min(MAX_GATE_TIMEOUT);
+
+                let changed_only = args["changed_only"].as_bool().unwrap_or(false);
+                if changed_only && gate != "all" {
+                    return Err(invalid_params("changed_only is only supported with gate \"all\""));
+                }
 
+                // Gates block for as long as the tools run, so keep them
+                // off the threads serving other requests
+                let project_dir = project_dir.to_string();
                 let results = match gate {
-                    "all" => {
-                        QualityGates::run_all(project_dir)
-                            .into_iter()
-                            .map(|r| json!({
-                                "gate": r.name,
-                                "passed": r.passed,
-                                "duration_ms": r.duration_ms,
-                                "output": r.output
-                            }))
-                            .collect::<Vec<_>>()
-                    }
-                    "rust_check" => {
-                        let r = QualityGates::rust_check(project_dir);
-                        vec![json!({
-                            "gate": r.name,
-                            "passed": r.passed,
-                            "duration_ms": r.duration_ms,
-                            "output": r.output
-                        })]
-                    }
-                    "rust_clippy" => {
-                        let r = QualityGates::rust_clippy(project_dir);
-                        vec![json!({
-                            "gate": r.name,
-                            "passed": r.passed,
-                            "duration_ms": r.duration_ms,
-                            "output": r.output
-                        })]
-                    }
-                    "npm_lint" => {
-                        let r = QualityGates::npm_lint(project_dir);
-                        vec![json!({
-                            "gate": r.name,
-                            "passed": r.passed,
-                            "duration_ms": r.duration_ms,
-                            "output": r.output
-                        })]
+                    "all" if changed_only => {
+                        let base_ref = args["base_ref"].as_str().unwrap_or("HEAD").to_string();
+                        tokio::task::spawn_blocking(move || QualityGates::run_changed(&project_dir, &base_ref, timeout))
                     }
-                    "npm_typecheck" => {
-                        let r = QualityGates::npm_typecheck(project_dir);
-                        vec![json!({
-                            "gate": r.name,
-                            "passed": r.passed,
-                            "duration_ms": r.duration_ms,
-                            "output": r.output
-                        })]
+                    "all" => tokio::task::spawn_blocking(move || QualityGates::run_all_with_timeout(&project_dir, timeout)),
+                    _ => {
+                        let run: fn(&str, std::time::Duration) -> QualityGateResult = match gate {
+                            "rust_check" => QualityGates::rust_check,
+                            "rust_clippy" => QualityGates::rust_clippy,
+                            "npm_lint" => QualityGates::npm_lint,
+                            "npm_typecheck" => QualityGates::npm_typecheck,
+                            "python_ruff" => QualityGates::python_ruff,
+                            "python_mypy" => QualityGates::python_mypy,
+                            "python_pytest" => QualityGates::python_pytest,
+                            "go_vet" => QualityGates::go_vet,
+                            "go_build" => QualityGates::go_build,
+                            "go_test" => QualityGates::go_test,
+                            _ => return Err(invalid_params(format!("Unknown gate: {}. Valid options: all, rust_check, rust_clippy, npm_lint, npm_typecheck, python_ruff, python_mypy, python_pytest, go_vet, go_build, go_test", gate))),
+                        };
+                        tokio::task::spawn_blocking(move || vec![run(&project_dir, timeout)])
                     }
-                    "python_ruff" => {
-                        let r = QualityGates::python_ruff(project_dir);
-                        vec![json!({
-                            "gate": r.name,
-                            "passed": r.passed,
-                            "duration_ms": r.duration_ms,
-                            "output": r.output
-                        })]
-                    }
-                    "python_mypy" => {
-                        let r = QualityGates::python_mypy(project_dir);
-                        vec![json!({
-                            "gate": r.name,
-                            "passed": r.passed,
-                            "duration_ms": r.duration_ms,
-                            "output": r.output
-                        })]
-                    }
-                    "python_pytest" => {
-                        let r = QualityGates::python_pytest(project_dir);
-                        vec![json!({
-                            "gate": r.name,
-                            "passed": r.passed,
-                            "duration_ms": r.duration_ms,
-                            "output": r.output
-                        })]
+                }
+                .await
+                .context("Quality gates panicked")?;
+
+                for r in &results {
+                    session_manager.metrics().record_gate(&r.name, r.passed, r.duration_ms);
+                }
+
+                let failed_gates: Vec<&str> = results.iter()
+                    .filter(|r| !r.passed)
+                    .map(|r| r.name.as_str())
+                    .collect();
+                // Nothing checked is not a pass
+                let gates_run = results.len();
+                let all_passed = gates_run > 0 && failed_gates.is_empty();
+                let failed_gates = json!(failed_gates);
+
+                let results: Vec<serde_json::Value> = results.into_iter().map(|r| json!({
+                    "gate": r.name,
+                    "passed": r.passed,
+                    "exit_code": r.exit_code,
+                    "duration_ms": r.duration_ms,
+                    "output": r.output,
+                    "stdout": r.stdout,
+                    "stderr": r.stderr
+                })).collect();
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({
+                            "all_passed": all_passed,
+                            "gates_run": gates_run,
+                            "failed_gates": failed_gates,
+                            "results": results
+                        }).to_string()
+                    }]
+                })
+            }
+
+            "list_blocked_sessions" | "wait_for_blocked" => {
+                let blocked = if tool_call.name == "wait_for_blocked" {
+                    let timeout = std::time::Duration::from_secs(args["timeout_secs"].as_u64().unwrap_or(60));
+                    session_manager.wait_for_blocked(timeout).await?
+                } else {
+                    session_manager.get_blocked_sessions().await?
+                };
+
+                let blocked_list: Vec<serde_json::Value> = blocked.iter().map(|a| {
+                    json!({
+                        "session_id": a.session_id,
+                        "state": a.state.as_str(),
+                        "approval_type": a.approval_type.map(|t| t.as_str()),
+                        "description": a.approval_description,
+                        "since": a.state_changed_at.to_rfc3339()
+                    })
+                }).collect();
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({ "blocked": blocked_list }).to_string()
+                    }]
+                })
+            }
+
+            "respond_to_approval" => {
+                let session_id = args["session_id"].as_str()
+                    .ok_or_else(|| invalid_params("session_id is required"))?;
+                let approved = args["approved"].as_bool()
+                    .ok_or_else(|| invalid_params("approved is required"))?;
+                let note = args["note"].as_str().map(String::from);
+
+                let session = session_manager.repository().get(session_id).await?
+                    .ok_or_else(|| not_found("Session not found"))?;
+
+                let provider_session_id = session.opencode_session_id
+                    .ok_or_else(|| anyhow::anyhow!("No provider session ID"))?;
+
+                session_manager.respond_to_approval(
+                    session_id,
+                    &provider_session_id,
+                    session.session_type.as_str(),
+                    approved,
+                    note,
+                ).await?;
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({ "session_id": session_id, "approved": approved }).to_string()
+                    }]
+                })
+            }
+
+            "interrupt_session" => {
+                let session_id = args["session_id"].as_str()
+                    .ok_or_else(|| invalid_params("session_id is required"))?;
+
+                let session = session_manager.repository().get(session_id).await?
+                    .ok_or_else(|| not_found("Session not found"))?;
+
+                let provider_session_id = session.opencode_session_id
+                    .ok_or_else(|| anyhow::anyhow!("No provider session ID"))?;
+
+                let interrupted = session_manager.interrupt_session(
+                    session_id,
+                    &provider_session_id,
+                    session.session_type.as_str(),
+                ).await?;
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({ "session_id": session_id, "interrupted": interrupted }).to_string()
+                    }]
+                })
+            }
+
+            "create_agent_config" => {
+                let name = args["name"].as_str()
+                    .ok_or_else(|| invalid_params("name is required"))?;
+                let agent_type = args["agent_type"].as_str()
+                    .ok_or_else(|| invalid_params("agent_type is required"))?;
+                let system_prompt = args["system_prompt"].as_str()
+                    .ok_or_else(|| invalid_params("system_prompt is required"))?;
+                let model = args["model"].as_str().unwrap_or("default");
+                let description = args["description"].as_str().map(String::from);
+
+                if name.is_empty() {
+                    return Err(invalid_params("name cannot be empty"));
+                }
+
+                let agent_type = crate::db::repositories::session::AgentType::from_str(agent_type)
+                    .map_err(|_| invalid_params(format!("Invalid agent_type: {}. Must be manager, developer, reviewer, or a custom name of lowercase letters, digits, '-' or '_'", agent_type)))?;
+
+                let config = session_manager.agent_configs().create(
+                    agent_type,
+                    name.to_string(),
+                    description,
+                    model.to_string(),
+                    Some(system_prompt.to_string()),
+                ).await?;
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({
+                            "id": config.id,
+                            "name": config.name,
+                            "agent_type": config.agent_type.as_str()
+                        }).to_string()
+                    }]
+                })
+            }
+
+            "list_agent_configs" => {
+                let agent_type = args["agent_type"].as_str()
+                    .map(crate::db::repositories::session::AgentType::from_str)
+                    .transpose()?;
+
+                let configs = session_manager.agent_configs().list(agent_type).await?;
+
+                let config_list: Vec<serde_json::Value> = configs.iter().map(|c| {
+                    json!({
+                        "id": c.id,
+                        "name": c.name,
+                        "agent_type": c.agent_type.as_str(),
+                        "description": c.description,
+                        "model": c.model,
+                        "system_prompt": c.system_prompt,
+                        "created_at": c.created_at.to_rfc3339()
+                    })
+                }).collect();
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: json!({ "agent_configs": config_list }).to_string()
+                    }]
+                })
+            }
+
+            "get_health" => {
+                let health: serde_json::Map<String, serde_json::Value> = session_manager
+                    .check_providers_health(None).await
+                    .into_iter()
+                    .map(|(session_type, healthy)| (session_type, json!(healthy)))
+                    .collect();
+
+                Ok(ToolCallResult {
+                    content: vec![ContentBlock::Text {
+                        text: serde_json::Value::Object(health).to_string()
+                    }]
+                })
+            }
+
+            "get_capabilities" => {
+                let session_types = match (args["session_id"].as_str(), args["session_type"].as_str()) {
+                    (Some(session_id), _) => {
+                        let session = session_manager.repository().get(session_id).await?
+                            .ok_or_else(|| not_found(format!("Session not found: {}", session_id)))?;
+                        vec![session.session_type.as_str().to_string()]
                     }
-                    _ => return Err(anyhow::anyhow!("Unknown gate: {}. Valid options: all, rust_check, rust_clippy, npm_lint, npm_typecheck, python_ruff, python_mypy, python_pytest", gate)),
+                    (None, Some(session_type)) => vec![session_type.to_string()],
+                    (None, None) => session_manager.session_types().into_iter().map(String::from).collect(),
                 };
 
+                let mut capabilities = serde_json::Map::new();
+                for session_type in session_types {
+                    let provider_capabilities = session_manager.provider_capabilities(&session_type)
+                        .map_err(|e| invalid_params(e.to_string()))?;
+                    capabilities.insert(session_type, json!(provider_capabilities));
+                }
+
                 Ok(ToolCallResult {
                     content: vec![ContentBlock::Text {
-                        text: json!({ "results": results }).to_string()
+                        text: json!({ "capabilities": capabilities }).to_string()
                     }]
                 })
             }
@@ -801,7 +2694,7 @@ impl McpServer {
 
             "accept_peer" => {
                 let name = args["name"].as_str()
-                    .ok_or_else(|| anyhow::anyhow!("name is required"))?;
+                    .ok_or_else(|| invalid_params("name is required"))?;
                 
                 Ok(ToolCallResult {
                     content: vec![ContentBlock::Text {
@@ -812,7 +2705,7 @@ impl McpServer {
 
             "deny_peer" => {
                 let name = args["name"].as_str()
-                    .ok_or_else(|| anyhow::anyhow!("name is required"))?;
+                    .ok_or_else(|| invalid_params("name is required"))?;
                 
                 Ok(ToolCallResult {
                     content: vec![ContentBlock::Text {
@@ -823,7 +2716,7 @@ impl McpServer {
 
             "connect_peer" => {
                 let name = args["name"].as_str()
-                    .ok_or_else(|| anyhow::anyhow!("name is required"))?;
+                    .ok_or_else(|| invalid_params("name is required"))?;
                 
                 Ok(ToolCallResult {
                     content: vec![ContentBlock::Text {
@@ -844,7 +2737,7 @@ impl McpServer {
                 })
             }
             
-            _ => Err(anyhow::anyhow!("Unknown tool: {}", tool_call.name)),
+            _ => Err(invalid_params(format!("Unknown tool: {}", tool_call.name))),
         }
     }
 }
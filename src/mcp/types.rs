@@ -1,12 +1,16 @@
 //! MCP protocol types
 
+use std::str::FromStr;
+
 use serde::{Deserialize, Serialize};
 
 /// JSON-RPC request
 #[derive(Debug, Deserialize, Serialize)]
 pub struct JsonRpcRequest {
     pub jsonrpc: String,
-    pub id: serde_json::Value,
+    /// Absent for notifications, which must not be answered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
     pub method: String,
     #[serde(default)]
     pub params: serde_json::Value,
@@ -45,6 +49,33 @@ impl JsonRpcResponse {
             }),
         }
     }
+
+    /// An error response carrying structured `data` for the client
+    pub fn error_with_data(id: serde_json::Value, code: i32, message: &str, data: serde_json::Value) -> Self {
+        let mut response = Self::error(id, code, message);
+        if let Some(error) = response.error.as_mut() {
+            error.data = Some(data);
+        }
+        response
+    }
+}
+
+/// JSON-RPC notification pushed by the server (no `id`, never answered)
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JsonRpcNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: serde_json::Value,
+}
+
+impl JsonRpcNotification {
+    pub fn new(method: &str, params: serde_json::Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -65,18 +96,24 @@ pub enum McpMethod {
     ResourcesList,
     ResourcesRead,
     PromptGet,
+    /// Liveness check; always answered with an empty result (`{}`)
+    Ping,
 }
 
-impl McpMethod {
-    pub fn from_str(s: &str) -> Option<Self> {
+impl FromStr for McpMethod {
+    /// Unknown methods carry nothing to report beyond their name
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
         match s {
-            "initialize" => Some(Self::Initialize),
-            "tools/list" => Some(Self::ToolsList),
-            "tools/call" => Some(Self::ToolsCall),
-            "resources/list" => Some(Self::ResourcesList),
-            "resources/read" => Some(Self::ResourcesRead),
-            "prompt/get" => Some(Self::PromptGet),
-            _ => None,
+            "initialize" => Ok(Self::Initialize),
+            "tools/list" => Ok(Self::ToolsList),
+            "tools/call" => Ok(Self::ToolsCall),
+            "resources/list" => Ok(Self::ResourcesList),
+            "resources/read" => Ok(Self::ResourcesRead),
+            "prompt/get" | "prompts/get" => Ok(Self::PromptGet),
+            "ping" => Ok(Self::Ping),
+            _ => Err(()),
         }
     }
 }
@@ -96,6 +133,29 @@ pub struct ToolCall {
     pub arguments: serde_json::Value,
 }
 
+/// Prompt get parameters
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PromptGetParams {
+    /// Agent type (built-in or custom) to preview the prompt for
+    pub name: String,
+    /// Optional `name`, `extra_prompt` and `agent_config` arguments
+    #[serde(default)]
+    pub arguments: Option<serde_json::Value>,
+}
+
+/// Prompt get result
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PromptGetResult {
+    pub description: String,
+    pub messages: Vec<PromptMessage>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PromptMessage {
+    pub role: String,
+    pub content: ContentBlock,
+}
+
 /// Initialize request params
 #[derive(Debug, Deserialize, Serialize)]
 pub struct InitializeParams {
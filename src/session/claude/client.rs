@@ -2,12 +2,16 @@
 
 use anyhow::{Context, Result};
 use std::collections::HashMap;
+use std::io::Read;
 use std::process::{Child, Command, Stdio};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{Notify, RwLock};
 use tracing::{debug, info, warn};
 
+use crate::session::ProviderError;
+
 /// Claude Code CLI client
 pub struct ClaudeClient {
     /// Path to claude CLI binary
@@ -18,13 +22,49 @@ pub struct ClaudeClient {
     processes: Arc<Mutex<HashMap<String, Child>>>,
     /// Session state: session_id -> session metadata
     sessions: Arc<RwLock<HashMap<String, ClaudeSession>>>,
+    /// How long a single message may run before the process is killed
+    message_timeout: Duration,
 }
 
+/// Default time a Claude Code message process may run
+const DEFAULT_MESSAGE_TIMEOUT: Duration = Duration::from_secs(300);
+
 #[derive(Debug, Clone)]
 pub struct ClaudeSession {
     pub id: String,
     pub session_id: String,
     pub working_dir: PathBuf,
+    /// Claude-assigned session id, passed as `--resume` on later messages
+    pub claude_session_id: Option<String>,
+    /// Resume with `--fork-session`, so the next message branches off
+    /// `claude_session_id` instead of continuing it. Set on forks until
+    /// Claude has assigned them their own id.
+    pub fork_on_resume: bool,
+    /// Extra system prompt appended to every message invocation
+    pub system_prompt: Option<String>,
+    /// Tool uses the last message was denied permission for. Non-empty
+    /// means the agent is blocked until someone approves them.
+    pub pending_approvals: Vec<PermissionDenial>,
+    /// Tools approved through `resolve_approvals`, allowed on every later
+    /// message with `--allowedTools`
+    pub allowed_tools: Vec<String>,
+    /// Raw stdout (and stderr, if any) of the last message invocation
+    pub last_output: Option<String>,
+    /// Notified by `interrupt` to stop waiting on the running message.
+    /// Replaced for every message, so a stale notification can't cut the
+    /// next one short.
+    pub interrupt: Arc<Notify>,
+}
+
+/// A tool use Claude Code was not allowed to run, from the
+/// `permission_denials` field of its print-mode JSON result
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PermissionDenial {
+    pub tool_name: String,
+    #[serde(default)]
+    pub tool_use_id: Option<String>,
+    #[serde(default)]
+    pub tool_input: serde_json::Value,
 }
 
 impl ClaudeClient {
@@ -35,10 +75,34 @@ impl ClaudeClient {
             work_dir: work_dir.into(),
             processes: Arc::new(Mutex::new(HashMap::new())),
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            message_timeout: DEFAULT_MESSAGE_TIMEOUT,
         }
     }
 
-    /// Create a new session - starts an interactive Claude session
+    /// Set how long a single message may run before the process is killed
+    pub fn with_message_timeout(mut self, timeout: Duration) -> Self {
+        self.message_timeout = timeout;
+        self
+    }
+
+    /// Use a different Claude Code CLI binary
+    pub fn with_claude_path(mut self, claude_path: impl Into<String>) -> Self {
+        self.claude_path = claude_path.into();
+        self
+    }
+
+    /// Use a different base directory for session working dirs
+    pub fn with_work_dir(mut self, work_dir: impl Into<PathBuf>) -> Self {
+        self.work_dir = work_dir.into();
+        self
+    }
+
+    /// Create a new session.
+    ///
+    /// No process is started here: Claude Code runs in print mode with one
+    /// process per message. Creating a session sets up its working directory,
+    /// remembers the system prompt, and records `resume_id` (if any) as the
+    /// Claude session the first message continues.
     pub async fn create_session(
         &self,
         system_prompt: Option<String>,
@@ -51,63 +115,54 @@ impl ClaudeClient {
         std::fs::create_dir_all(&work_dir)
             .context("Failed to create session working directory")?;
 
-        // Build the command
-        let mut cmd = Command::new(&self.claude_path);
-        cmd.arg("-p"); // Print mode (non-interactive)
-        cmd.arg("--output-format");
-        cmd.arg("json");
-        
-        if let Some(resume) = resume_id {
-            cmd.arg("--resume");
-            cmd.arg(&resume);
-        }
-        
-        // Set working directory
-        cmd.current_dir(&work_dir);
-        
-        // Set up pipes for communication
-        cmd.stdin(Stdio::piped());
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
-
-        debug!("Starting Claude Code session: {:?}", cmd);
-
-        let mut child = cmd.spawn()
-            .context("Failed to start Claude Code process")?;
-
-        // Get pipes
-        let stdin = child.stdin.take()
-            .context("Failed to get stdin")?;
-        
-        // Store the process immediately to avoid leak
-        self.processes.lock().unwrap().insert(session_id.clone(), child);
+        Ok(self.insert_session(session_id, work_dir, system_prompt, resume_id).await)
+    }
 
-        // Send system prompt first if provided
-        if let Some(prompt) = system_prompt {
-            use std::io::Write;
-            let mut stdin = stdin;
-            let init_message = format!("{}\n\n", prompt);
-            stdin.write_all(init_message.as_bytes())?;
-            stdin.flush()?;
-            // Keep stdin alive by not dropping
-            drop(stdin);
+    /// Create a new session that runs Claude Code in `working_dir`, which
+    /// must already exist, instead of a directory of its own
+    pub async fn create_session_in(
+        &self,
+        system_prompt: Option<String>,
+        working_dir: impl Into<PathBuf>,
+    ) -> Result<ClaudeSessionResponse> {
+        let working_dir = working_dir.into();
+        if !working_dir.is_dir() {
+            anyhow::bail!("Session working directory does not exist: {}", working_dir.display());
         }
 
+        let session_id = uuid::Uuid::new_v4().to_string();
+        Ok(self.insert_session(session_id, working_dir, system_prompt, None).await)
+    }
+
+    async fn insert_session(
+        &self,
+        session_id: String,
+        work_dir: PathBuf,
+        system_prompt: Option<String>,
+        resume_id: Option<String>,
+    ) -> ClaudeSessionResponse {
         // Store session info
         let session = ClaudeSession {
             id: session_id.clone(),
             session_id: session_id.clone(),
             working_dir: work_dir.clone(),
+            claude_session_id: resume_id,
+            fork_on_resume: false,
+            system_prompt,
+            pending_approvals: Vec::new(),
+            allowed_tools: Vec::new(),
+            last_output: None,
+            interrupt: Arc::new(Notify::new()),
         };
         self.sessions.write().await.insert(session_id.clone(), session);
 
-        info!("Created Claude Code session: {}", session_id);
+        info!("Created Claude Code session {} in {}", session_id, work_dir.display());
 
-        Ok(ClaudeSessionResponse {
+        ClaudeSessionResponse {
             id: session_id.clone(),
             session_id,
             working_dir: work_dir.to_string_lossy().to_string(),
-        })
+        }
     }
 
     /// Send a message to a session - uses a new process for each message
@@ -119,14 +174,36 @@ impl ClaudeClient {
             sessions.get(session_id).cloned()
         };
 
-        let session = session.context("Session not found")?;
+        let session = session.ok_or_else(|| ProviderError::NotFound(format!("Claude Code session not found: {}", session_id)))?;
         let work_dir = session.working_dir.clone();
 
+        self.reap_finished();
+
         // Build command for single-shot interaction
         let mut cmd = Command::new(&self.claude_path);
         cmd.arg("-p"); // Print mode
         cmd.arg("--output-format");
         cmd.arg("json");
+
+        // Continue the existing conversation once Claude has assigned an id
+        if let Some(claude_session_id) = &session.claude_session_id {
+            cmd.arg("--resume");
+            cmd.arg(claude_session_id);
+            if session.fork_on_resume {
+                cmd.arg("--fork-session");
+            }
+        }
+
+        if let Some(prompt) = &session.system_prompt {
+            cmd.arg("--append-system-prompt");
+            cmd.arg(prompt);
+        }
+
+        if !session.allowed_tools.is_empty() {
+            cmd.arg("--allowedTools");
+            cmd.arg(session.allowed_tools.join(","));
+        }
+
         cmd.current_dir(&work_dir);
         cmd.stdin(Stdio::piped());
         cmd.stdout(Stdio::piped());
@@ -136,7 +213,7 @@ impl ClaudeClient {
 
         // Use a new process for this message
         let mut child = cmd.spawn()
-            .context("Failed to start Claude Code process")?;
+            .map_err(|e| anyhow::Error::new(e).context(ProviderError::Unreachable("Failed to start Claude Code process".to_string())))?;
 
         // Get stdin and send message
         {
@@ -148,17 +225,118 @@ impl ClaudeClient {
             }
         }
 
-        // Read stdout
-        let output = child.wait_with_output()
-            .context("Failed to read Claude Code output")?;
+        // Drain the pipes on blocking threads and keep the child tracked so a
+        // timeout (or kill_session) can still kill it
+        let stdout = child.stdout.take().context("Failed to get stdout")?;
+        let stderr = child.stderr.take().context("Failed to get stderr")?;
+        let interrupt = Arc::new(Notify::new());
+        if let Some(s) = self.sessions.write().await.get_mut(session_id) {
+            s.interrupt = interrupt.clone();
+        }
+        self.processes.lock().unwrap().insert(session_id.to_string(), child);
+
+        let readers = async {
+            let stdout = tokio::task::spawn_blocking(move || read_pipe(stdout));
+            let stderr = tokio::task::spawn_blocking(move || read_pipe(stderr));
+            (stdout.await, stderr.await)
+        };
+
+        // Checked first: the interrupted process's pipes close as it dies, but
+        // its output is no reply. Stop waiting even if something it started
+        // still holds them open.
+        let finished = tokio::select! {
+            biased;
+            _ = interrupt.notified() => {
+                return Err(ProviderError::Interrupted(format!("Claude Code session {} was interrupted", session_id)).into());
+            }
+            finished = tokio::time::timeout(self.message_timeout, readers) => finished,
+        };
+
+        let (stdout, stderr) = match finished {
+            Ok((stdout, stderr)) => (
+                stdout.context("Claude Code stdout reader failed")?
+                    .context("Failed to read Claude Code output")?,
+                stderr.context("Claude Code stderr reader failed")?
+                    .context("Failed to read Claude Code output")?,
+            ),
+            Err(_) => {
+                if let Some(mut child) = self.processes.lock().unwrap().remove(session_id) {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                }
+                warn!("Claude Code session {} timed out; process killed", session_id);
+                return Err(ProviderError::Timeout(format!(
+                    "Claude Code did not respond within {}s; process killed",
+                    self.message_timeout.as_secs()
+                )).into());
+            }
+        };
+
+        // Keep the raw output around for diagnosing failed or odd replies
+        if let Some(s) = self.sessions.write().await.get_mut(session_id) {
+            s.last_output = Some(raw_output(&stdout, &stderr));
+        }
+
+        // Both pipes are closed, so the process has exited or is about to
+        let child = self.processes.lock().unwrap().remove(session_id);
+        let status = match child {
+            Some(mut child) => Some(
+                tokio::task::spawn_blocking(move || child.wait())
+                    .await
+                    .context("Claude Code wait failed")?
+                    .context("Failed to wait for Claude Code process")?,
+            ),
+            // kill_session drops the session along with its process
+            None if self.sessions.read().await.get(session_id).is_none() => {
+                anyhow::bail!("Claude Code session {} was killed", session_id)
+            }
+            // Reaped by a concurrent reap_finished; the exit status is gone
+            None => None,
+        };
+
+        if let Some(status) = status.filter(|s| !s.success()) {
+            let stderr = String::from_utf8_lossy(&stderr);
+            let code = status.code()
+                .map(|c| c.to_string())
+                .unwrap_or_else(|| "none (terminated by signal)".to_string());
+            anyhow::bail!(
+                "Claude Code exited with status {}: {}",
+                code,
+                stderr.trim()
+            );
+        }
 
         // Parse JSON output if possible
-        let response_text = String::from_utf8_lossy(&output.stdout).to_string();
+        let response_text = String::from_utf8_lossy(&stdout).to_string();
         
         // Try to extract meaningful content from JSON response
-        let content = if let Ok(json) = serde_json::from_str::<serde_json::Value>(&response_text) {
+        let json = serde_json::from_str::<serde_json::Value>(&response_text).ok();
+
+        // A message replaces whatever approvals the previous one was blocked on
+        let denials = json.as_ref().map(permission_denials).unwrap_or_default();
+        if !denials.is_empty() {
+            info!("Claude Code session {} is waiting on {} permission(s)", session_id, denials.len());
+        }
+        if let Some(s) = self.sessions.write().await.get_mut(session_id) {
+            s.pending_approvals = denials;
+        }
+
+        let content = if let Some(json) = json {
+            // Remember the Claude session id so the next message resumes this conversation
+            if let Some(claude_session_id) = json.get("session_id").and_then(|v| v.as_str()) {
+                // A fork's own id is assigned on its first message
+                if session.claude_session_id.as_deref() != Some(claude_session_id) || session.fork_on_resume {
+                    debug!("Claude session {} resumes as {}", session_id, claude_session_id);
+                    if let Some(s) = self.sessions.write().await.get_mut(session_id) {
+                        s.claude_session_id = Some(claude_session_id.to_string());
+                        s.fork_on_resume = false;
+                    }
+                }
+            }
+
             // Try to extract text content from Claude's JSON response
-            json.get("content")
+            json.get("result")
+                .or_else(|| json.get("content"))
                 .or_else(|| json.get("text"))
                 .or_else(|| json.get("message"))
                 .and_then(|v| v.as_str())
@@ -180,32 +358,199 @@ impl ClaudeClient {
         Ok(sessions.get(session_id).cloned())
     }
 
-    /// Check if a session is running
+    /// Check if a message is currently being processed for a session
     pub async fn is_running(&self, session_id: &str) -> bool {
+        match self.pid(session_id) {
+            Some(pid) => self.is_alive(pid),
+            None => false,
+        }
+    }
+
+    /// PID of the process handling a session's in-flight message, if any
+    pub fn pid(&self, session_id: &str) -> Option<u32> {
+        self.processes.lock().unwrap().get(session_id).map(|child| child.id())
+    }
+
+    /// Check whether `pid` is a Claude Code process this client started and
+    /// that has not exited yet.
+    ///
+    /// Only tracked children are considered, so unrelated processes (or a
+    /// reused PID) never count as alive.
+    pub fn is_alive(&self, pid: u32) -> bool {
         let mut processes = self.processes.lock().unwrap();
-        if let Some(child) = processes.get_mut(session_id) {
-            match child.try_wait() {
-                Ok(Some(_)) => false, // Process has exited
-                Ok(None) => true,      // Process is still running
-                Err(_) => false,       // Error checking status
-            }
-        } else {
-            false
+        match processes.values_mut().find(|child| child.id() == pid) {
+            Some(child) => matches!(child.try_wait(), Ok(None)),
+            None => false,
         }
     }
 
-    /// Terminate a session
-    pub fn kill_session(&self, session_id: &str) -> Result<()> {
+    /// Restart a session under a new id.
+    ///
+    /// Any in-flight process is killed. The replacement keeps the working
+    /// directory, system prompt and Claude conversation of the old session;
+    /// if the old session is unknown (e.g. after a server restart) its
+    /// working directory is still reused.
+    pub async fn restart_session(&self, session_id: &str) -> Result<ClaudeSessionResponse> {
+        let child = self.processes.lock().unwrap().remove(session_id);
+        if let Some(mut child) = child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+
+        let old = self.sessions.write().await.remove(session_id);
+        let (working_dir, claude_session_id, fork_on_resume, system_prompt, allowed_tools) = match old {
+            Some(old) => (old.working_dir, old.claude_session_id, old.fork_on_resume, old.system_prompt, old.allowed_tools),
+            None => (self.work_dir.join(session_id), None, false, None, Vec::new()),
+        };
+
+        std::fs::create_dir_all(&working_dir)
+            .context("Failed to create session working directory")?;
+
+        let new_id = uuid::Uuid::new_v4().to_string();
+        let session = ClaudeSession {
+            id: new_id.clone(),
+            session_id: new_id.clone(),
+            working_dir: working_dir.clone(),
+            claude_session_id,
+            fork_on_resume,
+            system_prompt,
+            pending_approvals: Vec::new(),
+            allowed_tools,
+            last_output: None,
+            interrupt: Arc::new(Notify::new()),
+        };
+        self.sessions.write().await.insert(new_id.clone(), session);
+
+        info!("Restarted Claude Code session {} as {}", session_id, new_id);
+
+        Ok(ClaudeSessionResponse {
+            id: new_id.clone(),
+            session_id: new_id,
+            working_dir: working_dir.to_string_lossy().to_string(),
+        })
+    }
+
+    /// Fork a session under a new id.
+    ///
+    /// The fork shares the working directory (Claude Code keeps
+    /// conversations per directory) and system prompt, and its first
+    /// message resumes the parent's conversation with `--fork-session`, so
+    /// it starts with the parent's context while the parent carries on
+    /// unaffected.
+    pub async fn fork_session(&self, session_id: &str) -> Result<ClaudeSessionResponse> {
+        let parent = self.sessions.read().await.get(session_id).cloned()
+            .ok_or_else(|| ProviderError::NotFound(format!("Claude Code session not found: {}", session_id)))?;
+
+        let new_id = uuid::Uuid::new_v4().to_string();
+        let session = ClaudeSession {
+            id: new_id.clone(),
+            session_id: new_id.clone(),
+            working_dir: parent.working_dir.clone(),
+            fork_on_resume: parent.claude_session_id.is_some(),
+            claude_session_id: parent.claude_session_id,
+            system_prompt: parent.system_prompt,
+            pending_approvals: Vec::new(),
+            allowed_tools: parent.allowed_tools,
+            last_output: None,
+            interrupt: Arc::new(Notify::new()),
+        };
+        self.sessions.write().await.insert(new_id.clone(), session);
+
+        info!("Forked Claude Code session {} as {}", session_id, new_id);
+
+        Ok(ClaudeSessionResponse {
+            id: new_id.clone(),
+            session_id: new_id,
+            working_dir: parent.working_dir.to_string_lossy().to_string(),
+        })
+    }
+
+    /// List all known sessions
+    pub async fn list_sessions(&self) -> Vec<ClaudeSession> {
+        self.sessions.read().await.values().cloned().collect()
+    }
+
+    /// Remove tracked processes that have already exited.
+    ///
+    /// Returns the number of entries removed.
+    pub fn reap_finished(&self) -> usize {
         let mut processes = self.processes.lock().unwrap();
-        if let Some(mut child) = processes.remove(session_id) {
+        let before = processes.len();
+        processes.retain(|session_id, child| match child.try_wait() {
+            Ok(None) => true,
+            Ok(Some(status)) => {
+                debug!("Reaped Claude Code process for session {}: {}", session_id, status);
+                false
+            }
+            Err(e) => {
+                warn!("Failed to check Claude Code process for session {}: {}", session_id, e);
+                false
+            }
+        });
+        before - processes.len()
+    }
+
+    /// Settle the permission denials a session's last message was blocked
+    /// on, returning them. Approved tools are allowed on every message from
+    /// the next one on; denied ones stay blocked, but the session no longer
+    /// waits on them.
+    pub async fn resolve_approvals(&self, session_id: &str, approved: bool) -> Result<Vec<PermissionDenial>> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions.get_mut(session_id)
+            .ok_or_else(|| ProviderError::NotFound(format!("Claude Code session not found: {}", session_id)))?;
+
+        let denials = std::mem::take(&mut session.pending_approvals);
+        if approved {
+            for denial in &denials {
+                if !session.allowed_tools.contains(&denial.tool_name) {
+                    session.allowed_tools.push(denial.tool_name.clone());
+                }
+            }
+        }
+
+        info!(
+            "{} {} permission(s) for Claude Code session {}",
+            if approved { "Granted" } else { "Denied" },
+            denials.len(),
+            session_id
+        );
+        Ok(denials)
+    }
+
+    /// Stop the message a session is processing by killing its process.
+    ///
+    /// Unlike `kill_session` the session is kept: its next message resumes
+    /// the conversation. Returns whether a message was running.
+    pub async fn interrupt(&self, session_id: &str) -> Result<bool> {
+        let sessions = self.sessions.read().await;
+        let session = sessions.get(session_id)
+            .ok_or_else(|| ProviderError::NotFound(format!("Claude Code session not found: {}", session_id)))?;
+
+        let child = self.processes.lock().unwrap().remove(session_id);
+        let Some(mut child) = child else {
+            return Ok(false);
+        };
+        session.interrupt.notify_one();
+        drop(sessions);
+
+        let _ = child.kill();
+        let _ = child.wait();
+        info!("Interrupted Claude Code session: {}", session_id);
+
+        Ok(true)
+    }
+
+    /// Terminate a session, killing any in-flight message process
+    pub async fn kill_session(&self, session_id: &str) -> Result<()> {
+        let child = self.processes.lock().unwrap().remove(session_id);
+        if let Some(mut child) = child {
             child.kill()?;
-            info!("Killed Claude Code session: {}", session_id);
+            let _ = child.wait();
         }
-        
-        // Also clean up from sessions
-        // Note: This requires &mut which we don't have here
-        // Sessions will be cleaned up on next access
-        
+
+        self.sessions.write().await.remove(session_id);
+        info!("Killed Claude Code session: {}", session_id);
+
         Ok(())
     }
 
@@ -236,10 +581,48 @@ impl Default for ClaudeClient {
             .map(PathBuf::from)
             .unwrap_or_else(|_| std::env::temp_dir().join("supercode-claude"));
 
-        Self::new(claude_path, work_dir)
+        let message_timeout = std::env::var("CLAUDE_MESSAGE_TIMEOUT")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_MESSAGE_TIMEOUT);
+
+        Self::new(claude_path, work_dir).with_message_timeout(message_timeout)
     }
 }
 
+/// Read a child pipe to the end
+fn read_pipe(mut pipe: impl Read) -> std::io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    pipe.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Combine a process's stdout and stderr into one log
+fn raw_output(stdout: &[u8], stderr: &[u8]) -> String {
+    let mut output = String::from_utf8_lossy(stdout).trim_end().to_string();
+    let stderr = String::from_utf8_lossy(stderr);
+    if !stderr.trim().is_empty() {
+        output.push_str("\n[stderr]\n");
+        output.push_str(stderr.trim_end());
+    }
+    output
+}
+
+/// Parse the `permission_denials` of a print-mode result, skipping malformed entries
+fn permission_denials(result: &serde_json::Value) -> Vec<PermissionDenial> {
+    result
+        .get("permission_denials")
+        .and_then(|v| v.as_array())
+        .map(|denials| {
+            denials
+                .iter()
+                .filter_map(|d| serde_json::from_value(d.clone()).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct ClaudeSessionResponse {
     pub id: String,
@@ -2,4 +2,4 @@
 
 pub mod client;
 
-pub use client::ClaudeClient;
+pub use client::{ClaudeClient, PermissionDenial};
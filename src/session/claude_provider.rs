@@ -1,11 +1,15 @@
 //! Claude Code session provider implementation
 
+use std::path::Path;
+
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use uuid::Uuid;
 
-use super::claude::ClaudeClient;
-use super::provider::{SessionHandle, SessionProvider, SessionStatus};
+use crate::db::repositories::session::{AgentState, ApprovalType};
+use super::claude::{ClaudeClient, PermissionDenial};
+use super::provider::{LiveState, MessagePart, ProviderCapabilities, SessionHandle, SessionProvider, SessionStatus};
+use super::ProviderError;
 
 pub struct ClaudeProvider {
     client: ClaudeClient,
@@ -19,6 +23,11 @@ impl ClaudeProvider {
     pub fn with_defaults() -> Self {
         Self::new(ClaudeClient::default())
     }
+
+    /// Get the Claude Code CLI path
+    pub fn claude_path(&self) -> &str {
+        self.client.claude_path()
+    }
 }
 
 #[async_trait]
@@ -37,6 +46,18 @@ impl SessionProvider for ClaudeProvider {
         })
     }
 
+    async fn create_session_in(&self, system_prompt: Option<String>, working_dir: &Path) -> Result<SessionHandle> {
+        let response = self.client
+            .create_session_in(system_prompt, working_dir)
+            .await
+            .context("Failed to create Claude Code session")?;
+
+        Ok(SessionHandle {
+            internal_id: Uuid::new_v4().to_string(),
+            provider_id: response.session_id,
+        })
+    }
+
     async fn send_message(&self, session_id: &str, message: &str) -> Result<String> {
         // Send message and get actual response from Claude Code
         let response = self.client
@@ -48,33 +69,47 @@ impl SessionProvider for ClaudeProvider {
         Ok(response)
     }
 
-    async fn get_status(&self, session_id: &str) -> Result<SessionStatus> {
-        let running = self.client.is_running(session_id).await;
-        
-        if running {
-            Ok(SessionStatus::Running)
-        } else {
-            // Check if session exists
-            let session = self.client.get_session(session_id).await?;
-            match session {
-                Some(_) => Ok(SessionStatus::Completed),
-                None => Ok(SessionStatus::Terminated),
+    async fn send_message_parts(&self, session_id: &str, parts: Vec<MessagePart>) -> Result<String> {
+        // Claude Code reads files (images included) from disk itself, so
+        // local files are passed as paths for it to open
+        let mut texts = Vec::new();
+        let mut files = Vec::new();
+        for part in parts {
+            match part {
+                MessagePart::Text { text } => texts.push(text),
+                MessagePart::File { url, .. } => match url.strip_prefix("file://") {
+                    Some(path) => files.push(format!("- {}", path)),
+                    None => anyhow::bail!("Claude Code sessions can only be sent local files, not {}", url),
+                },
             }
         }
+        if !files.is_empty() {
+            texts.push(format!("Attached files (read them from disk):\n{}", files.join("\n")));
+        }
+
+        self.send_message(session_id, &texts.join("\n\n")).await
     }
 
-    async fn fork_session(&self, _session_id: &str) -> Result<SessionHandle> {
-        // Claude Code doesn't support forking in the same way
-        // Create a new session instead
-        let internal_id = Uuid::new_v4().to_string();
-        
+    async fn get_status(&self, session_id: &str) -> Result<SessionStatus> {
+        // Processes only live for the duration of a message, so a known
+        // session is alive (and resumable) whether or not one is in flight.
+        // Sessions are only known to the process that created them, so an
+        // unknown one may well be alive in another; its status is unknown.
+        let session = self.client.get_session(session_id).await?;
+        match session {
+            Some(_) => Ok(SessionStatus::Running),
+            None => Err(ProviderError::NotFound(format!("Claude Code session {} is not held by this process", session_id)).into()),
+        }
+    }
+
+    async fn fork_session(&self, session_id: &str) -> Result<SessionHandle> {
         let response = self.client
-            .create_session(None, None)
+            .fork_session(session_id)
             .await
-            .context("Failed to create new Claude Code session")?;
+            .context("Failed to fork Claude Code session")?;
 
         Ok(SessionHandle {
-            internal_id,
+            internal_id: Uuid::new_v4().to_string(),
             provider_id: response.session_id,
         })
     }
@@ -82,10 +117,114 @@ impl SessionProvider for ClaudeProvider {
     async fn kill_session(&self, session_id: &str) -> Result<()> {
         self.client
             .kill_session(session_id)
+            .await
             .map_err(|e| anyhow::anyhow!("Failed to kill Claude Code session: {}", e))
     }
 
+    async fn restart_session(&self, session_id: &str) -> Result<SessionHandle> {
+        let internal_id = Uuid::new_v4().to_string();
+
+        let response = self.client
+            .restart_session(session_id)
+            .await
+            .context("Failed to restart Claude Code session")?;
+
+        Ok(SessionHandle {
+            internal_id,
+            provider_id: response.session_id,
+        })
+    }
+
+    /// Print mode denies permission prompts instead of asking, ending the
+    /// turn, so an answer can only apply to the next message: approving
+    /// lets the session use the denied tools from then on. Either way the
+    /// session stops waiting, and carries on once it is sent a message.
+    async fn respond_to_approval(&self, session_id: &str, approved: bool, _note: Option<String>) -> Result<bool> {
+        let denials = self.client.resolve_approvals(session_id, approved).await?;
+        if denials.is_empty() {
+            anyhow::bail!("Claude Code session {} has no pending approvals", session_id);
+        }
+        Ok(false)
+    }
+
+    /// Only turns started by this process can be stopped: the `claude`
+    /// process running a turn is a child of whichever server sent it
+    async fn interrupt(&self, session_id: &str) -> Result<bool> {
+        self.client.interrupt(session_id).await
+    }
+
+    async fn get_agent_state(&self, session_id: &str) -> Result<Option<LiveState>> {
+        let Some(session) = self.client.get_session(session_id).await? else {
+            return Ok(None);
+        };
+
+        if self.client.is_running(session_id).await {
+            return Ok(Some(LiveState {
+                state: AgentState::Processing,
+                approval_type: None,
+                approval_description: None,
+            }));
+        }
+
+        // Print mode denies permission prompts instead of asking, and lists
+        // them in the result; the agent is stuck until they are approved
+        if let Some(denial) = session.pending_approvals.first() {
+            return Ok(Some(LiveState {
+                state: AgentState::WaitingForApproval,
+                approval_type: Some(approval_type(&denial.tool_name)),
+                approval_description: Some(describe_denial(denial)),
+            }));
+        }
+
+        Ok(Some(LiveState {
+            state: AgentState::Idle,
+            approval_type: None,
+            approval_description: None,
+        }))
+    }
+
+    async fn get_output(&self, session_id: &str) -> Result<Option<String>> {
+        let session = self.client.get_session(session_id).await?;
+        Ok(session.and_then(|s| s.last_output))
+    }
+
     async fn health_check(&self) -> Result<bool> {
         self.client.health_check().map_err(|e| anyhow::anyhow!(e))
     }
+
+    fn unavailable_reason(&self) -> String {
+        format!("unavailable: `{} --version` failed", self.claude_path())
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            supports_fork: true,
+            supports_approval: true,
+            supports_interrupt: true,
+            ..ProviderCapabilities::default()
+        }
+    }
+}
+
+/// Map a Claude Code tool name onto the kind of action being approved
+fn approval_type(tool_name: &str) -> ApprovalType {
+    match tool_name {
+        "Write" | "Edit" | "MultiEdit" | "NotebookEdit" => ApprovalType::FileWrite,
+        "Bash" => ApprovalType::Command,
+        name if name.starts_with("mcp__") => ApprovalType::ToolUse,
+        "WebFetch" | "WebSearch" | "Task" => ApprovalType::ToolUse,
+        _ => ApprovalType::Other,
+    }
+}
+
+/// Describe a denied tool use by the input that identifies it best
+fn describe_denial(denial: &PermissionDenial) -> String {
+    let detail = ["file_path", "notebook_path", "command", "url"]
+        .iter()
+        .find_map(|key| denial.tool_input.get(*key).and_then(|v| v.as_str()));
+
+    match detail {
+        Some(detail) => format!("{}: {}", denial.tool_name, detail),
+        None => denial.tool_name.clone(),
+    }
 }
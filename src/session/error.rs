@@ -0,0 +1,59 @@
+//! Provider error classification
+
+use thiserror::Error;
+
+/// Why a provider call failed.
+///
+/// Providers attach one of these to the `anyhow::Error` they return (as the
+/// error or as context), so callers can tell a missing session from an
+/// outage from a rejected request without parsing messages. Use
+/// `ProviderError::find` to get it back.
+#[derive(Debug, Error)]
+pub enum ProviderError {
+    /// The provider has no such session
+    #[error("{0}")]
+    NotFound(String),
+
+    /// The provider could not be reached (not running, refused, DNS)
+    #[error("{0}")]
+    Unreachable(String),
+
+    /// The provider answered with an error status
+    #[error("{provider} API error: {status} - {body}")]
+    Api {
+        provider: &'static str,
+        status: u16,
+        body: String,
+    },
+
+    /// The provider did not answer in time
+    #[error("{0}")]
+    Timeout(String),
+
+    /// The turn was stopped by `interrupt` before it finished
+    #[error("{0}")]
+    Interrupted(String),
+}
+
+impl ProviderError {
+    /// The classification attached anywhere in `error`'s context chain
+    pub fn find(error: &anyhow::Error) -> Option<&ProviderError> {
+        error.downcast_ref::<ProviderError>()
+    }
+
+    /// Whether the provider itself was out of reach, so trying again later
+    /// may succeed. A provider that answered with an error is not.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, ProviderError::Unreachable(_) | ProviderError::Timeout(_))
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProviderError::NotFound(_) => "not_found",
+            ProviderError::Unreachable(_) => "unreachable",
+            ProviderError::Api { .. } => "api",
+            ProviderError::Timeout(_) => "timeout",
+            ProviderError::Interrupted(_) => "interrupted",
+        }
+    }
+}
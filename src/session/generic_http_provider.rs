@@ -0,0 +1,134 @@
+//! Session provider for OpenAI-compatible chat completions APIs
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::{Mutex, RwLock};
+use uuid::Uuid;
+
+use super::openai::{ChatMessage, OpenAiClient};
+use super::provider::{ProviderCapabilities, SessionHandle, SessionProvider, SessionStatus};
+use super::ProviderError;
+
+/// Runs sessions against a plain chat completions endpoint. The API is
+/// stateless, so each session's conversation is kept here and sent in full
+/// with every message; conversations don't survive a restart.
+pub struct GenericHttpProvider {
+    client: OpenAiClient,
+    /// provider session id -> conversation so far, locked for the length of
+    /// a send so concurrent messages to one session take turns
+    conversations: RwLock<HashMap<String, Arc<Mutex<Vec<ChatMessage>>>>>,
+}
+
+impl GenericHttpProvider {
+    pub fn new(client: OpenAiClient) -> Self {
+        Self {
+            client,
+            conversations: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Get the API base URL
+    pub fn base_url(&self) -> &str {
+        self.client.base_url()
+    }
+
+    /// Start a conversation, returning its handle
+    async fn start(&self, messages: Vec<ChatMessage>) -> SessionHandle {
+        let provider_id = format!("chat_{}", Uuid::new_v4().simple());
+        self.conversations.write().await.insert(provider_id.clone(), Arc::new(Mutex::new(messages)));
+
+        SessionHandle {
+            internal_id: Uuid::new_v4().to_string(),
+            provider_id,
+        }
+    }
+
+    async fn conversation(&self, session_id: &str) -> Result<Arc<Mutex<Vec<ChatMessage>>>> {
+        self.conversations.read().await.get(session_id).cloned()
+            .ok_or_else(|| ProviderError::NotFound(format!("No chat session {}", session_id)).into())
+    }
+}
+
+#[async_trait]
+impl SessionProvider for GenericHttpProvider {
+    async fn create_session(&self, system_prompt: Option<String>) -> Result<SessionHandle> {
+        let messages = system_prompt
+            .filter(|prompt| !prompt.is_empty())
+            .map(|prompt| ChatMessage::new("system", prompt))
+            .into_iter()
+            .collect();
+        Ok(self.start(messages).await)
+    }
+
+    async fn send_message(&self, session_id: &str, message: &str) -> Result<String> {
+        let conversation = self.conversation(session_id).await?;
+        // Held until the reply is recorded, so a second message waits for
+        // this exchange instead of being sent without it
+        let mut messages = conversation.lock().await;
+        let mut request = messages.clone();
+        request.push(ChatMessage::new("user", message));
+
+        // Only keep the exchange once the model has answered, so a failed
+        // request can simply be sent again
+        let reply = self.client.chat_completion(&request).await?;
+
+        let killed = !self.conversations.read().await.get(session_id)
+            .is_some_and(|current| Arc::ptr_eq(current, &conversation));
+        if killed {
+            return Err(ProviderError::NotFound(format!("Chat session {} was killed", session_id)).into());
+        }
+        messages.push(ChatMessage::new("user", message));
+        messages.push(ChatMessage::new("assistant", reply.clone()));
+        Ok(reply)
+    }
+
+    async fn get_status(&self, session_id: &str) -> Result<SessionStatus> {
+        // Conversations live in the process that started them; another
+        // process may hold one this one doesn't know
+        if self.conversations.read().await.contains_key(session_id) {
+            Ok(SessionStatus::Running)
+        } else {
+            Err(ProviderError::NotFound(format!("Chat session {} is not held by this process", session_id)).into())
+        }
+    }
+
+    async fn fork_session(&self, session_id: &str) -> Result<SessionHandle> {
+        let messages = self.conversation(session_id).await?.lock().await.clone();
+        Ok(self.start(messages).await)
+    }
+
+    async fn kill_session(&self, session_id: &str) -> Result<()> {
+        self.conversations.write().await.remove(session_id);
+        Ok(())
+    }
+
+    async fn restart_session(&self, session_id: &str) -> Result<SessionHandle> {
+        // A fresh conversation under the same system prompt
+        let messages = self.conversation(session_id).await?.lock().await
+            .iter()
+            .take_while(|message| message.role == "system")
+            .cloned()
+            .collect();
+        self.conversations.write().await.remove(session_id);
+        Ok(self.start(messages).await)
+    }
+
+    async fn health_check(&self) -> Result<bool> {
+        self.client.health_check().await
+    }
+
+    fn unavailable_reason(&self) -> String {
+        format!("unreachable at {}", self.base_url())
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        // Forks copy the conversation; nothing else outlives a request
+        ProviderCapabilities {
+            supports_fork: true,
+            ..ProviderCapabilities::default()
+        }
+    }
+}
@@ -1,56 +1,375 @@
 //! Session manager
 
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
 
-use crate::db::{repositories::session::{SessionRepository, SessionStatus as DbSessionStatus}, Database};
-use super::{SessionProvider, SessionHandle, OpenCodeProvider, ClaudeProvider, SessionStatus as ProviderSessionStatus};
+use chrono::Utc;
+
+use crate::db::{repositories::agent_config::{AgentConfig, AgentConfigRepository}, repositories::lease::LeaseRepository, repositories::message::{MessageRepository, MessageRole}, repositories::session::{AgentState, Session as DbSession, SessionActivity, SessionRepository, SessionStatus as DbSessionStatus}, Database};
+use crate::config::{peer::{LIST_SESSIONS, LIST_SESSIONS_RESULT, SPAWN_SESSION, SPAWN_SESSION_RESULT}, Config, PeerManager, PeerMessage};
+use crate::core::metrics::{Metrics, SessionGauges};
+use super::claude::ClaudeClient;
+use super::opencode::OpenCodeClient;
+use super::webhook::{Webhook, WebhookEvent};
+use super::workspace::{self, WorkspaceIsolation};
+use super::{GenericHttpProvider, LiveState, MessagePart, OpenAiClient, ProviderCapabilities, ProviderError, SessionProvider, SessionHandle, OpenCodeProvider, ClaudeProvider, SessionStatus as ProviderSessionStatus};
+
+/// How often `wait_for_idle` re-checks session activity
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Longest `wait_for_blocked` and `wait_for_idle` wait; longer timeouts
+/// are cut to this
+pub const MAX_WAIT: Duration = Duration::from_secs(3600);
+
+/// How long a peer may take to spawn a session, initial prompt included
+const REMOTE_SPAWN_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Lease a process holds while it runs the status reconciler. Taken first
+/// by `reattach_sessions`, so the process that reattached the sessions is
+/// the one that goes on to reconcile them.
+const RECONCILER_LEASE: &str = "reconciler";
+
+/// How long `reattach_sessions` holds the reconciler lease, unless the
+/// reconciler renews it
+const REATTACH_LEASE_TTL: Duration = Duration::from_secs(300);
+
+/// How old a session without a provider session must be before
+/// `reattach_sessions` takes its spawn for interrupted; a spawn, initial
+/// prompt included, finishes well within this
+const INTERRUPTED_SPAWN_AGE: Duration = Duration::from_secs(3600);
+
+/// How long a peer may take to list its sessions
+const REMOTE_LIST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How spawning retries a provider that can't be reached (e.g. an OpenCode
+/// server that is restarting). API errors are never retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The wait before each retry, doubling up to `max_backoff`
+    pub fn delays(&self) -> impl Iterator<Item = Duration> + '_ {
+        std::iter::successors(Some(self.initial_backoff), |d| Some((*d * 2).min(self.max_backoff)))
+            .take(self.max_retries as usize)
+    }
+}
+
+/// Outcome of `SessionManager::shutdown_all`
+#[derive(Debug, Default)]
+pub struct ShutdownSummary {
+    /// Sessions killed at their provider and marked terminated
+    pub terminated: Vec<String>,
+    /// Sessions the provider failed to kill, with the error; left running
+    pub failed: Vec<(String, String)>,
+}
+
+/// Outcome of `SessionManager::delete_sessions`
+#[derive(Debug, Default)]
+pub struct DeleteSummary {
+    /// Sessions deleted from the database
+    pub deleted: Vec<String>,
+    /// Sessions the provider failed to kill, with the error; not deleted
+    pub kept: Vec<(String, String)>,
+}
+
+/// Outcome of `SessionManager::reattach_sessions`
+#[derive(Debug, Default)]
+pub struct ReattachSummary {
+    /// Sessions whose provider session is still alive, now marked running
+    pub reattached: Vec<String>,
+    /// Sessions that can't be reattached, with the reason; marked failed
+    pub failed: Vec<(String, String)>,
+    /// Sessions whose provider couldn't be reached, with the error; left as they were
+    pub unreachable: Vec<(String, String)>,
+    /// Sessions that may be held by another process, with the reason; left as they were
+    pub skipped: Vec<(String, String)>,
+}
 
 pub struct SessionManager {
     db: Database,
     session_repo: SessionRepository,
-    opencode_provider: Arc<OpenCodeProvider>,
-    claude_provider: Arc<ClaudeProvider>,
+    message_repo: MessageRepository,
+    agent_config_repo: AgentConfigRepository,
+    /// Providers by the session type they back
+    providers: HashMap<String, Arc<dyn SessionProvider>>,
+    spawn_retry: RetryPolicy,
+    /// Refuse spawns once this many sessions are pending or running
+    max_concurrent_sessions: Option<usize>,
+    /// Where reconciliation reports blocked, completed and failed sessions
+    webhook: Option<Webhook>,
+    /// Newest activity snapshot already considered for the webhook
+    webhook_cursor: tokio::sync::Mutex<Option<i64>>,
+    metrics: Metrics,
+    /// Config naming the peers sessions can be spawned on (None: local only)
+    peers: Option<Arc<tokio::sync::RwLock<Config>>>,
+    /// Where isolated session workspaces are created
+    workspaces_dir: PathBuf,
+    /// Provider sessions this process created or attached to. Other
+    /// processes share the database, so these are the only ones it may
+    /// kill when it shuts down.
+    owned: tokio::sync::Mutex<HashSet<String>>,
+    /// Identifies this process to leases shared through the database
+    lease_holder: String,
 }
 
 impl SessionManager {
     pub fn new(db: Database) -> Self {
-        let opencode_provider = Arc::new(OpenCodeProvider::with_url("http://localhost:9090"));
-        let claude_provider = Arc::new(ClaudeProvider::with_defaults());
-        
-        Self {
-            db: db.clone(),
-            session_repo: SessionRepository::new(db),
-            opencode_provider,
-            claude_provider,
-        }
+        let providers = builtin_providers(OpenCodeProvider::with_url("http://localhost:9090"), ClaudeProvider::with_defaults());
+        Self::with_providers(db, providers)
     }
 
     pub fn with_opencode_url(db: Database, url: impl Into<String>) -> Self {
-        let opencode_provider = Arc::new(OpenCodeProvider::with_url(url));
-        let claude_provider = Arc::new(ClaudeProvider::with_defaults());
-        
+        let providers = builtin_providers(OpenCodeProvider::with_url(url), ClaudeProvider::with_defaults());
+        Self::with_providers(db, providers)
+    }
+
+    /// A session manager backed by `providers`, with default settings
+    fn with_providers(db: Database, providers: HashMap<String, Arc<dyn SessionProvider>>) -> Self {
         Self {
             db: db.clone(),
-            session_repo: SessionRepository::new(db),
-            opencode_provider,
-            claude_provider,
+            session_repo: SessionRepository::new(db.clone()),
+            message_repo: MessageRepository::new(db.clone()),
+            agent_config_repo: AgentConfigRepository::new(db),
+            providers,
+            spawn_retry: RetryPolicy::default(),
+            max_concurrent_sessions: None,
+            webhook: None,
+            webhook_cursor: tokio::sync::Mutex::new(None),
+            metrics: Metrics::new(),
+            peers: None,
+            workspaces_dir: default_workspaces_dir(),
+            owned: tokio::sync::Mutex::new(HashSet::new()),
+            lease_holder: uuid::Uuid::new_v4().to_string(),
         }
     }
 
+    /// Build a session manager with provider settings taken from `config`
+    pub fn from_config(db: Database, config: &Config) -> Result<Self> {
+        let request_timeout = (config.opencode_request_timeout_secs > 0)
+            .then(|| Duration::from_secs(config.opencode_request_timeout_secs));
+        let opencode_client = OpenCodeClient::new(config.opencode_url.clone())
+            .with_request_timeout(request_timeout)
+            .with_connect_timeout(Duration::from_secs(config.opencode_connect_timeout_secs));
+        let opencode_provider = OpenCodeProvider::new(opencode_client);
+
+        let mut claude_client = ClaudeClient::default()
+            .with_message_timeout(Duration::from_secs(config.claude_message_timeout_secs));
+        if let Some(path) = &config.claude_binary_path {
+            claude_client = claude_client.with_claude_path(path.clone());
+        }
+        if let Some(dir) = config.resolve_claude_sessions_dir()? {
+            claude_client = claude_client.with_work_dir(dir);
+        }
+        let mut providers = builtin_providers(opencode_provider, ClaudeProvider::new(claude_client));
+
+        match (&config.openai_base_url, &config.openai_model) {
+            (Some(base_url), Some(model)) => {
+                let mut client = OpenAiClient::new(base_url.clone(), model.clone());
+                let api_key = config.openai_api_key.clone()
+                    .or_else(|| std::env::var("OPENAI_API_KEY").ok())
+                    .filter(|key| !key.is_empty());
+                if let Some(key) = api_key {
+                    client = client.with_api_key(key);
+                }
+                providers.insert("openai".to_string(), Arc::new(GenericHttpProvider::new(client)));
+            }
+            (Some(_), None) => anyhow::bail!("openai_base_url is set but openai_model isn't; set both to enable openai sessions"),
+            (None, _) => {}
+        }
+
+        Ok(Self {
+            spawn_retry: RetryPolicy {
+                max_retries: config.spawn_retries,
+                ..RetryPolicy::default()
+            },
+            max_concurrent_sessions: config.max_concurrent_sessions,
+            webhook: config.webhook_url.as_deref().filter(|url| !url.is_empty()).map(Webhook::new),
+            workspaces_dir: config.resolve_workspaces_dir()?.unwrap_or_else(default_workspaces_dir),
+            ..Self::with_providers(db, providers)
+        })
+    }
+
+    /// Retry unreachable providers on spawn according to `policy`
+    pub fn with_spawn_retry(mut self, policy: RetryPolicy) -> Self {
+        self.spawn_retry = policy;
+        self
+    }
+
+    /// Create isolated session workspaces under `dir`
+    pub fn with_workspaces_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.workspaces_dir = dir.into();
+        self
+    }
+
+    /// POST an event to `url` whenever reconciliation sees a session
+    /// become blocked, complete or fail
+    pub fn with_webhook(mut self, url: impl Into<String>) -> Self {
+        self.webhook = Some(Webhook::new(url));
+        self
+    }
+
+    /// Back sessions of `session_type` with `provider`, replacing any
+    /// provider already registered for it
+    pub fn with_provider(mut self, session_type: impl Into<String>, provider: Arc<dyn SessionProvider>) -> Self {
+        self.providers.insert(session_type.into(), provider);
+        self
+    }
+
+    /// Back `openai` sessions with the API `client` talks to
+    pub fn with_openai(self, client: OpenAiClient) -> Self {
+        self.with_provider("openai", Arc::new(GenericHttpProvider::new(client)))
+    }
+
+    /// Allow spawning sessions on the peers in `config`
+    pub fn with_peers(mut self, config: Arc<tokio::sync::RwLock<Config>>) -> Self {
+        self.peers = Some(config);
+        self
+    }
+
+    /// Allow at most `limit` pending or running sessions at once
+    pub fn with_max_concurrent_sessions(mut self, limit: usize) -> Self {
+        self.max_concurrent_sessions = Some(limit);
+        self
+    }
+
     pub fn repository(&self) -> &SessionRepository {
         &self.session_repo
     }
 
+    pub fn messages(&self) -> &MessageRepository {
+        &self.message_repo
+    }
+
+    pub fn agent_configs(&self) -> &AgentConfigRepository {
+        &self.agent_config_repo
+    }
+
+    /// Counters for spawns, tool calls and quality gates
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Sessions per status and blocked sessions, for a metrics scrape
+    pub async fn session_gauges(&self) -> Result<SessionGauges> {
+        let mut by_status = Vec::new();
+        for status in [DbSessionStatus::Pending, DbSessionStatus::Running, DbSessionStatus::Completed, DbSessionStatus::Failed, DbSessionStatus::Terminated] {
+            let count = self.session_repo.count(None, Some(status), None).await?;
+            by_status.push((status.as_str(), count));
+        }
+        let blocked = self.session_repo.list_blocked().await?.len();
+        Ok(SessionGauges { by_status, blocked })
+    }
+
     /// Get the appropriate provider for a session type
     fn get_provider(&self, session_type: &str) -> Result<&dyn SessionProvider> {
-        match session_type {
-            "opencode" => Ok(self.opencode_provider.as_ref() as &dyn SessionProvider),
-            "claude" => Ok(self.claude_provider.as_ref() as &dyn SessionProvider),
-            _ => anyhow::bail!("Unknown session type: {}", session_type),
+        match self.providers.get(session_type) {
+            Some(provider) => Ok(provider.as_ref()),
+            None if session_type == "openai" => anyhow::bail!("openai sessions aren't configured; set openai_base_url and openai_model"),
+            None => anyhow::bail!("Unknown session type: {} (available: {})", session_type, self.session_types().join(", ")),
+        }
+    }
+
+    /// What the provider for `session_type` supports
+    pub fn provider_capabilities(&self, session_type: &str) -> Result<ProviderCapabilities> {
+        Ok(self.get_provider(session_type)?.capabilities())
+    }
+
+    /// Session types with a registered provider, sorted
+    pub fn session_types(&self) -> Vec<&str> {
+        let mut types: Vec<&str> = self.providers.keys().map(String::as_str).collect();
+        types.sort_unstable();
+        types
+    }
+
+    /// Ask a peer to spawn a session from `spawn_session` tool arguments,
+    /// returning the peer's result. The session lives on the peer.
+    pub async fn spawn_remote(&self, peer_name: &str, arguments: serde_json::Value) -> Result<serde_json::Value> {
+        let config = self.peer_config().await?;
+        let mut result = peer_request(
+            config, peer_name, SPAWN_SESSION, SPAWN_SESSION_RESULT, &arguments, REMOTE_SPAWN_TIMEOUT, "spawn the session",
+        ).await?;
+
+        if let Some(result) = result.as_object_mut() {
+            result.insert("peer".to_string(), serde_json::json!(peer_name));
         }
+        Ok(result)
+    }
+
+    /// Ask every verified peer for its sessions, filtered by `list_sessions`
+    /// tool arguments. Peers are asked concurrently; each gets its own result.
+    pub async fn list_peer_sessions(&self, arguments: &serde_json::Value) -> Result<Vec<(String, Result<serde_json::Value>)>> {
+        let config = self.peer_config().await?;
+
+        let mut requests = tokio::task::JoinSet::new();
+        for (peer_name, peer) in &config.peers {
+            if !peer.verified {
+                continue;
+            }
+            let (config, peer_name, arguments) = (config.clone(), peer_name.clone(), arguments.clone());
+            requests.spawn(async move {
+                let result = peer_request(
+                    config, &peer_name, LIST_SESSIONS, LIST_SESSIONS_RESULT, &arguments, REMOTE_LIST_TIMEOUT, "list its sessions",
+                ).await;
+                (peer_name, result)
+            });
+        }
+
+        let mut results = Vec::new();
+        while let Some(joined) = requests.join_next().await {
+            results.push(joined?);
+        }
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(results)
+    }
+
+    /// This node's name as its peers know it ("local" if it has none)
+    pub async fn node_name(&self) -> String {
+        match &self.peers {
+            Some(peers) => Some(peers.read().await.name.clone()).filter(|name| !name.is_empty()),
+            None => None,
+        }.unwrap_or_else(|| "local".to_string())
+    }
+
+    /// The peering config, if this node can talk to peers. A locked private
+    /// key is decrypted with `$SUPERCODE_PASSWORD` the first time it's
+    /// needed; there's no prompting, as stdin may be the MCP transport.
+    async fn peer_config(&self) -> Result<Config> {
+        let Some(peers) = &self.peers else {
+            anyhow::bail!("Talking to peers isn't enabled on this server");
+        };
+        let mut config = peers.read().await.clone();
+        if config.is_locked() {
+            let password = std::env::var("SUPERCODE_PASSWORD").map_err(|_| {
+                anyhow::anyhow!("Cannot reach peers: the private key is encrypted; set SUPERCODE_PASSWORD to unlock it")
+            })?;
+            config.unlock(Some(&password))?;
+            peers.write().await.private_key = config.private_key.clone();
+        }
+        if !config.can_peer() {
+            anyhow::bail!("Cannot reach peers: run 'supercode keygen' first");
+        }
+        Ok(config)
     }
 
     /// Spawn a new session using the appropriate provider
@@ -61,14 +380,44 @@ impl SessionManager {
         session_type: &str,
         name: Option<&str>,
         extra_prompt: Option<&str>,
+        agent_config: Option<&str>,
+    ) -> Result<SessionHandle> {
+        let result = self
+            .spawn_session_inner(session_id, agent_type, session_type, name, extra_prompt, agent_config)
+            .await;
+        self.metrics.record_spawn(agent_type, session_type, result.is_ok());
+        result
+    }
+
+    async fn spawn_session_inner(
+        &self,
+        session_id: &str,
+        agent_type: &str,
+        session_type: &str,
+        name: Option<&str>,
+        extra_prompt: Option<&str>,
+        agent_config: Option<&str>,
     ) -> Result<SessionHandle> {
         let provider = self.get_provider(session_type)?;
+        self.ensure_session_capacity(Some(session_id)).await?;
 
-        // Build the agent prompt from type + extra_prompt + compaction note
-        let agent_prompt = build_agent_prompt(agent_type, name, extra_prompt);
+        // Catch a mistyped working directory before the agent starts in it
+        let working_dir = match self.session_repo.get(session_id).await?.and_then(|s| s.working_dir) {
+            Some(dir) => {
+                let dir = Self::validate_working_dir(&dir)?;
+                self.session_repo
+                    .merge_metadata(session_id, serde_json::json!({ "git_repo": is_git_repo(&dir) }))
+                    .await?;
+                Some(dir)
+            }
+            None => None,
+        };
+
+        let agent_prompt = self.agent_prompt(agent_type, name, extra_prompt, agent_config).await?;
 
         // Create the session with empty system prompt (we'll send the full prompt as first message)
-        let handle = provider.create_session(None).await?;
+        let handle = self.create_with_retry(provider, session_type, working_dir.as_deref()).await?;
+        self.own(&handle).await;
 
         // Update the database with the provider session ID
         self.session_repo
@@ -86,17 +435,486 @@ impl SessionManager {
         Ok(handle)
     }
 
-    /// Send a message to a session
+    /// Create a provider session, retrying while the provider is unreachable.
+    /// A create that timed out isn't retried: the provider may have made
+    /// the session anyway, and a retry would leave a duplicate behind.
+    async fn create_with_retry(
+        &self,
+        provider: &dyn SessionProvider,
+        session_type: &str,
+        working_dir: Option<&Path>,
+    ) -> Result<SessionHandle> {
+        let mut delays = self.spawn_retry.delays();
+        loop {
+            let created = match working_dir {
+                Some(dir) => provider.create_session_in(None, dir).await,
+                None => provider.create_session(None).await,
+            };
+            match created {
+                Ok(handle) => return Ok(handle),
+                Err(e) if is_unreachable(&e) => match delays.next() {
+                    Some(delay) => {
+                        tracing::warn!("{} provider unreachable, retrying in {:?}: {:#}", session_type, delay, e);
+                        tokio::time::sleep(delay).await;
+                    }
+                    None => return Err(e),
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Check that a session working directory exists and is a directory,
+    /// returning its canonical path
+    pub fn validate_working_dir(working_dir: &str) -> Result<PathBuf> {
+        let path = Path::new(working_dir);
+        if !path.exists() {
+            anyhow::bail!("Working directory does not exist: {}", working_dir);
+        }
+        if !path.is_dir() {
+            anyhow::bail!("Working directory is not a directory: {}", working_dir);
+        }
+        path.canonicalize()
+            .with_context(|| format!("Cannot access working directory: {}", working_dir))
+    }
+
+    /// Give a session a workspace of its own: a git worktree or copy of its
+    /// working directory, created under the workspaces dir and named after
+    /// the session. The session is moved into it (into the same
+    /// subdirectory, for a worktree of a repository subdirectory) and the
+    /// metadata records where it came from. Returns the new working
+    /// directory.
+    pub async fn isolate_workspace(&self, session_id: &str, isolation: WorkspaceIsolation) -> Result<PathBuf> {
+        let source = self.workspace_source(session_id).await?;
+        if isolation == WorkspaceIsolation::None {
+            return Ok(source);
+        }
+
+        let dest = self.workspaces_dir.join(session_id);
+        let (from, to) = (source.clone(), dest.clone());
+        let work_dir = tokio::task::spawn_blocking(move || workspace::create_workspace(&from, &to, isolation))
+            .await
+            .context("Workspace setup panicked")??;
+
+        self.move_into_workspace(session_id, &dest, &work_dir, serde_json::json!({
+            "isolation": isolation.as_str(),
+            "source_dir": source.to_string_lossy(),
+        })).await?;
+
+        tracing::info!("Isolated session {} in {} ({} of {})", session_id, work_dir.display(), isolation.as_str(), source.display());
+        Ok(work_dir)
+    }
+
+    /// Give a session a git worktree of its own on a new branch,
+    /// `supercode/<session id>`, started from `base_branch` (default: the
+    /// repository's HEAD). The metadata records the branch as well as where
+    /// the worktree came from. Returns the session's new working directory
+    /// in the worktree and the branch, or `None` if the session's working
+    /// directory isn't in a git repository.
+    pub async fn create_worktree(&self, session_id: &str, base_branch: Option<&str>) -> Result<Option<(PathBuf, String)>> {
+        let source = self.workspace_source(session_id).await?;
+        if !is_git_repo(&source) {
+            return Ok(None);
+        }
+
+        let dest = self.workspaces_dir.join(session_id);
+        let branch = format!("supercode/{}", session_id);
+        let (from, to, new_branch, base) = (source.clone(), dest.clone(), branch.clone(), base_branch.map(String::from));
+        let work_dir = tokio::task::spawn_blocking(move || workspace::add_worktree(&from, &to, Some(&new_branch), base.as_deref()))
+            .await
+            .context("Worktree setup panicked")??;
+
+        self.move_into_workspace(session_id, &dest, &work_dir, serde_json::json!({
+            "isolation": WorkspaceIsolation::Worktree.as_str(),
+            "source_dir": source.to_string_lossy(),
+            "branch": branch,
+            "base_branch": base_branch,
+        })).await?;
+
+        tracing::info!("Created worktree {} on branch {} for session {}", dest.display(), branch, session_id);
+        Ok(Some((work_dir, branch)))
+    }
+
+    /// The validated working directory a session's workspace is made from
+    async fn workspace_source(&self, session_id: &str) -> Result<PathBuf> {
+        let session = self.session_repo.get(session_id).await?
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+        let source = session.working_dir
+            .ok_or_else(|| anyhow::anyhow!("Session {} has no working directory to isolate", session_id))?;
+        Self::validate_working_dir(&source)
+    }
+
+    /// Point a session at `work_dir` in its new workspace `dest` and record
+    /// `workspace` (which gains the workspace path) in its metadata
+    async fn move_into_workspace(&self, session_id: &str, dest: &Path, work_dir: &Path, mut workspace: serde_json::Value) -> Result<()> {
+        workspace["path"] = serde_json::json!(dest.to_string_lossy());
+        self.session_repo.set_working_dir(session_id, &work_dir.to_string_lossy()).await?;
+        self.session_repo
+            .merge_metadata(session_id, serde_json::json!({ "workspace": workspace }))
+            .await?;
+        Ok(())
+    }
+
+    /// Delete the workspace `isolate_workspace` or `create_worktree` made
+    /// for a session, e.g. when the session failed to start or is deleted.
+    /// Does nothing for sessions working in a directory of their own
+    /// choosing, or in a workspace they share with the session they were
+    /// forked from. A worktree with uncommitted changes is kept, failing,
+    /// unless `force` says to discard them.
+    pub async fn remove_workspace(&self, session_id: &str, force: bool) -> Result<()> {
+        let Some(session) = self.session_repo.get(session_id).await? else {
+            return Ok(());
+        };
+        let metadata: Option<serde_json::Value> = session.metadata.as_deref().and_then(|m| serde_json::from_str(m).ok());
+        let workspace = metadata.as_ref().and_then(|m| m.get("workspace"));
+        let isolation = workspace.and_then(|w| w["isolation"].as_str()).map(WorkspaceIsolation::from_str).transpose()?;
+        let source = workspace.and_then(|w| w["source_dir"].as_str()).map(PathBuf::from);
+        let dest = workspace.and_then(|w| w["path"].as_str()).map(PathBuf::from);
+        let (Some(isolation), Some(source), Some(dest)) = (isolation, source, dest) else {
+            return Ok(());
+        };
+        // Workspaces are named after the session that owns them
+        if dest.file_name() != Some(std::ffi::OsStr::new(session_id)) {
+            return Ok(());
+        }
+
+        tokio::task::spawn_blocking(move || workspace::remove_workspace(&source, &dest, isolation, force))
+            .await
+            .context("Workspace removal panicked")?
+    }
+
+    /// Assemble the initial prompt a spawned agent receives
+    pub async fn agent_prompt(
+        &self,
+        agent_type: &str,
+        name: Option<&str>,
+        extra_prompt: Option<&str>,
+        agent_config: Option<&str>,
+    ) -> Result<String> {
+        // An explicitly named template must exist; otherwise a template named
+        // after the agent type overrides the built-in prompt if present
+        let template = match agent_config {
+            Some(config_name) => Some(
+                self.agent_config_repo.get_by_name(config_name).await?
+                    .ok_or_else(|| anyhow::anyhow!("Agent config not found: {}", config_name))?,
+            ),
+            None => self.agent_config_repo.get_by_name(agent_type).await?,
+        };
+
+        // Build the agent prompt from type + extra_prompt + compaction note
+        Ok(build_agent_prompt(agent_type, name, extra_prompt, template.as_ref()))
+    }
+
+    /// Send a message to a session, recording both sides of the exchange
     pub async fn send_message(
         &self,
         session_id: &str,
         provider_session_id: &str,
         session_type: &str,
         message: &str,
+    ) -> Result<String> {
+        self.send_message_parts(session_id, provider_session_id, session_type, vec![MessagePart::text(message)]).await
+    }
+
+    /// Send a message made of text and file parts, recording both sides of
+    /// the exchange. Files appear in the history as `[file: name (mime)]`.
+    pub async fn send_message_parts(
+        &self,
+        session_id: &str,
+        provider_session_id: &str,
+        session_type: &str,
+        parts: Vec<MessagePart>,
     ) -> Result<String> {
         let provider = self.get_provider(session_type)?;
+        let message = MessagePart::describe(&parts);
+        let message = message.as_str();
+
+        self.message_repo.create(session_id, MessageRole::User, message).await?;
+        self.record_activity(session_id, AgentState::Processing, Some(message), None).await;
+
+        let response = match provider.send_message_parts(provider_session_id, parts).await {
+            Ok(response) => response,
+            Err(e) => {
+                // An interrupted agent is waiting for its next instruction
+                let state = match ProviderError::find(&e) {
+                    Some(ProviderError::Interrupted(_)) => AgentState::Idle,
+                    _ => AgentState::Error,
+                };
+                self.record_activity(session_id, state, Some(message), Some(&e.to_string())).await;
+                return Err(e);
+            }
+        };
+
+        self.message_repo.create(session_id, MessageRole::Assistant, &response).await?;
+        self.record_activity(session_id, AgentState::Idle, Some(message), Some(&response)).await;
+
+        Ok(response)
+    }
+
+    /// Raw output for a session, for diagnosing failed agents.
+    ///
+    /// Prefers what the provider holds (Claude Code's last process output,
+    /// OpenCode's transcript) and falls back to the stored message history.
+    /// Only the last `tail_lines` lines are returned when given.
+    pub async fn get_session_output(&self, session_id: &str, tail_lines: Option<usize>) -> Result<String> {
+        let session = self.session_repo.get(session_id).await?
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+
+        let mut output = None;
+        if let Some(provider_session_id) = session.opencode_session_id.as_deref() {
+            let provider = self.get_provider(session.session_type.as_str())?;
+            match provider.get_output(provider_session_id).await {
+                Ok(provider_output) => output = provider_output,
+                Err(e) => tracing::debug!("Falling back to stored messages for {}: {}", session_id, e),
+            }
+        }
+
+        let output = match output {
+            Some(output) => output,
+            None => self.message_repo.list_for_session(session_id).await?
+                .iter()
+                .map(|m| format!("[{}] {}", m.role.as_str(), m.content))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
 
-        provider.send_message(provider_session_id, message).await
+        Ok(match tail_lines {
+            Some(n) => {
+                let lines: Vec<&str> = output.lines().collect();
+                lines[lines.len().saturating_sub(n)..].join("\n")
+            }
+            None => output,
+        })
+    }
+
+    /// Render a session's stored messages as Markdown, e.g. for pasting
+    /// into a PR or issue. Each message gets a role heading; its content is
+    /// kept as written, so code blocks survive.
+    pub async fn export_transcript(&self, session_id: &str) -> Result<String> {
+        let session = self.session_repo.get(session_id).await?
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+        let messages = self.message_repo.list_for_session(session_id).await?;
+
+        let mut out = format!("# Session {}\n\n", session.name.as_deref().unwrap_or(&session.id));
+        out.push_str(&format!("- **Agent:** {} ({})\n", session.agent_type.as_str(), session.session_type.as_str()));
+        out.push_str(&format!("- **Status:** {}\n", session.status.as_str()));
+        out.push_str(&format!("- **Created:** {}\n", session.created_at.to_rfc3339()));
+        if let Some(dir) = &session.working_dir {
+            out.push_str(&format!("- **Working dir:** `{}`\n", dir));
+        }
+
+        if messages.is_empty() {
+            out.push_str("\n_No messages recorded._\n");
+        }
+        for message in &messages {
+            let role = match message.role {
+                MessageRole::User => "User",
+                MessageRole::Assistant => "Assistant",
+                MessageRole::System => "System",
+            };
+            out.push_str(&format!("\n## {} ({})\n\n", role, message.timestamp.to_rfc3339()));
+            out.push_str(message.content.trim_end());
+            out.push('\n');
+            // A truncated reply can leave a code block open; close it so it
+            // doesn't swallow the rest of the transcript
+            if let Some(fence) = unclosed_fence(&message.content) {
+                out.push_str(fence);
+                out.push('\n');
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Get the last recorded activity for a session
+    pub async fn get_session_activity(&self, session_id: &str) -> Result<Option<SessionActivity>> {
+        self.session_repo.latest_activity(session_id).await
+    }
+
+    /// Sessions whose latest activity is waiting for approval
+    pub async fn get_blocked_sessions(&self) -> Result<Vec<SessionActivity>> {
+        self.refresh_live_states().await;
+        self.session_repo.list_blocked().await
+    }
+
+    /// Record the live state of running sessions whose provider can report
+    /// it, so approvals raised inside the backend show up as blocked
+    async fn refresh_live_states(&self) {
+        let sessions = match self.session_repo.list(None, Some(DbSessionStatus::Running), None).await {
+            Ok(sessions) => sessions,
+            Err(e) => {
+                tracing::warn!("Failed to list running sessions: {}", e);
+                return;
+            }
+        };
+
+        for session in sessions {
+            let Some(provider_session_id) = session.opencode_session_id.as_deref() else {
+                continue;
+            };
+            let Ok(provider) = self.get_provider(session.session_type.as_str()) else {
+                continue;
+            };
+
+            match provider.get_agent_state(provider_session_id).await {
+                Ok(Some(live)) => self.sync_live_state(&session.id, live).await,
+                Ok(None) => {}
+                Err(e) => tracing::debug!("Failed to get live state for session {}: {}", session.id, e),
+            }
+        }
+    }
+
+    /// Record a provider-reported state unless it matches the latest activity
+    async fn sync_live_state(&self, session_id: &str, live: LiveState) {
+        let latest = match self.session_repo.latest_activity(session_id).await {
+            Ok(latest) => latest,
+            Err(e) => {
+                tracing::warn!("Failed to get activity for session {}: {}", session_id, e);
+                return;
+            }
+        };
+
+        if let Some(latest) = &latest {
+            if latest.state == live.state && latest.approval_description == live.approval_description {
+                return;
+            }
+        }
+
+        let activity = SessionActivity {
+            session_id: session_id.to_string(),
+            state: live.state,
+            last_message: latest.as_ref().and_then(|a| a.last_message.clone()),
+            last_response: latest.and_then(|a| a.last_response),
+            state_changed_at: Utc::now(),
+            approval_type: live.approval_type,
+            approval_description: live.approval_description,
+        };
+
+        if let Err(e) = self.session_repo.record_activity(&activity).await {
+            tracing::warn!("Failed to record activity for session {}: {}", session_id, e);
+        }
+    }
+
+    /// Whether a session is currently waiting for approval
+    pub async fn is_session_waiting(&self, session_id: &str) -> Result<bool> {
+        let activity = self.session_repo.latest_activity(session_id).await?;
+        Ok(matches!(activity, Some(a) if a.state == AgentState::WaitingForApproval))
+    }
+
+    /// Wait until a session becomes blocked, returning the sessions that
+    /// became blocked after the call started (empty on timeout, which is at
+    /// most `MAX_WAIT`)
+    pub async fn wait_for_blocked(&self, timeout: Duration) -> Result<Vec<SessionActivity>> {
+        let deadline = tokio::time::Instant::now() + timeout.min(MAX_WAIT);
+
+        // Sessions already blocked when we started don't count as transitions
+        let already_blocked: Vec<(String, chrono::DateTime<Utc>)> = self.get_blocked_sessions().await?
+            .into_iter()
+            .map(|a| (a.session_id, a.state_changed_at))
+            .collect();
+
+        loop {
+            let newly_blocked: Vec<SessionActivity> = self.get_blocked_sessions().await?
+                .into_iter()
+                .filter(|a| !already_blocked.contains(&(a.session_id.clone(), a.state_changed_at)))
+                .collect();
+
+            if !newly_blocked.is_empty() || tokio::time::Instant::now() >= deadline {
+                return Ok(newly_blocked);
+            }
+            tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Grant or deny the action a blocked session is waiting on
+    pub async fn respond_to_approval(
+        &self,
+        session_id: &str,
+        provider_session_id: &str,
+        session_type: &str,
+        approved: bool,
+        note: Option<String>,
+    ) -> Result<()> {
+        if !self.is_session_waiting(session_id).await? {
+            anyhow::bail!("Session {} is not waiting for approval", session_id);
+        }
+
+        let provider = self.get_provider(session_type)?;
+        let carries_on = provider.respond_to_approval(provider_session_id, approved, note).await?;
+
+        // Either way the agent is no longer blocked; it carries on with the
+        // answer, or waits for its next message if its turn already ended
+        let state = if carries_on { AgentState::Processing } else { AgentState::Idle };
+        self.record_activity(session_id, state, None, None).await;
+
+        Ok(())
+    }
+
+    /// Stop the turn a session is working on without ending the session, so
+    /// it can be given a corrected instruction
+    pub async fn interrupt_session(
+        &self,
+        session_id: &str,
+        provider_session_id: &str,
+        session_type: &str,
+    ) -> Result<bool> {
+        let provider = self.get_provider(session_type)?;
+        let stopped = provider.interrupt(provider_session_id).await?;
+
+        if stopped {
+            self.record_activity(session_id, AgentState::Idle, None, None).await;
+        }
+
+        Ok(stopped)
+    }
+
+    /// Poll a session's activity until it is no longer processing, returning
+    /// the settled activity. Fails if it is still processing after `timeout`
+    /// (at most `MAX_WAIT`).
+    pub async fn wait_for_idle(&self, session_id: &str, timeout: Duration) -> Result<Option<SessionActivity>> {
+        let timeout = timeout.min(MAX_WAIT);
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        loop {
+            let activity = self.session_repo.latest_activity(session_id).await?;
+            let processing = matches!(&activity, Some(a) if a.state == AgentState::Processing);
+            if !processing {
+                return Ok(activity);
+            }
+
+            if tokio::time::Instant::now() >= deadline {
+                anyhow::bail!(
+                    "Session {} still processing after {}s",
+                    session_id,
+                    timeout.as_secs()
+                );
+            }
+            tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Persist an activity snapshot. Failures are logged rather than
+    /// propagated so bookkeeping never masks the provider result.
+    async fn record_activity(
+        &self,
+        session_id: &str,
+        state: AgentState,
+        last_message: Option<&str>,
+        last_response: Option<&str>,
+    ) {
+        let activity = SessionActivity {
+            session_id: session_id.to_string(),
+            state,
+            last_message: last_message.map(String::from),
+            last_response: last_response.map(String::from),
+            state_changed_at: Utc::now(),
+            approval_type: None,
+            approval_description: None,
+        };
+
+        if let Err(e) = self.session_repo.record_activity(&activity).await {
+            tracing::warn!("Failed to record activity for session {}: {}", session_id, e);
+        }
     }
 
     /// Get session status from provider
@@ -110,15 +928,204 @@ impl SessionManager {
         provider.get_status(provider_session_id).await
     }
 
-    /// Fork a session
+    /// Ask the provider how a session is really doing and record it.
+    ///
+    /// Only pending or running rows change: terminal states are final, and
+    /// rows without a provider session have nothing to ask about. Returns
+    /// the status the row had before and the one it has now.
+    pub async fn refresh_status(&self, session_id: &str) -> Result<(DbSessionStatus, DbSessionStatus)> {
+        let session = self.session_repo.get(session_id).await?
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+        let previous = session.status;
+
+        let active = matches!(previous, DbSessionStatus::Pending | DbSessionStatus::Running);
+        let Some(provider_session_id) = session.opencode_session_id.filter(|_| active) else {
+            return Ok((previous, previous));
+        };
+
+        let current = match self.get_session_status(&provider_session_id, session.session_type.as_str()).await? {
+            ProviderSessionStatus::Pending => DbSessionStatus::Pending,
+            ProviderSessionStatus::Running => DbSessionStatus::Running,
+            ProviderSessionStatus::Completed => DbSessionStatus::Completed,
+            ProviderSessionStatus::Failed => DbSessionStatus::Failed,
+            ProviderSessionStatus::Terminated => DbSessionStatus::Terminated,
+        };
+        if current != previous {
+            tracing::info!("Session {} is {} at its provider (was {})", session_id, current.as_str(), previous.as_str());
+            self.session_repo.update_status(session_id, current).await?;
+        }
+
+        Ok((previous, current))
+    }
+
+    /// Refresh every pending or running session's status from its provider,
+    /// then record the live agent state of those still running. Returns how
+    /// many statuses changed. Sessions whose provider can't be asked are
+    /// left as they are.
+    pub async fn reconcile_statuses(&self) -> Result<usize> {
+        // Only report what happens from the first pass on, not history
+        if self.webhook.is_some() {
+            let mut cursor = self.webhook_cursor.lock().await;
+            if cursor.is_none() {
+                *cursor = Some(self.session_repo.latest_activity_id().await?);
+            }
+        }
+
+        let mut changed = 0;
+        let mut events = Vec::new();
+        for status in [DbSessionStatus::Pending, DbSessionStatus::Running] {
+            for session in self.session_repo.list(None, Some(status), None).await? {
+                match self.refresh_status(&session.id).await {
+                    Ok((previous, current)) if previous != current => {
+                        changed += 1;
+                        events.extend(WebhookEvent::from_status(&session.id, current));
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::debug!("Could not refresh status of session {}: {:#}", session.id, e),
+                }
+            }
+        }
+        self.refresh_live_states().await;
+
+        if let Err(e) = self.send_webhook_events(events).await {
+            tracing::warn!("Failed to send webhook events: {}", e);
+        }
+        Ok(changed)
+    }
+
+    /// Send `events` plus those in activity recorded since the last call,
+    /// whether it came from the reconciler or from messages, to the webhook.
+    ///
+    /// Activity events are delivered at least once: the cursor only moves
+    /// past a snapshot once its event is sent, so after a failed delivery
+    /// the next call starts again from there. Status events are sent once;
+    /// a failed one is logged and lost, as nothing records it to retry from.
+    async fn send_webhook_events(&self, events: Vec<WebhookEvent>) -> Result<()> {
+        let Some(webhook) = &self.webhook else {
+            return Ok(());
+        };
+
+        for event in events {
+            if let Err(e) = self.send_webhook_event(webhook, event).await {
+                tracing::warn!("{:#}", e);
+            }
+        }
+
+        // Held throughout, so concurrent calls can't send a snapshot twice
+        let mut cursor = self.webhook_cursor.lock().await;
+        for change in self.session_repo.activity_after(cursor.unwrap_or(0)).await? {
+            if let Some(event) = WebhookEvent::from_activity(&change) {
+                self.send_webhook_event(webhook, event).await?;
+            }
+            *cursor = Some(change.id);
+        }
+        Ok(())
+    }
+
+    /// Name `event`'s session and send it
+    async fn send_webhook_event(&self, webhook: &Webhook, mut event: WebhookEvent) -> Result<()> {
+        event.name = self.session_repo.get(&event.session_id).await?.and_then(|s| s.name);
+        webhook.send(&event).await
+    }
+
+    /// Run `reconcile_statuses` now and then every `every` in the background,
+    /// until the returned task is aborted or the runtime shuts down.
+    ///
+    /// Only one reconciler runs per database: each pass first takes a lease
+    /// shared by every process, so several servers on one database don't
+    /// each rewrite statuses and send every webhook event.
+    pub fn spawn_reconciler(self: &Arc<Self>, every: Duration) -> tokio::task::JoinHandle<()> {
+        let manager = Arc::clone(self);
+        let leases = LeaseRepository::new(self.db.clone());
+        let holder = self.lease_holder.clone();
+        // Outlives a missed pass or two, so a slow pass doesn't lose it
+        let ttl = chrono::Duration::from_std(every * 3).unwrap_or(chrono::Duration::days(1));
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(every);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                match leases.acquire(RECONCILER_LEASE, &holder, ttl).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        tracing::debug!("Another process is reconciling session statuses");
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to take the reconciler lease: {}", e);
+                        continue;
+                    }
+                }
+                match manager.reconcile_statuses().await {
+                    Ok(0) => {}
+                    Ok(changed) => tracing::info!("Updated the status of {} session(s) from their providers", changed),
+                    Err(e) => tracing::warn!("Failed to reconcile session statuses: {}", e),
+                }
+            }
+        })
+    }
+
+    /// Fork a session. Fails for providers without `supports_fork` rather
+    /// than starting an unrelated session.
     pub async fn fork_session(
         &self,
         provider_session_id: &str,
         session_type: &str,
     ) -> Result<SessionHandle> {
         let provider = self.get_provider(session_type)?;
+        if !provider.capabilities().supports_fork {
+            anyhow::bail!("Provider {} cannot fork sessions", session_type);
+        }
+        self.ensure_session_capacity(None).await?;
 
-        provider.fork_session(provider_session_id).await
+        let handle = provider.fork_session(provider_session_id).await?;
+        self.own(&handle).await;
+        Ok(handle)
+    }
+
+    /// Record that this process holds `handle`'s provider session
+    async fn own(&self, handle: &SessionHandle) {
+        self.owned.lock().await.insert(handle.provider_id.clone());
+    }
+
+    /// Fail if starting another session would go past
+    /// `max_concurrent_sessions`. `spawning` is the session being started,
+    /// whose own pending row doesn't count against the limit.
+    async fn ensure_session_capacity(&self, spawning: Option<&str>) -> Result<()> {
+        let Some(limit) = self.max_concurrent_sessions else {
+            return Ok(());
+        };
+
+        let mut active = 0;
+        for status in [DbSessionStatus::Pending, DbSessionStatus::Running] {
+            active += self.session_repo.count(None, Some(status), None).await?;
+        }
+        if let Some(id) = spawning {
+            let counted = self.session_repo.get(id).await?
+                .is_some_and(|s| matches!(s.status, DbSessionStatus::Pending | DbSessionStatus::Running));
+            if counted {
+                active -= 1;
+            }
+        }
+
+        if active >= limit {
+            anyhow::bail!(
+                "Session limit reached: {} of max_concurrent_sessions = {} already pending or running; kill a session first",
+                active,
+                limit
+            );
+        }
+        Ok(())
+    }
+
+    /// Provider IDs of the sessions the provider knows were forked from this one
+    pub async fn get_provider_children(
+        &self,
+        provider_session_id: &str,
+        session_type: &str,
+    ) -> Result<Vec<String>> {
+        let provider = self.get_provider(session_type)?;
+        provider.get_children(provider_session_id).await
     }
 
     /// Kill a session at the provider level
@@ -131,25 +1138,287 @@ impl SessionManager {
         provider.kill_session(provider_session_id).await
     }
 
-    /// Check OpenCode provider health
-    pub async fn check_opencode_health(&self) -> Result<bool> {
-        self.opencode_provider.health_check().await
+    /// Kill every running session this process created or attached to at
+    /// its provider and mark it terminated. Sessions held by other
+    /// processes sharing the database are left alone. Sessions whose
+    /// provider kill fails stay `running` so they can be found and cleaned
+    /// up later.
+    pub async fn shutdown_all(&self) -> Result<ShutdownSummary> {
+        let mut summary = ShutdownSummary::default();
+        let owned = self.owned.lock().await.clone();
+
+        for session in self.session_repo.list(None, Some(DbSessionStatus::Running), None).await? {
+            let Some(provider_id) = session.opencode_session_id.as_deref() else {
+                continue;
+            };
+            if !owned.contains(provider_id) {
+                continue;
+            }
+            if let Err(e) = self.kill_provider_session(provider_id, session.session_type.as_str()).await {
+                summary.failed.push((session.id, e.to_string()));
+                continue;
+            }
+
+            self.session_repo.update_status(&session.id, DbSessionStatus::Terminated).await?;
+            summary.terminated.push(session.id);
+        }
+
+        Ok(summary)
+    }
+
+    /// Delete sessions, first killing the provider sessions of any that are
+    /// still pending or running so none is left behind with no row to find
+    /// it by. A session whose provider kill fails is kept.
+    pub async fn delete_sessions(&self, sessions: &[DbSession]) -> Result<DeleteSummary> {
+        let mut summary = DeleteSummary::default();
+
+        for session in sessions {
+            let live = matches!(session.status, DbSessionStatus::Pending | DbSessionStatus::Running);
+            if let (true, Some(provider_id)) = (live, session.opencode_session_id.as_deref()) {
+                if let Err(e) = self.kill_provider_session(provider_id, session.session_type.as_str()).await {
+                    summary.kept.push((session.id.clone(), format!("{:#}", e)));
+                    continue;
+                }
+            }
+
+            self.session_repo.delete(&session.id).await?;
+            summary.deleted.push(session.id.clone());
+        }
+
+        Ok(summary)
+    }
+
+    /// Reconnect the sessions a previous run of the server left pending or
+    /// running. Meant to run once at startup, when this process owns none
+    /// of them yet.
+    ///
+    /// Another server may be running on the same database, holding sessions
+    /// of its own, so this first takes the reconciler lease and does nothing
+    /// (returning an empty summary) if another process has it. Run the
+    /// reconciler afterwards to keep the lease.
+    ///
+    /// Sessions whose provider session is still alive are marked running.
+    /// Sessions that can't be reattached are marked failed, with the reason
+    /// in their metadata. That covers spawns interrupted before a provider
+    /// session existed, once older than any spawn takes, and provider
+    /// sessions that are gone. Sessions whose provider is unreachable are
+    /// left alone for a later attempt, as are sessions this process has no
+    /// provider for or whose provider can't resume, which another process
+    /// may still hold.
+    pub async fn reattach_sessions(&self) -> Result<ReattachSummary> {
+        let mut summary = ReattachSummary::default();
+
+        let ttl = chrono::Duration::from_std(REATTACH_LEASE_TTL)?;
+        if !LeaseRepository::new(self.db.clone()).acquire(RECONCILER_LEASE, &self.lease_holder, ttl).await? {
+            tracing::info!("Another process holds the sessions; not reattaching them");
+            return Ok(summary);
+        }
+        let interrupted_before = Utc::now() - chrono::Duration::from_std(INTERRUPTED_SPAWN_AGE)?;
+
+        for status in [DbSessionStatus::Pending, DbSessionStatus::Running] {
+            for session in self.session_repo.list(None, Some(status), None).await? {
+                let resumed = match session.opencode_session_id.as_deref() {
+                    Some(provider_id) => {
+                        let provider = match self.get_provider(session.session_type.as_str()) {
+                            Ok(provider) => provider,
+                            Err(e) => {
+                                summary.skipped.push((session.id, format!("{:#}", e)));
+                                continue;
+                            }
+                        };
+                        if !provider.capabilities().supports_resume {
+                            let reason = format!("{} sessions can't be resumed by another process", session.session_type.as_str());
+                            summary.skipped.push((session.id, reason));
+                            continue;
+                        }
+                        provider.resume_session(provider_id).await
+                    }
+                    // Possibly a spawn another process is still running
+                    None if session.updated_at > interrupted_before => {
+                        summary.skipped.push((session.id, "Spawn may still be in progress".to_string()));
+                        continue;
+                    }
+                    None => Err(anyhow::anyhow!("Spawn was interrupted before a provider session was created")),
+                };
+
+                match resumed {
+                    Ok(handle) => {
+                        self.own(&handle).await;
+                        self.session_repo.set_opencode_session_id(&session.id, &handle.provider_id).await?;
+                        summary.reattached.push(session.id);
+                    }
+                    Err(e) if ProviderError::find(&e).is_some_and(ProviderError::is_transient) => {
+                        summary.unreachable.push((session.id, format!("{:#}", e)));
+                    }
+                    Err(e) => {
+                        let reason = format!("{:#}", e);
+                        self.session_repo.update_status(&session.id, DbSessionStatus::Failed).await?;
+                        self.session_repo.merge_metadata(&session.id, serde_json::json!({ "error": reason })).await?;
+                        summary.failed.push((session.id, reason));
+                    }
+                }
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Restart a session in place: the DB row is kept, the provider session is
+    /// replaced and the new provider id recorded, and the status reset to running.
+    ///
+    /// Most providers start the replacement with an empty conversation (only
+    /// Claude Code resumes it, and only while this process holds the
+    /// session), so the agent prompt the session was spawned with is sent
+    /// again before returning. Anything else the agent was told is lost.
+    pub async fn restart_session(
+        &self,
+        session_id: &str,
+        provider_session_id: Option<&str>,
+        session_type: &str,
+    ) -> Result<SessionHandle> {
+        let provider = self.get_provider(session_type)?;
+
+        let handle = match provider_session_id {
+            Some(provider_session_id) => provider.restart_session(provider_session_id).await?,
+            None => provider.create_session(None).await?,
+        };
+        self.own(&handle).await;
+
+        self.session_repo
+            .set_opencode_session_id(session_id, &handle.provider_id)
+            .await?;
+        self.session_repo
+            .update_status(session_id, DbSessionStatus::Running)
+            .await?;
+
+        let prompt = self.initial_prompt(session_id).await?;
+        self.send_message(session_id, &handle.provider_id, session_type, &prompt).await?;
+
+        Ok(handle)
+    }
+
+    /// The agent prompt a session was spawned with: its first message, or
+    /// one built from its agent type and name if it never got that far
+    async fn initial_prompt(&self, session_id: &str) -> Result<String> {
+        let first = self.message_repo.list_for_session(session_id).await?
+            .into_iter()
+            .find(|m| m.role == MessageRole::User);
+        if let Some(message) = first {
+            return Ok(message.content);
+        }
+
+        let session = self.session_repo.get(session_id).await?
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+        self.agent_prompt(session.agent_type.as_str(), session.name.as_deref(), None, None).await
+    }
+
+    /// Reattach a session to an existing provider session, e.g. one that
+    /// outlived a crash of this server, without creating a new one
+    pub async fn resume_session(
+        &self,
+        session_id: &str,
+        provider_session_id: &str,
+        session_type: &str,
+    ) -> Result<SessionHandle> {
+        let handle = self.get_provider(session_type)?
+            .resume_session(provider_session_id)
+            .await?;
+        self.own(&handle).await;
+
+        self.session_repo
+            .set_opencode_session_id(session_id, &handle.provider_id)
+            .await?;
+        self.record_activity(session_id, AgentState::Idle, None, None).await;
+
+        Ok(handle)
+    }
+
+    /// Fail fast with a descriptive error if the provider for `session_type`
+    /// is not reachable
+    pub async fn ensure_provider_available(&self, session_type: &str) -> Result<()> {
+        let provider = self.get_provider(session_type)?;
+
+        // Give a restarting server the same grace as spawning does
+        let mut delays = self.spawn_retry.delays();
+        while !provider.health_check().await.unwrap_or(false) {
+            match delays.next() {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Err(ProviderError::Unreachable(format!(
+                    "{} provider {}",
+                    session_type,
+                    provider.unavailable_reason()
+                )).into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the database is reachable
+    pub async fn check_database_health(&self) -> Result<bool> {
+        self.db.health_check().await
+    }
+
+    /// Check the health of the provider for `session_type`
+    pub async fn check_provider_health(&self, session_type: &str) -> Result<bool> {
+        self.get_provider(session_type)?.health_check().await
     }
 
-    /// Check Claude provider health
-    pub async fn check_claude_health(&self) -> Result<bool> {
-        self.claude_provider.health_check().await
+    /// Check every registered provider at once, by session type. A check
+    /// that takes longer than `timeout` counts as unhealthy.
+    pub async fn check_providers_health(&self, timeout: Option<Duration>) -> Vec<(String, bool)> {
+        let checks = self.session_types().into_iter().map(|session_type| async move {
+            let check = self.check_provider_health(session_type);
+            let healthy = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, check).await.unwrap_or(Ok(false)),
+                None => check.await,
+            };
+            (session_type.to_string(), healthy.unwrap_or(false))
+        });
+        futures::future::join_all(checks).await
     }
 }
 
-/// Build the agent prompt from type, extra_prompt, and compaction note
-fn build_agent_prompt(agent_type: &str, name: Option<&str>, extra_prompt: Option<&str>) -> String {
-    // Determine role name from agent_type
-    let role = match agent_type {
-        "manager" => "Manager",
-        "developer" => "Developer", 
-        "reviewer" => "Reviewer",
-        _ => "Agent",
+/// The providers every session manager starts with; others are added with
+/// `with_provider`
+fn builtin_providers(opencode: OpenCodeProvider, claude: ClaudeProvider) -> HashMap<String, Arc<dyn SessionProvider>> {
+    HashMap::from([
+        ("opencode".to_string(), Arc::new(opencode) as Arc<dyn SessionProvider>),
+        ("claude".to_string(), Arc::new(claude) as Arc<dyn SessionProvider>),
+    ])
+}
+
+/// The fence that would close a code block left open at the end of `text`
+fn unclosed_fence(text: &str) -> Option<&'static str> {
+    let mut open: Option<&'static str> = None;
+    for line in text.lines() {
+        let line = line.trim_start();
+        for fence in ["```", "~~~"] {
+            if line.starts_with(fence) {
+                open = match open {
+                    None => Some(fence),
+                    Some(current) if current == fence => None,
+                    other => other,
+                };
+            }
+        }
+    }
+    open
+}
+
+/// Build the agent prompt from type (or stored template), extra_prompt, and compaction note
+fn build_agent_prompt(
+    agent_type: &str,
+    name: Option<&str>,
+    extra_prompt: Option<&str>,
+    template: Option<&AgentConfig>,
+) -> String {
+    // Determine role name from the template or agent_type
+    let role = match (template, agent_type) {
+        (Some(template), _) => capitalize(&template.name),
+        (None, "manager") => "Manager".to_string(),
+        (None, "developer") => "Developer".to_string(),
+        (None, "reviewer") => "Reviewer".to_string(),
+        (None, custom) => capitalize(custom),
     };
 
     // Get the agent's name or use "Unnamed" as fallback
@@ -226,8 +1495,11 @@ When you need to communicate with a sub-agent, use the send_message tool.
 When you need information about existing sessions, use the list_sessions tool.
 When work is complete, summarize what was accomplished."#, agent_name, role);
 
-    // Build the final prompt
-    let mut prompt = base_prompt.to_string();
+    // Build the final prompt, preferring the stored template's prompt
+    let mut prompt = template
+        .and_then(|t| t.system_prompt.as_deref())
+        .unwrap_or(base_prompt)
+        .to_string();
     
     // Add extra prompt if provided
     if let Some(extra) = extra_prompt {
@@ -240,3 +1512,64 @@ When work is complete, summarize what was accomplished."#, agent_name, role);
     
     prompt
 }
+
+/// Send a request to a peer and wait for its answer, returning the answer's
+/// `result`. `what` describes the request for the error a refusal becomes.
+async fn peer_request(
+    config: Config,
+    peer_name: &str,
+    message_type: &str,
+    reply_type: &str,
+    arguments: &serde_json::Value,
+    timeout: Duration,
+    what: &str,
+) -> Result<serde_json::Value> {
+    let request = PeerMessage::new(message_type, arguments.to_string(), config.name.clone());
+    let request_id = request.id.clone();
+    let mut connection = PeerManager::new(config).connect_to_peer(peer_name).await
+        .map_err(|e| ProviderError::Unreachable(format!("Peer {} unreachable: {}", peer_name, e)))?;
+    connection.send(request).await?;
+
+    let reply = tokio::time::timeout(timeout, async {
+        loop {
+            let msg = connection.recv().await?;
+            if msg.message_type == reply_type && msg.in_reply_to.as_deref() == Some(request_id.as_str()) {
+                return Ok::<_, anyhow::Error>(msg);
+            }
+        }
+    }).await
+        .map_err(|_| ProviderError::Timeout(format!("Peer {} didn't answer within {:?}", peer_name, timeout)))??;
+
+    let mut reply: serde_json::Value = serde_json::from_str(&reply.payload)
+        .with_context(|| format!("Invalid {} from peer {}", reply_type, peer_name))?;
+    if let Some(error) = reply["error"].as_str() {
+        anyhow::bail!("Peer {} couldn't {}: {}", peer_name, what, error);
+    }
+    Ok(reply["result"].take())
+}
+
+/// Uppercase the first character of a role name
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Whether a provider call failed before reaching the provider, so nothing
+/// was done and it is safe to repeat
+fn is_unreachable(error: &anyhow::Error) -> bool {
+    matches!(ProviderError::find(error), Some(ProviderError::Unreachable(_)))
+}
+
+/// Isolated workspaces go in a temp dir unless configured otherwise
+fn default_workspaces_dir() -> PathBuf {
+    std::env::temp_dir().join("supercode-workspaces")
+}
+
+/// Whether `dir` is inside a git work tree (a `.git` dir, or file for
+/// worktrees and submodules, in it or an ancestor)
+fn is_git_repo(dir: &Path) -> bool {
+    dir.ancestors().any(|d| d.join(".git").exists())
+}
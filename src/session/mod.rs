@@ -1,15 +1,23 @@
 //! Session management module
 
+pub mod error;
 pub mod manager;
 pub mod provider;
 pub mod opencode;
 pub mod opencode_provider;
 pub mod claude;
 pub mod claude_provider;
+pub mod openai;
+pub mod generic_http_provider;
+pub mod webhook;
+pub mod workspace;
 
-pub use manager::SessionManager;
-pub use provider::{SessionHandle, SessionProvider, SessionStatus};
+pub use error::ProviderError;
+pub use manager::{ReattachSummary, RetryPolicy, SessionManager, ShutdownSummary};
+pub use provider::{LiveState, MessagePart, ProviderCapabilities, SessionHandle, SessionProvider, SessionStatus};
 pub use opencode::OpenCodeClient;
 pub use opencode_provider::OpenCodeProvider;
 pub use claude::ClaudeClient;
 pub use claude_provider::ClaudeProvider;
+pub use openai::OpenAiClient;
+pub use generic_http_provider::GenericHttpProvider;
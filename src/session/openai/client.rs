@@ -0,0 +1,173 @@
+//! Client for OpenAI-compatible `/chat/completions` APIs
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::session::ProviderError;
+
+/// Default limit on a whole completion request; models can be slow
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Default limit on establishing a connection to the API
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Health checks never wait longer than this
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One message of a chat conversation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    /// "system", "user" or "assistant"
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: impl Into<String>, content: impl Into<String>) -> Self {
+        Self { role: role.into(), content: content.into() }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ChatCompletionRequest<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionResponse {
+    choices: Vec<Choice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    message: ChoiceMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChoiceMessage {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+/// OpenAI-compatible API client
+pub struct OpenAiClient {
+    client: Client,
+    /// API root, e.g. `https://api.openai.com/v1`
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+impl OpenAiClient {
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            client: build_client(Some(DEFAULT_REQUEST_TIMEOUT)),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            model: model.into(),
+            api_key: None,
+        }
+    }
+
+    /// Send `key` as a bearer token
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Limit a whole completion request to `timeout` (None: no limit)
+    pub fn with_request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.client = build_client(timeout);
+        self
+    }
+
+    /// Ask the model to continue `messages`, returning its reply
+    pub async fn chat_completion(&self, messages: &[ChatMessage]) -> Result<String> {
+        let url = format!("{}/chat/completions", self.base_url);
+        let request = ChatCompletionRequest { model: &self.model, messages };
+
+        debug!("Requesting a completion from {} ({} messages)", self.model, messages.len());
+
+        let response = self.authorized(self.client.post(&url))
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| request_error(e, "Failed to reach the chat completions API"))?;
+
+        if !response.status().is_success() {
+            return Err(api_error(response).await);
+        }
+
+        let completion: ChatCompletionResponse = response
+            .json()
+            .await
+            .context("Failed to parse chat completion response")?;
+
+        let reply = completion.choices.into_iter().next()
+            .context("Chat completion response has no choices")?;
+        Ok(reply.message.content.unwrap_or_default())
+    }
+
+    /// Check the API answers, by listing its models
+    pub async fn health_check(&self) -> Result<bool> {
+        let url = format!("{}/models", self.base_url);
+
+        match self.authorized(self.client.get(&url)).timeout(HEALTH_CHECK_TIMEOUT).send().await {
+            Ok(response) => Ok(response.status().is_success()),
+            Err(e) => {
+                warn!("Chat completions API health check failed: {}", e);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Get the base URL
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Get the model name
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn authorized(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+}
+
+/// Build the HTTP client with the given request timeout
+fn build_client(request_timeout: Option<Duration>) -> Client {
+    let mut builder = Client::builder().connect_timeout(DEFAULT_CONNECT_TIMEOUT);
+    if let Some(timeout) = request_timeout {
+        builder = builder.timeout(timeout);
+    }
+    builder.build().unwrap_or_else(|_| Client::new()) // Fallback if config fails
+}
+
+/// Classify a request that never got an answer, keeping the reqwest error
+/// as the cause
+fn request_error(e: reqwest::Error, what: &str) -> anyhow::Error {
+    let class = if e.is_timeout() {
+        ProviderError::Timeout(what.to_string())
+    } else if e.is_connect() {
+        ProviderError::Unreachable(what.to_string())
+    } else {
+        return anyhow::Error::new(e).context(what.to_string());
+    };
+    anyhow::Error::new(e).context(class)
+}
+
+/// Turn an error status from the API into a `ProviderError`
+async fn api_error(response: reqwest::Response) -> anyhow::Error {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    ProviderError::Api { provider: "OpenAI-compatible", status: status.as_u16(), body }.into()
+}
@@ -0,0 +1,5 @@
+//! OpenAI-compatible chat completions provider
+
+pub mod client;
+
+pub use client::{ChatMessage, OpenAiClient};
@@ -1,16 +1,33 @@
 //! OpenCode HTTP API client
 
 use anyhow::{Context, Result};
+use futures::stream::{BoxStream, StreamExt};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 
+use crate::session::{MessagePart, ProviderError};
+
+/// Default limit on a whole OpenCode request, including a streamed reply
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default limit on establishing a connection to the OpenCode server
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Health checks never wait longer than this, however long requests may run
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// OpenCode API client
 pub struct OpenCodeClient {
     client: Client,
     base_url: String,
+    /// Limit on a whole request (None: no limit)
+    request_timeout: Option<Duration>,
+    connect_timeout: Duration,
     /// Cache of active sessions: opencode_session_id -> supercode_session_id
     sessions: Arc<RwLock<std::collections::HashMap<String, String>>>,
 }
@@ -38,46 +55,153 @@ struct SendMessageRequest {
 }
 
 #[derive(Debug, Serialize)]
-struct MessagePart {
-    #[serde(rename = "type")]
-    part_type: String,
-    text: String,
+struct ApprovalRequest {
+    /// "once" to allow the pending action, "reject" to deny it
+    response: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct SendMessageResponse {
-    // OpenCode returns the response content
-    // The exact structure depends on the API
-    #[serde(flatten)]
-    pub extra: serde_json::Value,
+    /// Message metadata (role, model, timings, ...)
+    #[serde(default)]
+    pub info: Option<serde_json::Value>,
+    /// Content parts of the assistant reply
+    #[serde(default)]
+    pub parts: Vec<ResponsePart>,
+    /// The raw response body, as returned by OpenCode
+    #[serde(skip)]
+    pub raw: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResponsePart {
+    #[serde(rename = "type")]
+    pub part_type: String,
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+impl SendMessageResponse {
+    /// Concatenated text of all `text` parts
+    pub fn text(&self) -> String {
+        self.parts
+            .iter()
+            .filter(|p| p.part_type == "text")
+            .filter_map(|p| p.text.as_deref())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Name of the error the reply ended with, e.g. `MessageAbortedError`
+    pub fn error_name(&self) -> Option<&str> {
+        self.info.as_ref()?.get("error")?.get("name")?.as_str()
+    }
 }
 
+/// A session as returned by `GET /session/{id}`
 #[derive(Debug, Deserialize)]
 pub struct SessionInfo {
     pub id: String,
-    #[serde(rename = "sessionId")]
+    #[serde(rename = "projectID", default)]
+    pub project_id: Option<String>,
+    #[serde(default)]
+    pub directory: Option<String>,
+    /// Set on sessions created by forking another
+    #[serde(rename = "parentID", default)]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub time: Option<SessionTime>,
+}
+
+/// Session timestamps, in milliseconds since the epoch
+#[derive(Debug, Deserialize)]
+pub struct SessionTime {
+    pub created: i64,
+    pub updated: i64,
+}
+
+/// Run state of a session, as returned by `GET /session/status`.
+/// Sessions missing from that map are idle.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RunStatus {
+    Idle,
+    Busy,
+    /// Busy, backing off after a failed model call
+    Retry {
+        #[serde(default)]
+        attempt: u32,
+        #[serde(default)]
+        message: Option<String>,
+    },
+}
+
+/// A pending permission request, as returned by `GET /permission`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Permission {
+    pub id: String,
+    #[serde(rename = "sessionID")]
     pub session_id: String,
-    pub status: Option<String>,
-    #[serde(rename = "children")]
-    pub children: Option<Vec<SessionInfo>>,
+    /// Tool family asking for permission, e.g. "edit", "bash" or "webfetch"
+    #[serde(rename = "type", alias = "permission")]
+    pub permission_type: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Command or path pattern the permission covers
+    #[serde(default, alias = "patterns")]
+    pub pattern: Option<serde_json::Value>,
+}
+
+impl Permission {
+    /// Human-readable description of what is being asked
+    pub fn description(&self) -> String {
+        if let Some(title) = &self.title {
+            return title.clone();
+        }
+        match &self.pattern {
+            Some(serde_json::Value::String(p)) => format!("{}: {}", self.permission_type, p),
+            Some(serde_json::Value::Array(ps)) => {
+                let ps: Vec<&str> = ps.iter().filter_map(|p| p.as_str()).collect();
+                format!("{}: {}", self.permission_type, ps.join(", "))
+            }
+            _ => self.permission_type.clone(),
+        }
+    }
 }
 
 impl OpenCodeClient {
-    /// Create a new OpenCode client with timeouts
+    /// Create a new OpenCode client with the default timeouts
     pub fn new(base_url: impl Into<String>) -> Self {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .connect_timeout(std::time::Duration::from_secs(5))
-            .build()
-            .unwrap_or_else(|_| Client::new()); // Fallback if config fails
-
         Self {
-            client,
+            client: build_client(Some(DEFAULT_REQUEST_TIMEOUT), DEFAULT_CONNECT_TIMEOUT),
             base_url: base_url.into(),
+            request_timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
             sessions: Arc::new(RwLock::new(std::collections::HashMap::new())),
         }
     }
 
+    /// Limit how long a whole request may take, including a streamed
+    /// reply (None: no limit, for slow models)
+    pub fn with_request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.request_timeout = timeout;
+        self.client = build_client(self.request_timeout, self.connect_timeout);
+        self
+    }
+
+    /// Limit how long connecting to the OpenCode server may take
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self.client = build_client(self.request_timeout, self.connect_timeout);
+        self
+    }
+
     /// Create a new session
     pub async fn create_session(
         &self,
@@ -102,14 +226,12 @@ impl OpenCodeClient {
             Ok(r) => r,
             Err(e) => {
                 error!("OpenCode HTTP error: {}", e);
-                return Err(e).context("Failed to connect to OpenCode server");
+                return Err(request_error(e, "Failed to connect to OpenCode server"));
             }
         };
 
         if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("OpenCode API error: {} - {}", status, body);
+            return Err(api_error(response).await);
         }
 
         let result: CreateSessionResponse = response
@@ -133,14 +255,20 @@ impl OpenCodeClient {
         &self,
         session_id: &str,
         message: impl Into<String>,
+    ) -> Result<SendMessageResponse> {
+        self.send_message_parts(session_id, vec![MessagePart::text(message)]).await
+    }
+
+    /// Send a message made of text and file parts to a session
+    pub async fn send_message_parts(
+        &self,
+        session_id: &str,
+        parts: Vec<MessagePart>,
     ) -> Result<SendMessageResponse> {
         let url = format!("{}/session/{}/message", self.base_url, session_id);
         
         let request = SendMessageRequest {
-            parts: vec![MessagePart {
-                part_type: "text".to_string(),
-                text: message.into(),
-            }],
+            parts,
             resume_id: None,
         };
 
@@ -151,22 +279,88 @@ impl OpenCodeClient {
             .json(&request)
             .send()
             .await
-            .context("Failed to send message to OpenCode session")?;
+            .map_err(|e| request_error(e, "Failed to send message to OpenCode session"))?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("OpenCode API error: {} - {}", status, body);
+            return Err(api_error(response).await);
         }
 
-        let result: SendMessageResponse = response
+        let raw: serde_json::Value = response
             .json()
             .await
             .context("Failed to parse OpenCode response")?;
 
+        let mut result: SendMessageResponse = serde_json::from_value(raw.clone())
+            .context("Unexpected OpenCode message response shape")?;
+        result.raw = raw;
+
         Ok(result)
     }
 
+    /// Send a message to a session and stream back text deltas as they arrive.
+    ///
+    /// Handles both server-sent events (`data: {...}` lines) and plain chunked
+    /// output. JSON payloads are reduced to their text delta where one can be found.
+    pub async fn send_message_streaming(
+        &self,
+        session_id: &str,
+        message: impl Into<String>,
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        let url = format!("{}/session/{}/message", self.base_url, session_id);
+
+        let request = SendMessageRequest {
+            parts: vec![MessagePart::text(message)],
+            resume_id: None,
+        };
+
+        debug!("Streaming message to OpenCode session: {}", session_id);
+
+        let response = self.client
+            .post(&url)
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| request_error(e, "Failed to send message to OpenCode session"))?;
+
+        if !response.status().is_success() {
+            return Err(api_error(response).await);
+        }
+
+        let state = DeltaStream {
+            response,
+            buffer: Vec::new(),
+            pending: VecDeque::new(),
+            finished: false,
+        };
+
+        let stream = futures::stream::try_unfold(state, |mut state| async move {
+            loop {
+                if let Some(delta) = state.pending.pop_front() {
+                    return Ok(Some((delta, state)));
+                }
+                if state.finished {
+                    return Ok(None);
+                }
+
+                match state.response.chunk().await.context("Failed to read OpenCode response stream")? {
+                    Some(bytes) => {
+                        state.buffer.extend_from_slice(&bytes);
+                        state.drain_lines();
+                    }
+                    None => {
+                        // Flush a trailing line without a newline
+                        state.finished = true;
+                        let rest = std::mem::take(&mut state.buffer);
+                        state.push_line(&rest);
+                    }
+                }
+            }
+        });
+
+        Ok(stream.boxed())
+    }
+
     /// Get session info
     pub async fn get_session(&self, session_id: &str) -> Result<SessionInfo> {
         let url = format!("{}/session/{}", self.base_url, session_id);
@@ -175,12 +369,10 @@ impl OpenCodeClient {
             .get(&url)
             .send()
             .await
-            .context("Failed to get OpenCode session")?;
+            .map_err(|e| request_error(e, "Failed to get OpenCode session"))?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("OpenCode API error: {} - {}", status, body);
+            return Err(api_error(response).await);
         }
 
         let result: SessionInfo = response
@@ -191,6 +383,97 @@ impl OpenCodeClient {
         Ok(result)
     }
 
+    /// Get the run state of a session
+    pub async fn get_session_status(&self, session_id: &str) -> Result<RunStatus> {
+        let url = format!("{}/session/status", self.base_url);
+
+        let response = self.client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| request_error(e, "Failed to get OpenCode session status"))?;
+
+        if !response.status().is_success() {
+            return Err(api_error(response).await);
+        }
+
+        let mut statuses: std::collections::HashMap<String, RunStatus> = response
+            .json()
+            .await
+            .context("Failed to parse OpenCode response")?;
+
+        Ok(statuses.remove(session_id).unwrap_or(RunStatus::Idle))
+    }
+
+    /// List the permission requests a session is waiting on, oldest first
+    pub async fn list_permissions(&self, session_id: &str) -> Result<Vec<Permission>> {
+        let url = format!("{}/permission", self.base_url);
+
+        let response = self.client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| request_error(e, "Failed to list OpenCode permissions"))?;
+
+        if !response.status().is_success() {
+            return Err(api_error(response).await);
+        }
+
+        let permissions: Vec<Permission> = response
+            .json()
+            .await
+            .context("Failed to parse OpenCode response")?;
+
+        Ok(permissions.into_iter().filter(|p| p.session_id == session_id).collect())
+    }
+
+    /// Get a session's conversation as text, one line per text part or
+    /// tool call
+    pub async fn get_transcript(&self, session_id: &str) -> Result<String> {
+        let url = format!("{}/session/{}/message", self.base_url, session_id);
+
+        let response = self.client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| request_error(e, "Failed to get OpenCode messages"))?;
+
+        if !response.status().is_success() {
+            return Err(api_error(response).await);
+        }
+
+        let messages: Vec<serde_json::Value> = response
+            .json()
+            .await
+            .context("Failed to parse OpenCode response")?;
+
+        let mut lines = Vec::new();
+        for message in &messages {
+            let role = message.pointer("/info/role").and_then(|r| r.as_str()).unwrap_or("unknown");
+            let parts = message.get("parts").and_then(|p| p.as_array()).map(Vec::as_slice).unwrap_or_default();
+
+            for part in parts {
+                match part.get("type").and_then(|t| t.as_str()) {
+                    Some("text") => {
+                        let text = part.get("text").and_then(|t| t.as_str()).unwrap_or_default();
+                        lines.push(format!("[{}] {}", role, text));
+                    }
+                    Some("tool") => {
+                        let tool = part.get("tool").and_then(|t| t.as_str()).unwrap_or("unknown");
+                        let status = part.pointer("/state/status").and_then(|s| s.as_str()).unwrap_or("unknown");
+                        lines.push(format!("[{}] tool {} ({})", role, tool, status));
+                        if let Some(output) = part.pointer("/state/output").and_then(|o| o.as_str()) {
+                            lines.push(output.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(lines.join("\n"))
+    }
+
     /// List child sessions (for forks)
     pub async fn get_children(&self, session_id: &str) -> Result<Vec<SessionInfo>> {
         let url = format!("{}/session/{}/children", self.base_url, session_id);
@@ -199,12 +482,10 @@ impl OpenCodeClient {
             .get(&url)
             .send()
             .await
-            .context("Failed to get OpenCode children")?;
+            .map_err(|e| request_error(e, "Failed to get OpenCode children"))?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("OpenCode API error: {} - {}", status, body);
+            return Err(api_error(response).await);
         }
 
         let result: Vec<SessionInfo> = response
@@ -223,12 +504,10 @@ impl OpenCodeClient {
             .post(&url)
             .send()
             .await
-            .context("Failed to fork OpenCode session")?;
+            .map_err(|e| request_error(e, "Failed to fork OpenCode session"))?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("OpenCode API error: {} - {}", status, body);
+            return Err(api_error(response).await);
         }
 
         let result: CreateSessionResponse = response
@@ -249,12 +528,10 @@ impl OpenCodeClient {
             .delete(&url)
             .send()
             .await
-            .context("Failed to kill OpenCode session")?;
+            .map_err(|e| request_error(e, "Failed to kill OpenCode session"))?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("OpenCode API error: {} - {}", status, body);
+            return Err(api_error(response).await);
         }
 
         info!("Killed OpenCode session: {}", session_id);
@@ -262,11 +539,66 @@ impl OpenCodeClient {
         Ok(())
     }
 
+    /// Abort the message a session is processing; the session stays usable
+    pub async fn abort_session(&self, session_id: &str) -> Result<()> {
+        let url = format!("{}/session/{}/abort", self.base_url, session_id);
+
+        let response = self.client
+            .post(&url)
+            .send()
+            .await
+            .map_err(|e| request_error(e, "Failed to abort OpenCode session"))?;
+
+        if !response.status().is_success() {
+            return Err(api_error(response).await);
+        }
+
+        info!("Aborted OpenCode session: {}", session_id);
+
+        Ok(())
+    }
+
+    /// Answer the oldest pending permission request on a session
+    pub async fn respond_to_approval(
+        &self,
+        session_id: &str,
+        approved: bool,
+        note: Option<String>,
+    ) -> Result<()> {
+        let permission = self.list_permissions(session_id).await?
+            .into_iter()
+            .next()
+            .with_context(|| format!("OpenCode session {} has no pending permission request", session_id))?;
+
+        let url = format!("{}/session/{}/permissions/{}", self.base_url, session_id, permission.id);
+
+        let request = ApprovalRequest {
+            response: if approved { "once" } else { "reject" }.to_string(),
+            message: note,
+        };
+
+        let response = self.client
+            .post(&url)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| request_error(e, "Failed to send approval to OpenCode session"))?;
+
+        if !response.status().is_success() {
+            return Err(api_error(response).await);
+        }
+
+        info!("{} pending action on OpenCode session: {}", if approved { "Approved" } else { "Denied" }, session_id);
+
+        Ok(())
+    }
+
     /// Check if OpenCode server is running
     pub async fn health_check(&self) -> Result<bool> {
         let url = format!("{}/health", self.base_url);
         
-        match self.client.get(&url).send().await {
+        let timeout = self.request_timeout.map_or(HEALTH_CHECK_TIMEOUT, |t| t.min(HEALTH_CHECK_TIMEOUT));
+        match self.client.get(&url).timeout(timeout).send().await {
             Ok(response) => Ok(response.status().is_success()),
             Err(e) => {
                 warn!("OpenCode health check failed: {}", e);
@@ -281,6 +613,106 @@ impl OpenCodeClient {
     }
 }
 
+/// Build the HTTP client with the given timeouts
+fn build_client(request_timeout: Option<Duration>, connect_timeout: Duration) -> Client {
+    let mut builder = Client::builder().connect_timeout(connect_timeout);
+    if let Some(timeout) = request_timeout {
+        builder = builder.timeout(timeout);
+    }
+    builder.build().unwrap_or_else(|_| Client::new()) // Fallback if config fails
+}
+
+/// Classify a request that never got an answer from the OpenCode server,
+/// keeping the reqwest error as the cause
+fn request_error(e: reqwest::Error, what: &str) -> anyhow::Error {
+    let class = if e.is_timeout() {
+        ProviderError::Timeout(what.to_string())
+    } else if e.is_connect() {
+        ProviderError::Unreachable(what.to_string())
+    } else {
+        return anyhow::Error::new(e).context(what.to_string());
+    };
+    anyhow::Error::new(e).context(class)
+}
+
+/// Turn an error status from the OpenCode server into a `ProviderError`
+async fn api_error(response: reqwest::Response) -> anyhow::Error {
+    let status = response.status();
+    let path = response.url().path().to_string();
+    let body = response.text().await.unwrap_or_default();
+
+    if status == reqwest::StatusCode::NOT_FOUND {
+        return ProviderError::NotFound(format!("OpenCode returned 404 for {}: {}", path, body)).into();
+    }
+    ProviderError::Api { provider: "OpenCode", status: status.as_u16(), body }.into()
+}
+
+/// Incremental line parser over a streaming OpenCode response
+struct DeltaStream {
+    response: reqwest::Response,
+    buffer: Vec<u8>,
+    pending: VecDeque<String>,
+    finished: bool,
+}
+
+impl DeltaStream {
+    /// Move every complete line out of the buffer
+    fn drain_lines(&mut self) {
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            self.push_line(&line);
+        }
+    }
+
+    /// Parse one line into a text delta, if it carries one
+    fn push_line(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches(['\r', '\n']);
+
+        if line.is_empty() || line.starts_with(':') {
+            return;
+        }
+
+        let payload = if let Some(data) = line.strip_prefix("data:") {
+            data.trim_start()
+        } else if line.starts_with("event:") || line.starts_with("id:") || line.starts_with("retry:") {
+            return;
+        } else {
+            line
+        };
+
+        if payload == "[DONE]" {
+            return;
+        }
+
+        let delta = match serde_json::from_str::<serde_json::Value>(payload) {
+            Ok(json) => extract_text_delta(&json),
+            Err(_) => Some(payload.to_string()),
+        };
+
+        if let Some(delta) = delta.filter(|d| !d.is_empty()) {
+            self.pending.push_back(delta);
+        }
+    }
+}
+
+/// Pull the text delta out of a streamed JSON event
+fn extract_text_delta(event: &serde_json::Value) -> Option<String> {
+    let candidates = [
+        event.get("delta"),
+        event.pointer("/properties/delta"),
+        event.pointer("/properties/part/text"),
+        event.pointer("/part/text"),
+        event.get("text"),
+    ];
+
+    candidates
+        .into_iter()
+        .flatten()
+        .find_map(|v| v.as_str())
+        .map(String::from)
+}
+
 impl Default for OpenCodeClient {
     fn default() -> Self {
         Self::new("http://localhost:9090")
@@ -2,4 +2,4 @@
 
 pub mod client;
 
-pub use client::OpenCodeClient;
+pub use client::{OpenCodeClient, RunStatus};
@@ -2,10 +2,13 @@
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use futures::stream::BoxStream;
 use uuid::Uuid;
 
-use super::opencode::OpenCodeClient;
-use super::provider::{SessionHandle, SessionProvider, SessionStatus};
+use crate::db::repositories::session::{AgentState, ApprovalType};
+use super::error::ProviderError;
+use super::opencode::{OpenCodeClient, RunStatus};
+use super::provider::{LiveState, MessagePart, ProviderCapabilities, SessionHandle, SessionProvider, SessionStatus};
 
 pub struct OpenCodeProvider {
     client: OpenCodeClient,
@@ -19,6 +22,11 @@ impl OpenCodeProvider {
     pub fn with_url(url: impl Into<String>) -> Self {
         Self::new(OpenCodeClient::new(url))
     }
+
+    /// Get the OpenCode server URL
+    pub fn base_url(&self) -> &str {
+        self.client.base_url()
+    }
 }
 
 #[async_trait]
@@ -37,33 +45,67 @@ impl SessionProvider for OpenCodeProvider {
         })
     }
 
+    async fn resume_session(&self, provider_id: &str) -> Result<SessionHandle> {
+        // The session lives on the OpenCode server, so there is nothing to
+        // recreate: confirm it still exists and hand back its ID
+        let info = self.client
+            .get_session(provider_id)
+            .await
+            .with_context(|| format!("Failed to resume OpenCode session {}", provider_id))?;
+
+        Ok(SessionHandle {
+            internal_id: Uuid::new_v4().to_string(),
+            provider_id: info.id,
+        })
+    }
+
     async fn send_message(&self, session_id: &str, message: &str) -> Result<String> {
+        self.send_message_parts(session_id, vec![MessagePart::text(message)]).await
+    }
+
+    async fn send_message_parts(&self, session_id: &str, parts: Vec<MessagePart>) -> Result<String> {
         let response = self.client
-            .send_message(session_id, message)
+            .send_message_parts(session_id, parts)
             .await
             .context("Failed to send message to OpenCode session")?;
 
-        // The response structure depends on OpenCode API
-        // For now, return the JSON as string
-        Ok(response.extra.to_string())
+        // An aborted turn still answers, with whatever it had so far
+        if response.error_name() == Some("MessageAbortedError") {
+            return Err(ProviderError::Interrupted(format!("OpenCode session {} was interrupted", session_id)).into());
+        }
+
+        // Return the assistant's text; fall back to the raw JSON when the
+        // reply has no text parts (e.g. tool calls only)
+        let text = response.text();
+        if text.is_empty() {
+            Ok(response.raw.to_string())
+        } else {
+            Ok(text)
+        }
     }
 
-    async fn get_status(&self, session_id: &str) -> Result<SessionStatus> {
-        let info = self.client
-            .get_session(session_id)
+    async fn send_message_stream(
+        &self,
+        session_id: &str,
+        message: &str,
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        self.client
+            .send_message_streaming(session_id, message)
             .await
-            .context("Failed to get OpenCode session status")?;
-
-        // Parse status from response
-        let status = match info.status.as_deref() {
-            Some("running") | Some("active") => SessionStatus::Running,
-            Some("completed") | Some("done") => SessionStatus::Completed,
-            Some("failed") | Some("error") => SessionStatus::Failed,
-            Some("terminated") | Some("cancelled") => SessionStatus::Terminated,
-            _ => SessionStatus::Pending,
-        };
+            .context("Failed to stream message to OpenCode session")
+    }
 
-        Ok(status)
+    async fn get_status(&self, session_id: &str) -> Result<SessionStatus> {
+        // OpenCode sessions have no lifecycle status of their own: a session
+        // that still exists is live, whether idle or busy, and one the server
+        // no longer knows has ended
+        match self.client.get_session(session_id).await {
+            Ok(_) => Ok(SessionStatus::Running),
+            Err(e) if matches!(ProviderError::find(&e), Some(ProviderError::NotFound(_))) => {
+                Ok(SessionStatus::Terminated)
+            }
+            Err(e) => Err(e).context("Failed to get OpenCode session status"),
+        }
     }
 
     async fn fork_session(&self, session_id: &str) -> Result<SessionHandle> {
@@ -87,7 +129,127 @@ impl SessionProvider for OpenCodeProvider {
             .context("Failed to kill OpenCode session")
     }
 
+    async fn restart_session(&self, session_id: &str) -> Result<SessionHandle> {
+        // The old session may already be gone; that is why we are restarting
+        if let Err(e) = self.client.kill_session(session_id).await {
+            tracing::debug!("Ignoring kill failure while restarting {}: {}", session_id, e);
+        }
+
+        // OpenCode can't carry a conversation into a new session, so the
+        // replacement starts empty
+        let internal_id = Uuid::new_v4().to_string();
+
+        let response = self.client
+            .create_session(None, None)
+            .await
+            .context("Failed to restart OpenCode session")?;
+
+        Ok(SessionHandle {
+            internal_id,
+            provider_id: response.id,
+        })
+    }
+
+    async fn respond_to_approval(&self, session_id: &str, approved: bool, note: Option<String>) -> Result<bool> {
+        self.client
+            .respond_to_approval(session_id, approved, note)
+            .await
+            .context("Failed to respond to OpenCode approval")?;
+        // The agent is still in its turn, and picks the answer up
+        Ok(true)
+    }
+
+    async fn interrupt(&self, session_id: &str) -> Result<bool> {
+        let status = self.client
+            .get_session_status(session_id)
+            .await
+            .context("Failed to get OpenCode session status")?;
+        if matches!(status, RunStatus::Idle) {
+            return Ok(false);
+        }
+
+        self.client
+            .abort_session(session_id)
+            .await
+            .context("Failed to interrupt OpenCode session")?;
+        Ok(true)
+    }
+
+    async fn get_agent_state(&self, session_id: &str) -> Result<Option<LiveState>> {
+        // A pending permission request blocks the session whatever its run state
+        let permissions = self.client
+            .list_permissions(session_id)
+            .await
+            .context("Failed to get OpenCode permissions")?;
+
+        if let Some(permission) = permissions.first() {
+            return Ok(Some(LiveState {
+                state: AgentState::WaitingForApproval,
+                approval_type: Some(approval_type(&permission.permission_type)),
+                approval_description: Some(permission.description()),
+            }));
+        }
+
+        let status = self.client
+            .get_session_status(session_id)
+            .await
+            .context("Failed to get OpenCode session status")?;
+
+        let state = match status {
+            RunStatus::Idle => AgentState::Idle,
+            RunStatus::Busy | RunStatus::Retry { .. } => AgentState::Processing,
+        };
+
+        Ok(Some(LiveState {
+            state,
+            approval_type: None,
+            approval_description: None,
+        }))
+    }
+
+    async fn get_output(&self, session_id: &str) -> Result<Option<String>> {
+        let transcript = self.client
+            .get_transcript(session_id)
+            .await
+            .context("Failed to get OpenCode session messages")?;
+
+        Ok(Some(transcript))
+    }
+
+    async fn get_children(&self, session_id: &str) -> Result<Vec<String>> {
+        let children = self.client
+            .get_children(session_id)
+            .await
+            .context("Failed to get OpenCode session children")?;
+
+        Ok(children.into_iter().map(|child| child.id).collect())
+    }
+
     async fn health_check(&self) -> Result<bool> {
         self.client.health_check().await
     }
+
+    fn unavailable_reason(&self) -> String {
+        format!("unreachable at {}", self.base_url())
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            supports_fork: true,
+            supports_streaming: true,
+            supports_resume: true,
+            supports_approval: true,
+            supports_interrupt: true,
+        }
+    }
+}
+
+/// Map an OpenCode permission type onto the kind of action being approved
+fn approval_type(permission_type: &str) -> ApprovalType {
+    match permission_type {
+        "edit" | "write" | "patch" => ApprovalType::FileWrite,
+        "bash" => ApprovalType::Command,
+        "webfetch" | "read" | "glob" | "grep" | "list" | "task" => ApprovalType::ToolUse,
+        _ => ApprovalType::Other,
+    }
 }
@@ -1,7 +1,13 @@
 //! Session provider trait
 
-use anyhow::Result;
+use std::path::Path;
+
+use anyhow::{Context, Result};
 use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::db::repositories::session::{AgentState, ApprovalType};
 
 /// Session provider trait for different agent backends
 #[async_trait]
@@ -9,9 +15,46 @@ pub trait SessionProvider: Send + Sync {
     /// Create a new session
     async fn create_session(&self, system_prompt: Option<String>) -> Result<SessionHandle>;
 
+    /// Create a new session whose agent works in `working_dir`.
+    ///
+    /// Providers that can't choose where their agents run keep this
+    /// default, which ignores the directory.
+    async fn create_session_in(&self, system_prompt: Option<String>, working_dir: &Path) -> Result<SessionHandle> {
+        let _ = working_dir;
+        self.create_session(system_prompt).await
+    }
+
     /// Send a message to a session
     async fn send_message(&self, session_id: &str, message: &str) -> Result<String>;
 
+    /// Send a message made of several parts, such as text plus file or
+    /// image references.
+    ///
+    /// The default sends text-only messages through `send_message` and
+    /// fails for anything else.
+    async fn send_message_parts(&self, session_id: &str, parts: Vec<MessagePart>) -> Result<String> {
+        let mut texts = Vec::new();
+        for part in &parts {
+            match part {
+                MessagePart::Text { text } => texts.push(text.as_str()),
+                MessagePart::File { url, .. } => anyhow::bail!("File parts are not supported for session {} ({})", session_id, url),
+            }
+        }
+        self.send_message(session_id, &texts.join("\n\n")).await
+    }
+
+    /// Send a message and stream the response as text deltas.
+    ///
+    /// Providers without native streaming yield the full response as a single item.
+    async fn send_message_stream(
+        &self,
+        session_id: &str,
+        message: &str,
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        let response = self.send_message(session_id, message).await?;
+        Ok(futures::stream::once(async move { Ok(response) }).boxed())
+    }
+
     /// Get session status
     async fn get_status(&self, session_id: &str) -> Result<SessionStatus>;
 
@@ -21,8 +64,172 @@ pub trait SessionProvider: Send + Sync {
     /// Kill/terminate a session
     async fn kill_session(&self, session_id: &str) -> Result<()>;
 
+    /// Restart a session in place, returning the handle of its replacement.
+    ///
+    /// The default kills the session and creates a fresh one, so the
+    /// conversation so far is lost; providers that can carry it over
+    /// should override this.
+    async fn restart_session(&self, session_id: &str) -> Result<SessionHandle> {
+        if let Err(e) = self.kill_session(session_id).await {
+            tracing::debug!("Ignoring kill failure while restarting {}: {}", session_id, e);
+        }
+        self.create_session(None).await
+    }
+
+    /// Reattach to an existing provider session (e.g. after a restart of
+    /// supercode) without creating a new one.
+    ///
+    /// Providers whose sessions don't outlive this process keep this
+    /// default, which fails.
+    async fn resume_session(&self, provider_id: &str) -> Result<SessionHandle> {
+        anyhow::bail!("Resuming is not supported for session {}", provider_id)
+    }
+
+    /// Grant or deny the action a session is waiting on. Returns whether
+    /// the agent carries on with its turn; if not, the turn already ended
+    /// and the agent waits for its next message.
+    ///
+    /// Providers without an approval mechanism keep this default, which fails.
+    async fn respond_to_approval(&self, session_id: &str, approved: bool, note: Option<String>) -> Result<bool> {
+        let _ = (approved, note);
+        anyhow::bail!("Approvals are not supported for session {}", session_id)
+    }
+
+    /// Stop the turn a session is working on, leaving the session alive to
+    /// take the next message. Returns whether a turn was running and has
+    /// been stopped; does nothing if none was.
+    ///
+    /// Providers that can't stop a turn keep this default, which fails.
+    async fn interrupt(&self, session_id: &str) -> Result<bool> {
+        anyhow::bail!("Interrupting is not supported for session {}", session_id)
+    }
+
+    /// Ask the backend what the agent is doing right now.
+    ///
+    /// Returns `None` for providers that can't report live state; their
+    /// activity comes only from what the manager records around messages.
+    async fn get_agent_state(&self, session_id: &str) -> Result<Option<LiveState>> {
+        let _ = session_id;
+        Ok(None)
+    }
+
+    /// Raw output the backend holds for a session (logs or transcript).
+    ///
+    /// Returns `None` when the provider keeps nothing beyond the replies
+    /// the manager already stores.
+    async fn get_output(&self, session_id: &str) -> Result<Option<String>> {
+        let _ = session_id;
+        Ok(None)
+    }
+
+    /// Provider IDs of the sessions forked from this one.
+    ///
+    /// Returns nothing for providers that don't track forks themselves.
+    async fn get_children(&self, session_id: &str) -> Result<Vec<String>> {
+        let _ = session_id;
+        Ok(Vec::new())
+    }
+
     /// Check if the provider is healthy
     async fn health_check(&self) -> Result<bool>;
+
+    /// Why the provider is unusable when `health_check` fails, e.g.
+    /// "unreachable at <url>"
+    fn unavailable_reason(&self) -> String {
+        "unavailable".to_string()
+    }
+
+    /// What the provider really supports, where the defaults above would
+    /// otherwise fall back or fail
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities::default()
+    }
+}
+
+/// Optional features of a provider
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ProviderCapabilities {
+    /// `fork_session` carries the conversation over rather than starting
+    /// an unrelated session
+    pub supports_fork: bool,
+    /// `send_message_stream` yields the response as it is generated
+    pub supports_streaming: bool,
+    /// `resume_session` can reattach to a session after a restart
+    pub supports_resume: bool,
+    /// `respond_to_approval` can answer a pending permission request
+    pub supports_approval: bool,
+    /// `interrupt` can stop a turn in progress
+    pub supports_interrupt: bool,
+}
+
+/// One part of a message, in the shape of OpenCode's `parts` array
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum MessagePart {
+    Text {
+        text: String,
+    },
+    /// A file or image, by `file://`, `data:` or `http(s)://` URL
+    File {
+        mime: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filename: Option<String>,
+        url: String,
+    },
+}
+
+impl MessagePart {
+    pub fn text(text: impl Into<String>) -> Self {
+        MessagePart::Text { text: text.into() }
+    }
+
+    /// Reference a local file by its absolute `file://` URL, guessing the
+    /// MIME type from the extension
+    pub fn file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let path = path.canonicalize()
+            .with_context(|| format!("Attachment not found: {}", path.display()))?;
+        if !path.is_file() {
+            anyhow::bail!("Attachment is not a file: {}", path.display());
+        }
+
+        Ok(MessagePart::File {
+            mime: guess_mime(&path).to_string(),
+            filename: path.file_name().map(|name| name.to_string_lossy().to_string()),
+            url: format!("file://{}", path.display()),
+        })
+    }
+
+    /// How the part reads in the stored message history
+    pub fn describe(parts: &[MessagePart]) -> String {
+        parts.iter()
+            .map(|part| match part {
+                MessagePart::Text { text } => text.clone(),
+                MessagePart::File { mime, filename, url } => {
+                    format!("[file: {} ({})]", filename.as_deref().unwrap_or(url), mime)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+/// MIME type for common attachment extensions
+fn guess_mime(path: &Path) -> &'static str {
+    let extension = path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "json" => "application/json",
+        "txt" | "md" | "log" | "rs" | "py" | "js" | "ts" | "toml" | "yml" | "yaml" => "text/plain",
+        _ => "application/octet-stream",
+    }
 }
 
 /// Handle to a created session
@@ -43,3 +250,13 @@ pub enum SessionStatus {
     Failed,
     Terminated,
 }
+
+/// Agent state as reported live by a provider
+#[derive(Debug, Clone)]
+pub struct LiveState {
+    pub state: AgentState,
+    /// What is awaiting approval, when `state` is `WaitingForApproval`
+    pub approval_type: Option<ApprovalType>,
+    /// Human-readable description of the pending action
+    pub approval_description: Option<String>,
+}
@@ -0,0 +1,110 @@
+//! Out-of-band notifications of session state changes
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::db::repositories::session::{ActivityChange, AgentState, SessionStatus};
+
+/// How long delivering one event may take
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Body POSTed to the webhook URL
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEvent {
+    /// `blocked`, `completed` or `failed`
+    pub event: &'static str,
+    pub session_id: String,
+    /// Name the agent was spawned with
+    pub name: Option<String>,
+    /// Agent state or session status that triggered the event
+    pub state: String,
+    pub last_response: Option<String>,
+    /// What is awaiting approval, for `blocked`
+    pub approval_description: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl WebhookEvent {
+    /// The event an activity snapshot represents, if any: the agent became
+    /// blocked on an approval, finished processing, or hit an error
+    pub fn from_activity(change: &ActivityChange) -> Option<Self> {
+        let activity = &change.activity;
+        let event = match (activity.state, change.previous_state) {
+            (AgentState::WaitingForApproval, Some(AgentState::WaitingForApproval)) => return None,
+            (AgentState::WaitingForApproval, _) => "blocked",
+            (AgentState::Idle, Some(AgentState::Processing)) => "completed",
+            (AgentState::Error, _) => "failed",
+            _ => return None,
+        };
+
+        Some(Self {
+            event,
+            session_id: activity.session_id.clone(),
+            name: None,
+            state: activity.state.as_str().to_string(),
+            last_response: activity.last_response.clone(),
+            approval_description: activity.approval_description.clone(),
+            timestamp: activity.state_changed_at,
+        })
+    }
+
+    /// The event a session status change represents, if any
+    pub fn from_status(session_id: &str, status: SessionStatus) -> Option<Self> {
+        let event = match status {
+            SessionStatus::Completed => "completed",
+            SessionStatus::Failed => "failed",
+            _ => return None,
+        };
+
+        Some(Self {
+            event,
+            session_id: session_id.to_string(),
+            name: None,
+            state: status.as_str().to_string(),
+            last_response: None,
+            approval_description: None,
+            timestamp: Utc::now(),
+        })
+    }
+}
+
+/// Delivers events to a configured URL
+pub struct Webhook {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    pub fn new(url: impl Into<String>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+
+        Self { url: url.into(), client }
+    }
+
+    /// POST `event` as JSON. Fails if the receiver couldn't be reached or
+    /// had a server error, so the event can be sent again later. An event
+    /// the receiver rejects (4xx) is logged and dropped instead: sending it
+    /// again would only be rejected again.
+    pub async fn send(&self, event: &WebhookEvent) -> Result<()> {
+        let response = self.client.post(&self.url).json(event).send().await
+            .with_context(|| format!("Failed to send {} webhook for session {} to {}", event.event, event.session_id, self.url))?;
+
+        let status = response.status();
+        if status.is_client_error() {
+            tracing::warn!("Webhook {} rejected {} event for session {}: {}", self.url, event.event, event.session_id, status);
+            return Ok(());
+        }
+        if !status.is_success() {
+            anyhow::bail!("Webhook {} answered {} for {} event of session {}", self.url, status, event.event, event.session_id);
+        }
+
+        tracing::debug!("Sent {} webhook for session {}", event.event, event.session_id);
+        Ok(())
+    }
+}
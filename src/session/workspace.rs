@@ -0,0 +1,168 @@
+//! Isolated working directories for spawned agents
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+
+/// How a session's working directory is separated from the one it was
+/// spawned for, so parallel agents don't edit the same checkout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WorkspaceIsolation {
+    /// Work in the given directory itself
+    #[default]
+    None,
+    /// A detached `git worktree` of the directory's repository at HEAD
+    Worktree,
+    /// A full copy of the directory, uncommitted changes included
+    Copy,
+}
+
+impl WorkspaceIsolation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkspaceIsolation::None => "none",
+            WorkspaceIsolation::Worktree => "worktree",
+            WorkspaceIsolation::Copy => "copy",
+        }
+    }
+}
+
+impl FromStr for WorkspaceIsolation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(WorkspaceIsolation::None),
+            "worktree" => Ok(WorkspaceIsolation::Worktree),
+            "copy" => Ok(WorkspaceIsolation::Copy),
+            _ => anyhow::bail!("Unknown workspace isolation: {}", s),
+        }
+    }
+}
+
+/// Set up `dest` as an isolated workspace for `source`. `dest` must not
+/// exist yet. Returns the directory to work in, which is `source` itself
+/// without isolation.
+pub fn create_workspace(source: &Path, dest: &Path, isolation: WorkspaceIsolation) -> Result<PathBuf> {
+    if dest.exists() {
+        anyhow::bail!("Workspace already exists: {}", dest.display());
+    }
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create workspace directory {}", parent.display()))?;
+    }
+
+    match isolation {
+        WorkspaceIsolation::None => Ok(source.to_path_buf()),
+        WorkspaceIsolation::Worktree => add_worktree(source, dest, None, None),
+        WorkspaceIsolation::Copy => copy_dir(source, dest)
+            .map(|_| dest.to_path_buf())
+            .with_context(|| format!("Failed to copy {} to {}", source.display(), dest.display())),
+    }
+}
+
+/// Add a git worktree of `source`'s repository at `dest`, checked out at
+/// `base` (default: HEAD), which can't start with `-`. With `branch` the
+/// worktree gets a new branch of that name; without, it is detached.
+///
+/// Returns the directory in the worktree that matches `source`, which may
+/// be a subdirectory of its repository. That is the worktree root if the
+/// checked out commit doesn't have the subdirectory.
+pub fn add_worktree(source: &Path, dest: &Path, branch: Option<&str>, base: Option<&str>) -> Result<PathBuf> {
+    // It would be taken for an option
+    if let Some(base) = base.filter(|base| base.starts_with('-')) {
+        anyhow::bail!("Invalid base branch: {}", base);
+    }
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create workspace directory {}", parent.display()))?;
+    }
+    let prefix = git(source, &["rev-parse", "--show-prefix"])
+        .with_context(|| format!("Failed to find {} in its git repository", source.display()))?;
+
+    let dest_str = dest.to_string_lossy();
+    let mut args = vec!["worktree", "add"];
+    match branch {
+        Some(branch) => args.extend(["-b", branch]),
+        None => args.push("--detach"),
+    }
+    args.extend([dest_str.as_ref(), base.unwrap_or("HEAD")]);
+
+    git(source, &args)
+        .with_context(|| format!("Failed to create a git worktree of {}", source.display()))?;
+
+    let prefix = prefix.trim().trim_end_matches('/');
+    let work_dir = dest.join(prefix);
+    Ok(if !prefix.is_empty() && work_dir.is_dir() { work_dir } else { dest.to_path_buf() })
+}
+
+/// Delete a workspace made by `create_workspace`, unregistering it from
+/// the source repository if it is a worktree. A worktree's branch is
+/// kept, along with anything committed to it. A worktree with uncommitted
+/// changes is left alone, failing, unless `force` says to discard them.
+pub fn remove_workspace(source: &Path, dest: &Path, isolation: WorkspaceIsolation, force: bool) -> Result<()> {
+    match isolation {
+        WorkspaceIsolation::None => Ok(()),
+        WorkspaceIsolation::Worktree => {
+            let dest = dest.to_string_lossy();
+            let mut args = vec!["worktree", "remove"];
+            if force {
+                args.push("--force");
+            }
+            args.push(&dest);
+            git(source, &args).map(drop)
+        }
+        WorkspaceIsolation::Copy if dest.exists() => std::fs::remove_dir_all(dest)
+            .with_context(|| format!("Failed to remove workspace {}", dest.display())),
+        WorkspaceIsolation::Copy => Ok(()),
+    }
+}
+
+/// Run git in `dir`, returning its stdout, or failing with its stderr if it
+/// exits unsuccessfully
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("Failed to run git")?;
+
+    if !output.status.success() {
+        anyhow::bail!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Recursively copy a directory, recreating symlinks rather than following them
+fn copy_dir(source: &Path, dest: &Path) -> Result<()> {
+    std::fs::create_dir(dest)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let target = dest.join(entry.file_name());
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(link: &Path, target: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(link)?, target)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(link: &Path, target: &Path) -> Result<()> {
+    std::fs::copy(link, target)?;
+    Ok(())
+}
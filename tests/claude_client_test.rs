@@ -0,0 +1,326 @@
+// Tests for the Claude Code CLI client, using a fake `claude` script
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use supercode::session::ClaudeClient;
+use tempfile::TempDir;
+
+/// Write an executable fake `claude` that logs its arguments next to itself
+/// and then runs `body`
+fn write_script(dir: &Path, body: &str) -> String {
+    let script = dir.join("claude");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\necho \"$@\" >> \"$(dirname \"$0\")/args.log\"\ncat > /dev/null\n{}\n",
+            body
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    script.to_string_lossy().to_string()
+}
+
+/// Fake `claude` that prints `output` to stdout
+fn fake_claude(dir: &Path, output: &str) -> String {
+    write_script(dir, &format!("echo '{}'", output))
+}
+
+fn logged_args(dir: &Path) -> Vec<String> {
+    fs::read_to_string(dir.join("args.log"))
+        .unwrap_or_default()
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_send_message_resumes_claude_session() {
+    let temp_dir = TempDir::new().unwrap();
+    let claude = fake_claude(
+        temp_dir.path(),
+        r#"{"type":"result","result":"hello back","session_id":"claude-abc"}"#,
+    );
+    let client = ClaudeClient::new(claude, temp_dir.path().join("sessions"));
+
+    let session = client.create_session(None, None).await.unwrap();
+
+    let first = client.send_message(&session.session_id, "hello").await.unwrap();
+    assert_eq!(first, "hello back");
+    assert!(!logged_args(temp_dir.path()).iter().any(|a| a.contains("--resume")));
+
+    // The second message continues the Claude-assigned session
+    client.send_message(&session.session_id, "again").await.unwrap();
+
+    let resumed: Vec<_> = logged_args(temp_dir.path())
+        .into_iter()
+        .filter(|a| a.contains("--resume"))
+        .collect();
+    assert_eq!(resumed.len(), 1);
+    assert!(resumed[0].contains("--resume claude-abc"));
+}
+
+#[tokio::test]
+async fn test_send_message_surfaces_stderr_on_failure() {
+    let temp_dir = TempDir::new().unwrap();
+    let claude = write_script(temp_dir.path(), "echo 'error: unknown option --bogus' >&2\nexit 2");
+    let client = ClaudeClient::new(claude, temp_dir.path().join("sessions"));
+
+    let session = client.create_session(None, None).await.unwrap();
+    let err = client.send_message(&session.session_id, "hello").await.unwrap_err();
+
+    let message = format!("{:#}", err);
+    assert!(message.contains("status 2"), "{}", message);
+    assert!(message.contains("unknown option --bogus"), "{}", message);
+}
+
+#[tokio::test]
+async fn test_send_message_times_out_and_kills_process() {
+    let temp_dir = TempDir::new().unwrap();
+    let claude = write_script(temp_dir.path(), "exec sleep 30");
+    let client = ClaudeClient::new(claude, temp_dir.path().join("sessions"))
+        .with_message_timeout(Duration::from_millis(500));
+
+    let session = client.create_session(None, None).await.unwrap();
+
+    let start = Instant::now();
+    let err = client.send_message(&session.session_id, "hello").await.unwrap_err();
+
+    assert!(err.to_string().contains("did not respond"), "{}", err);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(!client.is_running(&session.session_id).await);
+}
+
+#[tokio::test]
+async fn test_create_session_starts_no_process() {
+    let temp_dir = TempDir::new().unwrap();
+    let claude = fake_claude(temp_dir.path(), r#"{"result":"ok","session_id":"claude-abc"}"#);
+    let client = ClaudeClient::new(claude, temp_dir.path().join("sessions"));
+
+    let session = client
+        .create_session(Some("You are a reviewer.".to_string()), None)
+        .await
+        .unwrap();
+
+    assert!(logged_args(temp_dir.path()).is_empty());
+    assert!(!client.is_running(&session.session_id).await);
+
+    // The system prompt rides along with each message invocation
+    client.send_message(&session.session_id, "hello").await.unwrap();
+    let args = logged_args(temp_dir.path());
+    assert_eq!(args.len(), 1);
+    assert!(args[0].contains("--append-system-prompt You are a reviewer."));
+
+    client.kill_session(&session.session_id).await.unwrap();
+    assert!(client.get_session(&session.session_id).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_no_processes_left_after_messages() {
+    let temp_dir = TempDir::new().unwrap();
+    let claude = fake_claude(temp_dir.path(), r#"{"result":"ok"}"#);
+    let client = ClaudeClient::new(claude, temp_dir.path().join("sessions"));
+
+    let first = client.create_session(None, None).await.unwrap();
+    let second = client.create_session(None, None).await.unwrap();
+    client.send_message(&first.session_id, "one").await.unwrap();
+    client.send_message(&second.session_id, "two").await.unwrap();
+
+    assert_eq!(client.list_sessions().await.len(), 2);
+    assert_eq!(client.reap_finished(), 0);
+    assert!(!client.is_running(&first.session_id).await);
+    assert!(!client.is_running(&second.session_id).await);
+}
+
+#[tokio::test]
+async fn test_restart_session_keeps_working_dir_and_conversation() {
+    let temp_dir = TempDir::new().unwrap();
+    let claude = fake_claude(temp_dir.path(), r#"{"result":"ok","session_id":"claude-abc"}"#);
+    let client = ClaudeClient::new(claude, temp_dir.path().join("sessions"));
+
+    let session = client.create_session(None, None).await.unwrap();
+    client.send_message(&session.session_id, "hello").await.unwrap();
+
+    let restarted = client.restart_session(&session.session_id).await.unwrap();
+    assert_ne!(restarted.session_id, session.session_id);
+    assert_eq!(restarted.working_dir, session.working_dir);
+    assert!(client.get_session(&session.session_id).await.unwrap().is_none());
+
+    client.send_message(&restarted.session_id, "again").await.unwrap();
+    let args = logged_args(temp_dir.path());
+    assert!(args[1].contains("--resume claude-abc"));
+}
+
+#[tokio::test]
+async fn test_fork_session_branches_off_parent_conversation() {
+    let temp_dir = TempDir::new().unwrap();
+    let claude = fake_claude(temp_dir.path(), r#"{"result":"ok","session_id":"claude-abc"}"#);
+    let client = ClaudeClient::new(claude, temp_dir.path().join("sessions"));
+
+    let parent = client
+        .create_session(Some("You are a developer.".to_string()), None)
+        .await
+        .unwrap();
+    client.send_message(&parent.session_id, "hello").await.unwrap();
+
+    let fork = client.fork_session(&parent.session_id).await.unwrap();
+    assert_ne!(fork.session_id, parent.session_id);
+    assert_eq!(fork.working_dir, parent.working_dir);
+
+    client.send_message(&fork.session_id, "take the other approach").await.unwrap();
+    client.send_message(&parent.session_id, "carry on").await.unwrap();
+    client.send_message(&fork.session_id, "again").await.unwrap();
+
+    let args = logged_args(temp_dir.path());
+    assert!(args[1].contains("--resume claude-abc --fork-session"), "{}", args[1]);
+    assert!(args[1].contains("--append-system-prompt You are a developer."));
+    // Only the fork's first message branches; the parent is untouched
+    assert!(!args[2].contains("--fork-session"), "{}", args[2]);
+    assert!(!args[3].contains("--fork-session"), "{}", args[3]);
+
+    assert!(client.fork_session("missing").await.is_err());
+}
+
+#[tokio::test]
+async fn test_is_alive_tracks_only_own_children() {
+    let temp_dir = TempDir::new().unwrap();
+    let claude = write_script(temp_dir.path(), "sleep 1\necho '{\"result\":\"ok\"}'");
+    let client = std::sync::Arc::new(ClaudeClient::new(claude, temp_dir.path().join("sessions")));
+
+    let busy = client.create_session(None, None).await.unwrap();
+    let idle = client.create_session(None, None).await.unwrap();
+
+    let sender = client.clone();
+    let busy_id = busy.session_id.clone();
+    let handle = tokio::spawn(async move { sender.send_message(&busy_id, "hello").await });
+
+    let start = Instant::now();
+    let pid = loop {
+        if let Some(pid) = client.pid(&busy.session_id) {
+            break pid;
+        }
+        assert!(start.elapsed() < Duration::from_secs(5), "message process never started");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+
+    assert!(client.is_alive(pid));
+    assert!(client.is_running(&busy.session_id).await);
+    assert!(client.pid(&idle.session_id).is_none());
+    assert!(!client.is_running(&idle.session_id).await);
+
+    // Our own process is not one of our children
+    assert!(!client.is_alive(std::process::id()));
+
+    handle.await.unwrap().unwrap();
+    assert!(!client.is_alive(pid));
+    assert!(client.pid(&busy.session_id).is_none());
+}
+
+#[tokio::test]
+async fn test_permission_denials_block_session() {
+    use supercode::db::repositories::session::{AgentState, ApprovalType};
+    use supercode::session::{ClaudeProvider, SessionProvider};
+
+    let temp_dir = TempDir::new().unwrap();
+    let claude = fake_claude(
+        temp_dir.path(),
+        r#"{"type":"result","result":"I need permission to write that file.","session_id":"claude-abc","permission_denials":[{"tool_name":"Write","tool_use_id":"toolu_1","tool_input":{"file_path":"/work/src/lib.rs","content":"..."}}]}"#,
+    );
+    let provider = ClaudeProvider::new(ClaudeClient::new(claude, temp_dir.path().join("sessions")));
+
+    let handle = provider.create_session(None).await.unwrap();
+    let live = provider.get_agent_state(&handle.provider_id).await.unwrap().unwrap();
+    assert_eq!(live.state, AgentState::Idle);
+
+    provider.send_message(&handle.provider_id, "write it").await.unwrap();
+
+    let live = provider.get_agent_state(&handle.provider_id).await.unwrap().unwrap();
+    assert_eq!(live.state, AgentState::WaitingForApproval);
+    assert_eq!(live.approval_type, Some(ApprovalType::FileWrite));
+    assert_eq!(live.approval_description.as_deref(), Some("Write: /work/src/lib.rs"));
+
+    // The denied turn is over: approving unblocks the session, and the
+    // tool is allowed from its next message on
+    assert!(!provider.respond_to_approval(&handle.provider_id, true, None).await.unwrap());
+    let live = provider.get_agent_state(&handle.provider_id).await.unwrap().unwrap();
+    assert_eq!(live.state, AgentState::Idle);
+    assert!(provider.respond_to_approval(&handle.provider_id, true, None).await.is_err());
+
+    provider.send_message(&handle.provider_id, "try again").await.unwrap();
+    let args = logged_args(temp_dir.path());
+    assert!(!args[0].contains("--allowedTools"), "{}", args[0]);
+    assert!(args[1].contains("--allowedTools Write"), "{}", args[1]);
+
+    assert!(provider.get_agent_state("unknown").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_last_output_kept_for_failed_message() {
+    let temp_dir = TempDir::new().unwrap();
+    let claude = write_script(temp_dir.path(), "echo 'partial'\necho 'boom' >&2\nexit 1");
+    let client = ClaudeClient::new(claude, temp_dir.path().join("sessions"));
+
+    let session = client.create_session(None, None).await.unwrap();
+    assert!(client.send_message(&session.session_id, "hello").await.is_err());
+
+    let session = client.get_session(&session.session_id).await.unwrap().unwrap();
+    assert_eq!(session.last_output.as_deref(), Some("partial\n[stderr]\nboom"));
+}
+
+#[tokio::test]
+async fn test_create_session_in_runs_claude_in_working_dir() {
+    let temp_dir = TempDir::new().unwrap();
+    let claude = write_script(temp_dir.path(), "pwd > \"$(dirname \"$0\")/cwd.log\"\necho '{\"type\":\"result\",\"result\":\"ok\"}'");
+    let client = ClaudeClient::new(claude, temp_dir.path().join("sessions"));
+    let repo = temp_dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+
+    let session = client.create_session_in(None, &repo).await.unwrap();
+    assert_eq!(Path::new(&session.working_dir), repo);
+
+    client.send_message(&session.session_id, "hello").await.unwrap();
+    let cwd = fs::read_to_string(temp_dir.path().join("cwd.log")).unwrap();
+    assert_eq!(Path::new(cwd.trim()).canonicalize().unwrap(), repo.canonicalize().unwrap());
+    assert!(!temp_dir.path().join("sessions").exists());
+
+    assert!(client.create_session_in(None, temp_dir.path().join("missing")).await.is_err());
+}
+
+#[tokio::test]
+async fn test_interrupt_stops_message_and_keeps_session() {
+    use supercode::session::ProviderError;
+
+    let temp_dir = TempDir::new().unwrap();
+    // The first message hangs in a subprocess that outlives the killed CLI
+    let claude = write_script(
+        temp_dir.path(),
+        "marker=\"$(dirname \"$0\")/started\"\nif [ ! -f \"$marker\" ]; then touch \"$marker\"; sleep 8; fi\necho '{\"type\":\"result\",\"result\":\"corrected\"}'",
+    );
+    let client = std::sync::Arc::new(ClaudeClient::new(claude, temp_dir.path().join("sessions")));
+    let session = client.create_session(None, None).await.unwrap();
+
+    // Nothing to stop yet
+    assert!(!client.interrupt(&session.session_id).await.unwrap());
+
+    let sending = {
+        let (client, id) = (client.clone(), session.session_id.clone());
+        tokio::spawn(async move { client.send_message(&id, "do the wrong thing").await })
+    };
+    while !client.is_running(&session.session_id).await {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let start = Instant::now();
+    assert!(client.interrupt(&session.session_id).await.unwrap());
+    let err = sending.await.unwrap().unwrap_err();
+    assert!(matches!(ProviderError::find(&err), Some(ProviderError::Interrupted(_))), "{:#}", err);
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // The session takes the next message as usual
+    let reply = client.send_message(&session.session_id, "do the right thing").await.unwrap();
+    assert_eq!(reply, "corrected");
+
+    assert!(client.interrupt("missing").await.is_err());
+}
@@ -0,0 +1,219 @@
+// Tests for config loading and saving
+
+use supercode::config::{keygen, Config, PeerRequest};
+use tempfile::TempDir;
+
+#[test]
+fn test_encrypted_private_key_round_trip() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config.yml");
+    let path = path.to_str().unwrap();
+
+    let (private_key, public_key) = keygen::generate_keypair().unwrap();
+    let config = Config {
+        name: "node-a".to_string(),
+        encrypted_private_key: Some(keygen::encrypt_secret(&private_key, "hunter2").unwrap()),
+        private_key: private_key.clone(),
+        public_key,
+        ..Config::default()
+    };
+    config.save(Some(path)).unwrap();
+
+    // Only ciphertext reaches the disk
+    let raw = std::fs::read_to_string(path).unwrap();
+    assert!(!raw.contains(&private_key));
+    assert!(raw.contains("encrypted_private_key: argon2id-aes256gcm:"));
+
+    let loaded = Config::load_with_password(Some(path), Some("hunter2")).unwrap();
+    assert_eq!(loaded.private_key, private_key);
+    assert!(loaded.can_peer());
+
+    assert!(Config::load_with_password(Some(path), Some("wrong")).is_err());
+
+    let mut locked = Config::load_locked(Some(path)).unwrap();
+    assert!(locked.private_key.is_empty());
+    assert!(locked.is_locked());
+    assert!(!locked.can_peer());
+    assert_eq!(locked.name, "node-a");
+
+    // Saving a locked config (e.g. after `peer add`) keeps the key
+    locked.save(Some(path)).unwrap();
+    assert!(std::fs::read_to_string(path).unwrap().contains("encrypted_private_key: argon2id-aes256gcm:"));
+
+    // A locked config can be unlocked later, when it's needed for peering
+    assert!(locked.unlock(Some("wrong")).is_err());
+    assert!(locked.is_locked());
+    locked.unlock(Some("hunter2")).unwrap();
+    assert!(!locked.is_locked());
+    assert!(locked.can_peer());
+    assert_eq!(locked.private_key, private_key);
+
+    // Saving a decrypted config keeps the key encrypted
+    loaded.save(Some(path)).unwrap();
+    assert!(!std::fs::read_to_string(path).unwrap().contains(&private_key));
+}
+
+#[test]
+fn test_plaintext_private_key_still_loads() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config.yml");
+    let path = path.to_str().unwrap();
+
+    let (private_key, public_key) = keygen::generate_keypair().unwrap();
+    std::fs::write(path, format!("name: node-b\nprivate_key: {}\npublic_key: {}\n", private_key, public_key)).unwrap();
+
+    let loaded = Config::load(Some(path)).unwrap();
+    assert_eq!(loaded.private_key, private_key);
+    assert!(loaded.encrypted_private_key.is_none());
+}
+
+#[test]
+fn test_decrypt_secret_rejects_tampering() {
+    let encrypted = keygen::encrypt_secret("secret", "pw").unwrap();
+    assert_eq!(keygen::decrypt_secret(&encrypted, "pw").unwrap(), "secret");
+
+    // Each encryption uses a fresh salt and nonce
+    assert_ne!(encrypted, keygen::encrypt_secret("secret", "pw").unwrap());
+
+    let mut tampered = encrypted.clone();
+    tampered.replace_range(tampered.len() - 4.., "AAAA");
+    assert!(keygen::decrypt_secret(&tampered, "pw").is_err());
+    assert!(keygen::decrypt_secret("plain", "pw").is_err());
+}
+
+#[test]
+fn test_mismatched_keys_rejected_on_load() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config.yml");
+    let path = path.to_str().unwrap();
+
+    let (private_key, _) = keygen::generate_keypair().unwrap();
+    let (_, other_public_key) = keygen::generate_keypair().unwrap();
+    std::fs::write(path, format!("name: node-c\nprivate_key: {}\npublic_key: {}\n", private_key, other_public_key)).unwrap();
+
+    let err = Config::load(Some(path)).unwrap_err();
+    assert!(format!("{:#}", err).contains("key mismatch"), "{:#}", err);
+
+    // keygen can still read the config to replace the keys
+    let locked = Config::load_locked(Some(path)).unwrap();
+    assert!(!locked.can_peer());
+
+    let fixed = Config {
+        public_key: keygen::get_public_key(&private_key).unwrap(),
+        ..locked
+    };
+    assert!(fixed.validate_keys().is_ok());
+    assert!(fixed.can_peer());
+}
+
+#[test]
+fn test_verify_peer_requires_matching_key() {
+    use supercode::config::PeerConfig;
+
+    let (_, public_key) = keygen::generate_keypair().unwrap();
+    let (_, other_key) = keygen::generate_keypair().unwrap();
+
+    let mut config = Config::default();
+    config.add_peer("manual", PeerConfig {
+        auth: String::new(),
+        hostnames: vec!["10.0.0.2".to_string()],
+        public_key: String::new(),
+        verified: false,
+    });
+
+    // Nothing to compare against yet
+    assert!(config.verify_peer("manual", &public_key).is_err());
+
+    config.peers.get_mut("manual").unwrap().public_key = public_key.clone();
+
+    let err = config.verify_peer("manual", &other_key).unwrap_err();
+    assert!(err.to_string().contains("mismatch"), "{}", err);
+    assert!(!config.get_peer("manual").unwrap().verified);
+
+    config.verify_peer("manual", &format!("  {}\n", public_key)).unwrap();
+    assert!(config.get_peer("manual").unwrap().verified);
+
+    assert!(config.verify_peer("missing", &public_key).is_err());
+}
+
+#[test]
+fn test_server_host_defaults_to_loopback() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config.yml");
+    let path = path.to_str().unwrap();
+
+    assert_eq!(Config::default().server.host, "127.0.0.1");
+
+    // Older versions saved this default without binding it
+    std::fs::write(path, "name: node-a\nserver:\n  host: 0.0.0.0\n").unwrap();
+    let server = Config::load(Some(path)).unwrap().server;
+    assert_eq!(server.host, "0.0.0.0");
+    assert_eq!(server.bind_host(), "127.0.0.1");
+
+    std::fs::write(path, "name: node-a\nserver:\n  host: 0.0.0.0\n  allow_remote: true\n").unwrap();
+    assert_eq!(Config::load(Some(path)).unwrap().server.bind_host(), "0.0.0.0");
+
+    std::fs::write(path, "name: node-a\nserver:\n  host: ::1\n").unwrap();
+    assert_eq!(Config::load(Some(path)).unwrap().server.bind_host(), "::1");
+
+    std::fs::write(path, "name: node-a\n").unwrap();
+    assert_eq!(Config::load(Some(path)).unwrap().server.bind_host(), "127.0.0.1");
+}
+
+fn request(name: &str, received_at: chrono::DateTime<chrono::Utc>) -> PeerRequest {
+    PeerRequest {
+        name: name.to_string(),
+        public_key: format!("{}-key", name),
+        from_addr: "127.0.0.1".to_string(),
+        received_at,
+    }
+}
+
+#[test]
+fn test_saving_keeps_requests_queued_meanwhile() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config.yml");
+    let path = path.to_str().unwrap();
+
+    let mut server = Config::load_locked(Some(path)).unwrap();
+    server.store_pending_request(request("alice", chrono::Utc::now()), Some(path)).unwrap();
+
+    // The CLI loads, then the server queues another request before the
+    // CLI saves its denial
+    let mut cli = Config::load_locked(Some(path)).unwrap();
+    server.store_pending_request(request("bob", chrono::Utc::now()), Some(path)).unwrap();
+    cli.deny_peer("alice");
+    cli.save(Some(path)).unwrap();
+
+    let pending = Config::load_pending_requests(Some(path)).unwrap();
+    assert!(!pending.contains_key("alice"));
+    assert!(pending.contains_key("bob"));
+
+    // Nor does the server's next request bring the denied one back
+    server.store_pending_request(request("carol", chrono::Utc::now()), Some(path)).unwrap();
+    let mut names: Vec<_> = server.pending_requests.keys().cloned().collect();
+    names.sort();
+    assert_eq!(names, ["bob", "carol"]);
+}
+
+#[test]
+fn test_pending_requests_expire_and_are_capped() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config.yml");
+    let path = path.to_str().unwrap();
+
+    let now = chrono::Utc::now();
+    let mut config = Config::load_locked(Some(path)).unwrap();
+    config.add_pending_request(request("stale", now - chrono::Duration::days(8)));
+    for i in 0..105 {
+        config.add_pending_request(request(&format!("node-{}", i), now - chrono::Duration::minutes(i)));
+    }
+    config.save(Some(path)).unwrap();
+
+    // Only the newest 100 of the fresh ones are kept
+    let pending = Config::load_pending_requests(Some(path)).unwrap();
+    assert_eq!(pending.len(), 100);
+    assert!(!pending.contains_key("stale"));
+    assert!(pending.contains_key("node-99"));
+    assert!(!pending.contains_key("node-100"));
+}
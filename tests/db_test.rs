@@ -1,6 +1,10 @@
 // Tests for Supercode
 
-use supercode::db::{Database, repositories::session::{SessionRepository, AgentType, SessionType, SessionStatus}};
+use supercode::db::{Database, DatabaseConfig, repositories::session::{SessionFilter, SessionRepository, AgentType, SessionType, SessionStatus, AgentState, ApprovalType, SessionActivity}};
+use supercode::db::repositories::message::{MessageRepository, MessageRole};
+use supercode::db::repositories::project::ProjectRepository;
+use supercode::db::repositories::agent_config::AgentConfigRepository;
+use supercode::db::repositories::lease::LeaseRepository;
 use tempfile::TempDir;
 
 fn create_test_db() -> (Database, TempDir) {
@@ -49,7 +53,7 @@ async fn test_list_sessions() {
     ).await.unwrap();
     
     // List sessions
-    let sessions = repo.list(None, None).await.unwrap();
+    let sessions = repo.list(None, None, None).await.unwrap();
     assert_eq!(sessions.len(), 1);
 }
 
@@ -115,14 +119,750 @@ async fn test_filter_by_status() {
     repo.update_status(&s2.id, SessionStatus::Completed).await.unwrap();
     
     // Filter by running
-    let running = repo.list(None, Some(SessionStatus::Running)).await.unwrap();
+    let running = repo.list(None, Some(SessionStatus::Running), None).await.unwrap();
     assert_eq!(running.len(), 1);
     
     // Filter by completed
-    let completed = repo.list(None, Some(SessionStatus::Completed)).await.unwrap();
+    let completed = repo.list(None, Some(SessionStatus::Completed), None).await.unwrap();
     assert_eq!(completed.len(), 1);
     
     // All sessions
-    let all = repo.list(None, None).await.unwrap();
+    let all = repo.list(None, None, None).await.unwrap();
     assert_eq!(all.len(), 2);
 }
+
+#[tokio::test]
+async fn test_message_history() {
+    let (db, _temp) = create_test_db();
+    let session_repo = SessionRepository::new(db.clone());
+    let message_repo = MessageRepository::new(db);
+
+    let session = session_repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+
+    message_repo.create(&session.id, MessageRole::User, "hello").await.unwrap();
+    message_repo.create(&session.id, MessageRole::Assistant, "hi there").await.unwrap();
+
+    // Transcript comes back oldest first
+    let messages = message_repo.list_for_session(&session.id).await.unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].role, MessageRole::User);
+    assert_eq!(messages[0].content, "hello");
+    assert_eq!(messages[1].role, MessageRole::Assistant);
+    assert_eq!(messages[1].content, "hi there");
+
+    // Delete clears the transcript
+    let deleted = message_repo.delete_for_session(&session.id).await.unwrap();
+    assert_eq!(deleted, 2);
+    assert!(message_repo.list_for_session(&session.id).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_recent_messages_limit() {
+    let (db, _temp) = create_test_db();
+    let session_repo = SessionRepository::new(db.clone());
+    let message_repo = MessageRepository::new(db);
+
+    let session = session_repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    for i in 0..5 {
+        message_repo.create(&session.id, MessageRole::User, &format!("message {}", i)).await.unwrap();
+    }
+
+    // Only the last two are returned, still oldest first
+    let recent = message_repo.list_recent_for_session(&session.id, 2).await.unwrap();
+    assert_eq!(recent.len(), 2);
+    assert_eq!(recent[0].content, "message 3");
+    assert_eq!(recent[1].content, "message 4");
+}
+
+#[tokio::test]
+async fn test_update_project() {
+    let (db, _temp) = create_test_db();
+    let repo = ProjectRepository::new(db);
+
+    let project = repo.create("tpyo".to_string(), Some("original".to_string())).await.unwrap();
+
+    // Only the name is rewritten
+    let updated = repo.update(&project.id, Some("typo".to_string()), None).await.unwrap().unwrap();
+    assert_eq!(updated.id, project.id);
+    assert_eq!(updated.name, "typo");
+    assert_eq!(updated.description, Some("original".to_string()));
+    assert!(updated.updated_at >= project.updated_at);
+
+    // Unknown project
+    let missing = repo.update("nonexistent-id", Some("x".to_string()), None).await.unwrap();
+    assert!(missing.is_none());
+}
+
+#[tokio::test]
+async fn test_delete_session_cascades_messages() {
+    let (db, _temp) = create_test_db();
+    let session_repo = SessionRepository::new(db.clone());
+    let message_repo = MessageRepository::new(db);
+
+    let session = session_repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    message_repo.create(&session.id, MessageRole::User, "hello").await.unwrap();
+
+    session_repo.delete(&session.id).await.unwrap();
+
+    assert!(session_repo.get(&session.id).await.unwrap().is_none());
+    assert!(message_repo.list_for_session(&session.id).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_delete_where() {
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db);
+
+    let s1 = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    let s2 = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.update_status(&s1.id, SessionStatus::Terminated).await.unwrap();
+    repo.update_status(&s2.id, SessionStatus::Running).await.unwrap();
+
+    // Nothing is older than an hour ago
+    let cutoff = chrono::Utc::now() - chrono::Duration::hours(1);
+    assert_eq!(repo.delete_where(Some(SessionStatus::Terminated), cutoff).await.unwrap(), 0);
+
+    // Only the terminated session matches the status filter
+    let cutoff = chrono::Utc::now() + chrono::Duration::seconds(1);
+    assert_eq!(repo.delete_where(Some(SessionStatus::Terminated), cutoff).await.unwrap(), 1);
+    assert!(repo.get(&s1.id).await.unwrap().is_none());
+    assert!(repo.get(&s2.id).await.unwrap().is_some());
+
+    // No status filter deletes everything older than the cutoff
+    assert_eq!(repo.delete_where(None, cutoff).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn test_filter_by_agent_type() {
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db);
+
+    repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.create(AgentType::Reviewer, SessionType::OpenCode, None, None).await.unwrap();
+    let s3 = repo.create(AgentType::Reviewer, SessionType::Claude, None, None).await.unwrap();
+    repo.update_status(&s3.id, SessionStatus::Running).await.unwrap();
+
+    let reviewers = repo.list(None, None, Some(AgentType::Reviewer)).await.unwrap();
+    assert_eq!(reviewers.len(), 2);
+    assert!(reviewers.iter().all(|s| s.agent_type == AgentType::Reviewer));
+
+    // Combined with a status filter
+    let running_reviewers = repo.list(None, Some(SessionStatus::Running), Some(AgentType::Reviewer)).await.unwrap();
+    assert_eq!(running_reviewers.len(), 1);
+    assert_eq!(running_reviewers[0].id, s3.id);
+}
+
+#[tokio::test]
+async fn test_filter_by_project_and_status() {
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db);
+
+    let project_a = Some("project-a".to_string());
+    let project_b = Some("project-b".to_string());
+
+    let s1 = repo.create(AgentType::Developer, SessionType::OpenCode, project_a.clone(), None).await.unwrap();
+    let s2 = repo.create(AgentType::Developer, SessionType::OpenCode, project_a.clone(), None).await.unwrap();
+    let s3 = repo.create(AgentType::Developer, SessionType::OpenCode, project_b.clone(), None).await.unwrap();
+
+    repo.update_status(&s1.id, SessionStatus::Running).await.unwrap();
+    repo.update_status(&s2.id, SessionStatus::Completed).await.unwrap();
+    repo.update_status(&s3.id, SessionStatus::Running).await.unwrap();
+
+    let running_a = repo.list(Some("project-a"), Some(SessionStatus::Running), None).await.unwrap();
+    assert_eq!(running_a.len(), 1);
+    assert_eq!(running_a[0].id, s1.id);
+
+    let completed_b = repo.list(Some("project-b"), Some(SessionStatus::Completed), None).await.unwrap();
+    assert!(completed_b.is_empty());
+
+    let all_filters = repo.list(Some("project-b"), Some(SessionStatus::Running), Some(AgentType::Developer)).await.unwrap();
+    assert_eq!(all_filters.len(), 1);
+    assert_eq!(all_filters[0].id, s3.id);
+}
+
+#[tokio::test]
+async fn test_list_paginated() {
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db);
+
+    for _ in 0..5 {
+        repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    }
+    repo.create(AgentType::Reviewer, SessionType::OpenCode, None, None).await.unwrap();
+
+    let (page, total) = repo.list_paginated(None, None, None, Some(2), 0).await.unwrap();
+    assert_eq!(page.len(), 2);
+    assert_eq!(total, 6);
+
+    // Last page is short
+    let (page, total) = repo.list_paginated(None, None, None, Some(4), 4).await.unwrap();
+    assert_eq!(page.len(), 2);
+    assert_eq!(total, 6);
+
+    // Total respects filters
+    let (page, total) = repo.list_paginated(None, None, Some(AgentType::Developer), Some(1), 0).await.unwrap();
+    assert_eq!(page.len(), 1);
+    assert_eq!(total, 5);
+
+    assert_eq!(repo.count(None, None, Some(AgentType::Reviewer)).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn test_list_filtered_by_creation_time() {
+    use chrono::{Duration, TimeZone, Utc};
+    use supercode::db::repositories::session::SessionFilter;
+
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db.clone());
+
+    let day = |d: u32| Utc.with_ymd_and_hms(2024, 3, d, 12, 0, 0).unwrap();
+    for d in [1, 2, 3] {
+        let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+        db.get().await.unwrap()
+            .execute("UPDATE sessions SET created_at = ?1 WHERE id = ?2", rusqlite::params![day(d).to_rfc3339(), session.id])
+            .unwrap();
+    }
+
+    let filter = SessionFilter { created_after: Some(day(2)), ..Default::default() };
+    let (sessions, total) = repo.list_filtered(&filter, None, 0).await.unwrap();
+    assert_eq!(total, 2);
+    assert!(sessions.iter().all(|s| s.created_at >= day(2)));
+
+    // The lower bound is inclusive, the upper bound exclusive
+    let filter = SessionFilter { created_after: Some(day(2)), created_before: Some(day(3)), ..Default::default() };
+    let (sessions, total) = repo.list_filtered(&filter, None, 0).await.unwrap();
+    assert_eq!(total, 1);
+    assert_eq!(sessions[0].created_at, day(2));
+
+    let filter = SessionFilter { created_before: Some(day(1) + Duration::minutes(1)), agent_type: Some(AgentType::Reviewer), ..Default::default() };
+    assert_eq!(repo.list_filtered(&filter, None, 0).await.unwrap().1, 0);
+}
+
+#[tokio::test]
+async fn test_list_filtered_ordering() {
+    use chrono::{TimeZone, Utc};
+    use supercode::db::repositories::session::{SessionOrder, SessionOrderBy};
+
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db.clone());
+
+    let day = |d: u32| Utc.with_ymd_and_hms(2024, 3, d, 12, 0, 0).unwrap();
+    let mut ids = Vec::new();
+    for d in [1, 2] {
+        let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+        db.get().await.unwrap()
+            .execute("UPDATE sessions SET created_at = ?1, updated_at = ?1 WHERE id = ?2", rusqlite::params![day(d).to_rfc3339(), session.id])
+            .unwrap();
+        ids.push(session.id);
+    }
+    let (older, newer) = (&ids[0], &ids[1]);
+
+    // The older session is busy, which moves its updated_at
+    repo.record_activity(&SessionActivity {
+        session_id: older.clone(),
+        state: AgentState::Processing,
+        last_message: Some("keep going".to_string()),
+        last_response: None,
+        state_changed_at: Utc::now(),
+        approval_type: None,
+        approval_description: None,
+    }).await.unwrap();
+
+    let listed = |order: SessionOrder| {
+        let repo = &repo;
+        async move {
+            let filter = SessionFilter { order, ..Default::default() };
+            repo.list_filtered(&filter, None, 0).await.unwrap().0.into_iter().map(|s| s.id).collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(listed(SessionOrder::default()).await, vec![newer.clone(), older.clone()]);
+    assert_eq!(listed(SessionOrder { ascending: true, ..Default::default() }).await, vec![older.clone(), newer.clone()]);
+    assert_eq!(listed(SessionOrder { by: SessionOrderBy::UpdatedAt, ascending: false }).await, vec![older.clone(), newer.clone()]);
+    assert_eq!(listed(SessionOrder { by: SessionOrderBy::UpdatedAt, ascending: true }).await, vec![newer.clone(), older.clone()]);
+}
+
+#[tokio::test]
+async fn test_latest_activity() {
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db);
+
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    assert!(repo.latest_activity(&session.id).await.unwrap().is_none());
+
+    for (state, response) in [(AgentState::Processing, None), (AgentState::Idle, Some("done".to_string()))] {
+        repo.record_activity(&SessionActivity {
+            session_id: session.id.clone(),
+            state,
+            last_message: Some("do the thing".to_string()),
+            last_response: response,
+            state_changed_at: chrono::Utc::now(),
+            approval_type: None,
+            approval_description: None,
+        }).await.unwrap();
+    }
+
+    let latest = repo.latest_activity(&session.id).await.unwrap().unwrap();
+    assert_eq!(latest.state, AgentState::Idle);
+    assert_eq!(latest.last_message, Some("do the thing".to_string()));
+    assert_eq!(latest.last_response, Some("done".to_string()));
+}
+
+#[tokio::test]
+async fn test_merge_metadata() {
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db);
+
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+
+    repo.merge_metadata(&session.id, serde_json::json!({ "branch": "main", "git": { "dirty": false } })).await.unwrap();
+    let merged = repo.merge_metadata(&session.id, serde_json::json!({ "ticket_id": "T-1", "git": { "ahead": 2 } })).await.unwrap().unwrap();
+
+    // Earlier keys survive, nested objects merge
+    assert_eq!(merged, serde_json::json!({
+        "branch": "main",
+        "ticket_id": "T-1",
+        "git": { "dirty": false, "ahead": 2 }
+    }));
+
+    // Null removes a key
+    let merged = repo.merge_metadata(&session.id, serde_json::json!({ "branch": null })).await.unwrap().unwrap();
+    assert!(merged.get("branch").is_none());
+
+    let stored = repo.get(&session.id).await.unwrap().unwrap();
+    let stored: serde_json::Value = serde_json::from_str(&stored.metadata.unwrap()).unwrap();
+    assert_eq!(stored, merged);
+
+    assert!(repo.merge_metadata("nonexistent-id", serde_json::json!({})).await.unwrap().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_metadata_merges_keep_every_key() {
+    let (db, temp) = create_test_db();
+    let repo = std::sync::Arc::new(SessionRepository::new(db));
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+
+    // A second handle on the same file stands in for another process
+    let other = std::sync::Arc::new(SessionRepository::new(Database::new(temp.path().join("test.db")).unwrap()));
+
+    let mut merges = Vec::new();
+    for i in 0..20 {
+        let repo = if i % 2 == 0 { repo.clone() } else { other.clone() };
+        let id = session.id.clone();
+        merges.push(tokio::spawn(async move {
+            repo.merge_metadata(&id, serde_json::json!({ format!("key_{}", i): i })).await.unwrap();
+        }));
+    }
+    for merge in merges {
+        merge.await.unwrap();
+    }
+
+    let stored = repo.get(&session.id).await.unwrap().unwrap();
+    let stored: serde_json::Value = serde_json::from_str(&stored.metadata.unwrap()).unwrap();
+    assert_eq!(stored.as_object().unwrap().len(), 20, "{}", stored);
+}
+
+#[tokio::test]
+async fn test_database_pragmas() {
+    let (db, _temp) = create_test_db();
+    let conn = db.get().await.unwrap();
+
+    let journal_mode: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+    assert_eq!(journal_mode.to_lowercase(), "wal");
+    let busy_timeout: i64 = conn.query_row("PRAGMA busy_timeout", [], |row| row.get(0)).unwrap();
+    assert_eq!(busy_timeout, 5000);
+}
+
+#[tokio::test]
+async fn test_database_config_overrides() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = DatabaseConfig {
+        journal_mode: "DELETE".to_string(),
+        busy_timeout: std::time::Duration::from_millis(250),
+        ..DatabaseConfig::default()
+    };
+    let db = Database::with_config(temp_dir.path().join("test.db"), config).unwrap();
+    let conn = db.get().await.unwrap();
+
+    let journal_mode: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+    assert_eq!(journal_mode.to_lowercase(), "delete");
+    let busy_timeout: i64 = conn.query_row("PRAGMA busy_timeout", [], |row| row.get(0)).unwrap();
+    assert_eq!(busy_timeout, 250);
+}
+
+#[tokio::test]
+async fn test_connections_do_not_serialize() {
+    let (db, _temp) = create_test_db();
+
+    // Two checked-out connections at once, both with per-connection pragmas applied
+    let first = db.get().await.unwrap();
+    let second = db.get().await.unwrap();
+    for conn in [&first, &second] {
+        let foreign_keys: i64 = conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0)).unwrap();
+        assert_eq!(foreign_keys, 1);
+    }
+}
+
+#[tokio::test]
+async fn test_agent_configs() {
+    let (db, _temp) = create_test_db();
+    let repo = AgentConfigRepository::new(db);
+
+    repo.create(AgentType::Reviewer, "qa".to_string(), None, "default".to_string(), Some("You test things.".to_string()))
+        .await.unwrap();
+    repo.create(AgentType::Developer, "architect".to_string(), None, "default".to_string(), Some("You design things.".to_string()))
+        .await.unwrap();
+
+    let qa = repo.get_by_name("qa").await.unwrap().unwrap();
+    assert_eq!(qa.agent_type, AgentType::Reviewer);
+    assert_eq!(qa.system_prompt.as_deref(), Some("You test things."));
+    assert!(repo.get_by_name("missing").await.unwrap().is_none());
+
+    // Names are unique
+    assert!(repo.create(AgentType::Developer, "qa".to_string(), None, "default".to_string(), None).await.is_err());
+
+    assert_eq!(repo.list(None).await.unwrap().len(), 2);
+    let developers = repo.list(Some(AgentType::Developer)).await.unwrap();
+    assert_eq!(developers.len(), 1);
+    assert_eq!(developers[0].name, "architect");
+
+    assert!(repo.delete("qa").await.unwrap());
+    assert_eq!(repo.list(None).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_custom_agent_type_round_trips() {
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db);
+
+    let auditor = "security-auditor".parse::<AgentType>().unwrap();
+    assert_eq!(auditor, AgentType::Custom("security-auditor".to_string()));
+    assert_eq!("developer".parse::<AgentType>().unwrap(), AgentType::Developer);
+    assert!("Bad Name".parse::<AgentType>().is_err());
+    assert!("".parse::<AgentType>().is_err());
+
+    let session = repo.create(auditor.clone(), SessionType::Claude, None, None).await.unwrap();
+    let retrieved = repo.get(&session.id).await.unwrap().unwrap();
+    assert_eq!(retrieved.agent_type, auditor);
+
+    let auditors = repo.list(None, None, Some(auditor)).await.unwrap();
+    assert_eq!(auditors.len(), 1);
+
+    // Serializes as a plain string
+    assert_eq!(serde_json::to_value(&retrieved.agent_type).unwrap(), "security-auditor");
+}
+
+#[tokio::test]
+async fn test_list_blocked() {
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db);
+
+    let blocked = repo.create(AgentType::Developer, SessionType::Claude, None, None).await.unwrap();
+    let unblocked = repo.create(AgentType::Developer, SessionType::Claude, None, None).await.unwrap();
+
+    let waiting = |session_id: &str| SessionActivity {
+        session_id: session_id.to_string(),
+        state: AgentState::WaitingForApproval,
+        last_message: None,
+        last_response: None,
+        state_changed_at: chrono::Utc::now(),
+        approval_type: Some(ApprovalType::FileWrite),
+        approval_description: Some("Write src/main.rs".to_string()),
+    };
+
+    repo.record_activity(&waiting(&blocked.id)).await.unwrap();
+    repo.record_activity(&waiting(&unblocked.id)).await.unwrap();
+    // A later state supersedes the wait
+    repo.record_activity(&SessionActivity { state: AgentState::Processing, approval_type: None, ..waiting(&unblocked.id) })
+        .await.unwrap();
+
+    let list = repo.list_blocked().await.unwrap();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].session_id, blocked.id);
+    assert_eq!(list[0].approval_type, Some(ApprovalType::FileWrite));
+    assert_eq!(list[0].approval_description.as_deref(), Some("Write src/main.rs"));
+}
+
+#[tokio::test]
+async fn test_create_fork_keeps_metadata() {
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db);
+
+    let parent = repo.create(AgentType::Reviewer, SessionType::Claude, Some("p1".to_string()), Some("/work".to_string())).await.unwrap();
+    repo.merge_metadata(&parent.id, serde_json::json!({ "tags": ["auth"] })).await.unwrap();
+    let parent = repo.get(&parent.id).await.unwrap().unwrap();
+
+    let fork = repo.create_fork(&parent).await.unwrap();
+    let fork = repo.get(&fork.id).await.unwrap().unwrap();
+
+    assert_ne!(fork.id, parent.id);
+    assert_eq!(fork.forked_from.as_deref(), Some(parent.id.as_str()));
+    assert_eq!(fork.agent_type, AgentType::Reviewer);
+    assert_eq!(fork.project_id.as_deref(), Some("p1"));
+    assert_eq!(fork.working_dir.as_deref(), Some("/work"));
+    assert_eq!(fork.metadata, parent.metadata);
+    assert_eq!(fork.status, SessionStatus::Pending);
+    assert!(parent.forked_from.is_none());
+}
+
+#[tokio::test]
+async fn test_forked_from_added_to_old_databases() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("old.db");
+
+    // A sessions table from before forks were tracked
+    rusqlite::Connection::open(&db_path).unwrap().execute_batch(
+        "CREATE TABLE sessions (
+            id TEXT PRIMARY KEY, project_id TEXT, agent_type TEXT NOT NULL, session_type TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending', working_dir TEXT, opencode_session_id TEXT,
+            created_at TEXT NOT NULL, updated_at TEXT NOT NULL, metadata TEXT
+        );
+        INSERT INTO sessions VALUES ('s1', NULL, 'developer', 'opencode', 'running', NULL, NULL,
+            '2024-01-01T00:00:00+00:00', '2024-01-01T00:00:00+00:00', NULL);",
+    ).unwrap();
+
+    let repo = SessionRepository::new(Database::new(db_path).unwrap());
+    let session = repo.get("s1").await.unwrap().unwrap();
+    assert!(session.forked_from.is_none());
+
+    let fork = repo.create_fork(&session).await.unwrap();
+    assert_eq!(repo.get(&fork.id).await.unwrap().unwrap().forked_from.as_deref(), Some("s1"));
+}
+
+#[tokio::test]
+async fn test_session_tree() {
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db);
+
+    let manager = repo.create(AgentType::Manager, SessionType::Claude, None, None).await.unwrap();
+    let developer = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    let reviewer = repo.create(AgentType::Reviewer, SessionType::OpenCode, None, None).await.unwrap();
+    let other = repo.create(AgentType::Manager, SessionType::Claude, None, None).await.unwrap();
+    repo.set_parent_session_id(&developer.id, &manager.id).await.unwrap();
+    repo.set_parent_session_id(&reviewer.id, &manager.id).await.unwrap();
+
+    // Forks hang under the session they came from
+    let developer = repo.get(&developer.id).await.unwrap().unwrap();
+    let fork = repo.create_fork(&developer).await.unwrap();
+    assert_eq!(fork.parent_session_id.as_deref(), Some(developer.id.as_str()));
+
+    let children: Vec<String> = repo.children(&manager.id).await.unwrap().into_iter().map(|s| s.id).collect();
+    assert_eq!(children, [developer.id.clone(), reviewer.id.clone()]);
+
+    let roots: Vec<String> = repo.roots().await.unwrap().into_iter().map(|s| s.id).collect();
+    assert_eq!(roots, [manager.id.clone(), other.id.clone()]);
+
+    let tree = repo.tree(&manager.id).await.unwrap().unwrap();
+    assert_eq!(tree.session.id, manager.id);
+    assert_eq!(tree.children.len(), 2);
+    assert_eq!(tree.children[0].session.id, developer.id);
+    assert_eq!(tree.children[0].children[0].session.id, fork.id);
+    assert!(tree.children[1].children.is_empty());
+
+    assert!(repo.tree("missing").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_session_names() {
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db);
+
+    let frontend = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    let backend = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    assert!(frontend.name.is_none());
+
+    repo.set_name(&frontend.id, "frontend").await.unwrap();
+    repo.set_name(&backend.id, "backend").await.unwrap();
+
+    assert_eq!(repo.get(&frontend.id).await.unwrap().unwrap().name.as_deref(), Some("frontend"));
+    assert_eq!(repo.get_by_name("backend").await.unwrap().unwrap().id, backend.id);
+    assert!(repo.get_by_name("docs").await.unwrap().is_none());
+
+    let names: Vec<_> = repo.list(None, None, None).await.unwrap().into_iter().filter_map(|s| s.name).collect();
+    assert_eq!(names.len(), 2);
+
+    // A reused name finds the newest session
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    let again = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_name(&again.id, "frontend").await.unwrap();
+    assert_eq!(repo.get_by_name("frontend").await.unwrap().unwrap().id, again.id);
+}
+
+#[tokio::test]
+async fn test_session_tags() {
+    let (db, _temp) = create_test_db();
+    let repo = SessionRepository::new(db);
+
+    let a = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    let b = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+
+    assert!(repo.add_tag(&a.id, "sprint-14").await.unwrap());
+    assert!(!repo.add_tag(&a.id, "sprint-14").await.unwrap());
+    repo.add_tag(&a.id, "hotfix").await.unwrap();
+    repo.add_tag(&b.id, "sprint-14").await.unwrap();
+
+    assert_eq!(repo.tags(&a.id).await.unwrap(), vec!["hotfix", "sprint-14"]);
+    assert_eq!(repo.list_by_tag("sprint-14").await.unwrap().len(), 2);
+
+    // Every listed tag must match
+    let tags = vec!["sprint-14".to_string(), "hotfix".to_string(), "hotfix".to_string()];
+    let filter = SessionFilter { tags: &tags, ..SessionFilter::default() };
+    let (sessions, total) = repo.list_filtered(&filter, None, 0).await.unwrap();
+    assert_eq!(total, 1);
+    assert_eq!(sessions[0].id, a.id);
+
+    let by_session = repo.tags_for(&[a.id.as_str(), b.id.as_str()]).await.unwrap();
+    assert_eq!(by_session[&b.id], vec!["sprint-14"]);
+
+    assert!(repo.remove_tag(&a.id, "hotfix").await.unwrap());
+    assert!(!repo.remove_tag(&a.id, "hotfix").await.unwrap());
+    assert_eq!(repo.list_by_tag("hotfix").await.unwrap().len(), 0);
+
+    // Tags go with their session
+    repo.delete(&b.id).await.unwrap();
+    assert_eq!(repo.list_by_tag("sprint-14").await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_search_messages() {
+    let (db, _temp) = create_test_db();
+    let session_repo = SessionRepository::new(db.clone());
+    let message_repo = MessageRepository::new(db);
+
+    let failing = session_repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    let other = session_repo.create(AgentType::Reviewer, SessionType::OpenCode, None, None).await.unwrap();
+    message_repo.create(&failing.id, MessageRole::User, "run the tests").await.unwrap();
+    message_repo.create(&failing.id, MessageRole::Assistant, "thread 'main' panicked at src/main.rs:42: index out of bounds").await.unwrap();
+    message_repo.create(&other.id, MessageRole::Assistant, "Reviewed src/lib.rs, looks good").await.unwrap();
+
+    // File names and punctuation are searched as typed, case-insensitively
+    let matches = message_repo.search("SRC/MAIN.RS panicked", 10).await.unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].session_id, failing.id);
+    assert_eq!(matches[0].role, MessageRole::Assistant);
+    assert!(matches[0].snippet.contains("[panicked]"), "{}", matches[0].snippet);
+
+    let matches = message_repo.search("src", 10).await.unwrap();
+    assert_eq!(matches.len(), 2);
+    assert_eq!(message_repo.search("src", 1).await.unwrap().len(), 1);
+    assert!(message_repo.search("\"unbalanced", 10).await.unwrap().is_empty());
+    assert!(message_repo.search("   ", 10).await.is_err());
+
+    // Deleted messages leave the index
+    message_repo.delete_for_session(&failing.id).await.unwrap();
+    let matches = message_repo.search("src", 10).await.unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].session_id, other.id);
+}
+
+#[tokio::test]
+async fn test_search_indexes_messages_from_before_the_index() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("test.db");
+
+    let db = Database::new(&db_path).unwrap();
+    let session = SessionRepository::new(db.clone())
+        .create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    {
+        // As a database from before search existed
+        let conn = db.get().await.unwrap();
+        conn.execute_batch(
+            "DROP TRIGGER messages_fts_insert; DROP TRIGGER messages_fts_delete;
+             DROP TRIGGER messages_fts_update; DROP TABLE messages_fts;"
+        ).unwrap();
+    }
+    MessageRepository::new(db.clone()).create(&session.id, MessageRole::Assistant, "migration failed: no such column").await.unwrap();
+    drop(db);
+
+    let message_repo = MessageRepository::new(Database::new(&db_path).unwrap());
+    let matches = message_repo.search("migration", 10).await.unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].session_id, session.id);
+}
+
+#[tokio::test]
+async fn test_jsonl_export_import_round_trip() {
+    use supercode::db::dump::{export_jsonl, import_jsonl, DumpCounts};
+
+    let (db, _temp) = create_test_db();
+    let project = ProjectRepository::new(db.clone()).create("api".to_string(), Some("REST API".to_string())).await.unwrap();
+    let session_repo = SessionRepository::new(db.clone());
+    let session = session_repo.create(AgentType::Developer, SessionType::Claude, Some(project.id.clone()), Some("/work".to_string())).await.unwrap();
+    session_repo.set_name(&session.id, "api-dev").await.unwrap();
+    session_repo.set_opencode_session_id(&session.id, "provider-1").await.unwrap();
+    session_repo.add_tag(&session.id, "backend").await.unwrap();
+    session_repo.add_tag(&session.id, "sprint-3").await.unwrap();
+    session_repo.set_idempotency_key(&session.id, "build-7").await.unwrap();
+    let fork = session_repo.create_fork(&session_repo.get(&session.id).await.unwrap().unwrap()).await.unwrap();
+    let message_repo = MessageRepository::new(db.clone());
+    message_repo.create(&session.id, MessageRole::User, "add pagination").await.unwrap();
+    message_repo.create(&session.id, MessageRole::Assistant, "done, see src/routes.rs").await.unwrap();
+
+    let mut dump = Vec::new();
+    let counts = export_jsonl(&db, &mut dump).await.unwrap();
+    assert_eq!(counts, DumpCounts { projects: 1, sessions: 2, messages: 2 });
+    let dump = String::from_utf8(dump).unwrap();
+    assert_eq!(dump.lines().count(), 5);
+    assert!(dump.lines().next().unwrap().contains(r#""type":"project""#));
+
+    let (restored, _restored_temp) = create_test_db();
+    let summary = import_jsonl(&restored, dump.as_bytes()).await.unwrap();
+    assert_eq!(summary.imported, counts);
+    assert_eq!(summary.skipped, DumpCounts::default());
+
+    let restored_session = SessionRepository::new(restored.clone()).get(&session.id).await.unwrap().unwrap();
+    assert_eq!(restored_session.name.as_deref(), Some("api-dev"));
+    assert_eq!(restored_session.opencode_session_id.as_deref(), Some("provider-1"));
+    assert_eq!(restored_session.project_id, Some(project.id.clone()));
+    let restored_fork = SessionRepository::new(restored.clone()).get(&fork.id).await.unwrap().unwrap();
+    assert_eq!(restored_fork.forked_from, Some(session.id.clone()));
+    let restored_repo = SessionRepository::new(restored.clone());
+    assert_eq!(restored_repo.tags(&session.id).await.unwrap(), vec!["backend", "sprint-3"]);
+    assert!(restored_repo.tags(&fork.id).await.unwrap().is_empty());
+    assert_eq!(restored_repo.find_by_idempotency_key("build-7").await.unwrap().unwrap().id, session.id);
+
+    let restored_messages = MessageRepository::new(restored.clone());
+    let messages = restored_messages.list_for_session(&session.id).await.unwrap();
+    assert_eq!(messages.iter().map(|m| m.content.as_str()).collect::<Vec<_>>(), vec!["add pagination", "done, see src/routes.rs"]);
+    assert_eq!(restored_messages.search("routes.rs", 10).await.unwrap().len(), 1);
+
+    // A second import finds everything already there, but puts back
+    // tags removed since
+    restored_repo.remove_tag(&session.id, "backend").await.unwrap();
+    let again = import_jsonl(&restored, dump.as_bytes()).await.unwrap();
+    assert_eq!(again.imported, DumpCounts::default());
+    assert_eq!(again.skipped, counts);
+    assert_eq!(restored_repo.tags(&session.id).await.unwrap(), vec!["backend", "sprint-3"]);
+
+    let err = import_jsonl(&restored, "\n{\"type\":\"widget\"}\n".as_bytes()).await.unwrap_err();
+    assert!(err.to_string().contains("line 2"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_lease_has_one_holder_at_a_time() {
+    let (db, _temp) = create_test_db();
+    let leases = LeaseRepository::new(db);
+    let ttl = chrono::Duration::seconds(60);
+
+    assert!(leases.acquire("reconciler", "a", ttl).await.unwrap());
+    assert!(!leases.acquire("reconciler", "b", ttl).await.unwrap());
+    // The holder can renew its own lease
+    assert!(leases.acquire("reconciler", "a", ttl).await.unwrap());
+    // Other names are separate leases
+    assert!(leases.acquire("other", "b", ttl).await.unwrap());
+
+    leases.release("reconciler", "a").await.unwrap();
+    assert!(leases.acquire("reconciler", "b", ttl).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_expired_lease_can_be_taken_over() {
+    let (db, _temp) = create_test_db();
+    let leases = LeaseRepository::new(db);
+
+    assert!(leases.acquire("reconciler", "a", chrono::Duration::zero()).await.unwrap());
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert!(leases.acquire("reconciler", "b", chrono::Duration::seconds(60)).await.unwrap());
+    assert!(!leases.acquire("reconciler", "a", chrono::Duration::seconds(60)).await.unwrap());
+}
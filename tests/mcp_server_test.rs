@@ -0,0 +1,785 @@
+// Tests for the MCP server transport
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use supercode::db::Database;
+use supercode::mcp::{McpServer, MetricsServer};
+use supercode::session::{RetryPolicy, SessionManager};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+async fn start_server() -> (u16, TempDir) {
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let session_manager = Arc::new(SessionManager::with_opencode_url(db, "http://127.0.0.1:1"));
+
+    // Grab a free port, then hand it to the server
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let _ = McpServer::new(port, session_manager).run().await;
+    });
+
+    for _ in 0..50 {
+        if TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    (port, temp_dir)
+}
+
+/// Read one HTTP response and return its JSON body
+async fn read_response(stream: &mut TcpStream) -> serde_json::Value {
+    let mut buf = Vec::new();
+    loop {
+        let mut chunk = [0u8; 4096];
+        let n = stream.read(&mut chunk).await.unwrap();
+        assert!(n > 0, "connection closed early");
+        buf.extend_from_slice(&chunk[..n]);
+
+        let text = String::from_utf8_lossy(&buf).to_string();
+        if let Some((headers, body)) = text.split_once("\r\n\r\n") {
+            let length: usize = headers.lines()
+                .find_map(|l| l.strip_prefix("Content-Length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            if body.len() >= length {
+                return serde_json::from_str(&body[..length]).unwrap();
+            }
+        }
+    }
+}
+
+fn http_request(body: &str) -> String {
+    format!(
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+#[tokio::test]
+async fn test_large_request_split_across_writes() {
+    let (port, _temp) = start_server().await;
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+
+    // Well over the old 8KB buffer, sent in pieces
+    let padding = "x".repeat(64 * 1024);
+    let body = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": { "padding": padding }
+    }).to_string();
+    let request = http_request(&body);
+
+    for chunk in request.as_bytes().chunks(5000) {
+        stream.write_all(chunk).await.unwrap();
+        stream.flush().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let response = read_response(&mut stream).await;
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["result"]["server_info"]["name"], "supercode");
+
+    // The connection stays usable for the next request
+    let body = r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#;
+    stream.write_all(http_request(body).as_bytes()).await.unwrap();
+    let response = read_response(&mut stream).await;
+    assert_eq!(response["id"], 2);
+    assert!(response["result"]["tools"].as_array().unwrap().len() > 1);
+}
+
+#[tokio::test]
+async fn test_stdio_transport_serves_lines() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let session_manager = Arc::new(SessionManager::with_opencode_url(db, "http://127.0.0.1:1"));
+    let server = McpServer::new(0, session_manager);
+
+    let input = concat!(
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#, "\n",
+        r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#, "\n",
+        "not json\n",
+        r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#, "\n",
+    );
+    let mut output = Vec::new();
+    server.serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let responses: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+
+    // No response to the notification
+    assert_eq!(responses.len(), 3);
+    assert_eq!(responses[0]["id"], 1);
+    assert_eq!(responses[1]["error"]["code"], -32700);
+    assert_eq!(responses[2]["id"], 2);
+}
+
+#[tokio::test]
+async fn test_http_notification_gets_no_body() {
+    let (port, _temp) = start_server().await;
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+
+    let body = r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#;
+    stream.write_all(http_request(body).as_bytes()).await.unwrap();
+
+    let expected = b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n";
+    let mut ack = vec![0u8; expected.len()];
+    stream.read_exact(&mut ack).await.unwrap();
+    assert_eq!(&ack[..], &expected[..]);
+
+    let body = r#"{"jsonrpc":"2.0","id":7,"method":"tools/list"}"#;
+    stream.write_all(http_request(body).as_bytes()).await.unwrap();
+    let response = read_response(&mut stream).await;
+    assert_eq!(response["id"], 7);
+}
+
+#[tokio::test]
+async fn test_ping_returns_empty_result() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let server = McpServer::new(0, Arc::new(SessionManager::with_opencode_url(db, "http://127.0.0.1:1")));
+
+    let input = concat!(r#"{"jsonrpc":"2.0","id":"p1","method":"ping"}"#, "\n");
+    let mut output = Vec::new();
+    server.serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let response: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(response["id"], "p1");
+    assert_eq!(response["result"], serde_json::json!({}));
+}
+
+#[tokio::test]
+async fn test_tool_arguments_validated_against_schema() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let server = McpServer::new(0, Arc::new(SessionManager::with_opencode_url(db, "http://127.0.0.1:1")));
+
+    let call = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": {
+            "name": "spawn_session",
+            "arguments": { "name": "dev", "agent_type": "developer", "session_type": "Vim!" }
+        }
+    });
+    let input = format!("{}\n", call);
+    let mut output = Vec::new();
+    server.serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let response: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(response["error"]["code"], -32602);
+    let message = response["error"]["message"].as_str().unwrap();
+    assert!(message.contains("/session_type"), "{}", message);
+    assert!(message.contains("working_dir"), "{}", message);
+}
+
+#[tokio::test]
+async fn test_shutdown_finishes_in_flight_requests() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let session_manager = Arc::new(SessionManager::with_opencode_url(db, "http://127.0.0.1:1"));
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(async move {
+        McpServer::new(port, session_manager)
+            .run_until(async { let _ = shutdown_rx.await; })
+            .await
+    });
+
+    let mut stream = loop {
+        if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)).await {
+            break stream;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+
+    // One round trip so the connection is known to be accepted
+    let body = r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#;
+    stream.write_all(http_request(body).as_bytes()).await.unwrap();
+    assert_eq!(read_response(&mut stream).await["id"], 1);
+
+    // Shut down while a request is on its way; it still gets its answer
+    let body = r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#;
+    stream.write_all(http_request(body).as_bytes()).await.unwrap();
+    shutdown_tx.send(()).unwrap();
+    let response = read_response(&mut stream).await;
+    assert_eq!(response["id"], 2);
+    drop(stream);
+
+    // With its last connection closed the server returns well within the grace period
+    tokio::time::timeout(Duration::from_secs(2), server).await.unwrap().unwrap().unwrap();
+    assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_quality_gates_report_overall_verdict() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let server = McpServer::new(0, Arc::new(SessionManager::with_opencode_url(db, "http://127.0.0.1:1")));
+
+    // No go.mod, so the gate fails without running anything
+    let call = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": {
+            "name": "run_quality_gates",
+            "arguments": { "project_dir": temp_dir.path().to_string_lossy(), "gate": "go_vet" }
+        }
+    });
+    let input = format!("{}\n", call);
+    let mut output = Vec::new();
+    server.serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let response: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let text = response["result"]["content"][0]["text"].as_str().unwrap();
+    let verdict: serde_json::Value = serde_json::from_str(text).unwrap();
+    assert_eq!(verdict["all_passed"], false);
+    assert_eq!(verdict["failed_gates"], serde_json::json!(["go-vet"]));
+    assert_eq!(verdict["results"][0]["output"], "go.mod not found");
+
+    // With no project detected no gate runs, which doesn't count as passing
+    let call = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "tools/call",
+        "params": {
+            "name": "run_quality_gates",
+            "arguments": { "project_dir": temp_dir.path().to_string_lossy(), "gate": "all" }
+        }
+    });
+    let mut output = Vec::new();
+    server.serve_lines(format!("{}\n", call).as_bytes(), &mut output).await.unwrap();
+
+    let response: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let verdict: serde_json::Value = serde_json::from_str(response["result"]["content"][0]["text"].as_str().unwrap()).unwrap();
+    assert_eq!(verdict["gates_run"], 0);
+    assert_eq!(verdict["all_passed"], false);
+}
+
+#[tokio::test]
+async fn test_send_message_pushes_progress_over_stdio() {
+    use supercode::db::repositories::session::{AgentType, SessionType};
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let session_manager = Arc::new(SessionManager::with_opencode_url(db, "http://127.0.0.1:1"));
+    let session = session_manager.repository()
+        .create(AgentType::Developer, SessionType::OpenCode, None, None)
+        .await
+        .unwrap();
+    session_manager.repository().set_opencode_session_id(&session.id, "ses_1").await.unwrap();
+    let server = McpServer::new(0, session_manager);
+
+    let input = format!(
+        "{}\n",
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": {"name": "send_message", "arguments": {"session_id": session.id, "content": "hi"}}
+        })
+    );
+    let mut output = Vec::new();
+    server.serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let lines: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+
+    // Progress comes before the (failed) response, and ends on the final state
+    let (response, notifications) = lines.split_last().unwrap();
+    assert_eq!(response["id"], 1);
+    assert!(response["error"].is_object());
+    assert!(!notifications.is_empty());
+    for notification in notifications {
+        assert_eq!(notification["method"], "notifications/session/progress");
+        assert!(notification.get("id").is_none());
+        assert_eq!(notification["params"]["session_id"], session.id.as_str());
+    }
+    assert_eq!(notifications.last().unwrap()["params"]["state"], "error");
+}
+
+#[tokio::test]
+async fn test_progress_survives_activity_read_errors() {
+    use supercode::db::repositories::session::{AgentType, SessionType};
+
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("test.db");
+    let session_manager = Arc::new(SessionManager::with_opencode_url(Database::new(&db_path).unwrap(), "http://127.0.0.1:1"));
+    let session = session_manager.repository()
+        .create(AgentType::Developer, SessionType::OpenCode, None, None)
+        .await
+        .unwrap();
+    session_manager.repository().set_opencode_session_id(&session.id, "ses_1").await.unwrap();
+    let server = McpServer::new(0, session_manager);
+
+    // Activity can't be read, so there is no progress to report
+    rusqlite::Connection::open(&db_path).unwrap().execute_batch("DROP TABLE activity").unwrap();
+
+    let call = |id: u32| serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": {"name": "send_message", "arguments": {"session_id": session.id, "content": "hi"}}
+    });
+    let input = format!("{}\n{}\n", call(1), call(2));
+    let mut output = Vec::new();
+    server.serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    // The server keeps going and answers both calls
+    let ids: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["id"].clone())
+        .collect();
+    assert_eq!(ids, vec![serde_json::json!(1), serde_json::json!(2)]);
+}
+
+#[tokio::test]
+async fn test_bind_host_is_configurable() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let session_manager = Arc::new(SessionManager::with_opencode_url(db, "http://127.0.0.1:1"));
+
+    let err = McpServer::new(0, session_manager.clone())
+        .with_host("no such host.invalid")
+        .run()
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with("Failed to bind MCP server to no such host.invalid:0"), "{}", err);
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let _ = McpServer::new(port, session_manager).with_host("localhost").run().await;
+    });
+    for _ in 0..50 {
+        if TcpStream::connect(("localhost", port)).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("server never came up on localhost:{}", port);
+}
+
+#[tokio::test]
+async fn test_tool_calls_rate_limited_per_connection() {
+    use supercode::mcp::RateLimit;
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let session_manager = Arc::new(SessionManager::with_opencode_url(db, "http://127.0.0.1:1"));
+    let server = McpServer::new(0, session_manager)
+        .with_rate_limit(RateLimit { per_second: 0.001, burst: 2 });
+
+    let call = |id: u32| format!(
+        r#"{{"jsonrpc":"2.0","id":{},"method":"tools/call","params":{{"name":"list_sessions","arguments":{{}}}}}}"#,
+        id
+    );
+    let input = [call(1), call(2), call(3), r#"{"jsonrpc":"2.0","id":4,"method":"ping"}"#.to_string()].join("\n");
+    let mut output = Vec::new();
+    server.serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let responses: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+
+    assert!(responses[0]["result"].is_object());
+    assert!(responses[1]["result"].is_object());
+    assert_eq!(responses[2]["error"]["code"], -32000);
+    assert_eq!(responses[2]["error"]["message"], "rate limited");
+    // Only tool calls count against the limit
+    assert_eq!(responses[3]["result"], serde_json::json!({}));
+
+    // A fresh connection gets its own bucket
+    let mut output = Vec::new();
+    server.serve_lines(call(5).as_bytes(), &mut output).await.unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert!(response["result"].is_object());
+}
+
+#[tokio::test]
+async fn test_tool_errors_have_distinct_codes() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let server = McpServer::new(0, Arc::new(SessionManager::with_opencode_url(db, "http://127.0.0.1:1")));
+
+    let call = |id: u32, name: &str, arguments: serde_json::Value| serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": { "name": name, "arguments": arguments }
+    }).to_string();
+    let input = [
+        call(1, "send_message", serde_json::json!({ "session_id": "missing", "content": "" })),
+        call(2, "get_session", serde_json::json!({ "session_id": "missing" })),
+        call(3, "list_sessions", serde_json::json!({ "created_after": "yesterday" })),
+    ].join("\n");
+    let mut output = Vec::new();
+    server.serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let responses: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+
+    assert_eq!(responses[0]["error"]["code"], -32602);
+    assert_eq!(responses[0]["error"]["message"], "content cannot be empty");
+    assert_eq!(responses[1]["error"]["code"], -32001);
+    assert_eq!(responses[1]["error"]["data"]["kind"], "not_found");
+    assert_eq!(responses[2]["error"]["code"], -32602);
+}
+
+#[tokio::test]
+async fn test_spawn_session_idempotency_key() {
+    use supercode::db::repositories::session::{AgentType, SessionType};
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let manager = Arc::new(SessionManager::with_opencode_url(db, "http://127.0.0.1:1"));
+    let repo = manager.repository();
+
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&session.id, "ses_1").await.unwrap();
+    assert!(repo.set_idempotency_key(&session.id, "build-42").await.unwrap());
+
+    // The key is unique
+    let other = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    assert!(!repo.set_idempotency_key(&other.id, "build-42").await.unwrap());
+
+    let spawn = |id: u32, key: &str| serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": { "name": "spawn_session", "arguments": {
+            "name": "dev",
+            "agent_type": "developer",
+            "session_type": "opencode",
+            "working_dir": temp_dir.path().to_string_lossy(),
+            "idempotency_key": key
+        }}
+    }).to_string();
+    let input = [spawn(1, "build-42"), spawn(2, "build-43")].join("\n");
+    let mut output = Vec::new();
+    McpServer::new(0, manager.clone()).serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let responses: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+
+    // A known key is answered without contacting the (unreachable) provider
+    let replay: serde_json::Value = serde_json::from_str(responses[0]["result"]["content"][0]["text"].as_str().unwrap()).unwrap();
+    assert_eq!(replay["session_id"], session.id.as_str());
+    assert_eq!(replay["provider_session_id"], "ses_1");
+    assert_eq!(replay["status"], "running");
+    assert_eq!(replay["existing"], true);
+
+    // A new key spawns as usual
+    assert_eq!(responses[1]["error"]["code"], -32002);
+    assert!(repo.find_by_idempotency_key("build-43").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_metrics_endpoint_reports_counters() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let session_manager = Arc::new(
+        SessionManager::with_opencode_url(db, "http://127.0.0.1:1")
+            .with_spawn_retry(RetryPolicy { max_retries: 0, ..RetryPolicy::default() }),
+    );
+    let server = McpServer::new(0, session_manager.clone());
+
+    // Nothing listens on the OpenCode URL, so the spawn fails
+    assert!(session_manager.spawn_session("s1", "coder", "opencode", None, None, None).await.is_err());
+
+    let call = |id: u32, name: &str, arguments: serde_json::Value| serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": { "name": name, "arguments": arguments }
+    }).to_string();
+    let input = [
+        call(1, "run_quality_gates", serde_json::json!({ "project_dir": temp_dir.path().to_string_lossy(), "gate": "go_vet" })),
+        call(2, "get_session", serde_json::json!({ "session_id": "missing" })),
+        call(3, "no_such_tool", serde_json::json!({})),
+    ].join("\n");
+    let mut output = Vec::new();
+    server.serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let metrics = tokio::spawn(async move {
+        MetricsServer::new(port, session_manager)
+            .start_until(async { let _ = shutdown_rx.await; })
+            .await
+    });
+
+    let scrape = |path: &'static str| async move {
+        for _ in 0..50 {
+            if let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)).await {
+                let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                return response;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("metrics server never came up");
+    };
+
+    let response = scrape("/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+    assert!(response.contains("supercode_sessions_failed_total{agent_type=\"coder\",session_type=\"opencode\"} 1"));
+    assert!(response.contains("supercode_quality_gate_runs_total{gate=\"go-vet\",result=\"failed\"} 1"));
+    assert!(response.contains("supercode_tool_calls_total{tool=\"run_quality_gates\",outcome=\"ok\"} 1"));
+    assert!(response.contains("supercode_tool_calls_total{tool=\"get_session\",outcome=\"error\"} 1"));
+    assert!(response.contains("supercode_tool_calls_total{tool=\"unknown\",outcome=\"error\"} 1"));
+    assert!(response.contains("supercode_sessions_blocked 0"));
+
+    assert!(scrape("/").await.starts_with("HTTP/1.1 404 Not Found"));
+
+    // An endless header line is cut off instead of buffered
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nX-Padding: ").await.unwrap();
+    let _ = stream.write_all(&[b'a'; 64 * 1024]).await;
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response).await;
+    assert!(response.is_empty(), "{}", String::from_utf8_lossy(&response));
+
+    shutdown_tx.send(()).unwrap();
+    metrics.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_healthz_answers_plain_http_get() {
+    let (port, _temp_dir) = start_server().await;
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+
+    stream.write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let health = read_response(&mut stream).await;
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["database"], true);
+    // Nothing listens on the OpenCode URL
+    assert_eq!(health["opencode"], false);
+
+    // The connection still serves JSON-RPC afterwards
+    stream.write_all(http_request(r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#).as_bytes()).await.unwrap();
+    let response = read_response(&mut stream).await;
+    assert_eq!(response["result"], serde_json::json!({}));
+}
+
+#[tokio::test]
+async fn test_get_capabilities_reports_provider_features() {
+    use supercode::db::repositories::session::{AgentType, SessionRepository, SessionType};
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let claude = SessionRepository::new(db.clone())
+        .create(AgentType::Developer, SessionType::Claude, None, None).await.unwrap();
+    let server = McpServer::new(0, Arc::new(SessionManager::with_opencode_url(db, "http://127.0.0.1:1")));
+
+    let call = |id: u32, arguments: serde_json::Value| serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": { "name": "get_capabilities", "arguments": arguments }
+    }).to_string();
+    let input = [
+        call(1, serde_json::json!({})),
+        call(2, serde_json::json!({ "session_id": claude.id })),
+        call(3, serde_json::json!({ "session_type": "vim" })),
+    ].join("\n");
+    let mut output = Vec::new();
+    server.serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let responses: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    let capabilities = |response: &serde_json::Value| -> serde_json::Value {
+        serde_json::from_str::<serde_json::Value>(response["result"]["content"][0]["text"].as_str().unwrap()).unwrap()["capabilities"].clone()
+    };
+
+    let all = capabilities(&responses[0]);
+    assert_eq!(all["opencode"]["supports_fork"], true);
+    assert_eq!(all["opencode"]["supports_streaming"], true);
+    assert_eq!(all["claude"]["supports_fork"], true);
+    assert!(all.get("openai").is_none());
+
+    let session = capabilities(&responses[1]);
+    assert_eq!(session, serde_json::json!({
+        "claude": { "supports_fork": true, "supports_streaming": false, "supports_resume": false, "supports_approval": true, "supports_interrupt": true }
+    }));
+
+    assert_eq!(responses[2]["error"]["code"], -32602);
+}
+
+#[tokio::test]
+async fn test_cleanup_sessions_spares_live_sessions() {
+    use supercode::db::repositories::session::{AgentType, SessionStatus, SessionType};
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let manager = Arc::new(SessionManager::with_opencode_url(db, "http://127.0.0.1:1"));
+    let repo = manager.repository();
+
+    let done = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.update_status(&done.id, SessionStatus::Completed).await.unwrap();
+    let live = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&live.id, "ses_live").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let cleanup = |id: u32, arguments: serde_json::Value| serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": { "name": "cleanup_sessions", "arguments": arguments }
+    }).to_string();
+    let input = [
+        cleanup(1, serde_json::json!({ "older_than_days": 0 })),
+        // The provider is unreachable, so the running session can't be killed
+        cleanup(2, serde_json::json!({ "older_than_days": 0, "statuses": ["running"] })),
+        cleanup(3, serde_json::json!({ "older_than_days": 1u64 << 50 })),
+    ].join("\n");
+    let mut output = Vec::new();
+    McpServer::new(0, manager.clone()).serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let responses: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    let result = |i: usize| -> serde_json::Value {
+        serde_json::from_str(responses[i]["result"]["content"][0]["text"].as_str().unwrap()).unwrap()
+    };
+
+    // By default only finished sessions go
+    assert_eq!(result(0)["deleted"], 1);
+    assert!(repo.get(&done.id).await.unwrap().is_none());
+
+    assert_eq!(result(1)["deleted"], 0);
+    assert_eq!(result(1)["kept"][0]["session_id"], live.id.as_str());
+    assert!(repo.get(&live.id).await.unwrap().is_some());
+
+    assert_eq!(responses[2]["error"]["code"], -32602);
+}
+
+/// An OpenCode server that answers health checks but refuses to create
+/// sessions. Returns its base URL.
+async fn opencode_refusing_sessions() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                let header_end = loop {
+                    if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break pos + 4;
+                    }
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                };
+                let headers = String::from_utf8_lossy(&request[..header_end]).to_string();
+                let content_length = headers.lines()
+                    .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                    .unwrap_or(0);
+                while request.len() < header_end + content_length {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+
+                let (status, body) = if headers.starts_with("GET /health ") {
+                    ("200 OK", "true")
+                } else {
+                    ("500 Internal Server Error", r#"{"error":"out of capacity"}"#)
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            });
+        }
+    });
+
+    url
+}
+
+#[tokio::test]
+async fn test_failed_spawn_deletes_or_keeps_the_session() {
+    use supercode::db::repositories::session::SessionStatus;
+
+    let url = opencode_refusing_sessions().await;
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let manager = Arc::new(SessionManager::with_opencode_url(db, &url));
+    let work_dir = TempDir::new().unwrap();
+
+    let spawn = |id: u32, keep_failed: bool| serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": { "name": "spawn_session", "arguments": {
+            "name": "dev",
+            "agent_type": "developer",
+            "session_type": "opencode",
+            "working_dir": work_dir.path().to_string_lossy(),
+            "isolation": "copy",
+            "keep_failed": keep_failed
+        }}
+    }).to_string();
+    let input = [spawn(1, false), spawn(2, true)].join("\n");
+    let mut output = Vec::new();
+    McpServer::new(0, manager.clone()).serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let responses: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    let result = |i: usize| -> serde_json::Value {
+        serde_json::from_str(responses[i]["result"]["content"][0]["text"].as_str().unwrap()).unwrap()
+    };
+    let repo = manager.repository();
+
+    // By default the row goes
+    let deleted = result(0);
+    assert_eq!(deleted["status"], "deleted", "{}", deleted);
+    assert!(deleted["error"].as_str().unwrap().contains("Failed to create OpenCode session"), "{}", deleted);
+    assert!(repo.get(deleted["session_id"].as_str().unwrap()).await.unwrap().is_none());
+
+    // Kept, it is marked failed with the error alongside its workspace
+    let kept = result(1);
+    assert_eq!(kept["status"], "failed", "{}", kept);
+    let session = repo.get(kept["session_id"].as_str().unwrap()).await.unwrap().unwrap();
+    assert_eq!(session.status, SessionStatus::Failed);
+    let metadata: serde_json::Value = serde_json::from_str(session.metadata.as_deref().unwrap()).unwrap();
+    assert!(metadata["error"].as_str().unwrap().contains("Failed to create OpenCode session"), "{}", metadata);
+    let workspace = metadata["workspace"]["path"].as_str().unwrap();
+    assert!(std::path::Path::new(workspace).is_dir(), "{}", workspace);
+}
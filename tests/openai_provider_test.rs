@@ -0,0 +1,150 @@
+// Tests for the OpenAI-compatible session provider
+
+use std::time::Duration;
+
+use supercode::db::{Database, repositories::session::{AgentType, SessionRepository, SessionType}};
+use supercode::session::{GenericHttpProvider, OpenAiClient, ProviderError, SessionManager, SessionProvider, SessionStatus};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// A chat completions API that answers with how many messages it was sent
+/// and whether a bearer token came with them. Returns the API root.
+async fn fake_api() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/v1", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                let header_end = loop {
+                    if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break pos + 4;
+                    }
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                };
+                let headers = String::from_utf8_lossy(&request[..header_end]).to_string();
+                let content_length = headers.lines()
+                    .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                    .unwrap_or(0);
+                while request.len() < header_end + content_length {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+
+                let authorized = headers.lines().any(|l| l.eq_ignore_ascii_case("authorization: Bearer sk-test"));
+                let (status, body) = match headers.lines().next().unwrap() {
+                    line if line.starts_with("GET /v1/models ") => ("200 OK", r#"{"data":[]}"#.to_string()),
+                    line if line.starts_with("POST /v1/chat/completions ") => {
+                        let body: serde_json::Value = serde_json::from_slice(&request[header_end..]).unwrap();
+                        let messages = body["messages"].as_array().unwrap();
+                        if messages.last().unwrap()["content"] == "fail" {
+                            ("500 Internal Server Error", r#"{"error":"model overloaded"}"#.to_string())
+                        } else {
+                            let reply = format!("{} messages to {}, authorized: {}", messages.len(), body["model"].as_str().unwrap(), authorized);
+                            ("200 OK", serde_json::json!({ "choices": [{ "message": { "role": "assistant", "content": reply } }] }).to_string())
+                        }
+                    }
+                    _ => ("404 Not Found", "{}".to_string()),
+                };
+
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            });
+        }
+    });
+
+    url
+}
+
+#[tokio::test]
+async fn test_conversation_is_sent_in_full() {
+    let url = fake_api().await;
+    let provider = GenericHttpProvider::new(OpenAiClient::new(url, "gateway-model").with_api_key("sk-test"));
+    assert!(provider.health_check().await.unwrap());
+
+    let handle = provider.create_session(Some("Be brief".to_string())).await.unwrap();
+    assert!(matches!(provider.get_status(&handle.provider_id).await.unwrap(), SessionStatus::Running));
+
+    // system + user, then system + user + assistant + user
+    let first = provider.send_message(&handle.provider_id, "hello").await.unwrap();
+    assert_eq!(first, "2 messages to gateway-model, authorized: true");
+    let second = provider.send_message(&handle.provider_id, "again").await.unwrap();
+    assert!(second.starts_with("4 messages"), "{}", second);
+
+    // A failed request isn't kept in the conversation
+    let err = provider.send_message(&handle.provider_id, "fail").await.unwrap_err();
+    assert!(matches!(ProviderError::find(&err), Some(ProviderError::Api { status: 500, .. })), "{:#}", err);
+    let third = provider.send_message(&handle.provider_id, "once more").await.unwrap();
+    assert!(third.starts_with("6 messages"), "{}", third);
+
+    // Forks carry the history; restarts keep only the system prompt
+    let fork = provider.fork_session(&handle.provider_id).await.unwrap();
+    assert!(provider.send_message(&fork.provider_id, "hi").await.unwrap().starts_with("8 messages"));
+    let restarted = provider.restart_session(&handle.provider_id).await.unwrap();
+    assert!(provider.send_message(&restarted.provider_id, "hi").await.unwrap().starts_with("2 messages"));
+    let err = provider.get_status(&handle.provider_id).await.unwrap_err();
+    assert!(matches!(ProviderError::find(&err), Some(ProviderError::NotFound(_))), "{:#}", err);
+
+    provider.kill_session(&fork.provider_id).await.unwrap();
+    let err = provider.send_message(&fork.provider_id, "hi").await.unwrap_err();
+    assert!(matches!(ProviderError::find(&err), Some(ProviderError::NotFound(_))), "{:#}", err);
+}
+
+#[tokio::test]
+async fn test_concurrent_messages_keep_both_exchanges() {
+    let url = fake_api().await;
+    let provider = GenericHttpProvider::new(OpenAiClient::new(url, "gateway-model"));
+    let handle = provider.create_session(None).await.unwrap();
+
+    // Sent together, one waits for the other's exchange: 1 message, then 3
+    let (first, second) = tokio::join!(
+        provider.send_message(&handle.provider_id, "one"),
+        provider.send_message(&handle.provider_id, "two"),
+    );
+    let mut counts = [first.unwrap(), second.unwrap()];
+    counts.sort();
+    assert!(counts[0].starts_with("1 messages"), "{:?}", counts);
+    assert!(counts[1].starts_with("3 messages"), "{:?}", counts);
+
+    let next = provider.send_message(&handle.provider_id, "three").await.unwrap();
+    assert!(next.starts_with("5 messages"), "{}", next);
+}
+
+#[tokio::test]
+async fn test_spawn_openai_session() {
+    let url = fake_api().await;
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+
+    // Not configured: refused with a hint
+    let unconfigured = SessionManager::with_opencode_url(db.clone(), "http://127.0.0.1:1");
+    let err = unconfigured.ensure_provider_available("openai").await.unwrap_err();
+    assert!(err.to_string().contains("openai_base_url"), "{}", err);
+
+    let manager = SessionManager::with_opencode_url(db, "http://127.0.0.1:1")
+        .with_openai(OpenAiClient::new(url, "gateway-model").with_request_timeout(Some(Duration::from_secs(5))));
+    manager.ensure_provider_available("openai").await.unwrap();
+
+    let session = repo.create(AgentType::Developer, SessionType::OpenAi, None, None).await.unwrap();
+    let handle = manager.spawn_session(&session.id, "developer", "openai", Some("dev"), None, None).await.unwrap();
+    assert!(handle.provider_id.starts_with("chat_"));
+
+    let reply = manager.send_message(&session.id, &handle.provider_id, "openai", "status?").await.unwrap();
+    assert_eq!(reply, "3 messages to gateway-model, authorized: false");
+    let history = manager.messages().list_for_session(&session.id).await.unwrap();
+    assert_eq!(history.len(), 4);
+}
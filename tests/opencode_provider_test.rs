@@ -0,0 +1,910 @@
+// Tests for the OpenCode provider against a fake OpenCode server
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use supercode::db::{Database, repositories::session::{AgentState, AgentType, ApprovalType, SessionRepository, SessionStatus, SessionType}};
+use supercode::session::{OpenCodeClient, OpenCodeProvider, ProviderError, RetryPolicy, SessionManager, SessionProvider};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Serve canned JSON bodies keyed by "METHOD /path", returning the base URL
+async fn fake_opencode(routes: HashMap<&'static str, &'static str>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    serve(listener, routes);
+    url
+}
+
+fn serve(listener: TcpListener, routes: HashMap<&'static str, &'static str>) {
+    let routes = Arc::new(routes);
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let routes = routes.clone();
+            tokio::spawn(async move {
+                let Some(request) = read_request(&mut stream).await else {
+                    return;
+                };
+                let route = request.lines().next().unwrap().rsplit_once(' ').unwrap().0.to_string();
+                let (status, body) = match routes.get(route.as_str()) {
+                    Some(body) => ("200 OK", *body),
+                    None => ("404 Not Found", "{}"),
+                };
+
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            });
+        }
+    });
+}
+
+/// Read a whole request, body included so closing doesn't reset the
+/// connection. None if the client hung up first.
+async fn read_request(stream: &mut TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await.unwrap();
+        if n == 0 {
+            return None;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let header_end = request.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let content_length = String::from_utf8_lossy(&request[..header_end])
+        .lines()
+        .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+        .unwrap_or(0);
+    while request.len() < header_end + content_length {
+        let n = stream.read(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    Some(String::from_utf8_lossy(&request).into_owned())
+}
+
+/// Answer one request with a chunked `content_type` body sent piece by
+/// piece, so the client sees lines split across reads. Returns the base URL.
+async fn fake_stream(content_type: &'static str, pieces: Vec<&'static str>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        read_request(&mut stream).await.unwrap();
+
+        let headers = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n",
+            content_type
+        );
+        stream.write_all(headers.as_bytes()).await.unwrap();
+        for piece in pieces {
+            stream.write_all(format!("{:x}\r\n{}\r\n", piece.len(), piece).as_bytes()).await.unwrap();
+            stream.flush().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        stream.write_all(b"0\r\n\r\n").await.unwrap();
+    });
+
+    url
+}
+
+const SESSION: &str = r#"{"id":"ses_1","projectID":"p1","directory":"/work","title":"New session","version":"0.15.0","time":{"created":1,"updated":2}}"#;
+
+#[tokio::test]
+async fn test_agent_state_from_run_status() {
+    let url = fake_opencode(HashMap::from([
+        ("GET /session/ses_1", SESSION),
+        ("GET /permission", "[]"),
+        ("GET /session/status", r#"{"ses_1":{"type":"busy"},"ses_2":{"type":"retry","attempt":2,"message":"rate limited","next":3}}"#),
+    ])).await;
+    let provider = OpenCodeProvider::with_url(url);
+
+    assert!(matches!(provider.get_status("ses_1").await.unwrap(), supercode::session::SessionStatus::Running));
+
+    let live = provider.get_agent_state("ses_1").await.unwrap().unwrap();
+    assert_eq!(live.state, AgentState::Processing);
+
+    let live = provider.get_agent_state("ses_2").await.unwrap().unwrap();
+    assert_eq!(live.state, AgentState::Processing);
+
+    // Sessions missing from the status map are idle
+    let live = provider.get_agent_state("ses_3").await.unwrap().unwrap();
+    assert_eq!(live.state, AgentState::Idle);
+}
+
+#[tokio::test]
+async fn test_pending_permission_blocks_session() {
+    let url = fake_opencode(HashMap::from([
+        ("GET /permission", r#"[{"id":"per_1","type":"bash","pattern":"rm -rf target","sessionID":"ses_1","messageID":"msg_1","title":"rm -rf target","metadata":{},"time":{"created":1}}]"#),
+        ("GET /session/status", r#"{"ses_1":{"type":"busy"}}"#),
+    ])).await;
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let manager = SessionManager::with_opencode_url(db, url);
+
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&session.id, "ses_1").await.unwrap();
+    repo.update_status(&session.id, SessionStatus::Running).await.unwrap();
+
+    let blocked = manager.get_blocked_sessions().await.unwrap();
+    assert_eq!(blocked.len(), 1);
+    assert_eq!(blocked[0].session_id, session.id);
+    assert_eq!(blocked[0].approval_type, Some(ApprovalType::Command));
+    assert_eq!(blocked[0].approval_description.as_deref(), Some("rm -rf target"));
+
+    // An unchanged state is not recorded again
+    let blocked_again = manager.get_blocked_sessions().await.unwrap();
+    assert_eq!(blocked_again[0].state_changed_at, blocked[0].state_changed_at);
+}
+
+fn quick_retry(max_retries: u32) -> RetryPolicy {
+    RetryPolicy {
+        max_retries,
+        initial_backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_millis(400),
+    }
+}
+
+#[tokio::test]
+async fn test_spawn_retries_until_opencode_is_back() {
+    // The server comes up a little after the first attempt
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+        serve(listener, HashMap::from([
+            ("POST /session", r#"{"id":"ses_1"}"#),
+            ("POST /session/ses_1/message", r#"{"parts":[{"type":"text","text":"ready"}]}"#),
+        ]));
+    });
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let manager = SessionManager::with_opencode_url(db, format!("http://127.0.0.1:{}", port))
+        .with_spawn_retry(quick_retry(5));
+
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    let handle = manager.spawn_session(&session.id, "developer", "opencode", Some("dev"), None, None).await.unwrap();
+    assert_eq!(handle.provider_id, "ses_1");
+}
+
+#[tokio::test]
+async fn test_spawn_does_not_retry_api_errors() {
+    // Reachable, but every route is a 404
+    let url = fake_opencode(HashMap::new()).await;
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let manager = SessionManager::with_opencode_url(db, url).with_spawn_retry(RetryPolicy {
+        max_retries: 3,
+        initial_backoff: Duration::from_secs(30),
+        max_backoff: Duration::from_secs(30),
+    });
+
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    let start = Instant::now();
+    let err = manager.spawn_session(&session.id, "developer", "opencode", Some("dev"), None, None).await.unwrap_err();
+    assert!(format!("{:#}", err).contains("404"), "{:#}", err);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn test_spawn_does_not_retry_timed_out_creates() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Takes the request but never answers; the session may exist regardless
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let counter = attempts.clone();
+    tokio::spawn(async move {
+        let mut open = Vec::new();
+        loop {
+            open.push(listener.accept().await.unwrap().0);
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+
+    let config = supercode::config::Config {
+        opencode_url: url,
+        opencode_request_timeout_secs: 1,
+        ..supercode::config::Config::default()
+    };
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let manager = SessionManager::from_config(db, &config).unwrap().with_spawn_retry(quick_retry(3));
+
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    let err = manager.spawn_session(&session.id, "developer", "opencode", Some("dev"), None, None).await.unwrap_err();
+    assert!(matches!(supercode::session::ProviderError::find(&err), Some(supercode::session::ProviderError::Timeout(_))), "{:#}", err);
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_spawn_gives_up_after_max_retries() {
+    // Nothing listens on port 1
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let manager = SessionManager::with_opencode_url(db, "http://127.0.0.1:1").with_spawn_retry(quick_retry(2));
+
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    let start = Instant::now();
+    assert!(manager.spawn_session(&session.id, "developer", "opencode", Some("dev"), None, None).await.is_err());
+    // Two retries: 100ms then 200ms
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn test_list_session_forks() {
+    let url = fake_opencode(HashMap::from([
+        ("GET /session/ses_1/children", r#"[
+            {"id":"ses_2","projectID":"p1","directory":"/work","parentID":"ses_1","title":"Fork","version":"0.15.0","time":{"created":3,"updated":4}},
+            {"id":"ses_3","projectID":"p1","directory":"/work","parentID":"ses_1","title":"Fork","version":"0.15.0","time":{"created":5,"updated":6}}
+        ]"#),
+    ])).await;
+
+    let provider = OpenCodeProvider::with_url(url.clone());
+    assert_eq!(provider.get_children("ses_1").await.unwrap(), ["ses_2", "ses_3"]);
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let server = supercode::mcp::McpServer::new(0, Arc::new(SessionManager::with_opencode_url(db, url)));
+
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&session.id, "ses_1").await.unwrap();
+    let session = repo.get(&session.id).await.unwrap().unwrap();
+    let fork = repo.create_fork(&session).await.unwrap();
+    repo.set_opencode_session_id(&fork.id, "ses_2").await.unwrap();
+
+    let call = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": { "name": "list_session_forks", "arguments": { "session_id": session.id } }
+    });
+    let mut output = Vec::new();
+    server.serve_lines(format!("{}\n", call).as_bytes(), &mut output).await.unwrap();
+
+    let response: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let result: serde_json::Value = serde_json::from_str(response["result"]["content"][0]["text"].as_str().unwrap()).unwrap();
+    let forks = result["forks"].as_array().unwrap();
+
+    // The fork made here is listed once, with its DB row; the other only by provider ID
+    assert_eq!(forks.len(), 2);
+    assert_eq!(forks[0]["session_id"], fork.id);
+    assert_eq!(forks[0]["provider_session_id"], "ses_2");
+    assert_eq!(forks[0]["status"], "running");
+    assert_eq!(forks[1]["session_id"], serde_json::Value::Null);
+    assert_eq!(forks[1]["provider_session_id"], "ses_3");
+    assert_eq!(result["provider_error"], serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn test_shutdown_all_terminates_running_sessions() {
+    let url = fake_opencode(HashMap::from([
+        ("GET /session/ses_ok", r#"{"id":"ses_ok","projectID":"p1","directory":"/work","title":"Ok","version":"0.15.0","time":{"created":1,"updated":2}}"#),
+        ("GET /session/ses_gone", r#"{"id":"ses_gone","projectID":"p1","directory":"/work","title":"Gone","version":"0.15.0","time":{"created":1,"updated":2}}"#),
+        ("DELETE /session/ses_ok", "true"),
+        ("DELETE /session/ses_other", "true"),
+    ])).await;
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let manager = SessionManager::with_opencode_url(db, url);
+
+    let ok = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    manager.resume_session(&ok.id, "ses_ok", "opencode").await.unwrap();
+    let gone = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    manager.resume_session(&gone.id, "ses_gone", "opencode").await.unwrap();
+    let pending = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    // Running, but held by another process sharing the database
+    let other = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&other.id, "ses_other").await.unwrap();
+
+    let summary = manager.shutdown_all().await.unwrap();
+    assert_eq!(summary.terminated, vec![ok.id.clone()]);
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].0, gone.id);
+    assert!(summary.failed[0].1.contains("Failed to kill OpenCode session"), "{}", summary.failed[0].1);
+
+    assert_eq!(repo.get(&ok.id).await.unwrap().unwrap().status, SessionStatus::Terminated);
+    assert_eq!(repo.get(&gone.id).await.unwrap().unwrap().status, SessionStatus::Running);
+    assert_eq!(repo.get(&pending.id).await.unwrap().unwrap().status, SessionStatus::Pending);
+    assert_eq!(repo.get(&other.id).await.unwrap().unwrap().status, SessionStatus::Running);
+}
+
+#[tokio::test]
+async fn test_provider_errors_are_classified() {
+    use supercode::session::ProviderError;
+
+    let url = fake_opencode(HashMap::new()).await;
+    let provider = OpenCodeProvider::with_url(url.clone());
+
+    // The server answers, but has no such session
+    let err = provider.send_message("ses_missing", "hi").await.unwrap_err();
+    let class = ProviderError::find(&err).unwrap();
+    assert!(matches!(class, ProviderError::NotFound(_)), "{:?}", class);
+    assert!(!class.is_transient());
+
+    // Nothing listens on port 1
+    let err = OpenCodeProvider::with_url("http://127.0.0.1:1").send_message("ses_1", "hi").await.unwrap_err();
+    let class = ProviderError::find(&err).unwrap();
+    assert!(matches!(class, ProviderError::Unreachable(_)), "{:?}", class);
+    assert!(class.is_transient());
+
+    // Over MCP the two get distinct codes, and say whether a retry may help
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let server = supercode::mcp::McpServer::new(0, Arc::new(SessionManager::with_opencode_url(db, url)));
+
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&session.id, "ses_missing").await.unwrap();
+
+    let call = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": { "name": "send_message", "arguments": { "session_id": session.id, "content": "hi" } }
+    });
+    let mut output = Vec::new();
+    server.serve_lines(format!("{}\n", call).as_bytes(), &mut output).await.unwrap();
+
+    let response: serde_json::Value = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .next_back()
+        .unwrap();
+    assert_eq!(response["error"]["code"], -32001);
+    assert_eq!(response["error"]["data"]["kind"], "not_found");
+    assert_eq!(response["error"]["data"]["retryable"], false);
+}
+
+#[tokio::test]
+async fn test_refresh_status_from_provider() {
+    let url = fake_opencode(HashMap::from([
+        ("GET /session/ses_live", r#"{"id":"ses_live","projectID":"p1","directory":"/work","title":"Live","version":"0.15.0","time":{"created":1,"updated":2}}"#),
+    ])).await;
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let manager = Arc::new(SessionManager::with_opencode_url(db, url));
+
+    let live = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&live.id, "ses_live").await.unwrap();
+    let gone = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&gone.id, "ses_gone").await.unwrap();
+    let done = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&done.id, "ses_gone_too").await.unwrap();
+    repo.update_status(&done.id, SessionStatus::Completed).await.unwrap();
+    let unspawned = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+
+    assert_eq!(manager.reconcile_statuses().await.unwrap(), 1);
+    assert_eq!(repo.get(&live.id).await.unwrap().unwrap().status, SessionStatus::Running);
+    assert_eq!(repo.get(&gone.id).await.unwrap().unwrap().status, SessionStatus::Terminated);
+    assert_eq!(repo.get(&done.id).await.unwrap().unwrap().status, SessionStatus::Completed);
+    assert_eq!(repo.get(&unspawned.id).await.unwrap().unwrap().status, SessionStatus::Pending);
+
+    // The same check on demand over MCP
+    repo.update_status(&gone.id, SessionStatus::Running).await.unwrap();
+    let server = supercode::mcp::McpServer::new(0, manager);
+    let call = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": { "name": "refresh_session_status", "arguments": { "session_id": gone.id } }
+    });
+    let mut output = Vec::new();
+    server.serve_lines(format!("{}\n", call).as_bytes(), &mut output).await.unwrap();
+
+    let response: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let result: serde_json::Value = serde_json::from_str(response["result"]["content"][0]["text"].as_str().unwrap()).unwrap();
+    assert_eq!(result["previous_status"], "running");
+    assert_eq!(result["status"], "terminated");
+    assert_eq!(result["changed"], true);
+}
+
+#[tokio::test]
+async fn test_background_reconciler_syncs_status_and_activity() {
+    let url = fake_opencode(HashMap::from([
+        ("GET /session/ses_1", r#"{"id":"ses_1","projectID":"p1","directory":"/work","title":"Live","version":"0.15.0","time":{"created":1,"updated":2}}"#),
+        ("GET /permission", r#"[{"id":"per_1","type":"bash","pattern":"rm -rf target","sessionID":"ses_1","messageID":"msg_1","title":"rm -rf target","metadata":{},"time":{"created":1}}]"#),
+    ])).await;
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let manager = Arc::new(SessionManager::with_opencode_url(db, url));
+
+    let live = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&live.id, "ses_1").await.unwrap();
+    let gone = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&gone.id, "ses_gone").await.unwrap();
+
+    let reconciler = manager.spawn_reconciler(Duration::from_millis(50));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let ended = repo.get(&gone.id).await.unwrap().unwrap().status == SessionStatus::Terminated;
+        let blocked = repo.latest_activity(&live.id).await.unwrap()
+            .is_some_and(|a| a.state == AgentState::WaitingForApproval);
+        if ended && blocked {
+            break;
+        }
+        assert!(Instant::now() < deadline, "reconciler never caught up");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    reconciler.abort();
+
+    assert_eq!(repo.get(&live.id).await.unwrap().unwrap().status, SessionStatus::Running);
+    assert_eq!(repo.latest_activity(&live.id).await.unwrap().unwrap().approval_type, Some(ApprovalType::Command));
+}
+
+#[tokio::test]
+async fn test_resume_session_reattaches_without_creating() {
+    // No POST /session route: creating a session would fail
+    let url = fake_opencode(HashMap::from([("GET /session/ses_1", SESSION)])).await;
+
+    let provider = OpenCodeProvider::with_url(url.clone());
+    assert_eq!(provider.resume_session("ses_1").await.unwrap().provider_id, "ses_1");
+    let err = provider.resume_session("ses_gone").await.unwrap_err();
+    assert!(supercode::session::ProviderError::find(&err).is_some_and(|e| e.kind() == "not_found"), "{:#}", err);
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let manager = SessionManager::with_opencode_url(db, url);
+
+    // A session left terminated by a crash, and one that lost its provider ID
+    let crashed = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&crashed.id, "ses_1").await.unwrap();
+    repo.update_status(&crashed.id, SessionStatus::Terminated).await.unwrap();
+    let orphan = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+
+    let handle = manager.resume_session(&crashed.id, "ses_1", "opencode").await.unwrap();
+    assert_eq!(handle.provider_id, "ses_1");
+    let session = repo.get(&crashed.id).await.unwrap().unwrap();
+    assert_eq!(session.status, SessionStatus::Running);
+    assert_eq!(session.opencode_session_id.as_deref(), Some("ses_1"));
+    assert_eq!(repo.latest_activity(&crashed.id).await.unwrap().unwrap().state, AgentState::Idle);
+
+    // Over MCP, a row without a provider ID needs one passed in
+    let server = supercode::mcp::McpServer::new(0, Arc::new(manager));
+    let resume = |id: u32, arguments: serde_json::Value| serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": { "name": "resume_session", "arguments": arguments }
+    }).to_string();
+    let input = [
+        resume(1, serde_json::json!({ "session_id": orphan.id })),
+        resume(2, serde_json::json!({ "session_id": orphan.id, "provider_session_id": "ses_1" })),
+        resume(3, serde_json::json!({ "session_id": orphan.id, "provider_session_id": "ses_gone" })),
+    ].join("\n");
+    let mut output = Vec::new();
+    server.serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let responses: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(responses[0]["error"]["code"], -32602);
+    assert!(responses[1]["result"]["content"][0]["text"].as_str().unwrap().contains("ses_1"));
+    assert_eq!(responses[2]["error"]["code"], -32001);
+    assert_eq!(repo.get(&orphan.id).await.unwrap().unwrap().opencode_session_id.as_deref(), Some("ses_1"));
+}
+
+#[tokio::test]
+async fn test_reattach_sessions_on_startup() {
+    let url = fake_opencode(HashMap::from([("GET /session/ses_1", SESSION)])).await;
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+
+    let alive = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&alive.id, "ses_1").await.unwrap();
+    let gone = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&gone.id, "ses_gone").await.unwrap();
+    let interrupted = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    rusqlite::Connection::open(temp_dir.path().join("test.db")).unwrap()
+        .execute("UPDATE sessions SET updated_at = '2000-01-01T00:00:00Z' WHERE id = ?1", [&interrupted.id])
+        .unwrap();
+    let spawning = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    let claude = repo.create(AgentType::Developer, SessionType::Claude, None, None).await.unwrap();
+    repo.set_opencode_session_id(&claude.id, "claude-1").await.unwrap();
+    let done = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&done.id, "ses_old").await.unwrap();
+    repo.update_status(&done.id, SessionStatus::Completed).await.unwrap();
+
+    // An unreachable provider leaves its sessions for later
+    let offline = SessionManager::with_opencode_url(db.clone(), "http://127.0.0.1:1");
+    let summary = offline.reattach_sessions().await.unwrap();
+    let unreachable: Vec<_> = summary.unreachable.iter().map(|(id, _)| id.clone()).collect();
+    assert_eq!(unreachable.len(), 2);
+    assert!(unreachable.contains(&alive.id) && unreachable.contains(&gone.id));
+    assert_eq!(repo.get(&gone.id).await.unwrap().unwrap().status, SessionStatus::Running);
+
+    // Old interrupted spawns fail without asking. Recent spawns and
+    // providers that can't resume may belong to another process.
+    let failed: Vec<_> = summary.failed.iter().map(|(id, _)| id.clone()).collect();
+    assert_eq!(failed, vec![interrupted.id.clone()]);
+    let mut skipped: Vec<_> = summary.skipped.iter().map(|(id, _)| id.clone()).collect();
+    skipped.sort();
+    let mut expected = vec![spawning.id.clone(), claude.id.clone()];
+    expected.sort();
+    assert_eq!(skipped, expected);
+
+    // As if that server had long since stopped and its lease run out
+    rusqlite::Connection::open(temp_dir.path().join("test.db")).unwrap()
+        .execute("DELETE FROM leases", [])
+        .unwrap();
+    let manager = SessionManager::with_opencode_url(db, url);
+    let summary = manager.reattach_sessions().await.unwrap();
+    assert_eq!(summary.reattached, vec![alive.id.clone()]);
+    assert!(summary.unreachable.is_empty());
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].0, gone.id);
+    assert_eq!(summary.skipped.len(), 2);
+
+    assert_eq!(repo.get(&alive.id).await.unwrap().unwrap().status, SessionStatus::Running);
+    let session = repo.get(&interrupted.id).await.unwrap().unwrap();
+    assert_eq!(session.status, SessionStatus::Failed);
+    let metadata: serde_json::Value = serde_json::from_str(&session.metadata.unwrap()).unwrap();
+    assert!(metadata["error"].as_str().unwrap().contains("interrupted"));
+    assert_eq!(repo.get(&spawning.id).await.unwrap().unwrap().status, SessionStatus::Pending);
+    assert_eq!(repo.get(&claude.id).await.unwrap().unwrap().status, SessionStatus::Running);
+    assert_eq!(repo.get(&done.id).await.unwrap().unwrap().status, SessionStatus::Completed);
+}
+
+#[tokio::test]
+async fn test_reattach_leaves_sessions_held_by_other_processes() {
+    let url = fake_opencode(HashMap::from([
+        ("GET /session/ses_1", SESSION),
+        ("DELETE /session/ses_1", "true"),
+    ])).await;
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&session.id, "ses_1").await.unwrap();
+    let spawning = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+
+    let first = SessionManager::with_opencode_url(db.clone(), url.clone());
+    assert_eq!(first.reattach_sessions().await.unwrap().reattached, vec![session.id.clone()]);
+
+    // A second server on the same database while the first still runs
+    let second = SessionManager::with_opencode_url(db, url);
+    let summary = second.reattach_sessions().await.unwrap();
+    assert!(summary.reattached.is_empty() && summary.failed.is_empty() && summary.skipped.is_empty(), "{:?}", summary);
+    assert_eq!(repo.get(&spawning.id).await.unwrap().unwrap().status, SessionStatus::Pending);
+
+    // So it doesn't take the first server's session down when it stops
+    assert!(second.shutdown_all().await.unwrap().terminated.is_empty());
+    assert_eq!(repo.get(&session.id).await.unwrap().unwrap().status, SessionStatus::Running);
+}
+
+#[tokio::test]
+async fn test_request_timeout_is_configurable() {
+    // Accepts connections but never answers, like a model still thinking
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut open = Vec::new();
+        loop {
+            open.push(listener.accept().await.unwrap().0);
+        }
+    });
+
+    let config = supercode::config::Config {
+        opencode_url: url,
+        opencode_request_timeout_secs: 1,
+        ..supercode::config::Config::default()
+    };
+    assert_eq!(supercode::config::Config::default().opencode_request_timeout_secs, 30);
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let manager = SessionManager::from_config(db, &config).unwrap();
+
+    let started = Instant::now();
+    let err = manager.get_session_status("ses_1", "opencode").await.unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(matches!(supercode::session::ProviderError::find(&err), Some(supercode::session::ProviderError::Timeout(_))), "{:#}", err);
+}
+
+#[tokio::test]
+async fn test_send_message_with_attachments() {
+    use supercode::session::MessagePart;
+
+    let temp_dir = TempDir::new().unwrap();
+    let screenshot = temp_dir.path().join("failing-ui.PNG");
+    std::fs::write(&screenshot, b"\x89PNG").unwrap();
+
+    // Parts serialize to OpenCode's schema
+    let part = MessagePart::file(&screenshot).unwrap();
+    let json = serde_json::to_value(&part).unwrap();
+    assert_eq!(json["type"], "file");
+    assert_eq!(json["mime"], "image/png");
+    assert_eq!(json["filename"], "failing-ui.PNG");
+    assert!(json["url"].as_str().unwrap().starts_with("file:///"));
+    assert_eq!(serde_json::to_value(MessagePart::text("hi")).unwrap(), serde_json::json!({ "type": "text", "text": "hi" }));
+    assert!(MessagePart::file(temp_dir.path().join("missing.png")).is_err());
+
+    let url = fake_opencode(HashMap::from([
+        ("POST /session/ses_1/message", r#"{"parts":[{"type":"text","text":"the button overlaps"}]}"#),
+    ])).await;
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let manager = Arc::new(SessionManager::with_opencode_url(db, url));
+
+    let session = repo.create(AgentType::Reviewer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&session.id, "ses_1").await.unwrap();
+
+    let send = |id: u32, attachments: serde_json::Value| serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": { "name": "send_message", "arguments": {
+            "session_id": session.id,
+            "content": "What's wrong here?",
+            "attachments": attachments
+        }}
+    }).to_string();
+    let input = [
+        send(1, serde_json::json!([{ "path": screenshot.to_string_lossy() }])),
+        send(2, serde_json::json!([{ "url": "https://example.com/shot.png" }])),
+        send(3, serde_json::json!([{ "path": temp_dir.path().join("missing.png").to_string_lossy() }])),
+    ].join("\n");
+    let mut output = Vec::new();
+    supercode::mcp::McpServer::new(0, manager.clone()).serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let responses: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+        .filter(|message| message.get("id").is_some()) // skip progress notifications
+        .collect();
+    assert_eq!(responses[0]["result"]["content"][0]["text"], "the button overlaps");
+    assert_eq!(responses[1]["error"]["code"], -32602);
+    assert_eq!(responses[2]["error"]["code"], -32602);
+
+    let history = manager.messages().list_for_session(&session.id).await.unwrap();
+    assert_eq!(history[0].content, "What's wrong here?\n\n[file: failing-ui.PNG (image/png)]");
+}
+
+#[tokio::test]
+async fn test_spawn_session_on_remote_peer() {
+    use supercode::config::{keygen, Config, PeerConfig};
+    use tokio::sync::RwLock;
+
+    let node = |name: &str| {
+        let (private_key, public_key) = keygen::generate_keypair().unwrap();
+        Config { name: name.to_string(), private_key, public_key, ..Config::default() }
+    };
+    let peer = |hostname: String, public_key: &str| PeerConfig {
+        auth: "shared-token".to_string(),
+        hostnames: vec![hostname],
+        public_key: public_key.to_string(),
+        verified: true,
+    };
+    let mut alice = node("alice");
+    let mut bob = node("bob");
+    bob.add_peer("alice", peer(String::new(), &alice.public_key));
+    bob.accept_peer_spawns = true;
+
+    // Bob runs the agents, against his own OpenCode server
+    let url = fake_opencode(HashMap::from([
+        ("GET /health", "{}"),
+        ("POST /session", SESSION),
+        ("POST /session/ses_1/message", r#"{"parts":[{"type":"text","text":"ready"}]}"#),
+    ])).await;
+    let bob_dir = TempDir::new().unwrap();
+    let bob_config_path = bob_dir.path().join("config.yml").to_string_lossy().to_string();
+    bob.save(Some(&bob_config_path)).unwrap();
+    let bob_manager = Arc::new(SessionManager::with_opencode_url(Database::new(bob_dir.path().join("test.db")).unwrap(), url));
+    let bob_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let bob_key = bob.public_key.clone();
+    let server = supercode::mcp::PeerServer::new(bob_port, Arc::new(RwLock::new(bob)))
+        .with_config_path(bob_config_path)
+        .with_session_manager(bob_manager.clone());
+    tokio::spawn(async move { server.start().await });
+    for _ in 0..50 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", bob_port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    // Alice has no OpenCode server of her own
+    alice.add_peer("bob", peer(format!("127.0.0.1:{}", bob_port), &bob_key));
+    let alice_dir = TempDir::new().unwrap();
+    let alice_manager = Arc::new(
+        SessionManager::with_opencode_url(Database::new(alice_dir.path().join("test.db")).unwrap(), "http://127.0.0.1:1")
+            .with_peers(Arc::new(RwLock::new(alice))),
+    );
+
+    let spawn = |id: u32, working_dir: String| serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": { "name": "spawn_session", "arguments": {
+            "agent_type": "developer",
+            "session_type": "opencode",
+            "working_dir": working_dir,
+            "name": "remote-dev",
+            "remote_peer": "bob"
+        }}
+    }).to_string();
+    let input = [
+        spawn(1, bob_dir.path().to_string_lossy().to_string()),
+        spawn(2, bob_dir.path().join("missing").to_string_lossy().to_string()),
+    ].join("\n");
+    let mut output = Vec::new();
+    supercode::mcp::McpServer::new(0, alice_manager.clone()).serve_lines(input.as_bytes(), &mut output).await.unwrap();
+    let responses: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+        .filter(|message| message.get("id").is_some())
+        .collect();
+
+    let text = responses[0]["result"]["content"][0]["text"].as_str().unwrap();
+    let spawned: serde_json::Value = serde_json::from_str(text).unwrap();
+    assert_eq!(spawned["peer"], "bob");
+    assert_eq!(spawned["provider_session_id"], "ses_1");
+    let session_id = spawned["session_id"].as_str().unwrap();
+
+    // The session is bob's, not alice's
+    let remote = bob_manager.repository().get(session_id).await.unwrap().unwrap();
+    assert_eq!(remote.name.as_deref(), Some("remote-dev"));
+    assert!(alice_manager.repository().get(session_id).await.unwrap().is_none());
+
+    let error = responses[1]["error"]["message"].as_str().unwrap();
+    assert!(error.contains("Peer bob couldn't spawn the session"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_restart_sends_agent_prompt_again() {
+    use supercode::db::repositories::message::{MessageRepository, MessageRole};
+
+    let url = fake_opencode(HashMap::from([
+        ("DELETE /session/ses_old", "true"),
+        ("POST /session", r#"{"id":"ses_new"}"#),
+        ("POST /session/ses_new/message", r#"{"parts":[{"type":"text","text":"ready"}]}"#),
+    ])).await;
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let messages = MessageRepository::new(db.clone());
+    let manager = SessionManager::with_opencode_url(db, url);
+
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&session.id, "ses_old").await.unwrap();
+    messages.create(&session.id, MessageRole::User, "You are the developer agent").await.unwrap();
+    messages.create(&session.id, MessageRole::Assistant, "ok").await.unwrap();
+    messages.create(&session.id, MessageRole::User, "fix the build").await.unwrap();
+
+    let handle = manager.restart_session(&session.id, Some("ses_old"), "opencode").await.unwrap();
+    assert_eq!(handle.provider_id, "ses_new");
+
+    // The new conversation starts from the original agent prompt
+    let history = messages.list_for_session(&session.id).await.unwrap();
+    let resent = &history[history.len() - 2];
+    assert_eq!(resent.role, MessageRole::User);
+    assert_eq!(resent.content, "You are the developer agent");
+    assert_eq!(history.last().unwrap().content, "ready");
+
+    let session = repo.get(&session.id).await.unwrap().unwrap();
+    assert_eq!(session.opencode_session_id.as_deref(), Some("ses_new"));
+    assert_eq!(session.status, SessionStatus::Running);
+}
+
+#[tokio::test]
+async fn test_interrupt_reports_whether_a_turn_was_stopped() {
+    use supercode::session::ProviderError;
+
+    let url = fake_opencode(HashMap::from([
+        ("GET /session/status", r#"{"ses_1":{"type":"busy"}}"#),
+        ("POST /session/ses_1/abort", "true"),
+        ("POST /session/ses_1/message", r#"{"info":{"id":"msg_1","role":"assistant","error":{"name":"MessageAbortedError","data":{}}},"parts":[{"type":"text","text":"half an ans"}]}"#),
+    ])).await;
+    let provider = OpenCodeProvider::with_url(url);
+
+    assert!(provider.interrupt("ses_1").await.unwrap());
+    assert!(!provider.interrupt("ses_2").await.unwrap());
+
+    // The reply to the stopped turn is an interruption, not an answer
+    let err = provider.send_message("ses_1", "hi").await.unwrap_err();
+    assert!(matches!(ProviderError::find(&err), Some(ProviderError::Interrupted(_))), "{:?}", err);
+}
+
+#[tokio::test]
+async fn test_reply_text_joins_text_parts_only() {
+    let url = fake_opencode(HashMap::from([
+        ("POST /session/ses_1/message", r#"{"info":{"id":"msg_1","role":"assistant","modelID":"m1"},"parts":[{"type":"step-start"},{"type":"reasoning","text":"thinking it over"},{"type":"text","text":"First"},{"type":"tool","tool":"bash","state":{"status":"completed"}},{"type":"text","text":"Second"},{"type":"step-finish"}]}"#),
+        ("POST /session/ses_2/message", r#"{"info":{"id":"msg_2","role":"assistant"},"parts":[{"type":"tool","tool":"bash"}]}"#),
+    ])).await;
+
+    // The typed response keeps the metadata and the raw body alongside the parts
+    let response = OpenCodeClient::new(url.clone()).send_message("ses_1", "hi").await.unwrap();
+    assert_eq!(response.parts.len(), 6);
+    assert_eq!(response.info.as_ref().unwrap()["modelID"], "m1");
+    assert_eq!(response.raw["parts"][3]["tool"], "bash");
+    assert_eq!(response.text(), "First\nSecond");
+
+    let provider = OpenCodeProvider::with_url(url);
+    assert_eq!(provider.send_message("ses_1", "hi").await.unwrap(), "First\nSecond");
+
+    // A reply without text comes back as the raw JSON
+    let reply = provider.send_message("ses_2", "hi").await.unwrap();
+    let raw: serde_json::Value = serde_json::from_str(&reply).unwrap();
+    assert_eq!(raw["parts"][0]["tool"], "bash");
+}
+
+#[tokio::test]
+async fn test_streaming_reads_sse_deltas_split_across_chunks() {
+    use futures::TryStreamExt;
+
+    // Every shape of delta OpenCode has sent, with a line broken mid-JSON
+    // and one broken between its \r and \n
+    let url = fake_stream("text/event-stream", vec![
+        ": keep-alive\r\nevent: message.part.updated\r\nid: 1\r\ndata: {\"delta\":\"Hel\"}\r\n\r\n",
+        "data: {\"properties\":{\"part\":{\"te",
+        "xt\":\"lo\"}}}\r",
+        "\n\r\ndata: {\"properties\":{\"delta\":\" wor\"}}\n\n",
+        "data: {\"part\":{\"text\":\"ld\"}}\n\ndata:{\"text\":\"!\"}\n\n",
+        // No delta, an empty one, and the end marker carry no text
+        "retry: 1000\ndata: {\"type\":\"session.idle\"}\n\ndata: {\"delta\":\"\"}\n\ndata: [DONE]\n\n",
+    ]).await;
+
+    let client = OpenCodeClient::new(url);
+    let deltas: Vec<String> = client.send_message_streaming("ses_1", "hi").await.unwrap().try_collect().await.unwrap();
+    assert_eq!(deltas, ["Hel", "lo", " wor", "ld", "!"]);
+}
+
+#[tokio::test]
+async fn test_streaming_reads_plain_chunked_lines() {
+    use futures::TryStreamExt;
+
+    // Without SSE framing each line is JSON or raw text, and the last one
+    // needn't end in a newline
+    let url = fake_stream("application/x-ndjson", vec![
+        "{\"delta\":\"a\"}\nplain ",
+        "text\n\n{\"text\":\"b\"}",
+    ]).await;
+
+    let client = OpenCodeClient::new(url);
+    let deltas: Vec<String> = client.send_message_streaming("ses_1", "hi").await.unwrap().try_collect().await.unwrap();
+    assert_eq!(deltas, ["a", "plain text", "b"]);
+}
+
+#[tokio::test]
+async fn test_streaming_to_unknown_session_fails() {
+    let url = fake_opencode(HashMap::new()).await;
+    let client = OpenCodeClient::new(url);
+
+    let err = client.send_message_streaming("ses_1", "hi").await.err().unwrap();
+    assert!(matches!(ProviderError::find(&err), Some(ProviderError::NotFound(_))), "{:#}", err);
+}
@@ -0,0 +1,633 @@
+// Tests for peer connections
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use supercode::config::{keygen, Config, PeerConfig, PeerConnection, PeerManager, PeerMessage};
+use supercode::db::repositories::peer_message::PeerMessageRepository;
+use supercode::db::Database;
+use supercode::mcp::PeerServer;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tempfile::TempDir;
+use tokio::sync::RwLock;
+
+/// Two ends of a local TCP connection wrapped as peer connections
+async fn connected_pair() -> (PeerConnection, PeerConnection) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+    (
+        PeerConnection::new("server", client.unwrap(), "server-key"),
+        PeerConnection::new("client", server.unwrap().0, "client-key"),
+    )
+}
+
+#[tokio::test]
+async fn test_send_and_recv_messages() {
+    let (mut client, mut server) = connected_pair().await;
+
+    let first = PeerMessage::new("spawn_session", r#"{"agent_type":"developer"}"#, "node-a");
+    let second = PeerMessage::new("ping", "", "node-a");
+    let first_id = first.id.clone();
+
+    client.send(first).await.unwrap();
+    client.send(second).await.unwrap();
+
+    let received = server.recv().await.unwrap();
+    assert_eq!(received.id, first_id);
+    assert_eq!(received.message_type, "spawn_session");
+    assert_eq!(received.payload, r#"{"agent_type":"developer"}"#);
+    assert_eq!(received.from, "node-a");
+    assert_eq!(server.recv().await.unwrap().message_type, "ping");
+
+    // Replies flow the other way over the same connection
+    server.send(PeerMessage::new("ack", first_id.clone(), "node-b")).await.unwrap();
+    assert_eq!(client.recv().await.unwrap().payload, first_id);
+}
+
+#[tokio::test]
+async fn test_recv_fails_on_close_and_garbage() {
+    let (mut client, mut server) = connected_pair().await;
+
+    client.writer.write_all(b"\nnot json\n").await.unwrap();
+    assert!(server.recv().await.is_err());
+
+    drop(client);
+    let err = server.recv().await.unwrap_err();
+    assert!(err.to_string().contains("closed the connection"), "{}", err);
+}
+
+fn node(name: &str) -> Config {
+    let (private_key, public_key) = keygen::generate_keypair().unwrap();
+    Config {
+        name: name.to_string(),
+        private_key,
+        public_key,
+        ..Config::default()
+    }
+}
+
+fn peer(hostname: String, public_key: &str) -> PeerConfig {
+    PeerConfig {
+        auth: "shared-token".to_string(),
+        hostnames: vec![hostname],
+        public_key: public_key.to_string(),
+        verified: true,
+    }
+}
+
+/// Start a peer server for `config`, returning its address and the
+/// config file it persists state next to
+async fn start_peer_server(config: Config) -> (String, TempDir) {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.yml").to_string_lossy().to_string();
+
+    config.save(Some(&config_path)).unwrap();
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let server = PeerServer::new(port, Arc::new(RwLock::new(config))).with_config_path(config_path);
+    tokio::spawn(async move { server.start().await });
+
+    let addr = format!("127.0.0.1:{}", port);
+    for _ in 0..50 {
+        if TcpStream::connect(&addr).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    (addr, temp_dir)
+}
+
+#[tokio::test]
+async fn test_handshake_proves_key_ownership() {
+    let mut alice = node("alice");
+    let mut bob = node("bob");
+
+    bob.add_peer("alice", peer(String::new(), &alice.public_key));
+    let bob_key = bob.public_key.clone();
+    let (addr, _server_dir) = start_peer_server(bob).await;
+
+    alice.add_peer("bob", peer(addr, &bob_key));
+    let connection = PeerManager::new(alice).connect_to_peer("bob").await.unwrap();
+    assert_eq!(connection.remote_public_key, bob_key);
+    assert!(connection.is_encrypted());
+}
+
+#[tokio::test]
+async fn test_handshake_rejects_spoofed_peer() {
+    let alice = node("alice");
+    let mut bob = node("bob");
+
+    bob.add_peer("alice", peer(String::new(), &alice.public_key));
+    let bob_key = bob.public_key.clone();
+    let (addr, _server_dir) = start_peer_server(bob).await;
+
+    // Knows alice's name and auth token, but not her private key
+    let mut mallory = node("alice");
+    mallory.add_peer("bob", peer(addr, &bob_key));
+
+    let Err(err) = PeerManager::new(mallory).connect_to_peer("bob").await else {
+        panic!("spoofed handshake was accepted");
+    };
+    assert!(err.to_string().contains("Could not connect"), "{}", err);
+}
+
+#[test]
+fn test_handshake_proof_round_trip() {
+    let alice = node("alice");
+    let bob = node("bob");
+    let nonce = keygen::generate_nonce();
+
+    let proof = keygen::handshake_proof(&alice.private_key, &bob.public_key, &nonce, "alice").unwrap();
+
+    assert!(keygen::verify_handshake_proof(&bob.private_key, &alice.public_key, &nonce, "alice", &proof).unwrap());
+    assert!(!keygen::verify_handshake_proof(&bob.private_key, &alice.public_key, &nonce, "mallory", &proof).unwrap());
+    assert!(!keygen::verify_handshake_proof(&bob.private_key, &alice.public_key, &keygen::generate_nonce(), "alice", &proof).unwrap());
+    assert!(!keygen::verify_handshake_proof(&bob.private_key, &alice.public_key, &nonce, "alice", "not base64!").unwrap());
+}
+
+#[tokio::test]
+async fn test_payloads_encrypted_with_session_key() {
+    let alice = node("alice");
+    let bob = node("bob");
+    let nonce = keygen::generate_nonce();
+
+    // Both ends derive the same key from their own private key
+    let alice_key = keygen::session_key(&alice.private_key, &bob.public_key, &nonce).unwrap();
+    let bob_key = keygen::session_key(&bob.private_key, &alice.public_key, &nonce).unwrap();
+    assert_eq!(alice_key, bob_key);
+    assert_ne!(alice_key, keygen::session_key(&alice.private_key, &bob.public_key, &keygen::generate_nonce()).unwrap());
+
+    let (client, server) = connected_pair().await;
+    let mut client = client.with_session_key(alice_key);
+    let mut server = server.with_session_key(bob_key);
+
+    client.send(PeerMessage::new("spawn_session", "top secret", "alice")).await.unwrap();
+    let received = server.recv().await.unwrap();
+    assert_eq!(received.payload, "top secret");
+    assert!(!received.encrypted);
+}
+
+#[tokio::test]
+async fn test_encrypted_payload_on_the_wire() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+
+    let mut client = PeerConnection::new("server", client.unwrap(), "server-key").with_session_key([7u8; 32]);
+    client.send(PeerMessage::new("spawn_session", "top secret", "alice")).await.unwrap();
+
+    let mut raw = BufReader::new(server.unwrap().0);
+    let mut line = String::new();
+    raw.read_line(&mut line).await.unwrap();
+
+    let wire: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(wire["encrypted"], true);
+    assert!(!line.contains("top secret"));
+
+    // A plaintext message is refused once a session key is in place
+    let mut raw = raw.into_inner();
+    let plain = serde_json::to_string(&PeerMessage::new("ping", "hi", "mallory")).unwrap();
+    raw.write_all(format!("{}\n", plain).as_bytes()).await.unwrap();
+    let err = client.recv().await.unwrap_err();
+    assert!(err.to_string().contains("unencrypted"), "{}", err);
+
+    // As is ciphertext under a different key
+    let (other, mut receiver) = connected_pair().await;
+    let mut other = other.with_session_key([8u8; 32]);
+    receiver = receiver.with_session_key([7u8; 32]);
+    other.send(PeerMessage::new("ping", "hi", "mallory")).await.unwrap();
+    assert!(receiver.recv().await.is_err());
+}
+
+/// One encrypted message as it appears on the wire
+async fn sealed_line(key: [u8; 32], msg: PeerMessage) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+
+    let mut client = PeerConnection::new("server", client.unwrap(), "server-key").with_session_key(key);
+    client.send(msg).await.unwrap();
+
+    let mut line = String::new();
+    BufReader::new(server.unwrap().0).read_line(&mut line).await.unwrap();
+    line
+}
+
+/// A connection reading whatever is written to the returned stream
+async fn raw_sender(key: [u8; 32]) -> (TcpStream, PeerConnection) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (raw, receiver) = tokio::join!(TcpStream::connect(addr), listener.accept());
+    (raw.unwrap(), PeerConnection::new("client", receiver.unwrap().0, "client-key").with_session_key(key))
+}
+
+#[tokio::test]
+async fn test_encrypted_header_cannot_be_rewritten() {
+    let line = sealed_line([7u8; 32], PeerMessage::new("list_sessions", "{}", "alice")).await;
+
+    for (field, value) in [("message_type", "spawn_session"), ("from", "mallory"), ("in_reply_to", "other-id")] {
+        let mut wire: serde_json::Value = serde_json::from_str(&line).unwrap();
+        wire[field] = serde_json::json!(value);
+
+        let (mut raw, mut receiver) = raw_sender([7u8; 32]).await;
+        raw.write_all(format!("{}\n", wire).as_bytes()).await.unwrap();
+        let err = receiver.recv().await.unwrap_err();
+        assert!(format!("{:#}", err).contains("failed authentication"), "{}: {:#}", field, err);
+    }
+}
+
+#[tokio::test]
+async fn test_replayed_message_is_refused() {
+    let line = sealed_line([7u8; 32], PeerMessage::new("spawn_session", "{}", "alice")).await;
+
+    let (mut raw, mut receiver) = raw_sender([7u8; 32]).await;
+    raw.write_all(line.as_bytes()).await.unwrap();
+    raw.write_all(line.as_bytes()).await.unwrap();
+
+    assert_eq!(receiver.recv().await.unwrap().message_type, "spawn_session");
+    let err = receiver.recv().await.unwrap_err();
+    assert!(err.to_string().contains("replayed"), "{}", err);
+
+    // Our own messages reflected back are refused the same way
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+    let mut client = PeerConnection::new("server", client.unwrap(), "server-key").with_session_key([7u8; 32]);
+    client.send(PeerMessage::new("ping", "", "alice")).await.unwrap();
+
+    let mut raw = BufReader::new(server.unwrap().0);
+    let mut line = String::new();
+    raw.read_line(&mut line).await.unwrap();
+    raw.into_inner().write_all(line.as_bytes()).await.unwrap();
+    let err = client.recv().await.unwrap_err();
+    assert!(err.to_string().contains("replayed"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_fetch_public_key_from_unknown_peer() {
+    let mut alice = node("alice");
+    let bob = node("bob");
+    let bob_key = bob.public_key.clone();
+
+    // Bob doesn't know alice; he answers with a pending response carrying his key
+    let (addr, _server_dir) = start_peer_server(bob).await;
+    alice.add_peer("bob", peer(addr, ""));
+
+    let manager = PeerManager::new(alice);
+    assert_eq!(manager.fetch_public_key("bob").await.unwrap(), bob_key);
+    assert!(manager.fetch_public_key("nobody").await.is_err());
+}
+
+#[tokio::test]
+async fn test_pending_requests_visible_to_cli() {
+    let mut alice = node("alice");
+    let (addr, server_dir) = start_peer_server(node("bob")).await;
+    let config_path = server_dir.path().join("config.yml");
+    let config_path = config_path.to_str().unwrap();
+
+    alice.add_peer("bob", peer(addr, ""));
+    let alice_key = alice.public_key.clone();
+    assert!(PeerManager::new(alice).connect_to_peer("bob").await.is_err());
+
+    // A separate process (the CLI) sees the request the server queued
+    let mut cli = Config::load(Some(config_path)).unwrap();
+    let request = cli.pending_requests.get("alice").expect("request not persisted");
+    assert_eq!(request.public_key, alice_key);
+
+    cli.deny_peer("alice");
+    cli.save(Some(config_path)).unwrap();
+    assert!(Config::load(Some(config_path)).unwrap().pending_requests.is_empty());
+}
+
+#[tokio::test]
+async fn test_cli_accept_reaches_running_server() {
+    let mut alice = node("alice");
+    let bob = node("bob");
+    let bob_key = bob.public_key.clone();
+    let (addr, server_dir) = start_peer_server(bob).await;
+    let config_path = server_dir.path().join("config.yml");
+    let config_path = config_path.to_str().unwrap();
+
+    alice.add_peer("bob", peer(addr, &bob_key));
+    let alice_key = alice.public_key.clone();
+    let manager = PeerManager::new(alice);
+    assert!(manager.connect_to_peer("bob").await.is_err());
+
+    // Accept from "the CLI" while the server keeps running
+    let mut cli = Config::load(Some(config_path)).unwrap();
+    assert!(cli.pending_requests.contains_key("alice"));
+    cli.add_peer("alice", peer(String::new(), &alice_key));
+    cli.clear_pending_request("alice");
+    cli.save(Some(config_path)).unwrap();
+
+    let connection = manager.connect_to_peer("bob").await.unwrap();
+    assert!(connection.is_encrypted());
+
+    // And removal takes effect just the same
+    let mut cli = Config::load(Some(config_path)).unwrap();
+    cli.remove_peer("alice");
+    cli.save(Some(config_path)).unwrap();
+    assert!(manager.connect_to_peer("bob").await.is_err());
+}
+
+/// A port nothing is listening on
+fn closed_addr() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_messages_queued_for_unreachable_peer() {
+    let db_dir = TempDir::new().unwrap();
+    let outbox = PeerMessageRepository::new(Database::new(db_dir.path().join("test.db")).unwrap());
+
+    let mut alice = node("alice");
+    let mut bob = node("bob");
+    bob.add_peer("alice", peer(String::new(), &alice.public_key));
+    let bob_key = bob.public_key.clone();
+
+    // Bob is down: the message waits in the outbox
+    alice.add_peer("bob", peer(closed_addr(), &bob_key));
+    let manager = PeerManager::new(alice.clone()).with_outbox(outbox.clone());
+    let message = PeerMessage::new("spawn_session", r#"{"agent_type":"developer"}"#, "alice");
+    assert!(!manager.send_to_peer("bob", message).await.unwrap());
+    assert_eq!(outbox.pending("bob").await.unwrap().len(), 1);
+    assert_eq!(outbox.peers_with_pending().await.unwrap(), vec!["bob".to_string()]);
+
+    // Bob comes back: the retry delivers it
+    let (addr, _server_dir) = start_peer_server(bob).await;
+    alice.add_peer("bob", peer(addr, &bob_key));
+    let manager = PeerManager::new(alice).with_outbox(outbox.clone());
+    assert_eq!(manager.retry_outbox().await.unwrap(), 1);
+    assert!(outbox.pending("bob").await.unwrap().is_empty());
+
+    // With nothing queued, new messages go straight out
+    let message = PeerMessage::new("ping", "", "alice");
+    assert!(manager.send_to_peer("bob", message).await.unwrap());
+    assert!(outbox.peers_with_pending().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_queued_messages_delivered_when_peer_reconnects() {
+    let db_dir = TempDir::new().unwrap();
+    let outbox = PeerMessageRepository::new(Database::new(db_dir.path().join("test.db")).unwrap());
+
+    let mut alice = node("alice");
+    let mut bob = node("bob");
+    let (alice_key, bob_key) = (alice.public_key.clone(), bob.public_key.clone());
+
+    // Alice's server, with a message for bob already queued
+    let alice_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let alice_addr = format!("127.0.0.1:{}", alice_port);
+    bob.add_peer("alice", peer(alice_addr.clone(), &alice_key));
+    let (bob_addr, _bob_dir) = start_peer_server(bob.clone()).await;
+    alice.add_peer("bob", peer(bob_addr, &bob_key));
+
+    outbox.enqueue("bob", &PeerMessage::new("spawn_session", "{}", "alice")).await.unwrap();
+
+    let alice_dir = TempDir::new().unwrap();
+    let alice_config_path = alice_dir.path().join("config.yml").to_string_lossy().to_string();
+    alice.save(Some(&alice_config_path)).unwrap();
+    let server = PeerServer::new(alice_port, Arc::new(RwLock::new(alice)))
+        .with_config_path(alice_config_path)
+        .with_outbox(outbox.clone());
+    tokio::spawn(async move { server.start().await });
+    for _ in 0..50 {
+        if TcpStream::connect(&alice_addr).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    // Bob connecting tells alice he's back
+    let _connection = PeerManager::new(bob).connect_to_peer("alice").await.unwrap();
+    for _ in 0..100 {
+        if outbox.pending("bob").await.unwrap().is_empty() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("queued message was not delivered after bob reconnected");
+}
+
+#[tokio::test]
+async fn test_concurrent_flushes_deliver_once() {
+    let db_dir = TempDir::new().unwrap();
+    let outbox = PeerMessageRepository::new(Database::new(db_dir.path().join("test.db")).unwrap());
+
+    let mut alice = node("alice");
+    let mut bob = node("bob");
+    bob.add_peer("alice", peer(String::new(), &alice.public_key));
+    let bob_key = bob.public_key.clone();
+    let (addr, _server_dir) = start_peer_server(bob).await;
+    alice.add_peer("bob", peer(addr, &bob_key));
+
+    // The periodic retry and a reconnect flush race for the same message
+    outbox.enqueue("bob", &PeerMessage::new("ping", "", "alice")).await.unwrap();
+    let first = PeerManager::new(alice.clone()).with_outbox(outbox.clone());
+    let second = PeerManager::new(alice).with_outbox(outbox.clone());
+    let (a, b) = tokio::join!(first.flush_outbox("bob"), second.flush_outbox("bob"));
+    assert_eq!(a.unwrap() + b.unwrap(), 1);
+    assert!(outbox.pending("bob").await.unwrap().is_empty());
+
+    // A claim keeps other attempts off a message until it is released
+    outbox.enqueue("bob", &PeerMessage::new("ping", "", "alice")).await.unwrap();
+    let claimed = outbox.claim("bob").await.unwrap();
+    assert_eq!(claimed.len(), 1);
+    assert!(outbox.claim("bob").await.unwrap().is_empty());
+    outbox.release(&[claimed[0].id.clone()]).await.unwrap();
+    assert_eq!(outbox.claim("bob").await.unwrap()[0].id, claimed[0].id);
+}
+
+#[tokio::test]
+async fn test_redelivered_message_is_acknowledged_but_not_run() {
+    let mut alice = node("alice");
+    let mut bob = node("bob");
+    bob.add_peer("alice", peer(String::new(), &alice.public_key));
+    let bob_key = bob.public_key.clone();
+
+    let bob_dir = TempDir::new().unwrap();
+    let bob_config_path = bob_dir.path().join("config.yml").to_string_lossy().to_string();
+    bob.save(Some(&bob_config_path)).unwrap();
+    let bob_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let bob_addr = format!("127.0.0.1:{}", bob_port);
+    let server = PeerServer::new(bob_port, Arc::new(RwLock::new(bob)))
+        .with_config_path(bob_config_path)
+        .with_outbox(PeerMessageRepository::new(Database::new(bob_dir.path().join("test.db")).unwrap()));
+    tokio::spawn(async move { server.start().await });
+    for _ in 0..50 {
+        if TcpStream::connect(&bob_addr).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    alice.add_peer("bob", peer(bob_addr, &bob_key));
+
+    let request = PeerMessage::new("list_sessions", "{}", "alice");
+    let request_id = request.id.clone();
+    let request_json = serde_json::to_string(&request).unwrap();
+    let manager = PeerManager::new(alice);
+
+    // First delivery: acknowledged, then answered
+    let mut connection = manager.connect_to_peer("bob").await.unwrap();
+    connection.send(request).await.unwrap();
+    let ack = connection.recv().await.unwrap();
+    assert_eq!(ack.message_type, "ack");
+    assert_eq!(ack.in_reply_to, Some(request_id));
+    assert_eq!(connection.recv().await.unwrap().message_type, "list_sessions_result");
+
+    // The same message again, as after a lost ack: acknowledged only
+    let mut connection = manager.connect_to_peer("bob").await.unwrap();
+    connection.send(serde_json::from_str(&request_json).unwrap()).await.unwrap();
+    assert_eq!(connection.recv().await.unwrap().message_type, "ack");
+    assert!(tokio::time::timeout(Duration::from_millis(300), connection.recv()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_list_remote_sessions_merges_nodes() {
+    use supercode::db::repositories::session::{AgentType, SessionType};
+    use supercode::mcp::McpServer;
+    use supercode::session::SessionManager;
+
+    let mut alice = node("alice");
+    let mut bob = node("bob");
+    bob.add_peer("alice", peer(String::new(), &alice.public_key));
+    let bob_key = bob.public_key.clone();
+
+    // Bob serves his sessions to peers
+    let bob_dir = TempDir::new().unwrap();
+    let bob_config_path = bob_dir.path().join("config.yml").to_string_lossy().to_string();
+    bob.save(Some(&bob_config_path)).unwrap();
+    let bob_manager = Arc::new(SessionManager::with_opencode_url(Database::new(bob_dir.path().join("test.db")).unwrap(), "http://127.0.0.1:1"));
+    let remote = bob_manager.repository().create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    let bob_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let server = PeerServer::new(bob_port, Arc::new(RwLock::new(bob)))
+        .with_config_path(bob_config_path)
+        .with_session_manager(bob_manager);
+    tokio::spawn(async move { server.start().await });
+    for _ in 0..50 {
+        if TcpStream::connect(("127.0.0.1", bob_port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    // Carol is down
+    let carol = node("carol");
+    alice.add_peer("bob", peer(format!("127.0.0.1:{}", bob_port), &bob_key));
+    alice.add_peer("carol", peer(closed_addr(), &carol.public_key));
+    let alice_dir = TempDir::new().unwrap();
+    let alice_manager = Arc::new(
+        SessionManager::with_opencode_url(Database::new(alice_dir.path().join("test.db")).unwrap(), "http://127.0.0.1:1")
+            .with_peers(Arc::new(RwLock::new(alice))),
+    );
+    let local = alice_manager.repository().create(AgentType::Manager, SessionType::Claude, None, None).await.unwrap();
+
+    let call = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": { "name": "list_remote_sessions", "arguments": {} }
+    });
+    let input = format!("{}\n", call);
+    let mut output = Vec::new();
+    McpServer::new(0, alice_manager).serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let response: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let text = response["result"]["content"][0]["text"].as_str().unwrap();
+    let listed: serde_json::Value = serde_json::from_str(text).unwrap();
+
+    assert_eq!(listed["total"], 2);
+    let nodes: Vec<(&str, &str)> = listed["sessions"].as_array().unwrap().iter()
+        .map(|s| (s["id"].as_str().unwrap(), s["node"].as_str().unwrap()))
+        .collect();
+    assert!(nodes.contains(&(local.id.as_str(), "alice")), "{:?}", nodes);
+    assert!(nodes.contains(&(remote.id.as_str(), "bob")), "{:?}", nodes);
+    assert_eq!(listed["unreachable"][0]["node"], "carol");
+}
+
+#[tokio::test]
+async fn test_list_remote_sessions_without_peers_lists_local() {
+    use supercode::db::repositories::session::{AgentType, SessionType};
+    use supercode::mcp::McpServer;
+    use supercode::session::SessionManager;
+
+    // No peers configured: the local sessions are still listed, up to the limit
+    let dir = TempDir::new().unwrap();
+    let manager = Arc::new(SessionManager::with_opencode_url(Database::new(dir.path().join("test.db")).unwrap(), "http://127.0.0.1:1"));
+    for _ in 0..3 {
+        manager.repository().create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    }
+
+    let call = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": { "name": "list_remote_sessions", "arguments": { "limit": 2 } }
+    });
+    let input = format!("{}\n", call);
+    let mut output = Vec::new();
+    McpServer::new(0, manager).serve_lines(input.as_bytes(), &mut output).await.unwrap();
+
+    let response: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let text = response["result"]["content"][0]["text"].as_str().unwrap();
+    let listed: serde_json::Value = serde_json::from_str(text).unwrap();
+
+    assert_eq!(listed["sessions"].as_array().unwrap().len(), 2, "{}", text);
+    assert_eq!(listed["total"], 3);
+    assert_eq!(listed["unreachable"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn test_session_requests_need_verified_peer() {
+    use supercode::session::SessionManager;
+
+    let alice = node("alice");
+    let mut bob = node("bob");
+    // Bob knows alice's key but hasn't verified her
+    bob.add_peer("alice", PeerConfig { verified: false, ..peer(String::new(), &alice.public_key) });
+    let bob_key = bob.public_key.clone();
+
+    let bob_dir = TempDir::new().unwrap();
+    let bob_config_path = bob_dir.path().join("config.yml").to_string_lossy().to_string();
+    bob.save(Some(&bob_config_path)).unwrap();
+    let bob_manager = Arc::new(SessionManager::with_opencode_url(Database::new(bob_dir.path().join("test.db")).unwrap(), "http://127.0.0.1:1"));
+    let bob_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let bob_config = Arc::new(RwLock::new(bob));
+    let server = PeerServer::new(bob_port, bob_config.clone())
+        .with_config_path(bob_config_path.clone())
+        .with_session_manager(bob_manager);
+    tokio::spawn(async move { server.start().await });
+    for _ in 0..50 {
+        if TcpStream::connect(("127.0.0.1", bob_port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let mut alice = alice;
+    alice.add_peer("bob", peer(format!("127.0.0.1:{}", bob_port), &bob_key));
+    let alice_dir = TempDir::new().unwrap();
+    let alice_manager = SessionManager::with_opencode_url(Database::new(alice_dir.path().join("test.db")).unwrap(), "http://127.0.0.1:1")
+        .with_peers(Arc::new(RwLock::new(alice)));
+
+    let listed = alice_manager.list_peer_sessions(&serde_json::json!({})).await.unwrap();
+    let err = listed[0].1.as_ref().unwrap_err().to_string();
+    assert!(err.contains("Peer alice is not verified"), "{}", err);
+
+    let spawn = serde_json::json!({ "agent_type": "developer", "session_type": "opencode", "working_dir": "/tmp", "name": "dev" });
+    let err = alice_manager.spawn_remote("bob", spawn.clone()).await.unwrap_err().to_string();
+    assert!(err.contains("not verified"), "{}", err);
+
+    // Verified, listing works but spawning still needs accept_peer_spawns
+    let mut on_disk = Config::load_locked(Some(&bob_config_path)).unwrap();
+    on_disk.peers.get_mut("alice").unwrap().verified = true;
+    on_disk.save(Some(&bob_config_path)).unwrap();
+
+    assert!(alice_manager.list_peer_sessions(&serde_json::json!({})).await.unwrap()[0].1.is_ok());
+    let err = alice_manager.spawn_remote("bob", spawn).await.unwrap_err().to_string();
+    assert!(err.contains("accept_peer_spawns"), "{}", err);
+}
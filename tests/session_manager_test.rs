@@ -0,0 +1,673 @@
+// Tests for the session manager
+
+use supercode::config::Config;
+use std::sync::Arc;
+use std::time::Duration;
+
+use supercode::db::{Database, repositories::session::{AgentState, AgentType, ApprovalType, SessionActivity, SessionRepository, SessionType}};
+use supercode::session::{SessionHandle, SessionManager, SessionProvider, SessionStatus as ProviderSessionStatus};
+use tempfile::TempDir;
+
+fn create_test_manager(opencode_url: &str) -> (SessionManager, TempDir) {
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    (SessionManager::with_opencode_url(db, opencode_url), temp_dir)
+}
+
+#[tokio::test]
+async fn test_unreachable_opencode_fails_fast() {
+    // Nothing listens on port 1
+    let (manager, _temp) = create_test_manager("http://127.0.0.1:1");
+
+    assert!(!manager.check_provider_health("opencode").await.unwrap());
+
+    let err = manager.ensure_provider_available("opencode").await.unwrap_err();
+    assert_eq!(err.to_string(), "opencode provider unreachable at http://127.0.0.1:1");
+
+    assert!(manager.ensure_provider_available("unknown").await.is_err());
+}
+
+#[tokio::test]
+async fn test_from_config_uses_configured_opencode_url() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let config = Config {
+        opencode_url: "http://127.0.0.1:2".to_string(),
+        ..Config::default()
+    };
+
+    let manager = SessionManager::from_config(db, &config).unwrap();
+
+    let err = manager.ensure_provider_available("opencode").await.unwrap_err();
+    assert!(err.to_string().contains("http://127.0.0.1:2"));
+}
+
+#[tokio::test]
+async fn test_agent_prompt_uses_stored_template() {
+    let (manager, _temp) = create_test_manager("http://127.0.0.1:1");
+
+    let default = manager.agent_prompt("developer", Some("Dev"), None, None).await.unwrap();
+    assert!(default.starts_with("You are a Developer Agent"));
+    assert!(default.contains("Your name is: Dev"));
+
+    manager.agent_configs()
+        .create(
+            "security-auditor".parse::<AgentType>().unwrap(),
+            "security-auditor".to_string(),
+            None,
+            "default".to_string(),
+            Some("You audit code for vulnerabilities.".to_string()),
+        )
+        .await
+        .unwrap();
+
+    let custom = manager.agent_prompt("security-auditor", None, Some("Focus on auth."), None).await.unwrap();
+    assert!(custom.starts_with("You audit code for vulnerabilities."));
+    assert!(custom.contains("Focus on auth."));
+    assert!(custom.contains("Your role is: Security-auditor"));
+
+    assert!(manager.agent_prompt("developer", None, None, Some("missing")).await.is_err());
+}
+
+fn activity(session_id: &str, state: AgentState, last_response: Option<&str>) -> SessionActivity {
+    SessionActivity {
+        session_id: session_id.to_string(),
+        state,
+        last_message: Some("do the thing".to_string()),
+        last_response: last_response.map(String::from),
+        state_changed_at: chrono::Utc::now(),
+        approval_type: None,
+        approval_description: None,
+    }
+}
+
+#[tokio::test]
+async fn test_wait_for_idle() {
+    let (manager, _temp) = create_test_manager("http://127.0.0.1:1");
+    let session = manager.repository()
+        .create(AgentType::Developer, SessionType::Claude, None, None)
+        .await
+        .unwrap();
+
+    manager.repository()
+        .record_activity(&activity(&session.id, AgentState::Processing, None))
+        .await
+        .unwrap();
+
+    // Still processing: times out
+    let err = manager.wait_for_idle(&session.id, Duration::from_millis(100)).await.unwrap_err();
+    assert!(err.to_string().contains("still processing"));
+
+    // Finishes while we wait
+    let repo = SessionRepository::new(manager.repository().db().clone());
+    let id = session.id.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        repo.record_activity(&activity(&id, AgentState::Idle, Some("done"))).await.unwrap();
+    });
+
+    // An overlong timeout is capped rather than overflowing the deadline
+    let settled = manager.wait_for_idle(&session.id, Duration::MAX).await.unwrap().unwrap();
+    assert_eq!(settled.state, AgentState::Idle);
+    assert_eq!(settled.last_response.as_deref(), Some("done"));
+}
+
+#[tokio::test]
+async fn test_respond_to_approval_requires_waiting_session() {
+    let (manager, _temp) = create_test_manager("http://127.0.0.1:1");
+    let session = manager.repository()
+        .create(AgentType::Developer, SessionType::Claude, None, None)
+        .await
+        .unwrap();
+
+    let err = manager.respond_to_approval(&session.id, "provider-id", "claude", true, None).await.unwrap_err();
+    assert!(err.to_string().contains("not waiting for approval"));
+
+    manager.repository()
+        .record_activity(&SessionActivity {
+            approval_type: Some(ApprovalType::FileWrite),
+            ..activity(&session.id, AgentState::WaitingForApproval, None)
+        })
+        .await
+        .unwrap();
+    assert!(manager.is_session_waiting(&session.id).await.unwrap());
+
+    // The provider has to know the session to answer for it
+    let err = manager.respond_to_approval(&session.id, "provider-id", "claude", true, None).await.unwrap_err();
+    assert!(err.to_string().contains("not found"), "{}", err);
+    assert!(manager.is_session_waiting(&session.id).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_wait_for_blocked() {
+    let (manager, _temp) = create_test_manager("http://127.0.0.1:1");
+    let old = manager.repository()
+        .create(AgentType::Developer, SessionType::Claude, None, None)
+        .await
+        .unwrap();
+    let new = manager.repository()
+        .create(AgentType::Developer, SessionType::Claude, None, None)
+        .await
+        .unwrap();
+
+    // Already blocked before the wait starts
+    manager.repository()
+        .record_activity(&activity(&old.id, AgentState::WaitingForApproval, None))
+        .await
+        .unwrap();
+    assert!(manager.wait_for_blocked(Duration::from_millis(100)).await.unwrap().is_empty());
+
+    let repo = SessionRepository::new(manager.repository().db().clone());
+    let id = new.id.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        repo.record_activity(&activity(&id, AgentState::WaitingForApproval, None)).await.unwrap();
+    });
+
+    // An overlong timeout is capped rather than overflowing the deadline
+    let blocked = manager.wait_for_blocked(Duration::MAX).await.unwrap();
+    assert_eq!(blocked.len(), 1);
+    assert_eq!(blocked[0].session_id, new.id);
+}
+
+#[tokio::test]
+async fn test_session_output_falls_back_to_messages() {
+    use supercode::db::repositories::message::MessageRole;
+
+    let (manager, _temp) = create_test_manager("http://127.0.0.1:1");
+    let session = manager.repository()
+        .create(AgentType::Developer, SessionType::Claude, None, None)
+        .await
+        .unwrap();
+
+    manager.messages().create(&session.id, MessageRole::User, "build it").await.unwrap();
+    manager.messages().create(&session.id, MessageRole::Assistant, "cargo failed:\nerror[E0425]").await.unwrap();
+
+    let output = manager.get_session_output(&session.id, None).await.unwrap();
+    assert_eq!(output, "[user] build it\n[assistant] cargo failed:\nerror[E0425]");
+
+    let tail = manager.get_session_output(&session.id, Some(2)).await.unwrap();
+    assert_eq!(tail, "[assistant] cargo failed:\nerror[E0425]");
+
+    assert!(manager.get_session_output("missing", None).await.is_err());
+}
+
+#[tokio::test]
+async fn test_spawn_checks_working_dir() {
+    let (manager, temp) = create_test_manager("http://127.0.0.1:1");
+    let repo = manager.repository();
+
+    let missing = temp.path().join("projct").to_string_lossy().to_string();
+    let err = SessionManager::validate_working_dir(&missing).unwrap_err();
+    assert_eq!(err.to_string(), format!("Working directory does not exist: {}", missing));
+
+    let file = temp.path().join("test.db").to_string_lossy().to_string();
+    assert!(SessionManager::validate_working_dir(&file).is_err());
+
+    // A bad directory fails before any provider is contacted
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, Some(missing)).await.unwrap();
+    let err = manager.spawn_session(&session.id, "developer", "opencode", Some("dev"), None, None).await.unwrap_err();
+    assert!(err.to_string().contains("does not exist"), "{}", err);
+
+    // A good one is recorded as a git repo or not, then spawning carries on
+    let project = temp.path().join("project");
+    std::fs::create_dir_all(project.join(".git")).unwrap();
+    let dir = SessionManager::validate_working_dir(&project.join("..").join("project").to_string_lossy()).unwrap();
+    assert_eq!(dir, project.canonicalize().unwrap());
+
+    let session = repo.create(AgentType::Developer, SessionType::OpenCode, None, Some(dir.to_string_lossy().to_string())).await.unwrap();
+    assert!(manager.spawn_session(&session.id, "developer", "opencode", Some("dev"), None, None).await.is_err());
+    let metadata: serde_json::Value = serde_json::from_str(&repo.get(&session.id).await.unwrap().unwrap().metadata.unwrap()).unwrap();
+    assert_eq!(metadata["git_repo"], true);
+}
+
+#[tokio::test]
+async fn test_max_concurrent_sessions() {
+    use supercode::db::repositories::session::SessionStatus;
+    use supercode::session::RetryPolicy;
+
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let manager = SessionManager::with_opencode_url(db, "http://127.0.0.1:1")
+        .with_spawn_retry(RetryPolicy { max_retries: 0, ..RetryPolicy::default() })
+        .with_max_concurrent_sessions(2);
+    let repo = manager.repository();
+
+    let running = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.set_opencode_session_id(&running.id, "ses_1").await.unwrap();
+    let pending = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    let done = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    repo.update_status(&done.id, SessionStatus::Completed).await.unwrap();
+
+    // Two others are active: the third is refused before any provider is contacted
+    let new = repo.create(AgentType::Developer, SessionType::OpenCode, None, None).await.unwrap();
+    let err = manager.spawn_session(&new.id, "developer", "opencode", Some("dev"), None, None).await.unwrap_err();
+    assert!(err.to_string().starts_with("Session limit reached: 2 of max_concurrent_sessions = 2"), "{}", err);
+    assert!(manager.fork_session("ses_1", "opencode").await.unwrap_err().to_string().starts_with("Session limit reached"));
+
+    // Freeing a slot lets it through to the (unreachable) provider
+    repo.update_status(&pending.id, SessionStatus::Terminated).await.unwrap();
+    let err = manager.spawn_session(&new.id, "developer", "opencode", Some("dev"), None, None).await.unwrap_err();
+    assert!(!err.to_string().contains("Session limit"), "{}", err);
+}
+
+/// Accept webhook POSTs, forwarding each JSON body. The first `fail_first`
+/// are answered with a server error instead.
+async fn webhook_receiver(fail_first: usize) -> (String, tokio::sync::mpsc::UnboundedReceiver<serde_json::Value>) {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let failures = Arc::new(AtomicUsize::new(fail_first));
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let tx = tx.clone();
+            let failures = failures.clone();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+
+                    let Some(header_end) = request.windows(4).position(|w| w == b"\r\n\r\n").map(|p| p + 4) else {
+                        continue;
+                    };
+                    let content_length = String::from_utf8_lossy(&request[..header_end])
+                        .lines()
+                        .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if request.len() >= header_end + content_length {
+                        if failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() {
+                            stream.write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await.unwrap();
+                            return;
+                        }
+                        tx.send(serde_json::from_slice(&request[header_end..header_end + content_length]).unwrap()).unwrap();
+                        stream.write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n").await.unwrap();
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    (url, rx)
+}
+
+#[tokio::test]
+async fn test_webhook_reports_state_transitions() {
+    let (url, mut events) = webhook_receiver(0).await;
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let manager = SessionManager::with_opencode_url(db, "http://127.0.0.1:1").with_webhook(url);
+    let repo = manager.repository();
+
+    let session = repo.create(AgentType::Developer, SessionType::Claude, None, None).await.unwrap();
+    repo.set_name(&session.id, "frontend").await.unwrap();
+
+    // History from before the first pass isn't reported
+    repo.record_activity(&activity(&session.id, AgentState::Processing, None)).await.unwrap();
+    repo.record_activity(&activity(&session.id, AgentState::Idle, Some("old news"))).await.unwrap();
+    manager.reconcile_statuses().await.unwrap();
+
+    repo.record_activity(&activity(&session.id, AgentState::Processing, None)).await.unwrap();
+    repo.record_activity(&activity(&session.id, AgentState::Idle, Some("all done"))).await.unwrap();
+    repo.record_activity(&SessionActivity {
+        approval_description: Some("rm -rf target".to_string()),
+        ..activity(&session.id, AgentState::WaitingForApproval, Some("all done"))
+    }).await.unwrap();
+    repo.record_activity(&activity(&session.id, AgentState::Error, Some("boom"))).await.unwrap();
+    manager.reconcile_statuses().await.unwrap();
+
+    let completed = events.recv().await.unwrap();
+    assert_eq!(completed["event"], "completed");
+    assert_eq!(completed["session_id"], session.id.as_str());
+    assert_eq!(completed["name"], "frontend");
+    assert_eq!(completed["state"], "idle");
+    assert_eq!(completed["last_response"], "all done");
+
+    let blocked = events.recv().await.unwrap();
+    assert_eq!(blocked["event"], "blocked");
+    assert_eq!(blocked["approval_description"], "rm -rf target");
+
+    let failed = events.recv().await.unwrap();
+    assert_eq!(failed["event"], "failed");
+    assert_eq!(failed["last_response"], "boom");
+
+    // Nothing new, nothing sent
+    manager.reconcile_statuses().await.unwrap();
+    assert!(events.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_webhook_retries_undelivered_events() {
+    let (url, mut events) = webhook_receiver(1).await;
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let manager = SessionManager::with_opencode_url(db, "http://127.0.0.1:1").with_webhook(url);
+    let repo = manager.repository();
+
+    let session = repo.create(AgentType::Developer, SessionType::Claude, None, None).await.unwrap();
+    manager.reconcile_statuses().await.unwrap();
+
+    repo.record_activity(&activity(&session.id, AgentState::Processing, None)).await.unwrap();
+    repo.record_activity(&activity(&session.id, AgentState::Idle, Some("all done"))).await.unwrap();
+    repo.record_activity(&activity(&session.id, AgentState::Error, Some("boom"))).await.unwrap();
+
+    // The receiver fails the first POST, so nothing gets through
+    manager.reconcile_statuses().await.unwrap();
+    assert!(events.try_recv().is_err());
+
+    // The next pass starts again from the undelivered event, in order
+    manager.reconcile_statuses().await.unwrap();
+    assert_eq!(events.recv().await.unwrap()["event"], "completed");
+    assert_eq!(events.recv().await.unwrap()["event"], "failed");
+
+    manager.reconcile_statuses().await.unwrap();
+    assert!(events.try_recv().is_err());
+}
+
+/// Replies with the message it was sent
+struct EchoProvider;
+
+#[async_trait::async_trait]
+impl SessionProvider for EchoProvider {
+    async fn create_session(&self, _system_prompt: Option<String>) -> anyhow::Result<SessionHandle> {
+        Ok(SessionHandle { internal_id: "internal".to_string(), provider_id: "echo_1".to_string() })
+    }
+
+    async fn send_message(&self, _session_id: &str, message: &str) -> anyhow::Result<String> {
+        Ok(format!("echo: {}", message))
+    }
+
+    async fn get_status(&self, _session_id: &str) -> anyhow::Result<ProviderSessionStatus> {
+        Ok(ProviderSessionStatus::Running)
+    }
+
+    async fn fork_session(&self, session_id: &str) -> anyhow::Result<SessionHandle> {
+        anyhow::bail!("Can't fork {}", session_id)
+    }
+
+    async fn kill_session(&self, _session_id: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn health_check(&self) -> anyhow::Result<bool> {
+        Ok(true)
+    }
+}
+
+#[tokio::test]
+async fn test_registered_provider_backs_custom_session_type() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+    let repo = SessionRepository::new(db.clone());
+    let manager = SessionManager::with_opencode_url(db, "http://127.0.0.1:1")
+        .with_provider("echo", Arc::new(EchoProvider));
+
+    assert_eq!(manager.session_types(), vec!["claude", "echo", "opencode"]);
+    let err = manager.ensure_provider_available("parrot").await.unwrap_err();
+    assert!(err.to_string().contains("available: claude, echo, opencode"), "{}", err);
+    manager.ensure_provider_available("echo").await.unwrap();
+
+    let session_type = "echo".parse::<SessionType>().unwrap();
+    assert_eq!(session_type, SessionType::Custom("echo".to_string()));
+    assert!("Echo!".parse::<SessionType>().is_err());
+
+    let session = repo.create(AgentType::Developer, session_type.clone(), None, None).await.unwrap();
+    let handle = manager.spawn_session(&session.id, "developer", "echo", Some("dev"), None, None).await.unwrap();
+    assert_eq!(handle.provider_id, "echo_1");
+
+    let reply = manager.send_message(&session.id, &handle.provider_id, "echo", "hello").await.unwrap();
+    assert_eq!(reply, "echo: hello");
+
+    // Without supports_fork the manager refuses before asking the provider
+    let err = manager.fork_session(&handle.provider_id, "echo").await.unwrap_err();
+    assert!(err.to_string().contains("cannot fork"), "{}", err);
+
+    let stored = repo.get(&session.id).await.unwrap().unwrap();
+    assert_eq!(stored.session_type, session_type);
+    assert_eq!(serde_json::to_value(&stored.session_type).unwrap(), "echo");
+}
+
+#[tokio::test]
+async fn test_export_transcript_as_markdown() {
+    use supercode::db::repositories::message::MessageRole;
+
+    let (manager, _temp) = create_test_manager("http://127.0.0.1:1");
+    let session = manager.repository()
+        .create(AgentType::Developer, SessionType::OpenCode, None, Some("/work/api".to_string()))
+        .await.unwrap();
+    manager.repository().set_name(&session.id, "api-dev").await.unwrap();
+
+    let messages = manager.messages();
+    messages.create(&session.id, MessageRole::User, "Fix the failing test").await.unwrap();
+    messages.create(&session.id, MessageRole::Assistant, "Done:\n\n```rust\nassert_eq!(1 + 1, 2);\n```\n").await.unwrap();
+    messages.create(&session.id, MessageRole::Assistant, "Partial output:\n```\ncargo test").await.unwrap();
+
+    let transcript = manager.export_transcript(&session.id).await.unwrap();
+
+    assert!(transcript.starts_with("# Session api-dev\n"), "{}", transcript);
+    assert!(transcript.contains("- **Agent:** developer (opencode)"));
+    assert!(transcript.contains("- **Working dir:** `/work/api`"));
+    assert!(transcript.contains("## User ("));
+    assert!(transcript.contains("Fix the failing test"));
+    assert!(transcript.contains("```rust\nassert_eq!(1 + 1, 2);\n```\n"));
+    // The cut-off code block is closed
+    assert!(transcript.ends_with("```\ncargo test\n```\n"), "{}", transcript);
+    assert_eq!(transcript.matches("## Assistant (").count(), 2);
+
+    assert!(manager.export_transcript("missing").await.is_err());
+}
+
+/// Run git in `dir` with a throwaway identity
+fn git(dir: &std::path::Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+#[tokio::test]
+async fn test_isolate_workspace_as_worktree() {
+    use supercode::session::workspace::WorkspaceIsolation;
+
+    let (manager, temp) = create_test_manager("http://127.0.0.1:1");
+    let manager = manager.with_workspaces_dir(temp.path().join("workspaces"));
+    let repo = temp.path().join("repo");
+    std::fs::create_dir(&repo).unwrap();
+    git(&repo, &["init", "-q"]);
+    std::fs::write(repo.join("lib.rs"), "fn main() {}\n").unwrap();
+    git(&repo, &["add", "lib.rs"]);
+    git(&repo, &["commit", "-q", "-m", "init"]);
+    // Uncommitted work stays in the original checkout
+    std::fs::write(repo.join("wip.rs"), "// half done\n").unwrap();
+
+    let session = manager.repository()
+        .create(AgentType::Developer, SessionType::Claude, None, Some(repo.to_string_lossy().to_string()))
+        .await.unwrap();
+
+    let workspace = manager.isolate_workspace(&session.id, WorkspaceIsolation::Worktree).await.unwrap();
+    assert_eq!(workspace, temp.path().join("workspaces").join(&session.id));
+    assert!(workspace.join("lib.rs").exists());
+    assert!(!workspace.join("wip.rs").exists());
+
+    let stored = manager.repository().get(&session.id).await.unwrap().unwrap();
+    assert_eq!(stored.working_dir.as_deref(), Some(workspace.to_string_lossy().as_ref()));
+    let metadata: serde_json::Value = serde_json::from_str(stored.metadata.as_deref().unwrap()).unwrap();
+    assert_eq!(metadata["workspace"]["isolation"], "worktree");
+    assert_eq!(metadata["workspace"]["source_dir"], repo.canonicalize().unwrap().to_string_lossy().as_ref());
+
+    manager.remove_workspace(&session.id, false).await.unwrap();
+    assert!(!workspace.exists());
+    assert!(repo.join("wip.rs").exists());
+}
+
+#[tokio::test]
+async fn test_worktree_of_subdirectory_keeps_the_subdirectory() {
+    use supercode::session::workspace::WorkspaceIsolation;
+
+    let (manager, temp) = create_test_manager("http://127.0.0.1:1");
+    let manager = manager.with_workspaces_dir(temp.path().join("workspaces"));
+    let repo = temp.path().join("repo");
+    std::fs::create_dir_all(repo.join("crates").join("core")).unwrap();
+    git(&repo, &["init", "-q"]);
+    std::fs::write(repo.join("crates").join("core").join("lib.rs"), "// core\n").unwrap();
+    git(&repo, &["add", "."]);
+    git(&repo, &["commit", "-q", "-m", "init"]);
+
+    let subdir = repo.join("crates").join("core");
+    let session = manager.repository()
+        .create(AgentType::Developer, SessionType::Claude, None, Some(subdir.to_string_lossy().to_string()))
+        .await.unwrap();
+
+    let root = temp.path().join("workspaces").join(&session.id);
+    let workspace = manager.isolate_workspace(&session.id, WorkspaceIsolation::Worktree).await.unwrap();
+    assert_eq!(workspace, root.join("crates").join("core"));
+    assert!(workspace.join("lib.rs").exists());
+
+    let stored = manager.repository().get(&session.id).await.unwrap().unwrap();
+    assert_eq!(stored.working_dir.as_deref(), Some(workspace.to_string_lossy().as_ref()));
+    let metadata: serde_json::Value = serde_json::from_str(stored.metadata.as_deref().unwrap()).unwrap();
+    assert_eq!(metadata["workspace"]["path"], root.to_string_lossy().as_ref());
+
+    // The whole worktree goes, not just the subdirectory
+    manager.remove_workspace(&session.id, false).await.unwrap();
+    assert!(!root.exists());
+}
+
+#[tokio::test]
+async fn test_isolate_workspace_as_copy() {
+    use supercode::session::workspace::WorkspaceIsolation;
+
+    let (manager, temp) = create_test_manager("http://127.0.0.1:1");
+    let manager = manager.with_workspaces_dir(temp.path().join("workspaces"));
+    let dir = temp.path().join("project");
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(dir.join("src").join("main.rs"), "fn main() {}\n").unwrap();
+
+    let session = manager.repository()
+        .create(AgentType::Developer, SessionType::Claude, None, Some(dir.to_string_lossy().to_string()))
+        .await.unwrap();
+
+    let workspace = manager.isolate_workspace(&session.id, WorkspaceIsolation::Copy).await.unwrap();
+    assert_eq!(std::fs::read_to_string(workspace.join("src").join("main.rs")).unwrap(), "fn main() {}\n");
+
+    // Edits in the copy leave the original alone
+    std::fs::write(workspace.join("src").join("main.rs"), "fn main() { todo!() }\n").unwrap();
+    assert_eq!(std::fs::read_to_string(dir.join("src").join("main.rs")).unwrap(), "fn main() {}\n");
+
+    // Worktrees need a git repository
+    let plain = manager.repository()
+        .create(AgentType::Developer, SessionType::Claude, None, Some(dir.to_string_lossy().to_string()))
+        .await.unwrap();
+    assert!(manager.isolate_workspace(&plain.id, WorkspaceIsolation::Worktree).await.is_err());
+
+    manager.remove_workspace(&session.id, false).await.unwrap();
+    assert!(!workspace.exists());
+    assert!(dir.join("src").join("main.rs").exists());
+}
+
+#[tokio::test]
+async fn test_create_worktree_on_new_branch() {
+    let (manager, temp) = create_test_manager("http://127.0.0.1:1");
+    let manager = manager.with_workspaces_dir(temp.path().join("workspaces"));
+    let repo = temp.path().join("repo");
+    std::fs::create_dir(&repo).unwrap();
+    git(&repo, &["init", "-q"]);
+    std::fs::write(repo.join("lib.rs"), "// v1\n").unwrap();
+    git(&repo, &["add", "lib.rs"]);
+    git(&repo, &["commit", "-q", "-m", "v1"]);
+    git(&repo, &["branch", "release"]);
+    std::fs::write(repo.join("lib.rs"), "// v2\n").unwrap();
+    git(&repo, &["commit", "-q", "-am", "v2"]);
+
+    let session = manager.repository()
+        .create(AgentType::Developer, SessionType::Claude, None, Some(repo.to_string_lossy().to_string()))
+        .await.unwrap();
+
+    let (worktree, branch) = manager.create_worktree(&session.id, Some("release")).await.unwrap().unwrap();
+    assert_eq!(branch, format!("supercode/{}", session.id));
+    assert_eq!(std::fs::read_to_string(worktree.join("lib.rs")).unwrap(), "// v1\n");
+
+    let stored = manager.repository().get(&session.id).await.unwrap().unwrap();
+    assert_eq!(stored.working_dir.as_deref(), Some(worktree.to_string_lossy().as_ref()));
+    let metadata: serde_json::Value = serde_json::from_str(stored.metadata.as_deref().unwrap()).unwrap();
+    assert_eq!(metadata["workspace"]["branch"], branch.as_str());
+    assert_eq!(metadata["workspace"]["base_branch"], "release");
+    assert_eq!(metadata["workspace"]["path"], worktree.to_string_lossy().as_ref());
+
+    // A fork shares the worktree, so deleting it leaves the worktree alone
+    let fork = manager.repository().create_fork(&stored).await.unwrap();
+    manager.remove_workspace(&fork.id, false).await.unwrap();
+    assert!(worktree.exists());
+
+    // Removing the worktree keeps the branch and its commits
+    manager.remove_workspace(&session.id, false).await.unwrap();
+    assert!(!worktree.exists());
+    git(&repo, &["rev-parse", "--verify", "-q", &branch]);
+
+    // Outside a git repository there is nothing to branch
+    let plain = temp.path().join("plain");
+    std::fs::create_dir(&plain).unwrap();
+    let session = manager.repository()
+        .create(AgentType::Developer, SessionType::Claude, None, Some(plain.to_string_lossy().to_string()))
+        .await.unwrap();
+    assert!(manager.create_worktree(&session.id, None).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_worktree_with_uncommitted_changes_is_kept() {
+    let (manager, temp) = create_test_manager("http://127.0.0.1:1");
+    let manager = manager.with_workspaces_dir(temp.path().join("workspaces"));
+    let repo = temp.path().join("repo");
+    std::fs::create_dir(&repo).unwrap();
+    git(&repo, &["init", "-q"]);
+    std::fs::write(repo.join("lib.rs"), "// v1\n").unwrap();
+    git(&repo, &["add", "lib.rs"]);
+    git(&repo, &["commit", "-q", "-m", "v1"]);
+
+    let session = manager.repository()
+        .create(AgentType::Developer, SessionType::Claude, None, Some(repo.to_string_lossy().to_string()))
+        .await.unwrap();
+
+    // A base that git would read as an option is refused
+    let err = manager.create_worktree(&session.id, Some("--orphan")).await.unwrap_err();
+    assert!(err.to_string().contains("Invalid base branch"), "{}", err);
+
+    let (worktree, _) = manager.create_worktree(&session.id, None).await.unwrap().unwrap();
+    std::fs::write(worktree.join("lib.rs"), "// agent's work\n").unwrap();
+
+    assert!(manager.remove_workspace(&session.id, false).await.is_err());
+    assert_eq!(std::fs::read_to_string(worktree.join("lib.rs")).unwrap(), "// agent's work\n");
+
+    manager.remove_workspace(&session.id, true).await.unwrap();
+    assert!(!worktree.exists());
+}
+
+#[tokio::test]
+async fn test_reconcile_leaves_sessions_held_by_other_processes() {
+    let (manager, _temp) = create_test_manager("http://127.0.0.1:1");
+    let repo = manager.repository();
+
+    // Spawned by another server process, whose Claude client holds it
+    let session = repo.create(AgentType::Developer, SessionType::Claude, None, None).await.unwrap();
+    repo.set_opencode_session_id(&session.id, "claude-elsewhere").await.unwrap();
+    repo.update_status(&session.id, supercode::db::repositories::session::SessionStatus::Running).await.unwrap();
+
+    assert_eq!(manager.reconcile_statuses().await.unwrap(), 0);
+    let stored = repo.get(&session.id).await.unwrap().unwrap();
+    assert_eq!(stored.status, supercode::db::repositories::session::SessionStatus::Running);
+}
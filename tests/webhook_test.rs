@@ -0,0 +1,74 @@
+// Tests for which state changes become webhook events
+
+use chrono::Utc;
+use supercode::db::repositories::session::{ActivityChange, AgentState, SessionActivity, SessionStatus};
+use supercode::session::webhook::WebhookEvent;
+
+fn change(state: AgentState, previous_state: Option<AgentState>) -> ActivityChange {
+    ActivityChange {
+        id: 1,
+        activity: SessionActivity {
+            session_id: "s1".to_string(),
+            state,
+            last_message: None,
+            last_response: Some("done".to_string()),
+            state_changed_at: Utc::now(),
+            approval_type: None,
+            approval_description: Some("rm -rf target".to_string()),
+        },
+        previous_state,
+    }
+}
+
+fn event(state: AgentState, previous_state: Option<AgentState>) -> Option<&'static str> {
+    WebhookEvent::from_activity(&change(state, previous_state)).map(|e| e.event)
+}
+
+#[test]
+fn test_activity_transitions() {
+    use AgentState::*;
+
+    // Becoming blocked, however the session got there
+    assert_eq!(event(WaitingForApproval, None), Some("blocked"));
+    assert_eq!(event(WaitingForApproval, Some(Processing)), Some("blocked"));
+    assert_eq!(event(WaitingForApproval, Some(Idle)), Some("blocked"));
+    // Still blocked is not news
+    assert_eq!(event(WaitingForApproval, Some(WaitingForApproval)), None);
+
+    // Only finishing a turn completes one
+    assert_eq!(event(Idle, Some(Processing)), Some("completed"));
+    assert_eq!(event(Idle, Some(Idle)), None);
+    assert_eq!(event(Idle, Some(WaitingForApproval)), None);
+    assert_eq!(event(Idle, None), None);
+
+    assert_eq!(event(Error, None), Some("failed"));
+    assert_eq!(event(Error, Some(Processing)), Some("failed"));
+
+    assert_eq!(event(Processing, Some(Idle)), None);
+    assert_eq!(event(Processing, None), None);
+}
+
+#[test]
+fn test_activity_event_carries_snapshot() {
+    let change = change(AgentState::WaitingForApproval, Some(AgentState::Processing));
+    let event = WebhookEvent::from_activity(&change).unwrap();
+
+    assert_eq!(event.session_id, "s1");
+    assert_eq!(event.state, "waiting_for_approval");
+    assert_eq!(event.last_response.as_deref(), Some("done"));
+    assert_eq!(event.approval_description.as_deref(), Some("rm -rf target"));
+    assert_eq!(event.timestamp, change.activity.state_changed_at);
+    // Filled in by the manager, which knows the session
+    assert!(event.name.is_none());
+}
+
+#[test]
+fn test_status_transitions() {
+    let event = |status| WebhookEvent::from_status("s1", status).map(|e| (e.event, e.state));
+
+    assert_eq!(event(SessionStatus::Completed), Some(("completed", "completed".to_string())));
+    assert_eq!(event(SessionStatus::Failed), Some(("failed", "failed".to_string())));
+    assert_eq!(event(SessionStatus::Pending), None);
+    assert_eq!(event(SessionStatus::Running), None);
+    assert_eq!(event(SessionStatus::Terminated), None);
+}